@@ -0,0 +1,110 @@
+//! Compares the aspect ratios of images and monitors
+//!
+//! Reading the dimensions of images needs the `aspect` feature.
+//! Without it, the dimensions are unknown, so `aspect_tolerance` doesn't filter any image.
+
+use std::path::Path;
+
+use tracing::error;
+
+use crate::config::{Cache, Dimensions};
+
+/// Returns the width and height of the image
+///
+/// Only the header of the file gets read.
+#[cfg(feature = "aspect")]
+pub fn read(image: &Path) -> anyhow::Result<(u32, u32)> {
+    use anyhow::Context;
+
+    image::image_dimensions(image).context("while reading image dimensions")
+}
+
+#[cfg(not(feature = "aspect"))]
+pub fn read(_image: &Path) -> anyhow::Result<(u32, u32)> {
+    anyhow::bail!("reading image dimensions needs the `aspect` feature")
+}
+
+/// Returns the width and height of the image
+///
+/// The dimensions are only read again if the image was modified since the last time.
+pub fn dimensions_of(cache: &mut Cache, image: &Path) -> Option<(u32, u32)> {
+    if !cfg!(feature = "aspect") {
+        return None;
+    }
+    let modified = match std::fs::metadata(image).and_then(|m| m.modified()) {
+        Ok(modified) => modified,
+        Err(e) => {
+            error!("can't read modification time of {}: {}", image.display(), e);
+            return None;
+        }
+    };
+    if let Some(cached) = cache
+        .dimensions
+        .get(image)
+        .filter(|cached| *cached.modified == modified)
+    {
+        return Some((cached.width, cached.height));
+    }
+
+    match read(image) {
+        Ok((width, height)) => {
+            cache.dimensions.insert(
+                image.to_owned(),
+                Dimensions {
+                    modified: modified.into(),
+                    width,
+                    height,
+                },
+            );
+            Some((width, height))
+        }
+        Err(e) => {
+            error!("can't read dimensions of {}: {:#}", image.display(), e);
+            None
+        }
+    }
+}
+
+/// Returns whether the aspect ratio of the image differs at most by `tolerance` from the
+/// aspect ratio of the monitor
+///
+/// The tolerance is relative, so `0.1` allows a difference of 10%.
+pub fn fits(image: (u32, u32), monitor: (u32, u32), tolerance: f32) -> bool {
+    let ratio = |(width, height): (u32, u32)| f64::from(width) / f64::from(height.max(1));
+    let monitor_ratio = ratio(monitor);
+    if monitor_ratio == 0.0 {
+        return true;
+    }
+    (ratio(image) / monitor_ratio - 1.0).abs() <= f64::from(tolerance)
+}
+
+/// The orientation of an image or monitor
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Orientation {
+    Portrait,
+    Landscape,
+    Square,
+}
+
+impl std::fmt::Display for Orientation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Portrait => write!(f, "portrait"),
+            Self::Landscape => write!(f, "landscape"),
+            Self::Square => write!(f, "square"),
+        }
+    }
+}
+
+/// Returns the orientation of something with this width and height
+///
+/// Aspect ratios which differ at most 5% from 1 count as square.
+pub fn orientation(dimensions: (u32, u32)) -> Orientation {
+    if fits(dimensions, (1, 1), 0.05) {
+        Orientation::Square
+    } else if dimensions.0 > dimensions.1 {
+        Orientation::Landscape
+    } else {
+        Orientation::Portrait
+    }
+}
@@ -0,0 +1,74 @@
+//! The document written by `wallpaper export` and read by `wallpaper import`
+//!
+//! It holds the config file and the cache, with the images of the cache relative to
+//! `image_dir`, so both can be moved to another machine whose images live elsewhere.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context};
+
+use crate::config::{Cache, Config};
+
+/// The version of the export format, increased on incompatible changes
+pub const BACKUP_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Backup {
+    pub version: u32,
+    /// The `image_dir` of the exporting machine
+    pub image_dir: PathBuf,
+    /// The config file as it was written, not resolved
+    pub config: Config,
+    /// The cache with the images inside `image_dir` relative to it
+    pub cache: Cache,
+}
+
+impl Backup {
+    pub fn new(config: Config, mut cache: Cache) -> Self {
+        let image_dir = config.image_dir.clone();
+        cache.map_images(|image| {
+            image
+                .strip_prefix(&image_dir)
+                .map_or_else(|_| image.to_path_buf(), Path::to_path_buf)
+        });
+        Self {
+            version: BACKUP_VERSION,
+            image_dir,
+            config,
+            cache,
+        }
+    }
+
+    /// Reads a backup and fails if it was written by an incompatible version
+    pub fn read(reader: impl std::io::Read) -> anyhow::Result<Self> {
+        let value: serde_json::Value =
+            serde_json::from_reader(reader).context("while parsing backup")?;
+        let version = value.get("version").and_then(serde_json::Value::as_u64);
+        if version != Some(BACKUP_VERSION.into()) {
+            bail!(
+                "backup has incompatible version. Expected version {} but got {}",
+                BACKUP_VERSION,
+                version.map_or_else(|| "none".to_string(), |version| version.to_string())
+            );
+        }
+        serde_json::from_value(value).context("while parsing backup")
+    }
+
+    /// Returns the config and the cache with the images moved into `image_dir`
+    ///
+    /// Fails if the cache can't be migrated or the config is invalid, so nothing is
+    /// written for a broken backup.
+    pub fn restore(self, image_dir: &Path) -> anyhow::Result<(Config, Cache)> {
+        let mut config = self.config;
+        config.image_dir = image_dir.to_path_buf();
+        let mut cache = self.cache.migrate().context("while migrating cache")?;
+        cache.map_images(|image| image_dir.join(image));
+
+        config
+            .clone()
+            .resolve()
+            .context("while checking the imported config")?;
+
+        Ok((config, cache))
+    }
+}
@@ -0,0 +1,124 @@
+//! Analyzes the brightness of images in the background
+//!
+//! The analysis needs the `brightness` feature. Without it, no image gets analyzed,
+//! so `night_max_brightness` doesn't filter any image.
+
+use std::{
+    collections::HashSet,
+    path::PathBuf,
+    sync::mpsc::{channel, Receiver, TryRecvError},
+};
+
+use tracing::{debug, error};
+
+use crate::config::{Brightness, State};
+
+/// Analyzes images which are not analyzed yet in a background thread
+#[derive(Debug, Default)]
+pub struct Analyzer {
+    results: Option<Receiver<(PathBuf, anyhow::Result<Brightness>)>>,
+    /// Images which can't be analyzed, so they are not retried
+    failed: HashSet<PathBuf>,
+}
+
+impl Analyzer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Stores the finished results in the cache and starts analyzing new images
+    ///
+    /// This never blocks. Returns whether the cache changed.
+    pub fn poll(&mut self, state: &mut State) -> bool {
+        let mut changed = false;
+        if let Some(results) = &self.results {
+            loop {
+                match results.try_recv() {
+                    Ok((image, Ok(brightness))) => {
+                        debug!(
+                            "{} has a luminance of {:.2}",
+                            image.display(),
+                            brightness.luminance
+                        );
+                        state.cache.brightness.insert(image, brightness);
+                        changed = true;
+                    }
+                    Ok((image, Err(e))) => {
+                        error!("can't analyze {}: {:#}", image.display(), e);
+                        self.failed.insert(image);
+                    }
+                    Err(TryRecvError::Empty) => return changed,
+                    Err(TryRecvError::Disconnected) => {
+                        self.results = None;
+                        break;
+                    }
+                }
+            }
+        }
+
+        if state.config.night_max_brightness.is_none() || !cfg!(feature = "brightness") {
+            return changed;
+        }
+        let pending: Vec<_> = state
+            .config
+            .images
+            .keys()
+            .chain(state.source_images.keys())
+            .map(|path| state.config.image_dir.join(path))
+            .filter(|image| image.is_file())
+            .filter(|image| !self.failed.contains(image))
+            .filter(|image| state.cache.luminance_of(image).is_none())
+            .collect();
+        if pending.is_empty() {
+            return changed;
+        }
+
+        debug!("analyzing the brightness of {} images", pending.len());
+        let (sender, results) = channel();
+        std::thread::spawn(move || {
+            for image in pending {
+                let brightness = analyze(&image);
+                if sender.send((image, brightness)).is_err() {
+                    break;
+                }
+            }
+        });
+        self.results = Some(results);
+
+        changed
+    }
+}
+
+#[cfg(feature = "brightness")]
+fn analyze(image: &std::path::Path) -> anyhow::Result<Brightness> {
+    use anyhow::Context;
+
+    let modified = std::fs::metadata(image)
+        .and_then(|m| m.modified())
+        .context("while reading modification time")?;
+    let pixels = image::open(image)
+        .context("while decoding image")?
+        // the average doesn't need every pixel
+        .thumbnail(256, 256)
+        .into_rgb8();
+    let sum: f64 = pixels
+        .pixels()
+        .map(|pixel| {
+            let [r, g, b] = pixel.0.map(|c| f64::from(c) / 255.0);
+            // relative luminance as defined by ITU-R BT.709
+            0.2126 * r + 0.7152 * g + 0.0722 * b
+        })
+        .sum();
+    let count = u64::from(pixels.width()) * u64::from(pixels.height());
+    let luminance = if count == 0 { 0.0 } else { sum / count as f64 };
+
+    Ok(Brightness {
+        modified: modified.into(),
+        luminance: luminance as f32,
+    })
+}
+
+#[cfg(not(feature = "brightness"))]
+fn analyze(_image: &std::path::Path) -> anyhow::Result<Brightness> {
+    anyhow::bail!("analyzing images needs the `brightness` feature")
+}
@@ -0,0 +1,1023 @@
+//! `wallpaper check`, which finds mistakes in the config and the images
+
+use std::{
+    collections::{BTreeMap, HashSet},
+    path::{Path, PathBuf},
+};
+
+use tracing::{debug, error, info, warn};
+use wallpaper::{
+    aspect, dedupe,
+    ipc::{self, IpcEvent, IpcResponse},
+    query_monitors, scheduler, sources,
+    span::SpanLayout,
+    term, validate, AspectTolerance, DateRange, FixAction, ImageEntry, MonitorInfo, Monitors,
+    State,
+};
+
+/// Checks the config, `log` prints the findings as they come
+pub fn run(state: &State, deep: bool, full: bool, log: bool) -> anyhow::Result<CheckReport> {
+    let mut report = CheckReport::new(log);
+    info!("checking the config for errors");
+
+    for entry in &state.config.interval_schedule {
+        if let Err(e) = entry.times.check() {
+            report.error(format!("interval schedule: {}", e));
+        }
+    }
+
+    for (name, times) in &state.config.time_presets {
+        for time in times {
+            if let Err(e) = time.check() {
+                report.error(format!("time preset @{}: {}", name, e));
+            }
+        }
+    }
+
+    for rule in &state.config.name_rules {
+        if let Err(e) = rule.regex() {
+            report.error(e);
+        }
+    }
+
+    let mut listings = sources::Listings::default();
+    let mut source_images = Vec::new();
+    for source in &state.config.image_sources {
+        let dir = state.config.image_dir.join(&source.dir);
+        let images: Vec<_> = match listings.files(&dir, source.recursive) {
+            Ok(files) => files
+                .into_iter()
+                .filter(|file| state.config.has_allowed_extension(file))
+                .collect(),
+            Err(e) => {
+                report.error(format!("can't list images of {}: {:#}", dir.display(), e));
+                Vec::new()
+            }
+        };
+        if images.is_empty() {
+            report.warning(format!("image source {} contains no images", dir.display()));
+        } else {
+            info!(
+                "image source {} contains {} images",
+                dir.display(),
+                images.len()
+            );
+        }
+        for image in &images {
+            if let Some(reason) = state.config.rejection(image) {
+                report.warning(format!(
+                    "image {} is filtered out, because {}",
+                    image.display(),
+                    reason
+                ));
+            }
+        }
+        source_images.extend(images);
+        for time in &source.times {
+            if let Err(e) = time.check() {
+                report.error(format!("image source {}: {}", dir.display(), e));
+            }
+        }
+    }
+
+    for source in &state.config.remote_sources {
+        if !source.url.starts_with("http://") && !source.url.starts_with("https://") {
+            report.error(format!(
+                "remote source {}: only http and https are supported",
+                source.url
+            ));
+        }
+        let dir = state.remote_dir(source);
+        let downloaded = listings
+            .files(&dir, false)
+            .map(|files| {
+                files
+                    .into_iter()
+                    .filter(|file| state.config.has_allowed_extension(file))
+                    .count()
+            })
+            .unwrap_or(0);
+        info!(
+            "remote source {} has {} downloaded images in {}",
+            source.url,
+            downloaded,
+            dir.display()
+        );
+    }
+    if !state.config.remote_sources.is_empty() && !cfg!(feature = "remote") {
+        report
+            .warning("remote_sources are not downloaded, because the `remote` feature is disabled");
+    }
+
+    for (workspace, images) in &state.config.workspace_images {
+        for (file_path, times) in images {
+            let image = state.config.image_dir.join(file_path);
+            if !image.is_file() {
+                report.error(format!(
+                    "workspace {}: image {} does not exist!",
+                    workspace,
+                    image.to_string_lossy()
+                ));
+            }
+            for time in times {
+                if let Err(e) = time.check() {
+                    report.error(format!(
+                        "workspace {}: image {}: {}",
+                        workspace,
+                        image.to_string_lossy(),
+                        e
+                    ));
+                }
+            }
+        }
+    }
+    if !state.config.workspace_images.is_empty() {
+        if state.config.mirror {
+            report.error("mirror can't be combined with workspace_images, as mirrored monitors show the same images");
+        } else if state.cache.mirror == Some(true) {
+            report.error("mirror mode was turned on with `wallpaper mirror true`, but can't be combined with workspace_images; turn it off with `wallpaper mirror false`");
+        }
+    }
+    if state.config.inhibit_on_fullscreen && !cfg!(feature = "sway-ipc") {
+        report.warning(
+            "inhibit_on_fullscreen has no effect, because the `sway-ipc` feature is disabled",
+        );
+    }
+    if state.config.skip_when_off && !cfg!(feature = "sway-ipc") {
+        report.warning("skip_when_off has no effect, because the `sway-ipc` feature is disabled");
+    }
+    if state
+        .config
+        .lockscreen
+        .as_ref()
+        .is_some_and(|lockscreen| lockscreen.scale)
+        && !cfg!(feature = "prescale")
+    {
+        report
+            .warning("lockscreen.scale has no effect, because the `prescale` feature is disabled");
+    }
+    let all_effects = state
+        .config
+        .effects
+        .iter()
+        .map(|effects| ("effects", effects))
+        .chain(
+            state
+                .config
+                .monitor_settings
+                .values()
+                .filter_map(|settings| settings.effects.as_ref())
+                .map(|effects| ("effects of a monitor", effects)),
+        );
+    for (name, effects) in all_effects {
+        if effects.dim.is_some_and(|dim| !(0.0..=1.0).contains(&dim)) {
+            report.warning(format!("{}: dim is not between 0 and 1", name));
+        }
+        if effects.blur.is_some_and(|blur| blur < 0.0) {
+            report.warning(format!("{}: blur is negative", name));
+        }
+        if !cfg!(feature = "effects") {
+            report.warning(format!(
+                "{} have no effect, because the `effects` feature is disabled",
+                name
+            ));
+        }
+    }
+    if state.config.prescale && !cfg!(feature = "prescale") {
+        report.warning("prescale has no effect, because the `prescale` feature is disabled");
+    }
+    if state.config.rating_factor.0 <= 0.0 {
+        report.error("rating_factor has to be positive");
+    }
+    if state.cache.favorites_only {
+        let favorites = state
+            .config
+            .images
+            .keys()
+            .filter(|path| state.is_favorite(&state.config.image_dir.join(path)))
+            .count();
+        if favorites < 3 {
+            report.warning(format!(
+                "favorites-only mode is enabled, but only {} images are rated at least {}",
+                favorites, state.config.favorites_threshold
+            ));
+        }
+    }
+    if state.config.dedupe {
+        if cfg!(feature = "dedupe") {
+            for images in dedupe::duplicates(state) {
+                let images: Vec<_> = images
+                    .iter()
+                    .map(|image| image.display().to_string())
+                    .collect();
+                report.warning(format!(
+                    "images have the same content: {}",
+                    images.join(", ")
+                ));
+            }
+        } else {
+            report.warning("dedupe has no effect, because the `dedupe` feature is disabled");
+        }
+    }
+    if !state.config.workspace_images.is_empty() && !cfg!(feature = "sway-ipc") {
+        report.warning("workspace_images only apply after `wallpaper workspace`, because the `sway-ipc` feature is disabled");
+    }
+
+    if let Some(night) = &state.config.night_max_brightness {
+        if let Err(e) = night.times.check() {
+            report.error(format!("night_max_brightness: {}", e));
+        }
+        if !(0.0..=1.0).contains(&night.max) {
+            report.warning(format!(
+                "night_max_brightness: max {} is not between 0 and 1",
+                night.max
+            ));
+        }
+        if !cfg!(feature = "brightness") {
+            report.warning(
+                "night_max_brightness has no effect, because the `brightness` feature is disabled",
+            );
+        }
+    }
+
+    // the images in a missing `image_dir` are not reported one by one
+    let image_dir_usable = check_image_dir(state, &mut report);
+    let reports_missing = |file_path: &Path| image_dir_usable || file_path.is_absolute();
+    for (file_path, entry) in &state.config.images {
+        let image = state.config.image_dir.join(file_path);
+        if !image.is_file() && reports_missing(file_path) {
+            report.error(format!("image {} does not exist!", image.to_string_lossy()));
+        }
+        for time in &entry.times {
+            if let Err(e) = time.check() {
+                report.error(format!("image {}: {}", image.to_string_lossy(), e));
+            }
+        }
+        if let Some(reason) = state.config.rejection(&image) {
+            report.warning(format!(
+                "image {} is filtered out, because {}",
+                image.display(),
+                reason
+            ));
+        }
+        if let Some(Err(e)) = entry.dates.as_ref().map(DateRange::check) {
+            report.warning(format!("image {}: {}", image.to_string_lossy(), e));
+        }
+        check_inside_image_dir(state, file_path, &mut report);
+    }
+
+    for (monitor, settings) in &state.config.monitor_settings {
+        for file_path in settings.images.keys() {
+            let image = state.config.image_dir.join(file_path);
+            if !image.is_file() && reports_missing(file_path) {
+                report.error(format!(
+                    "image {} for monitor {} does not exist!",
+                    image.to_string_lossy(),
+                    monitor
+                ));
+            }
+            check_inside_image_dir(state, file_path, &mut report);
+        }
+        if let Some(image) = &settings.static_image {
+            let image = state.config.image_dir.join(image);
+            if !image.is_file() {
+                report.error(format!(
+                    "static image {} for monitor {} does not exist!",
+                    image.to_string_lossy(),
+                    monitor
+                ));
+            }
+        }
+    }
+
+    check_duplicates(state, &mut report);
+    check_coverage(state, &mut report);
+    if deep {
+        check_decodable(state, source_images, full, &mut report)?;
+    }
+
+    // e.g. in a test of the config there is no compositor
+    let monitor_infos = match query_monitors() {
+        Ok(monitor_infos) => monitor_infos,
+        Err(e) => {
+            report.warning(format!("can't check the monitors: {:#}", e));
+            info!("checked the config for errors");
+            return Ok(report);
+        }
+    };
+    check_aspect(state, &monitor_infos, &mut report);
+    check_span_groups(state, &monitor_infos, &mut report);
+
+    let monitors: HashSet<_> = monitor_infos
+        .into_iter()
+        .map(|monitor| monitor.name)
+        .collect();
+    match &state.config.monitors {
+        Monitors::Some(list) => {
+            for monitor in list {
+                if !monitors.contains(monitor) {
+                    report.warning(format!("monitor {} not available", monitor));
+                }
+            }
+        }
+        Monitors::All => {}
+    }
+
+    info!("checked the config for errors");
+
+    Ok(report)
+}
+
+/// Repairs the entries of missing images and lets the daemon reload the config
+pub fn fix_missing_images(state: &mut State, dry_run: bool) -> anyhow::Result<()> {
+    let fixes = state.fix_missing_images(dry_run)?;
+    let (would, fixed) = if dry_run {
+        ("would ", "would fix")
+    } else {
+        ("", "fixed")
+    };
+    for fix in &fixes {
+        let key = match &fix.monitor {
+            Some(monitor) => format!("{} (monitor {})", fix.key.display(), monitor),
+            None => fix.key.display().to_string(),
+        };
+        match &fix.action {
+            FixAction::Move(new) => println!("{}move {} to {}", would, key, new.display()),
+            FixAction::Remove => println!("{}remove {}", would, key),
+            FixAction::Ambiguous(candidates) => {
+                let candidates: Vec<_> = candidates
+                    .iter()
+                    .map(|candidate| candidate.display().to_string())
+                    .collect();
+                println!(
+                    "keeping {}, because it may be any of {}",
+                    key,
+                    candidates.join(", ")
+                );
+            }
+        }
+    }
+    let changes = fixes
+        .iter()
+        .filter(|fix| !matches!(fix.action, FixAction::Ambiguous(_)))
+        .count();
+    println!("{} {} missing images", fixed, changes);
+
+    if dry_run || changes == 0 {
+        return Ok(());
+    }
+    // without a daemon the config is read on its start
+    if let Ok(mut client) = ipc::Client::connect() {
+        match client.send(IpcEvent::Reload)? {
+            IpcResponse::Ok => {}
+            IpcResponse::Error(e) => anyhow::bail!("daemon returned error: {}", e),
+            response => anyhow::bail!("unexpected response from daemon: {:?}", response),
+        }
+    }
+    Ok(())
+}
+
+/// The findings of `wallpaper check`
+#[derive(Debug, serde::Serialize)]
+pub struct CheckReport {
+    errors: Vec<String>,
+    warnings: Vec<String>,
+    /// Whether the findings are logged as they come instead of printed afterwards
+    #[serde(skip)]
+    log: bool,
+}
+
+impl CheckReport {
+    fn new(log: bool) -> Self {
+        Self {
+            errors: Vec::new(),
+            warnings: Vec::new(),
+            log,
+        }
+    }
+
+    /// Logs the error and remembers it
+    fn error(&mut self, message: impl Into<String>) {
+        let message = message.into();
+        if self.log {
+            error!("{}", message);
+        } else {
+            debug!("error: {}", message);
+        }
+        self.errors.push(message);
+    }
+
+    /// Logs the warning and remembers it
+    fn warning(&mut self, message: impl Into<String>) {
+        let message = message.into();
+        if self.log {
+            warn!("{}", message);
+        } else {
+            debug!("warning: {}", message);
+        }
+        self.warnings.push(message);
+    }
+
+    /// The number of errors and warnings
+    pub fn summary(&self) -> String {
+        format!(
+            "{} errors, {} warnings",
+            self.errors.len(),
+            self.warnings.len()
+        )
+    }
+
+    /// The exit code of `wallpaper check`, which fails on warnings too if `strict` is set
+    pub fn exit_code(&self, strict: bool) -> i32 {
+        if !self.errors.is_empty() || (strict && !self.warnings.is_empty()) {
+            1
+        } else {
+            0
+        }
+    }
+
+    /// Prints the findings and a summary, colored on a terminal
+    pub fn print(&self) {
+        for error in &self.errors {
+            println!("{} {}", term::paint("error:", term::Style::Red), error);
+        }
+        for warning in &self.warnings {
+            println!(
+                "{} {}",
+                term::paint("warning:", term::Style::Yellow),
+                warning
+            );
+        }
+        let summary = self.summary();
+        let style = match (self.errors.is_empty(), self.warnings.is_empty()) {
+            (false, _) => term::Style::Red,
+            (true, false) => term::Style::Yellow,
+            (true, true) => term::Style::Green,
+        };
+        println!("{}", term::paint(summary, style));
+    }
+}
+
+/// Reports the configured images which can't be decoded
+fn check_decodable(
+    state: &State,
+    source_images: Vec<PathBuf>,
+    full: bool,
+    report: &mut CheckReport,
+) -> anyhow::Result<()> {
+    let mut images: Vec<_> = state
+        .config
+        .images
+        .keys()
+        .map(PathBuf::as_path)
+        .chain(
+            state
+                .config
+                .workspace_images
+                .values()
+                .flat_map(|images| images.keys())
+                .map(Path::new),
+        )
+        .map(|path| state.config.image_dir.join(path))
+        .chain(source_images)
+        .filter(|image| image.is_file())
+        .collect();
+    images.sort();
+    images.dedup();
+
+    info!("decoding {} images", images.len());
+    let undecodable = validate::undecodable(&images, full)?;
+    for (image, e) in &undecodable {
+        report.error(format!(
+            "image {} can't be decoded: {:#}",
+            image.display(),
+            e
+        ));
+    }
+    info!(
+        "{} of {} images can be decoded",
+        images.len() - undecodable.len(),
+        images.len()
+    );
+
+    Ok(())
+}
+
+/// Reports how many images fit the aspect ratio and the orientation of every monitor
+fn check_aspect(state: &State, monitors: &[MonitorInfo], report: &mut CheckReport) {
+    let tolerance = state
+        .config
+        .aspect_tolerance
+        .map(|AspectTolerance(tolerance)| tolerance);
+    let any_strict = monitors
+        .iter()
+        .any(|monitor| state.config.is_orientation_strict(&monitor.name));
+    if tolerance.is_none() && !any_strict {
+        return;
+    }
+    if !cfg!(feature = "aspect") {
+        if tolerance.is_some() {
+            report.warning(
+                "aspect_tolerance has no effect, because the `aspect` feature is disabled",
+            );
+        }
+        if any_strict {
+            report.warning(
+                "orientation_strict has no effect, because the `aspect` feature is disabled",
+            );
+        }
+        return;
+    }
+    let dimensions: Vec<_> = state
+        .config
+        .images
+        .keys()
+        .map(|path| state.config.image_dir.join(path))
+        .filter(|image| image.is_file())
+        .filter_map(|image| match aspect::read(&image) {
+            Ok(dimensions) => Some(dimensions),
+            Err(e) => {
+                report.error(format!("image {}: {:#}", image.to_string_lossy(), e));
+                None
+            }
+        })
+        .collect();
+    for monitor in monitors {
+        let strict = state.config.is_orientation_strict(&monitor.name);
+        if tolerance.is_none() && !strict {
+            continue;
+        }
+        let Some(size) = monitor.size else {
+            report.warning(format!("size of monitor {} is unknown", monitor.name));
+            continue;
+        };
+        if let Some(tolerance) = tolerance {
+            let fitting = dimensions
+                .iter()
+                .filter(|dimensions| aspect::fits(**dimensions, size, tolerance))
+                .count();
+            let message = format!(
+                "{} of {} images fit the aspect ratio of monitor {} ({}x{})",
+                fitting,
+                dimensions.len(),
+                monitor.name,
+                size.0,
+                size.1
+            );
+            if fitting == 0 {
+                report.warning(format!(
+                    "{}. Images with other aspect ratios will be used",
+                    message
+                ));
+            } else {
+                info!("{}", message);
+            }
+        }
+        if strict {
+            let orientation = aspect::orientation(size);
+            let matching = dimensions
+                .iter()
+                .filter(|dimensions| aspect::orientation(**dimensions) == orientation)
+                .count();
+            if matching == 0 {
+                report.warning(format!(
+                    "no image has the {} orientation of monitor {}, so its image never changes",
+                    orientation, monitor.name
+                ));
+            } else {
+                info!(
+                    "{} of {} images have the {} orientation of monitor {}",
+                    matching,
+                    dimensions.len(),
+                    orientation,
+                    monitor.name
+                );
+            }
+        }
+    }
+}
+
+/// Reports errors in the span groups and how many panoramic images each of them has
+fn check_span_groups(state: &State, monitors: &[MonitorInfo], report: &mut CheckReport) {
+    if state.config.span_groups.is_empty() {
+        return;
+    }
+    if !cfg!(feature = "span") {
+        report.warning("span_groups have no effect, because the `span` feature is disabled");
+        return;
+    }
+    let mut grouped = HashSet::new();
+    for group in &state.config.span_groups {
+        for monitor in &group.monitors {
+            if !grouped.insert(&monitor.name) {
+                report.error(format!(
+                    "monitor {} is in multiple span groups",
+                    monitor.name
+                ));
+            }
+            if state.config.static_image(&monitor.name).is_some() {
+                report.warning(format!(
+                    "monitor {} of a span group has a static image, so the group never spans an image",
+                    monitor.name
+                ));
+            }
+        }
+        let names: Vec<_> = group.monitors.iter().map(|m| m.name.as_str()).collect();
+        let Some(layout) = SpanLayout::new(group, monitors) else {
+            report.warning(format!(
+                "span group {}: a monitor is not connected or its size is unknown",
+                names.join(", ")
+            ));
+            continue;
+        };
+        let panoramic = state
+            .config
+            .images
+            .keys()
+            .map(|path| state.config.image_dir.join(path))
+            .filter(|image| image.is_file())
+            .filter(|image| {
+                aspect::read(image).is_ok_and(|dimensions| {
+                    aspect::fits(dimensions, (layout.width, layout.height), layout.tolerance)
+                })
+            })
+            .count();
+        let message = format!(
+            "{} images fit the span group {} ({}x{})",
+            panoramic,
+            names.join(", "),
+            layout.width,
+            layout.height
+        );
+        if panoramic == 0 {
+            report.warning(format!("{}. Its monitors are updated separately", message));
+        } else {
+            info!("{}", message);
+        }
+    }
+}
+
+/// Reports if `image_dir` can't be read or contains none of the configured images
+///
+/// Returns whether the images in it can be checked.
+fn check_image_dir(state: &State, report: &mut CheckReport) -> bool {
+    let image_dir = &state.config.image_dir;
+    if image_dir.as_os_str().is_empty() {
+        return true;
+    }
+    if !image_dir.exists() {
+        report.error(format!(
+            "image directory {} does not exist. Create it or set image_dir to the directory of your images",
+            image_dir.display()
+        ));
+        return false;
+    }
+    if !image_dir.is_dir() {
+        report.error(format!(
+            "image directory {} is no directory. Set image_dir to the directory of your images",
+            image_dir.display()
+        ));
+        return false;
+    }
+    if let Err(e) = std::fs::read_dir(image_dir) {
+        report.error(format!(
+            "image directory {} can't be read: {}. Check its permissions",
+            image_dir.display(),
+            e
+        ));
+        return false;
+    }
+    let inside: Vec<_> = state
+        .config
+        .images
+        .keys()
+        .filter(|key| !state.config.is_outside_image_dir(key))
+        .collect();
+    if !inside.is_empty() && !inside.iter().any(|key| image_dir.join(key).is_file()) {
+        report.error(format!(
+            "image directory {} contains none of the {} configured images. \
+            Set image_dir to the directory of your images or run `wallpaper check --fix`",
+            image_dir.display(),
+            inside.len()
+        ));
+        return false;
+    }
+    true
+}
+
+/// Warns if the image bypasses `image_dir`, as the config then only works on this machine
+fn check_inside_image_dir(state: &State, file_path: &Path, report: &mut CheckReport) {
+    let image_dir = &state.config.image_dir;
+    if image_dir.as_os_str().is_empty() {
+        return;
+    }
+    if !state.config.is_outside_image_dir(file_path) {
+        if file_path.is_absolute() {
+            report.warning(format!(
+                "image {} is inside of the image directory, but absolute. Use {} instead",
+                file_path.display(),
+                state.config.image_key(file_path).display()
+            ));
+        }
+        return;
+    }
+    if file_path.is_absolute() {
+        report.warning(format!(
+            "image {} is outside of the image directory {}. \
+            Move it into the image directory, e.g. with `wallpaper select --import copy`",
+            file_path.display(),
+            image_dir.display()
+        ));
+    } else {
+        report.warning(format!(
+            "image {} escapes the image directory {} via `..`. \
+            Move it into the image directory and use a path without `..`",
+            file_path.display(),
+            image_dir.display()
+        ));
+    }
+}
+
+/// Reports the entries which are the same file as another entry and the images
+/// whose times for a monitor differ from the global ones
+fn check_duplicates(state: &State, report: &mut CheckReport) {
+    let config = &state.config;
+    let lists = std::iter::once((String::from("images"), &config.images)).chain(
+        config
+            .monitor_settings
+            .iter()
+            .map(|(monitor, settings)| (format!("monitor {}", monitor), &settings.images)),
+    );
+    for (name, images) in lists {
+        for group in config.duplicate_images(images) {
+            let (first, others) = (&group[0], &group[1..]);
+            for other in others {
+                if same_times(&images[first], &images[other]) {
+                    report.warning(format!(
+                        "{}: {} is redundant, because it is the same file as {}",
+                        name,
+                        other.display(),
+                        first.display()
+                    ));
+                } else {
+                    report.error(format!(
+                        "{}: {} is the same file as {}, but has other times. Only the ones of {} are used",
+                        name,
+                        other.display(),
+                        first.display(),
+                        first.display()
+                    ));
+                }
+            }
+        }
+    }
+
+    let global: BTreeMap<_, _> = config
+        .images
+        .iter()
+        .map(|(key, entry)| (config.canonical_image(key), (key, entry)))
+        .collect();
+    for (monitor, settings) in &config.monitor_settings {
+        for (key, entry) in &settings.images {
+            let Some((global_key, global_entry)) = global.get(&config.canonical_image(key)) else {
+                continue;
+            };
+            if !same_times(global_entry, entry) {
+                report.warning(format!(
+                    "image {} has other times for monitor {} than {} in images. \
+                    The ones of the monitor apply there",
+                    key.display(),
+                    monitor,
+                    global_key.display()
+                ));
+            }
+        }
+    }
+}
+
+/// Whether both entries are valid at the same times of the next week
+fn same_times(a: &ImageEntry, b: &ImageEntry) -> bool {
+    if a.time_refs == b.time_refs && a.dates == b.dates {
+        return true;
+    }
+    let start = chrono::Local::now()
+        .date_naive()
+        .and_time(chrono::NaiveTime::MIN);
+    (0..7 * 24 * 60)
+        .map(|minute| start + chrono::Duration::minutes(minute))
+        .all(|time| a.is_valid_at(&time) == b.is_valid_at(&time))
+}
+
+/// Warns about the parts of the next week in which no image is valid
+fn check_coverage(state: &State, report: &mut CheckReport) {
+    fn format_seconds(seconds: u32) -> String {
+        let (hours, minutes, seconds) = (seconds / 3600, seconds / 60 % 60, seconds % 60);
+        if seconds != 0 {
+            format!("{:02}:{:02}:{:02}", hours, minutes, seconds)
+        } else {
+            format!("{:02}:{:02}", hours, minutes)
+        }
+    }
+
+    // the gaps can differ between days because of weekdays, dates and the sun
+    let today = chrono::Local::now().date_naive();
+    let mut days_by_gaps: Vec<(Vec<_>, Vec<String>)> = Vec::new();
+    for day in today.iter_days().take(7) {
+        let gaps = scheduler::uncovered_seconds(&state.config, day);
+        if gaps.is_empty() {
+            continue;
+        }
+        let day = day.format("%a %F").to_string();
+        match days_by_gaps.iter_mut().find(|(other, _)| *other == gaps) {
+            Some((_, days)) => days.push(day),
+            None => days_by_gaps.push((gaps, vec![day])),
+        }
+    }
+
+    for (gaps, days) in &days_by_gaps {
+        let gaps = gaps
+            .iter()
+            .map(|(start, end)| format!("{}-{}", format_seconds(*start), format_seconds(*end)))
+            .collect::<Vec<_>>()
+            .join(", ");
+        report.warning(format!(
+            "no image is valid on {} during {}. The fallback images will be used then",
+            days.join(", "),
+            gaps
+        ));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use wallpaper::{Cache, Config, ValidTime};
+
+    use super::*;
+    use crate::test_util::TempDir;
+
+    /// A state which is not read from or written to the files of the user
+    fn state(config: Config) -> State {
+        State::new(config, Cache::default()).unwrap()
+    }
+
+    /// A config with the images in `image_dir`, valid at all times
+    fn config(image_dir: &Path, images: &[&str]) -> Config {
+        let images = images
+            .iter()
+            .map(|image| (PathBuf::from(image), ImageEntry::new(vec![ValidTime::ALL])))
+            .collect();
+        Config {
+            image_dir: image_dir.to_owned(),
+            images,
+            ..Config::default()
+        }
+    }
+
+    /// Checks the config, without the findings about the monitors, which depend on swww
+    fn check_config(state: &State) -> CheckReport {
+        let mut report = run(state, false, false, false).unwrap();
+        report
+            .warnings
+            .retain(|warning| !warning.starts_with("can't check the monitors"));
+        report
+    }
+
+    #[test]
+    fn check_counts_the_findings() {
+        let dir = TempDir::new();
+        dir.touch("images/a.png");
+        dir.touch("images/notes.txt");
+        let mut config = config(
+            &dir.path().join("images"),
+            &["a.png", "missing.png", "notes.txt"],
+        );
+        config.rating_factor.0 = 0.0;
+        let report = check_config(&state(config));
+        assert_eq!(report.errors.len(), 2, "{:?}", report.errors);
+        assert!(report.errors[0].contains("rating_factor"));
+        assert!(report.errors[1].contains("missing.png does not exist"));
+        assert_eq!(report.warnings.len(), 1, "{:?}", report.warnings);
+        assert!(report.warnings[0].contains("notes.txt is filtered out"));
+        assert_eq!(report.summary(), "2 errors, 1 warnings");
+    }
+
+    #[test]
+    fn check_fails_on_errors_and_strictly_on_warnings() {
+        let mut report = CheckReport::new(false);
+        assert_eq!(report.summary(), "0 errors, 0 warnings");
+        assert_eq!((report.exit_code(false), report.exit_code(true)), (0, 0));
+
+        report.warning("a warning");
+        assert_eq!((report.exit_code(false), report.exit_code(true)), (0, 1));
+
+        report.error("an error");
+        report.error("another error");
+        assert_eq!((report.exit_code(false), report.exit_code(true)), (1, 1));
+        assert_eq!(report.summary(), "2 errors, 1 warnings");
+    }
+
+    #[test]
+    fn check_reports_a_clean_config() {
+        let dir = TempDir::new();
+        dir.touch("images/a.png");
+        let report = check_config(&state(config(&dir.path().join("images"), &["a.png"])));
+        assert!(report.errors.is_empty(), "{:?}", report.errors);
+        assert!(report.warnings.is_empty(), "{:?}", report.warnings);
+        assert_eq!(report.exit_code(true), 0);
+    }
+
+    #[test]
+    fn check_reports_mirror_with_workspace_images() {
+        let dir = TempDir::new();
+        dir.touch("images/a.png");
+        let mut config = config(&dir.path().join("images"), &["a.png"]);
+        config.workspace_images.insert(
+            String::from("1"),
+            [(String::from("a.png"), vec![ValidTime::ALL])].into(),
+        );
+        let mirror_errors = |state: &State| {
+            check_config(state)
+                .errors
+                .into_iter()
+                .filter(|error| error.contains("mirror"))
+                .collect::<Vec<_>>()
+        };
+
+        let mut state = state(config);
+        assert_eq!(mirror_errors(&state), Vec::<String>::new());
+        state.cache.mirror = Some(false);
+        assert_eq!(mirror_errors(&state), Vec::<String>::new());
+
+        state.cache.mirror = Some(true);
+        let errors = mirror_errors(&state);
+        assert_eq!(errors.len(), 1, "{:?}", errors);
+        assert!(errors[0].contains("`wallpaper mirror false`"));
+
+        state.config.mirror = true;
+        let errors = mirror_errors(&state);
+        assert_eq!(errors.len(), 1, "{:?}", errors);
+        assert!(errors[0].starts_with("mirror can't be combined"));
+    }
+
+    #[test]
+    fn check_reports_a_missing_image_dir_once() {
+        let dir = TempDir::new();
+        let report = check_config(&state(config(
+            &dir.path().join("missing"),
+            &["a.png", "b.png"],
+        )));
+        assert_eq!(report.errors.len(), 1, "{:?}", report.errors);
+        assert!(report.errors[0].contains("does not exist. Create it"));
+    }
+
+    #[test]
+    fn check_reports_an_image_dir_which_is_a_file() {
+        let dir = TempDir::new();
+        let file = dir.touch("images");
+        let report = check_config(&state(config(&file, &["a.png"])));
+        assert_eq!(report.errors.len(), 1, "{:?}", report.errors);
+        assert!(report.errors[0].contains("is no directory"));
+    }
+
+    #[test]
+    fn check_reports_an_image_dir_without_the_images() {
+        let dir = TempDir::new();
+        dir.touch("other/a.png");
+        std::fs::create_dir(dir.path().join("images")).unwrap();
+        let report = check_config(&state(config(
+            &dir.path().join("images"),
+            &["a.png", "b.png"],
+        )));
+        assert_eq!(report.errors.len(), 1, "{:?}", report.errors);
+        assert!(report.errors[0].contains("contains none of the 2 configured images"));
+    }
+
+    #[test]
+    fn check_warns_about_images_outside_of_the_image_dir() {
+        let dir = TempDir::new();
+        dir.touch("images/a.png");
+        let outside = dir.touch("other/b.png");
+        let inside = dir.path().join("images/a.png");
+        let config = config(
+            &dir.path().join("images"),
+            &[
+                "a.png",
+                outside.to_str().unwrap(),
+                "../other/b.png",
+                inside.to_str().unwrap(),
+            ],
+        );
+        let report = check_config(&state(config));
+        assert!(report.errors.is_empty(), "{:?}", report.errors);
+        let out_of_tree: Vec<_> = report
+            .warnings
+            .iter()
+            .filter(|warning| {
+                warning.contains("is outside of the image directory")
+                    || warning.contains("escapes the image directory")
+                    || warning.contains("is inside of the image directory, but absolute")
+            })
+            .collect();
+        assert_eq!(out_of_tree.len(), 3, "{:?}", report.warnings);
+    }
+}
@@ -0,0 +1,85 @@
+//! Finds the dominant color of images
+//!
+//! This needs the `colors` feature, without it no colors are found.
+
+use std::path::Path;
+
+use crate::config::Cache;
+
+/// Returns the dominant color of the image as hex string like `#1a2b3c`
+///
+/// The color is only computed again if the image was modified since the last time.
+#[cfg(feature = "colors")]
+pub fn dominant_color(cache: &mut Cache, image: &Path) -> Option<String> {
+    use crate::config::DominantColor;
+    use tracing::{debug, error};
+
+    let modified = match std::fs::metadata(image).and_then(|m| m.modified()) {
+        Ok(modified) => modified,
+        Err(e) => {
+            error!("can't read modification time of {}: {}", image.display(), e);
+            return None;
+        }
+    };
+    if let Some(cached) = cache
+        .dominant_colors
+        .get(image)
+        .filter(|cached| *cached.modified == modified)
+    {
+        return Some(cached.color.clone());
+    }
+
+    match compute(image) {
+        Ok(color) => {
+            debug!("dominant color of {} is {}", image.display(), color);
+            cache.dominant_colors.insert(
+                image.to_owned(),
+                DominantColor {
+                    modified: modified.into(),
+                    color: color.clone(),
+                },
+            );
+            Some(color)
+        }
+        Err(e) => {
+            error!("can't find dominant color of {}: {:#}", image.display(), e);
+            None
+        }
+    }
+}
+
+#[cfg(not(feature = "colors"))]
+pub fn dominant_color(_cache: &mut Cache, _image: &Path) -> Option<String> {
+    None
+}
+
+#[cfg(feature = "colors")]
+fn compute(image: &Path) -> anyhow::Result<String> {
+    use anyhow::Context;
+
+    let pixels = image::open(image)
+        .context("while decoding image")?
+        // the dominant color doesn't need every pixel
+        .thumbnail(256, 256)
+        .into_rgb8();
+
+    // quantize to 4 bits per channel and sum up the real colors of every bucket
+    let mut buckets = vec![(0u64, [0u64; 3]); 1 << 12];
+    for pixel in pixels.pixels() {
+        let [r, g, b] = pixel.0;
+        let index = (usize::from(r >> 4) << 8) | (usize::from(g >> 4) << 4) | usize::from(b >> 4);
+        let (count, sum) = &mut buckets[index];
+        *count += 1;
+        for (sum, c) in sum.iter_mut().zip(pixel.0) {
+            *sum += u64::from(c);
+        }
+    }
+    let (count, sum) = buckets
+        .into_iter()
+        .max_by_key(|(count, _sum)| *count)
+        .filter(|(count, _sum)| *count > 0)
+        .ok_or_else(|| anyhow::anyhow!("image is empty"))?;
+    let [r, g, b] = sum.map(|sum| sum / count);
+
+    Ok(format!("#{:02x}{:02x}{:02x}", r, g, b))
+}
@@ -1,15 +1,21 @@
 use std::{
-    collections::BTreeMap,
+    borrow::Cow,
+    collections::{BTreeMap, BTreeSet, HashSet, VecDeque},
     hash::{DefaultHasher, Hash, Hasher},
-    path::PathBuf,
+    path::{Component, Path, PathBuf},
 };
 
 use anyhow::{anyhow, Context};
-use chrono::{NaiveTime, Timelike};
+use chrono::{Datelike, Local, NaiveDate, NaiveDateTime, NaiveTime, Timelike, Weekday};
 use directories::ProjectDirs;
 use humantime::{Duration, Timestamp};
+use regex::Regex;
 use serde::{de::Error, Deserialize, Serialize};
-use tracing::{debug, error, info};
+use tracing::{debug, error, info, trace, warn};
+
+use crate::{
+    explain::SelectionTrace, power::PowerProfile, scheduler::Schedule, sources::Listings, Pending,
+};
 
 #[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq, Eq, Hash)]
 #[serde(untagged)]
@@ -36,13 +42,175 @@ pub struct Config {
     #[serde(serialize_with = "ser_duration")]
     #[serde(deserialize_with = "deser_duration")]
     pub update_interval: Duration,
+    /// Maximum random deviation from `update_interval` in both directions
+    #[serde(default = "zero_duration")]
+    #[serde(serialize_with = "ser_duration")]
+    #[serde(deserialize_with = "deser_duration")]
+    pub interval_jitter: Duration,
+    /// Update intervals which differ from `update_interval` at specific times
+    #[serde(default)]
+    pub interval_schedule: Vec<IntervalScheduleEntry>,
     pub transitions: Vec<String>,
+    #[serde(default)]
+    pub transition_order: TransitionOrder,
+    /// Map from image, relative to `image_dir`, to when it is shown
+    ///
+    /// Paths which are not valid utf-8 are written as `file://` urls with percent-encoded bytes.
+    #[serde(serialize_with = "ser_images")]
     #[serde(deserialize_with = "deser_images")]
-    pub images: BTreeMap<String, Vec<ValidTime>>,
+    pub images: BTreeMap<PathBuf, ImageEntry>,
+    /// Minimum time before an image is shown again
+    #[serde(default = "zero_duration")]
+    #[serde(serialize_with = "ser_duration")]
+    #[serde(deserialize_with = "deser_duration")]
+    pub cooldown: Duration,
     pub image_dir: PathBuf,
     pub fps: u8,
     #[serde(default)]
     pub monitors: Monitors,
+    /// Named time ranges which can be used for images with `@name`
+    #[serde(default)]
+    pub time_presets: BTreeMap<String, Vec<ValidTime>>,
+    /// Location used for times relative to sunrise and sunset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub location: Option<Location>,
+    /// Settings for specific monitors
+    #[serde(default)]
+    pub monitor_settings: BTreeMap<String, MonitorSettings>,
+    /// Whether the daemon updates the wallpapers right after starting
+    #[serde(default)]
+    pub on_start: OnStart,
+    /// What the daemon does with swww when it stops, only if the daemon started swww
+    #[serde(default)]
+    pub on_exit: OnExit,
+    /// A switch which repeats the previous one within this time is dropped, e.g. when a key
+    /// binding fires twice
+    #[serde(default = "default_switch_debounce")]
+    #[serde(serialize_with = "ser_duration")]
+    #[serde(deserialize_with = "deser_duration")]
+    pub switch_debounce: Duration,
+    /// How long the daemon waits for swww when starting
+    #[serde(default = "default_startup_timeout")]
+    #[serde(serialize_with = "ser_duration")]
+    #[serde(deserialize_with = "deser_duration")]
+    pub startup_timeout: Duration,
+    /// How long a switch of a not connected monitor waits for the monitor
+    #[serde(default = "default_pending_expiry")]
+    #[serde(serialize_with = "ser_duration")]
+    #[serde(deserialize_with = "deser_duration")]
+    pub pending_expiry: Duration,
+    /// How long the cache keeps the entries of a monitor which is not connected anymore
+    #[serde(default = "default_monitor_retention")]
+    #[serde(serialize_with = "ser_duration")]
+    #[serde(deserialize_with = "deser_duration")]
+    pub monitor_retention: Duration,
+    /// Monitors whose entries are kept in the cache, however long they are not connected
+    #[serde(default)]
+    pub keep_monitors: Vec<String>,
+    /// Only show dark images during some hours
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub night_max_brightness: Option<NightMaxBrightness>,
+    /// Command which chooses the image from the candidates instead of a random choice
+    ///
+    /// The command gets the candidates on stdin, one absolute path per line,
+    /// and the monitor in `WALLPAPER_MONITOR`. It has to print one of the candidates.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub selector_command: Option<String>,
+    /// How long the `selector_command` may run
+    #[serde(default = "default_selector_timeout")]
+    #[serde(serialize_with = "ser_duration")]
+    #[serde(deserialize_with = "deser_duration")]
+    pub selector_timeout: Duration,
+    /// Map from workspace to the images which are shown instead of `images`
+    /// while it is focused
+    #[serde(default)]
+    pub workspace_images: BTreeMap<String, BTreeMap<String, Vec<ValidTime>>>,
+    /// Defer updates of monitors while they show a fullscreen window
+    #[serde(default)]
+    pub inhibit_on_fullscreen: bool,
+    /// Defer updates of monitors while they are powered off and update them once they turn on
+    #[serde(default)]
+    pub skip_when_off: bool,
+    /// How many of the last images of a monitor are avoided when choosing the next one
+    #[serde(default = "default_history_size")]
+    pub history_size: usize,
+    /// How many changes of every monitor are kept for `wallpaper history` and `wallpaper undo`
+    #[serde(default = "default_history_limit")]
+    pub history_limit: usize,
+    #[serde(default)]
+    pub selection_mode: SelectionMode,
+    /// The order of the images in sequential mode
+    #[serde(default)]
+    pub sort: SortOrder,
+    /// Show the same image on all monitors
+    #[serde(default)]
+    pub mirror: bool,
+    /// Let the fallbacks show an image on multiple monitors before using images which aren't valid
+    #[serde(default)]
+    pub allow_duplicate_across_monitors: bool,
+    /// Prefer images whose aspect ratio differs at most this much from the monitor's, e.g. `0.1`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub aspect_tolerance: Option<AspectTolerance>,
+    /// Groups of monitors which show one panoramic image together
+    #[serde(default)]
+    pub span_groups: Vec<SpanGroup>,
+    /// Give swww copies of the images which are scaled to the resolution of the monitor
+    #[serde(default)]
+    pub prescale: bool,
+    /// Maximum size of all scaled copies in MiB, the least recently used ones get removed first
+    #[serde(default = "default_prescale_cache_mb")]
+    pub prescale_cache_mb: u64,
+    /// Effects for all monitors which don't have their own
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub effects: Option<Effects>,
+    /// Treat images with the same content as one image when choosing the next one
+    #[serde(default)]
+    pub dedupe: bool,
+    /// How much more often an image is shown per point of its rating
+    #[serde(default)]
+    pub rating_factor: RatingFactor,
+    /// The lowest rating of the images which are shown in favorites-only mode
+    #[serde(default = "default_favorites_threshold")]
+    pub favorites_threshold: i32,
+    /// Directories whose images may be shown in addition to `images`
+    #[serde(default)]
+    pub image_sources: Vec<ImageSource>,
+    /// Servers whose images are downloaded and may be shown in addition to `images`
+    #[serde(default)]
+    pub remote_sources: Vec<RemoteSource>,
+    /// Only show files with these extensions, without the leading dot
+    #[serde(default = "default_allowed_extensions")]
+    pub allowed_extensions: Vec<String>,
+    /// Don't show files which are larger than this many bytes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_file_size: Option<u64>,
+    /// How many levels of directories `wallpaper select` walks into
+    #[serde(default = "default_select_max_depth")]
+    pub select_max_depth: usize,
+    /// The rules of `wallpaper select --parse-names`, the first matching one applies
+    #[serde(default = "default_name_rules")]
+    pub name_rules: Vec<NameRule>,
+    /// Settings which depend on whether the system runs on battery
+    #[serde(default)]
+    pub power: Power,
+    /// Commands which run after the wallpaper changed
+    #[serde(default)]
+    pub hooks: Hooks,
+    /// Keep the symlinks `current-<monitor>` and `current` in the cache directory pointing at the
+    /// shown images
+    #[serde(default = "default_current_links")]
+    pub current_links: bool,
+    /// The monitor whose image the `current` symlink points at, the first one by name if unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub primary_monitor: Option<String>,
+    /// Keeps the image of the lock screen in sync with the wallpaper
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lockscreen: Option<Lockscreen>,
+    /// The directory of the log files, the `logs` of the state directory if unset
+    ///
+    /// Only read on start, as logging starts before the config is loaded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub log_dir: Option<PathBuf>,
 }
 
 impl Default for Config {
@@ -50,80 +218,1861 @@ impl Default for Config {
         Self {
             check_interval: std::time::Duration::from_secs(60 * 5).into(),
             update_interval: std::time::Duration::from_secs(60 * 60).into(),
+            interval_jitter: zero_duration(),
+            interval_schedule: Vec::new(),
             transitions: Default::default(),
+            transition_order: TransitionOrder::default(),
             images: Default::default(),
+            cooldown: zero_duration(),
             image_dir: PathBuf::default(),
             fps: 30,
             monitors: Monitors::default(),
+            time_presets: BTreeMap::new(),
+            location: None,
+            monitor_settings: BTreeMap::new(),
+            on_start: OnStart::default(),
+            on_exit: OnExit::default(),
+            switch_debounce: default_switch_debounce(),
+            startup_timeout: default_startup_timeout(),
+            pending_expiry: default_pending_expiry(),
+            monitor_retention: default_monitor_retention(),
+            keep_monitors: Vec::new(),
+            night_max_brightness: None,
+            selector_command: None,
+            selector_timeout: default_selector_timeout(),
+            workspace_images: BTreeMap::new(),
+            inhibit_on_fullscreen: false,
+            skip_when_off: false,
+            history_size: default_history_size(),
+            history_limit: default_history_limit(),
+            selection_mode: SelectionMode::default(),
+            sort: SortOrder::default(),
+            mirror: false,
+            allow_duplicate_across_monitors: false,
+            aspect_tolerance: None,
+            span_groups: Vec::new(),
+            prescale: false,
+            prescale_cache_mb: default_prescale_cache_mb(),
+            effects: None,
+            dedupe: false,
+            rating_factor: RatingFactor::default(),
+            favorites_threshold: default_favorites_threshold(),
+            image_sources: Vec::new(),
+            remote_sources: Vec::new(),
+            allowed_extensions: default_allowed_extensions(),
+            max_file_size: None,
+            select_max_depth: default_select_max_depth(),
+            name_rules: default_name_rules(),
+            power: Power::default(),
+            hooks: Hooks::default(),
+            log_dir: None,
+            current_links: default_current_links(),
+            primary_monitor: None,
+            lockscreen: None,
         }
     }
 }
 
-const CACHE_VERSION: usize = 0;
+impl Config {
+    /// Resolves the time presets of all images and sets the location
+    /// for all times which depend on sunrise or sunset
+    ///
+    /// Fails if an unknown preset is used or no location is configured, but such a time is used.
+    pub fn resolve(&mut self) -> anyhow::Result<()> {
+        resolve_images(&mut self.images, &self.time_presets, self.location)?;
+        for (monitor, settings) in &mut self.monitor_settings {
+            resolve_images(&mut settings.images, &self.time_presets, self.location)
+                .map_err(|e| anyhow!("monitor {}: {}", monitor, e))?;
+        }
+        for entry in &mut self.interval_schedule {
+            entry
+                .times
+                .set_location(self.location)
+                .map_err(|e| anyhow!("interval schedule: {}", e))?;
+        }
+        for source in &mut self.image_sources {
+            for time in &mut source.times {
+                time.set_location(self.location)
+                    .map_err(|e| anyhow!("image source {}: {}", source.dir.display(), e))?;
+            }
+        }
+        for (workspace, images) in &mut self.workspace_images {
+            for (image, times) in images {
+                for time in times {
+                    time.set_location(self.location)
+                        .map_err(|e| anyhow!("workspace {}: image {}: {}", workspace, image, e))?;
+                }
+            }
+        }
+        if let Some(night) = &mut self.night_max_brightness {
+            night
+                .times
+                .set_location(self.location)
+                .map_err(|e| anyhow!("night_max_brightness: {}", e))?;
+        }
 
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
-pub struct Cache {
-    version: usize,
-    #[serde(serialize_with = "ser_timestamp")]
-    #[serde(deserialize_with = "deser_timestamp")]
-    pub last_update: Timestamp,
+        Ok(())
+    }
 
-    // Map from monitor to transition/ image
-    pub last_transitions: BTreeMap<String, String>,
-    pub last_images: BTreeMap<String, PathBuf>,
-}
+    /// Returns the images which may be shown on the monitor while the workspace is focused
+    ///
+    /// Without images of the workspace or the monitor, these are the configured `images`
+    /// and the images of the `image_sources`, which are listed by [`State::refresh_sources`].
+    /// The configured entry wins if both contain an image.
+    /// Of entries for the same file only the first of [`Self::duplicate_images`] is kept.
+    pub fn images_for<'a>(
+        &'a self,
+        workspace: Option<&str>,
+        monitor: Option<&str>,
+        source_images: &BTreeMap<PathBuf, ImageEntry>,
+    ) -> Cow<'a, BTreeMap<PathBuf, ImageEntry>> {
+        let images = self.all_images_for(workspace, monitor, source_images);
+        let duplicates = self.duplicate_images(&images);
+        if duplicates.is_empty() {
+            return images;
+        }
+        let mut images = images.into_owned();
+        for group in duplicates {
+            for key in &group[1..] {
+                images.remove(key);
+            }
+        }
+        Cow::Owned(images)
+    }
 
-impl Cache {
-    pub fn update(&mut self, monitor: String, image: PathBuf, transition: String) {
-        self.last_update = std::time::SystemTime::now().into();
-        self.last_images.insert(monitor.clone(), image);
-        self.last_transitions.insert(monitor, transition);
+    /// Returns the full path of the image with all symlinks, `.` and `..` resolved
+    ///
+    /// Missing images are only resolved lexically.
+    pub fn canonical_image(&self, key: &Path) -> PathBuf {
+        let image = self.image_dir.join(key);
+        image
+            .canonicalize()
+            .unwrap_or_else(|_| image.components().collect())
+    }
+
+    /// Returns the groups of keys which are the same file, e.g. `a.jpg` and `./a.jpg`
+    ///
+    /// The shortest key comes first in every group.
+    pub fn duplicate_images(&self, images: &BTreeMap<PathBuf, ImageEntry>) -> Vec<Vec<PathBuf>> {
+        let mut by_image: BTreeMap<_, Vec<PathBuf>> = BTreeMap::new();
+        for key in images.keys() {
+            by_image
+                .entry(self.canonical_image(key))
+                .or_default()
+                .push(key.clone());
+        }
+        by_image
+            .into_values()
+            .filter(|group| group.len() > 1)
+            .map(|mut group| {
+                group.sort_by_key(|key| key.components().count());
+                group
+            })
+            .collect()
+    }
+
+    fn all_images_for<'a>(
+        &'a self,
+        workspace: Option<&str>,
+        monitor: Option<&str>,
+        source_images: &BTreeMap<PathBuf, ImageEntry>,
+    ) -> Cow<'a, BTreeMap<PathBuf, ImageEntry>> {
+        if let Some(images) = workspace.and_then(|workspace| self.workspace_images.get(workspace)) {
+            return Cow::Owned(
+                images
+                    .iter()
+                    .map(|(image, times)| (PathBuf::from(image), ImageEntry::new(times.clone())))
+                    .collect(),
+            );
+        }
+        if let Some(images) = monitor
+            .and_then(|monitor| self.monitor_settings.get(monitor))
+            .map(|settings| &settings.images)
+            .filter(|images| !images.is_empty())
+        {
+            return Cow::Borrowed(images);
+        }
+        if source_images.is_empty() {
+            return Cow::Borrowed(&self.images);
+        }
+        let configured: HashSet<_> = self
+            .images
+            .keys()
+            .map(|path| self.image_dir.join(path))
+            .collect();
+        let mut images: BTreeMap<_, _> = source_images
+            .iter()
+            .filter(|(path, _entry)| !configured.contains(&self.image_dir.join(path)))
+            .map(|(path, entry)| (path.clone(), entry.clone()))
+            .collect();
+        images.extend(self.images.clone());
+
+        Cow::Owned(images)
+    }
+
+    /// Returns whether the workspace has its own images
+    pub fn has_workspace_images(&self, workspace: Option<&str>) -> bool {
+        workspace.is_some_and(|workspace| self.workspace_images.contains_key(workspace))
+    }
+
+    /// Returns whether the file has one of the `allowed_extensions`
+    pub fn has_allowed_extension(&self, path: &Path) -> bool {
+        path.extension().is_some_and(|extension| {
+            self.allowed_extensions
+                .iter()
+                .any(|allowed| extension.eq_ignore_ascii_case(allowed.trim_start_matches('.')))
+        })
+    }
+
+    /// Returns why the image is never shown, if `allowed_extensions` or `max_file_size`
+    /// filter it out
+    pub fn rejection(&self, image: &Path) -> Option<String> {
+        if !self.has_allowed_extension(image) {
+            return Some(String::from("its extension is not in allowed_extensions"));
+        }
+        let max_file_size = self.max_file_size?;
+        let size = std::fs::metadata(image).ok()?.len();
+        (size > max_file_size).then(|| {
+            format!(
+                "it has {} bytes, more than max_file_size of {}",
+                size, max_file_size
+            )
+        })
+    }
+
+    /// Returns the image which should always be shown on this monitor, if any
+    pub fn static_image(&self, monitor: &str) -> Option<PathBuf> {
+        self.monitor_settings
+            .get(monitor)?
+            .static_image
+            .as_ref()
+            .map(|image| self.image_dir.join(image))
+    }
+
+    /// Returns all configured images, including those of workspaces and static images
+    ///
+    /// The images of the `image_sources` are not included.
+    pub fn configured_images(&self) -> BTreeSet<PathBuf> {
+        self.images
+            .keys()
+            .map(PathBuf::as_path)
+            .chain(
+                self.workspace_images
+                    .values()
+                    .flat_map(|images| images.keys())
+                    .map(Path::new),
+            )
+            .chain(
+                self.monitor_settings
+                    .values()
+                    .flat_map(|settings| settings.images.keys())
+                    .map(PathBuf::as_path),
+            )
+            .map(|path| self.image_dir.join(path))
+            .chain(
+                self.monitor_settings
+                    .keys()
+                    .filter_map(|monitor| self.static_image(monitor)),
+            )
+            .collect()
+    }
+
+    /// Returns whether only images with the orientation of the monitor may be shown on it
+    pub fn is_orientation_strict(&self, monitor: &str) -> bool {
+        self.monitor_settings
+            .get(monitor)
+            .is_some_and(|settings| settings.orientation_strict)
+    }
+
+    /// Returns the effects for the monitor, if they change the image
+    pub fn effects_for(&self, monitor: &str) -> Option<Effects> {
+        self.monitor_settings
+            .get(monitor)
+            .and_then(|settings| settings.effects)
+            .or(self.effects)
+            .filter(|effects| !effects.is_noop())
+    }
+
+    /// Returns the cooldown of an image from `images`
+    pub fn cooldown_of(&self, image: &Path) -> Duration {
+        self.images
+            .get(image)
+            .and_then(|entry| entry.cooldown)
+            .unwrap_or(self.cooldown)
+    }
+
+    /// Returns how long an image may be shown at most, if it is limited
+    ///
+    /// Unlike [`Self::cooldown_of`] this takes the full path of the image.
+    pub fn max_duration_of(&self, image: &Path) -> Option<Duration> {
+        self.images
+            .iter()
+            .find(|(path, _entry)| self.image_dir.join(path) == image)
+            .and_then(|(_path, entry)| entry.max_duration)
+    }
+
+    /// Returns the longest cooldown of any image
+    pub fn max_cooldown(&self) -> Duration {
+        self.images
+            .values()
+            .filter_map(|entry| entry.cooldown.map(|cooldown| *cooldown))
+            .fold(*self.cooldown, std::time::Duration::max)
+            .into()
+    }
+
+    /// Returns the key of the image in `images`
+    ///
+    /// Images inside `image_dir` get a relative key, so the config can be moved to another machine.
+    /// All other images keep their path, which `image_dir.join` leaves as is if it is absolute.
+    pub fn image_key(&self, image: &Path) -> PathBuf {
+        if self.image_dir.as_os_str().is_empty() {
+            return image.to_owned();
+        }
+        // the image or `image_dir` may be given with symlinks
+        let image_dir = self.image_dir.canonicalize().ok();
+        let canonical = image.canonicalize().ok();
+        let candidates = [
+            Some((image, self.image_dir.as_path())),
+            image_dir.as_deref().map(|image_dir| (image, image_dir)),
+            canonical.as_deref().zip(image_dir.as_deref()),
+        ];
+        let key = candidates
+            .into_iter()
+            .flatten()
+            .filter_map(|(image, image_dir)| image.strip_prefix(image_dir).ok())
+            .find(|key| !key.components().any(|c| c == Component::ParentDir))
+            .map_or_else(|| image.to_owned(), Path::to_owned);
+        key
+    }
+
+    /// Whether the image of the key is not inside `image_dir`
+    pub fn is_outside_image_dir(&self, key: &Path) -> bool {
+        let key = self.image_key(&self.image_dir.join(key));
+        key.is_absolute() || key.components().any(|c| c == Component::ParentDir)
+    }
+
+    /// Returns the overrides which apply in the power profile
+    pub fn power_overrides(&self, profile: PowerProfile) -> Option<&BatteryOverrides> {
+        match profile {
+            PowerProfile::Ac => None,
+            PowerProfile::Battery => self.power.on_battery.as_ref(),
+        }
+    }
+
+    /// Returns the fps of the transitions in the power profile
+    pub fn fps_in(&self, profile: PowerProfile) -> u8 {
+        self.power_overrides(profile)
+            .map_or(self.fps, |overrides| overrides.fps)
+    }
+
+    /// Returns the transitions which may be used in the power profile
+    pub fn transitions_in(&self, profile: PowerProfile) -> &[String] {
+        self.power_overrides(profile)
+            .map(|overrides| &overrides.transitions)
+            .filter(|transitions| !transitions.is_empty())
+            .unwrap_or(&self.transitions)
+    }
+
+    /// Returns the update interval which applies at the given time in the power profile
+    ///
+    /// The `update_interval` on battery replaces the `interval_schedule`.
+    pub fn update_interval_at(&self, time: &NaiveDateTime, profile: PowerProfile) -> Duration {
+        if let Some(interval) = self
+            .power_overrides(profile)
+            .and_then(|overrides| overrides.update_interval)
+        {
+            return interval;
+        }
+        self.interval_schedule
+            .iter()
+            .find(|entry| entry.times.matches(time))
+            .map_or(self.update_interval, |entry| entry.update_interval)
     }
 }
 
-impl Default for Cache {
+/// Settings which depend on the power supply
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub struct Power {
+    /// Overrides which apply while the system runs on battery
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub on_battery: Option<BatteryOverrides>,
+}
+
+/// Cheaper settings for [`PowerProfile::Battery`]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub struct BatteryOverrides {
+    pub fps: u8,
+    /// Replaces `transitions` if it isn't empty
+    #[serde(default)]
+    pub transitions: Vec<String>,
+    /// Replaces `update_interval` and `interval_schedule`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[serde(serialize_with = "ser_opt_duration")]
+    #[serde(deserialize_with = "deser_opt_duration")]
+    pub update_interval: Option<Duration>,
+}
+
+/// Commands which run with `sh -c` after the wallpaper changed
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub struct Hooks {
+    /// Run for every monitor whose image changed, with the monitor, image and transition in
+    /// `WALLPAPER_MONITOR`, `WALLPAPER_IMAGE` and `WALLPAPER_TRANSITION`
+    #[serde(default)]
+    pub post_change: Vec<String>,
+    /// Run once per update which changed any image, with the changes as json on stdin
+    #[serde(default)]
+    pub post_update: Vec<String>,
+    /// Hooks which run longer get killed
+    #[serde(default = "default_hook_timeout")]
+    #[serde(serialize_with = "ser_duration")]
+    #[serde(deserialize_with = "deser_duration")]
+    pub timeout: Duration,
+    /// Run for every monitor before its image changes, with the monitor and the new image in
+    /// `WALLPAPER_MONITOR` and `WALLPAPER_IMAGE`. Exiting with 10 keeps the old image this time
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pre_change: Option<String>,
+    /// The change happens anyway if the `pre_change` hook runs longer
+    #[serde(default = "default_pre_change_timeout")]
+    #[serde(serialize_with = "ser_duration")]
+    #[serde(deserialize_with = "deser_duration")]
+    pub pre_change_timeout: Duration,
+}
+
+impl Default for Hooks {
     fn default() -> Self {
         Self {
-            version: CACHE_VERSION,
-            last_update: std::time::UNIX_EPOCH.into(),
-            last_images: Default::default(),
-            last_transitions: Default::default(),
+            post_change: Vec::new(),
+            post_update: Vec::new(),
+            timeout: default_hook_timeout(),
+            pre_change: None,
+            pre_change_timeout: default_pre_change_timeout(),
         }
     }
 }
 
-#[derive(Debug, Clone)]
-pub struct State {
-    pub cache: Cache,
-    pub config: Config,
-    project_dirs: ProjectDirs,
-    pub rng: rand::rngs::ThreadRng,
-    last_loaded_cache_hash: u64,
-    last_loaded_config_hash: u64,
+/// Where the lock screen gets its image from
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub struct Lockscreen {
+    pub mode: LockscreenMode,
+    /// The path of the link or copy, or the command for [`LockscreenMode::Command`]
+    ///
+    /// `{monitor}` is replaced by the name of the monitor. Without it, only the image of the
+    /// `primary_monitor` is used, or the last changed one if none is set.
+    pub target: String,
+    /// Scale copies to the size of the monitor, so the lock screen loads them faster
+    #[serde(default)]
+    pub scale: bool,
 }
 
-impl State {
-    pub fn project_dirs() -> anyhow::Result<ProjectDirs> {
-        ProjectDirs::from("", "akida", "wallpaper")
-            .ok_or_else(|| anyhow!("can't find project directories"))
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum LockscreenMode {
+    /// Link to the image
+    Symlink,
+    /// Copy the image
+    Copy,
+    /// Run the command with `sh -c` and the monitor and image in `WALLPAPER_MONITOR`
+    /// and `WALLPAPER_IMAGE`
+    Command,
+}
+
+/// An image from the config with the settings specific to it
+///
+/// In the config file this is either one or many [`ValidTime`]s or an object
+/// with additional settings.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub struct ImageEntry {
+    /// The times as written in the config, which may refer to presets
+    #[serde(rename = "times", default = "all_times")]
+    pub time_refs: TimeRefs,
+    /// The times with all presets resolved
+    #[serde(skip)]
+    pub times: Vec<ValidTime>,
+    /// Only show the image during these days of the year
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dates: Option<DateRange>,
+    /// Overrides the global cooldown for this image
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[serde(serialize_with = "ser_opt_duration")]
+    #[serde(deserialize_with = "deser_opt_duration")]
+    pub cooldown: Option<Duration>,
+    /// Only show the image in this mode, in all modes if `None`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mode: Option<Mode>,
+    /// Switch away from the image after it was shown this long, even if no update is due
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[serde(serialize_with = "ser_opt_duration")]
+    #[serde(deserialize_with = "deser_opt_duration")]
+    pub max_duration: Option<Duration>,
+    /// Makes the image this many times as likely to be chosen as an image without weight
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub weight: Option<u32>,
+}
+
+impl ImageEntry {
+    pub fn new(times: Vec<ValidTime>) -> Self {
+        Self {
+            time_refs: TimeRefs::new(times.iter().cloned().map(TimeRef::Time).collect()),
+            times,
+            dates: None,
+            cooldown: None,
+            mode: None,
+            max_duration: None,
+            weight: None,
+        }
+    }
+
+    /// Returns whether this entry only consists of times
+    fn is_simple(&self) -> bool {
+        *self
+            == Self {
+                time_refs: self.time_refs.clone(),
+                times: self.times.clone(),
+                ..Self::new(Vec::new())
+            }
+    }
+
+    /// Returns whether the image may be shown at the given time
+    pub fn is_valid_at(&self, time: &NaiveDateTime) -> bool {
+        self.times.iter().any(|t| t.matches(time))
+            && self
+                .dates
+                .as_ref()
+                .is_none_or(|dates| dates.matches(&time.date()))
+    }
+
+    /// Returns whether the image may be shown in the given mode
+    pub fn fits_mode(&self, mode: Option<Mode>) -> bool {
+        self.mode.is_none() || mode.is_none() || self.mode == mode
+    }
+}
+
+fn all_times() -> TimeRefs {
+    TimeRefs::new(vec![TimeRef::Time(ValidTime::ALL)])
+}
+
+/// A directory whose images may be shown
+///
+/// The directory is listed again before every update, if it was modified.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub struct ImageSource {
+    /// The directory, relative to `image_dir`
+    pub dir: PathBuf,
+    /// The times at which the images may be shown
+    #[serde(default = "all_valid_times")]
+    pub times: Vec<ValidTime>,
+    /// Also show the images in subdirectories
+    #[serde(default)]
+    pub recursive: bool,
+}
+
+/// Derives the times of an image from its name in `wallpaper select --parse-names`
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub struct NameRule {
+    pub name: String,
+    /// A regex which is matched against the file name without extension
+    pub pattern: String,
+    /// The times of the matching images, `$1` and `${name}` are replaced by the groups of `pattern`
+    pub times: String,
+}
+
+impl NameRule {
+    pub fn regex(&self) -> Result<Regex, String> {
+        Regex::new(&self.pattern).map_err(|e| format!("name rule {}: {}", self.name, e))
+    }
+
+    /// Returns the times of the image, if its name matches `regex`, which belongs to this rule
+    pub fn times_of(&self, regex: &Regex, image: &Path) -> Option<Result<TimeRefs, String>> {
+        let name = image.file_stem()?.to_string_lossy();
+        let captures = regex.captures(&name)?;
+        let mut times = String::new();
+        captures.expand(&self.times, &mut times);
+        Some(times.parse().map_err(|e| {
+            format!(
+                "name rule {} gives invalid times {} for {}: {}",
+                self.name,
+                times,
+                image.display(),
+                e
+            )
+        }))
+    }
+}
+
+/// A server which lists images
+///
+/// The images are downloaded in the background, so they are shown after the next refresh.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub struct RemoteSource {
+    /// Returns either a JSON list of image URLs or a directory index
+    pub url: String,
+    /// How often the list and the images get downloaded again
+    #[serde(default = "default_remote_refresh")]
+    #[serde(serialize_with = "ser_duration")]
+    #[serde(deserialize_with = "deser_duration")]
+    pub refresh: Duration,
+}
+
+fn default_remote_refresh() -> Duration {
+    std::time::Duration::from_secs(60 * 60).into()
+}
+
+fn all_valid_times() -> Vec<ValidTime> {
+    vec![ValidTime::ALL]
+}
+
+/// A time range of an image, which may refer to one of the `time_presets`
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum TimeRef {
+    Time(ValidTime),
+    /// Name of the preset without the leading `@`
+    Preset(String),
+}
+
+impl std::fmt::Display for TimeRef {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Time(time) => write!(f, "{}", time),
+            Self::Preset(name) => write!(f, "@{}", name),
+        }
+    }
+}
+
+impl std::str::FromStr for TimeRef {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().strip_prefix('@') {
+            Some(name) => Ok(Self::Preset(name.to_owned())),
+            None => s.parse().map(Self::Time),
+        }
+    }
+}
+
+impl serde::Serialize for TimeRef {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for TimeRef {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(D::Error::custom)
+    }
+}
+
+/// The time ranges of an image as written in the config
+///
+/// This is either a list or a single string, in which multiple ranges are separated by commas.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct TimeRefs {
+    refs: Vec<TimeRef>,
+    /// The single string the ranges were parsed from, if they were written like this
+    source: Option<String>,
+}
+
+impl TimeRefs {
+    pub fn new(refs: Vec<TimeRef>) -> Self {
+        Self { refs, source: None }
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, TimeRef> {
+        self.refs.iter()
+    }
+
+    /// Appends the ranges of `other` which are not contained yet
+    pub fn append(&mut self, other: &TimeRefs) {
+        let new: Vec<_> = other
+            .refs
+            .iter()
+            .filter(|time| !self.refs.contains(time))
+            .cloned()
+            .collect();
+        if new.is_empty() {
+            return;
+        }
+        // a single string stays a single string
+        if let Some(source) = &mut self.source {
+            for time in &new {
+                source.push_str(&format!(",{}", time));
+            }
+        }
+        self.refs.extend(new);
+    }
+}
+
+impl<'a> IntoIterator for &'a TimeRefs {
+    type Item = &'a TimeRef;
+    type IntoIter = std::slice::Iter<'a, TimeRef>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl std::fmt::Display for TimeRefs {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(source) = &self.source {
+            return write!(f, "{}", source);
+        }
+        let refs: Vec<_> = self.refs.iter().map(ToString::to_string).collect();
+        write!(f, "{}", refs.join(","))
+    }
+}
+
+impl std::str::FromStr for TimeRefs {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // commas also separate the weekdays, so a part consisting only of weekdays
+        // belongs to the next part
+        let mut segments = Vec::new();
+        let mut pending = String::new();
+        for part in s.split(',') {
+            if !pending.is_empty() {
+                pending.push(',');
+            }
+            pending.push_str(part);
+            let part = part.trim();
+            if !part.is_empty()
+                && !part.contains(char::is_whitespace)
+                && part.parse::<Weekdays>().is_ok()
+            {
+                continue;
+            }
+            segments.push(std::mem::take(&mut pending));
+        }
+        if !pending.is_empty() {
+            segments.push(pending);
+        }
+
+        let refs = segments
+            .iter()
+            .map(|segment| {
+                segment
+                    .parse()
+                    .map_err(|e| format!("invalid segment \"{}\" in \"{}\": {}", segment, s, e))
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(Self {
+            refs,
+            source: Some(s.trim().to_owned()),
+        })
+    }
+}
+
+impl serde::Serialize for TimeRefs {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        if let Some(source) = &self.source {
+            serializer.serialize_str(source)
+        } else {
+            self.refs.serialize(serializer)
+        }
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for TimeRefs {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        #[derive(Deserialize, Debug)]
+        #[serde(untagged)]
+        enum OneOrMany {
+            One(String),
+            Vec(Vec<String>),
+        }
+
+        match OneOrMany::deserialize(deserializer)? {
+            OneOrMany::One(s) => s.parse().map_err(D::Error::custom),
+            OneOrMany::Vec(v) => v
+                .iter()
+                .map(|s| s.parse().map_err(D::Error::custom))
+                .collect::<Result<_, _>>()
+                .map(Self::new),
+        }
+    }
+}
+
+/// A range of days in the year, which may wrap around the end of the year
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct DateRange {
+    /// Month and day of the first day
+    start: (u32, u32),
+    /// Month and day of the last day
+    end: (u32, u32),
+}
+
+impl DateRange {
+    pub fn matches(&self, date: &NaiveDate) -> bool {
+        let date = (date.month(), date.day());
+        if self.start <= self.end {
+            (self.start..=self.end).contains(&date)
+        } else {
+            date >= self.start || date <= self.end
+        }
+    }
+
+    /// Returns an error if a day of the range doesn't exist in any year
+    pub fn check(&self) -> Result<(), String> {
+        for (month, day) in [self.start, self.end] {
+            // use a leap year so that the 29th of February is valid
+            if NaiveDate::from_ymd_opt(2000, month, day).is_none() {
+                let never = if self.start == self.end {
+                    ", so the range can never match"
+                } else {
+                    ""
+                };
+                return Err(format!(
+                    "the date {:02}-{:02} does not exist{}",
+                    month, day, never
+                ));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl std::fmt::Display for DateRange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{:02}-{:02}..{:02}-{:02}",
+            self.start.0, self.start.1, self.end.0, self.end.1
+        )
+    }
+}
+
+impl std::str::FromStr for DateRange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        fn from_s(s: &str, what: &str) -> Result<(u32, u32), String> {
+            let (month, day) = s
+                .trim()
+                .split_once('-')
+                .ok_or_else(|| format!("invalid {} date {}, expected MM-DD", what, s))?;
+            let month: u32 = month
+                .parse()
+                .map_err(|_| format!("invalid month for {} in {}", what, s))?;
+            let day: u32 = day
+                .parse()
+                .map_err(|_| format!("invalid day for {} in {}", what, s))?;
+            if !(1..=12).contains(&month) {
+                return Err(format!("invalid month for {} in {}", what, s));
+            }
+            if !(1..=31).contains(&day) {
+                return Err(format!("invalid day for {} in {}", what, s));
+            }
+            Ok((month, day))
+        }
+
+        let (start, end) = s
+            .split_once("..")
+            .ok_or_else(|| format!("invalid date range {}, expected MM-DD..MM-DD", s))?;
+
+        Ok(Self {
+            start: from_s(start, "start")?,
+            end: from_s(end, "end")?,
+        })
+    }
+}
+
+impl serde::Serialize for DateRange {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for DateRange {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(D::Error::custom)
+    }
+}
+
+/// How the transition for the next change is chosen
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Default, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum TransitionOrder {
+    /// Pick a random transition each time
+    #[default]
+    Random,
+    /// Go through the transitions in order, separately for each monitor
+    Cycle,
+}
+
+/// Limits the brightness of the shown images during some hours
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct NightMaxBrightness {
+    pub times: ValidTime,
+    /// Maximum average luminance between 0 (black) and 1 (white)
+    pub max: f32,
+}
+
+impl Eq for NightMaxBrightness {}
+
+impl Hash for NightMaxBrightness {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.times.hash(state);
+        self.max.to_bits().hash(state);
+    }
+}
+
+/// The maximum relative difference between the aspect ratios of an image and a monitor
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(transparent)]
+pub struct AspectTolerance(pub f32);
+
+impl Eq for AspectTolerance {}
+
+impl Hash for AspectTolerance {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0.to_bits().hash(state);
+    }
+}
+
+/// The factor by which the selection weight of an image changes per point of its rating
+///
+/// With the default of 2, an image rated 1 is shown twice as often as an unrated one
+/// and an image rated -1 half as often.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(transparent)]
+pub struct RatingFactor(pub f32);
+
+impl Default for RatingFactor {
+    fn default() -> Self {
+        Self(2.0)
+    }
+}
+
+impl Eq for RatingFactor {}
+
+impl Hash for RatingFactor {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0.to_bits().hash(state);
+    }
+}
+
+impl RatingFactor {
+    /// Returns the selection weight of an image with this rating
+    pub fn weight(self, rating: i32) -> f64 {
+        f64::from(self.0).powi(rating)
+    }
+}
+
+/// Changes applied to the images before they are shown
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq)]
+pub struct Effects {
+    /// Standard deviation of the gaussian blur in pixels
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub blur: Option<f32>,
+    /// How much darker the image gets, between 0 (unchanged) and 1 (black)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dim: Option<f32>,
+}
+
+impl Effects {
+    /// Returns whether the effects change the image
+    pub fn is_noop(&self) -> bool {
+        self.blur.is_none_or(|blur| blur <= 0.0) && self.dim.is_none_or(|dim| dim <= 0.0)
+    }
+}
+
+impl Eq for Effects {}
+
+impl Hash for Effects {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.blur.map(f32::to_bits).hash(state);
+        self.dim.map(f32::to_bits).hash(state);
+    }
+}
+
+/// Monitors which show one panoramic image together
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub struct SpanGroup {
+    pub monitors: Vec<SpanMonitor>,
+    /// How much the aspect ratio of a panoramic image may differ from the area of all monitors
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tolerance: Option<AspectTolerance>,
+}
+
+impl SpanGroup {
+    pub fn tolerance(&self) -> f32 {
+        self.tolerance
+            .map_or(0.1, |AspectTolerance(tolerance)| tolerance)
+    }
+
+    pub fn contains(&self, monitor: &str) -> bool {
+        self.monitors.iter().any(|other| other.name == monitor)
+    }
+}
+
+/// A monitor of a [`SpanGroup`]
+///
+/// The position is in pixels from the top left corner of the area of all monitors.
+/// Without a position, the monitor is placed right of the previous monitors.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub struct SpanMonitor {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub x: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub y: Option<u32>,
+}
+
+/// Whether the system uses a light or dark theme
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum Mode {
+    Light,
+    Dark,
+}
+
+impl std::fmt::Display for Mode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Light => write!(f, "light"),
+            Self::Dark => write!(f, "dark"),
+        }
+    }
+}
+
+impl std::str::FromStr for Mode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "light" => Ok(Self::Light),
+            "dark" => Ok(Self::Dark),
+            _ => Err(format!("invalid mode {}, expected light or dark", s)),
+        }
+    }
+}
+
+/// How the next image is chosen from the valid images
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Default, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum SelectionMode {
+    /// Choose a random image each time
+    #[default]
+    Random,
+    /// Show every image once before any image is shown again, separately for each monitor
+    Shuffle,
+    /// Show the images in the order given by `sort`, separately for each monitor
+    Sequential,
+}
+
+/// The order of the images in sequential mode
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Default, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum SortOrder {
+    /// Sort by path
+    #[default]
+    Name,
+    /// Sort by modification time, the oldest first
+    Mtime,
+}
+
+/// The images which were not shown yet in the current round of the shuffle mode
+#[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq, Eq, Hash)]
+pub struct ShuffleBag {
+    /// All images of the round, a new round starts if the images change
+    pub pool: BTreeSet<PathBuf>,
+    pub remaining: BTreeSet<PathBuf>,
+}
+
+/// What the daemon does with swww when it stops, if it started swww
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Default, PartialEq, Eq, Hash)]
+#[serde(rename_all = "kebab-case")]
+pub enum OnExit {
+    /// Leave swww running, so it keeps showing the last images
+    #[default]
+    Keep,
+    /// Clear the images with `swww clear`
+    Clear,
+    /// Stop swww with `swww kill`
+    Kill,
+}
+
+/// What the daemon does when it starts
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Default, PartialEq, Eq, Hash)]
+#[serde(rename_all = "kebab-case")]
+pub enum OnStart {
+    /// Always update the wallpapers
+    Always,
+    /// Only update the wallpapers if an update is due since the last update
+    #[default]
+    IfDue,
+    /// Never update the wallpapers, the next update happens after a full interval
+    Never,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq, Eq, Hash)]
+pub struct MonitorSettings {
+    /// Image which is always shown on this monitor instead of rotating
+    #[serde(rename = "static", default, skip_serializing_if = "Option::is_none")]
+    pub static_image: Option<String>,
+    /// Only show images with the orientation of this monitor, even if no such image is valid
+    #[serde(default)]
+    pub orientation_strict: bool,
+    /// Overrides the global `effects`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub effects: Option<Effects>,
+    /// Shown on this monitor instead of the global `images` and `image_sources`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    #[serde(serialize_with = "ser_images")]
+    #[serde(deserialize_with = "deser_images")]
+    pub images: BTreeMap<PathBuf, ImageEntry>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub struct IntervalScheduleEntry {
+    pub times: ValidTime,
+    #[serde(serialize_with = "ser_duration")]
+    #[serde(deserialize_with = "deser_duration")]
+    pub update_interval: Duration,
+}
+
+const CACHE_VERSION: usize = 7;
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Cache {
+    version: usize,
+    #[serde(serialize_with = "ser_timestamp")]
+    #[serde(deserialize_with = "deser_timestamp")]
+    pub last_update: Timestamp,
+    /// Map from monitor to the time of its last update
+    #[serde(default)]
+    #[serde(serialize_with = "ser_timestamps")]
+    #[serde(deserialize_with = "deser_timestamps")]
+    pub last_updates: BTreeMap<String, Timestamp>,
+
+    // Map from monitor to transition/ image
+    pub last_transitions: BTreeMap<String, String>,
+    pub last_images: BTreeMap<String, PathBuf>,
+    /// Map from monitor to the index of the next transition when cycling
+    #[serde(default)]
+    pub transition_indices: BTreeMap<String, usize>,
+    /// Map from monitor to its last images, the newest first
+    #[serde(default)]
+    pub history: BTreeMap<String, VecDeque<HistoryEntry>>,
+    /// Map from monitor to its images which were not shown yet in shuffle mode
+    #[serde(default)]
+    pub shuffle_bags: BTreeMap<String, ShuffleBag>,
+    /// Map from monitor to the last image shown in sequential mode
+    #[serde(default)]
+    pub cursors: BTreeMap<String, PathBuf>,
+    /// Map from image to the last time it was shown on any monitor
+    #[serde(default)]
+    #[serde(serialize_with = "ser_timestamps")]
+    #[serde(deserialize_with = "deser_timestamps")]
+    pub recently_shown: BTreeMap<PathBuf, Timestamp>,
+    /// The active mode, images of all modes are shown if `None`
+    #[serde(default)]
+    pub mode: Option<Mode>,
+    /// Overrides `mirror` of the config if set
+    #[serde(default)]
+    pub mirror: Option<bool>,
+    /// Only show images rated at least `favorites_threshold`
+    #[serde(default)]
+    pub favorites_only: bool,
+    /// Map from monitor to the time its current image was set
+    #[serde(default)]
+    #[serde(serialize_with = "ser_timestamps")]
+    #[serde(deserialize_with = "deser_timestamps")]
+    pub shown_since: BTreeMap<String, Timestamp>,
+    /// Map from image to its analyzed brightness
+    #[serde(default)]
+    pub brightness: BTreeMap<PathBuf, Brightness>,
+    /// Map from image to its dominant color
+    #[serde(default)]
+    pub dominant_colors: BTreeMap<PathBuf, DominantColor>,
+    /// Map from monitor to the dominant color of its current image
+    #[serde(default)]
+    pub monitor_colors: BTreeMap<String, String>,
+    /// Map from image to its width and height
+    #[serde(default)]
+    pub dimensions: BTreeMap<PathBuf, Dimensions>,
+    /// Map from image to the hash of its content
+    #[serde(default)]
+    pub content_hashes: BTreeMap<PathBuf, ContentHash>,
+    /// Map from image to its rating from `wallpaper like` and `wallpaper dislike`
+    #[serde(default)]
+    pub ratings: BTreeMap<PathBuf, i32>,
+    /// Map from image to how often it was shown
+    #[serde(default)]
+    pub display_stats: BTreeMap<PathBuf, DisplayStats>,
+    /// Map from monitor to the last time it was connected
+    #[serde(default)]
+    #[serde(serialize_with = "ser_timestamps")]
+    #[serde(deserialize_with = "deser_timestamps")]
+    pub monitors_seen: BTreeMap<String, Timestamp>,
+}
+
+/// An image which was shown on a monitor
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(try_from = "HistoryEntryRepr")]
+pub struct HistoryEntry {
+    pub image: PathBuf,
+    /// `None` for images which were shown before version 4 of the cache
+    pub transition: Option<String>,
+    /// `None` for images which were shown before version 4 of the cache
+    #[serde(serialize_with = "ser_opt_timestamp")]
+    pub shown_at: Option<Timestamp>,
+}
+
+/// Caches before version 4 only stored the image of a history entry
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum HistoryEntryRepr {
+    Image(PathBuf),
+    Entry {
+        image: PathBuf,
+        transition: Option<String>,
+        shown_at: Option<String>,
+    },
+}
+
+impl TryFrom<HistoryEntryRepr> for HistoryEntry {
+    type Error = String;
+
+    fn try_from(repr: HistoryEntryRepr) -> Result<Self, Self::Error> {
+        match repr {
+            HistoryEntryRepr::Image(image) => Ok(Self {
+                image,
+                transition: None,
+                shown_at: None,
+            }),
+            HistoryEntryRepr::Entry {
+                image,
+                transition,
+                shown_at,
+            } => {
+                let shown_at = shown_at
+                    .map(|shown_at| shown_at.parse())
+                    .transpose()
+                    .map_err(|e| format!("can't parse timestamp: {}", e))?;
+                Ok(Self {
+                    image,
+                    transition,
+                    shown_at,
+                })
+            }
+        }
+    }
+}
+
+/// How often and when an image was shown, for `wallpaper stats`
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct DisplayStats {
+    /// Counts every monitor which showed the image
+    pub count: u64,
+    #[serde(serialize_with = "ser_timestamp")]
+    #[serde(deserialize_with = "deser_timestamp")]
+    pub last_shown: Timestamp,
+}
+
+/// The width and height of an image file
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Dimensions {
+    /// Modification time of the file when it was read
+    #[serde(serialize_with = "ser_timestamp")]
+    #[serde(deserialize_with = "deser_timestamp")]
+    pub modified: Timestamp,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// The hash of the content of an image file
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ContentHash {
+    /// Modification time of the file when it was hashed
+    #[serde(serialize_with = "ser_timestamp")]
+    #[serde(deserialize_with = "deser_timestamp")]
+    pub modified: Timestamp,
+    /// The BLAKE3 hash as hex string
+    pub hash: String,
+}
+
+/// The most common color of an image file
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct DominantColor {
+    /// Modification time of the file when it was analyzed
+    #[serde(serialize_with = "ser_timestamp")]
+    #[serde(deserialize_with = "deser_timestamp")]
+    pub modified: Timestamp,
+    /// The color as hex string like `#1a2b3c`
+    pub color: String,
+}
+
+/// The average brightness of an image file
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Brightness {
+    /// Modification time of the file when it was analyzed
+    #[serde(serialize_with = "ser_timestamp")]
+    #[serde(deserialize_with = "deser_timestamp")]
+    pub modified: Timestamp,
+    /// Average luminance between 0 (black) and 1 (white)
+    pub luminance: f32,
+}
+
+impl Cache {
+    pub fn update(
+        &mut self,
+        monitor: String,
+        image: PathBuf,
+        transition: String,
+        color: Option<String>,
+    ) {
+        self.last_update = std::time::SystemTime::now().into();
+        match color {
+            Some(color) => self.monitor_colors.insert(monitor.clone(), color),
+            None => self.monitor_colors.remove(&monitor),
+        };
+        self.recently_shown
+            .insert(image.clone(), self.last_update.clone());
+        let stats = self
+            .display_stats
+            .entry(image.clone())
+            .or_insert_with(|| DisplayStats {
+                count: 0,
+                last_shown: self.last_update.clone(),
+            });
+        stats.count += 1;
+        stats.last_shown = self.last_update.clone();
+        let history = self.history.entry(monitor.clone()).or_default();
+        if history.front().map(|entry| &entry.image) != Some(&image) {
+            history.push_front(HistoryEntry {
+                image: image.clone(),
+                transition: Some(transition.clone()),
+                shown_at: Some(self.last_update.clone()),
+            });
+        }
+        self.last_images.insert(monitor.clone(), image);
+        self.shown_since
+            .insert(monitor.clone(), self.last_update.clone());
+        self.last_updates
+            .insert(monitor.clone(), self.last_update.clone());
+        self.last_transitions.insert(monitor, transition);
+    }
+
+    /// The time of the last update of the monitor, or of any monitor if it wasn't updated yet
+    pub fn last_update_of(&self, monitor: &str) -> std::time::SystemTime {
+        **self.last_updates.get(monitor).unwrap_or(&self.last_update)
+    }
+
+    /// Remembers that the monitors are connected now
+    pub fn saw_monitors<'a>(&mut self, monitors: impl IntoIterator<Item = &'a String>) {
+        let now: Timestamp = std::time::SystemTime::now().into();
+        for monitor in monitors {
+            self.monitors_seen.insert(monitor.clone(), now.clone());
+        }
+    }
+
+    /// Returns the monitors with entries which were not connected for `retention`
+    ///
+    /// Monitors which were never seen count as seen now, so they are kept for `retention` first.
+    pub fn stale_monitors(
+        &mut self,
+        retention: std::time::Duration,
+        keep: &[String],
+    ) -> Vec<String> {
+        let monitors: BTreeSet<_> = self
+            .last_images
+            .keys()
+            .chain(self.last_transitions.keys())
+            .chain(self.transition_indices.keys())
+            .chain(self.history.keys())
+            .chain(self.shuffle_bags.keys())
+            .chain(self.cursors.keys())
+            .chain(self.shown_since.keys())
+            .chain(self.monitor_colors.keys())
+            .chain(self.monitors_seen.keys())
+            .cloned()
+            .collect();
+        let now = std::time::SystemTime::now();
+        monitors
+            .into_iter()
+            .filter(|monitor| !keep.contains(monitor))
+            .filter(|monitor| {
+                let seen = self
+                    .monitors_seen
+                    .entry(monitor.clone())
+                    .or_insert_with(|| now.into());
+                now.duration_since(**seen).unwrap_or_default() > retention
+            })
+            .collect()
+    }
+
+    /// Removes all entries of the monitor
+    pub fn forget_monitor(&mut self, monitor: &str) {
+        // every field is listed, so new ones can't be forgotten here
+        let Self {
+            version: _,
+            last_update: _,
+            last_updates,
+            last_transitions,
+            last_images,
+            transition_indices,
+            history,
+            shuffle_bags,
+            cursors,
+            recently_shown: _,
+            mode: _,
+            mirror: _,
+            favorites_only: _,
+            shown_since,
+            brightness: _,
+            dominant_colors: _,
+            monitor_colors,
+            dimensions: _,
+            content_hashes: _,
+            ratings: _,
+            display_stats: _,
+            monitors_seen,
+        } = self;
+        last_updates.remove(monitor);
+        last_transitions.remove(monitor);
+        last_images.remove(monitor);
+        transition_indices.remove(monitor);
+        history.remove(monitor);
+        shuffle_bags.remove(monitor);
+        cursors.remove(monitor);
+        shown_since.remove(monitor);
+        monitor_colors.remove(monitor);
+        monitors_seen.remove(monitor);
+    }
+
+    /// Returns the monitors which show the image currently
+    pub fn monitors_showing(&self, image: &Path) -> Vec<String> {
+        self.last_images
+            .iter()
+            .filter(|(_monitor, shown)| *shown == image)
+            .map(|(monitor, _shown)| monitor.clone())
+            .collect()
+    }
+
+    /// Removes the image from the history, the cooldowns and the shuffle bags
+    ///
+    /// Monitors which still show the image keep it as their current image.
+    pub fn forget_image(&mut self, image: &Path) {
+        for (monitor, history) in &mut self.history {
+            let current = self.last_images.get(monitor).map(PathBuf::as_path) == Some(image);
+            let mut front = true;
+            history.retain(|entry| {
+                let keep = (front && current) || entry.image != image;
+                front = false;
+                keep
+            });
+        }
+        self.recently_shown.remove(image);
+        for bag in self.shuffle_bags.values_mut() {
+            bag.pool.remove(image);
+            bag.remaining.remove(image);
+        }
+    }
+
+    /// Shows the previous image of the monitor again and removes the current one from its history
+    ///
+    /// Unlike [`Cache::update`], this doesn't count as a change, so `last_update` and
+    /// `recently_shown` stay the same. Returns `None` if there is no previous image.
+    pub fn undo(&mut self, monitor: &str, color: Option<String>) -> Option<PathBuf> {
+        let history = self.history.get_mut(monitor)?;
+        let image = history.get(1)?.image.clone();
+        history.pop_front();
+        match color {
+            Some(color) => self.monitor_colors.insert(monitor.to_owned(), color),
+            None => self.monitor_colors.remove(monitor),
+        };
+        self.last_images.insert(monitor.to_owned(), image.clone());
+        self.shown_since
+            .insert(monitor.to_owned(), std::time::SystemTime::now().into());
+        Some(image)
+    }
+
+    /// Returns the luminance of the image, if it was analyzed since it was last modified
+    pub fn luminance_of(&self, image: &Path) -> Option<f32> {
+        let brightness = self.brightness.get(image)?;
+        let modified = std::fs::metadata(image).and_then(|m| m.modified()).ok()?;
+        (modified == *brightness.modified).then_some(brightness.luminance)
+    }
+
+    /// Returns the content hash of the image, if it was hashed since it was last modified
+    pub fn content_hash_of(&self, image: &Path) -> Option<&str> {
+        let hash = self.content_hashes.get(image)?;
+        let modified = std::fs::metadata(image).and_then(|m| m.modified()).ok()?;
+        (modified == *hash.modified).then_some(hash.hash.as_str())
+    }
+
+    /// Returns whether the image was shown less than `cooldown` ago
+    pub fn is_cooling_down(&self, image: &PathBuf, cooldown: Duration) -> bool {
+        self.recently_shown
+            .get(image)
+            .and_then(|shown| shown.elapsed().ok())
+            .is_some_and(|elapsed| elapsed < *cooldown)
+    }
+
+    /// Removes all images from `recently_shown` whose cooldown is over
+    pub fn prune_recently_shown(&mut self, max_cooldown: Duration) {
+        self.recently_shown.retain(|_, shown| {
+            shown
+                .elapsed()
+                .map_or(true, |elapsed| elapsed < *max_cooldown)
+        });
+    }
+
+    /// Keeps only the last `size` images of every monitor in the history
+    pub fn prune_history(&mut self, size: usize) {
+        for history in self.history.values_mut() {
+            history.truncate(size);
+        }
+    }
+
+    /// Forgets the images for which `deleted` returns true in the cooldown, the history and
+    /// the shuffle bags
+    ///
+    /// Returns the number of removed entries.
+    pub fn forget_images(&mut self, deleted: impl Fn(&Path) -> bool) -> usize {
+        let mut forgotten = 0;
+        let mut keep = |image: &Path| {
+            let keep = !deleted(image);
+            forgotten += usize::from(!keep);
+            keep
+        };
+        self.recently_shown.retain(|image, _| keep(image));
+        for history in self.history.values_mut() {
+            history.retain(|entry| keep(&entry.image));
+        }
+        for bag in self.shuffle_bags.values_mut() {
+            bag.pool.retain(|image| keep(image));
+            bag.remaining.retain(|image| keep(image));
+        }
+        forgotten
+    }
+
+    /// Returns every image which has an entry in the cache, some of them more than once
+    pub fn images(&self) -> impl Iterator<Item = &PathBuf> {
+        self.last_images
+            .values()
+            .chain(self.cursors.values())
+            .chain(self.history.values().flatten().map(|entry| &entry.image))
+            .chain(
+                self.shuffle_bags
+                    .values()
+                    .flat_map(|bag| bag.pool.iter().chain(&bag.remaining)),
+            )
+            .chain(self.recently_shown.keys())
+            .chain(self.brightness.keys())
+            .chain(self.dominant_colors.keys())
+            .chain(self.dimensions.keys())
+            .chain(self.content_hashes.keys())
+            .chain(self.ratings.keys())
+            .chain(self.display_stats.keys())
+    }
+
+    /// Forgets everything about the images, also their analysis, ratings and statistics
+    ///
+    /// The current images and the cursors of the monitors are kept.
+    /// Returns the number of removed entries.
+    pub fn forget_stale_images(&mut self, stale: &BTreeSet<PathBuf>) -> usize {
+        fn retain<V>(map: &mut BTreeMap<PathBuf, V>, stale: &BTreeSet<PathBuf>) -> usize {
+            let len = map.len();
+            map.retain(|image, _| !stale.contains(image));
+            len - map.len()
+        }
+
+        self.forget_images(|image| stale.contains(image))
+            + retain(&mut self.brightness, stale)
+            + retain(&mut self.dominant_colors, stale)
+            + retain(&mut self.dimensions, stale)
+            + retain(&mut self.content_hashes, stale)
+            + retain(&mut self.ratings, stale)
+            + retain(&mut self.display_stats, stale)
+    }
+
+    /// Replaces the path of every image in the cache with `f` of it
+    pub(crate) fn map_images(&mut self, f: impl Fn(&Path) -> PathBuf) {
+        fn map_keys<V>(map: &mut BTreeMap<PathBuf, V>, f: &impl Fn(&Path) -> PathBuf) {
+            *map = std::mem::take(map)
+                .into_iter()
+                .map(|(image, value)| (f(&image), value))
+                .collect();
+        }
+
+        for image in self
+            .last_images
+            .values_mut()
+            .chain(self.cursors.values_mut())
+        {
+            *image = f(image);
+        }
+        for entry in self.history.values_mut().flatten() {
+            entry.image = f(&entry.image);
+        }
+        for bag in self.shuffle_bags.values_mut() {
+            bag.pool = bag.pool.iter().map(|image| f(image)).collect();
+            bag.remaining = bag.remaining.iter().map(|image| f(image)).collect();
+        }
+        map_keys(&mut self.recently_shown, &f);
+        map_keys(&mut self.brightness, &f);
+        map_keys(&mut self.dominant_colors, &f);
+        map_keys(&mut self.dimensions, &f);
+        map_keys(&mut self.content_hashes, &f);
+        map_keys(&mut self.ratings, &f);
+        map_keys(&mut self.display_stats, &f);
+    }
+
+    /// Adds the monitors and images of `other` which are not in this cache yet
+    ///
+    /// The mode and the other global settings of this cache are kept.
+    pub(crate) fn merge_missing(&mut self, other: Cache) {
+        fn add<K: Ord, V>(map: &mut BTreeMap<K, V>, other: BTreeMap<K, V>) {
+            for (key, value) in other {
+                map.entry(key).or_insert(value);
+            }
+        }
+
+        add(&mut self.last_updates, other.last_updates);
+        add(&mut self.last_transitions, other.last_transitions);
+        add(&mut self.last_images, other.last_images);
+        add(&mut self.transition_indices, other.transition_indices);
+        add(&mut self.history, other.history);
+        add(&mut self.shuffle_bags, other.shuffle_bags);
+        add(&mut self.cursors, other.cursors);
+        add(&mut self.recently_shown, other.recently_shown);
+        add(&mut self.shown_since, other.shown_since);
+        add(&mut self.brightness, other.brightness);
+        add(&mut self.dominant_colors, other.dominant_colors);
+        add(&mut self.monitor_colors, other.monitor_colors);
+        add(&mut self.dimensions, other.dimensions);
+        add(&mut self.content_hashes, other.content_hashes);
+        add(&mut self.ratings, other.ratings);
+        add(&mut self.display_stats, other.display_stats);
+        add(&mut self.monitors_seen, other.monitors_seen);
+    }
+
+    /// Converts a cache of an older version to the current version, one version at a time
+    ///
+    /// Fails if the version is unknown.
+    pub(crate) fn migrate(mut self) -> anyhow::Result<Self> {
+        if !Self::is_known_version(self.version) {
+            return Err(anyhow!(
+                "read cache with incompatible version. Expected version {} but got {}",
+                CACHE_VERSION,
+                self.version
+            ));
+        }
+        let version = self.version;
+        while self.version < CACHE_VERSION {
+            self.upgrade();
+        }
+        if version != CACHE_VERSION {
+            info!(
+                "migrated cache from version {} to {}",
+                version, CACHE_VERSION
+            );
+        }
+        Ok(self)
+    }
+
+    /// Whether a cache of the version can be migrated, so newer versions are not overwritten
+    fn is_known_version(version: usize) -> bool {
+        // the first cache had version 0
+        version <= CACHE_VERSION
+    }
+
+    /// Converts the cache to the next version
+    ///
+    /// Every version is read into the current layout, as the fields added later have defaults
+    /// and the history entries of version 3 are read without their transition and time.
+    /// Versions 1, 2, 4, 5 and 6 only added fields with defaults.
+    fn upgrade(&mut self) {
+        match self.version {
+            // version 3 added the history, which starts with the current images
+            2 => {
+                for (monitor, image) in &self.last_images {
+                    self.history
+                        .entry(monitor.clone())
+                        .or_default()
+                        .push_front(HistoryEntry {
+                            image: image.clone(),
+                            transition: None,
+                            shown_at: None,
+                        });
+                }
+            }
+            // version 7 added the last update of every monitor, which was the same for all
+            6 => {
+                for monitor in self.last_images.keys() {
+                    self.last_updates
+                        .insert(monitor.clone(), self.last_update.clone());
+                }
+            }
+            // the other versions only added fields with defaults
+            _ => {}
+        }
+        self.version += 1;
+    }
+}
+
+/// Returns the images of the cache which are neither configured nor `exist`
+///
+/// The current images of the monitors are never stale, as they are still shown.
+pub fn stale_images(
+    config: &Config,
+    cache: &Cache,
+    exists: impl Fn(&Path) -> bool,
+) -> BTreeSet<PathBuf> {
+    let configured = config.configured_images();
+    let shown: BTreeSet<_> = cache.last_images.values().collect();
+    cache
+        .images()
+        .filter(|image| !shown.contains(image) && !configured.contains(*image))
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .filter(|image| !exists(image))
+        .cloned()
+        .collect()
+}
+
+/// The version of a cache file, read before the cache in case it has an unknown layout
+#[derive(Deserialize)]
+struct CacheHeader {
+    version: usize,
+}
+
+impl Default for Cache {
+    fn default() -> Self {
+        Self {
+            version: CACHE_VERSION,
+            last_update: std::time::UNIX_EPOCH.into(),
+            last_updates: Default::default(),
+            last_images: Default::default(),
+            last_transitions: Default::default(),
+            transition_indices: Default::default(),
+            history: Default::default(),
+            shuffle_bags: Default::default(),
+            cursors: Default::default(),
+            recently_shown: Default::default(),
+            mode: None,
+            mirror: None,
+            favorites_only: false,
+            shown_since: Default::default(),
+            brightness: Default::default(),
+            dominant_colors: Default::default(),
+            monitor_colors: Default::default(),
+            dimensions: Default::default(),
+            content_hashes: Default::default(),
+            ratings: Default::default(),
+            display_stats: Default::default(),
+            monitors_seen: Default::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct State {
+    pub cache: Cache,
+    pub config: Config,
+    project_dirs: ProjectDirs,
+    /// Seeded from entropy, unless [`State::seed`] is called for reproducible runs
+    pub rng: rand::rngs::StdRng,
+    /// Map from monitor to its focused workspace, only known to the daemon
+    pub workspaces: BTreeMap<String, String>,
+    /// Monitors which show a fullscreen window, only known to the daemon
+    pub fullscreen: BTreeSet<String>,
+    /// Monitors which are powered off, only known to the daemon
+    pub off: BTreeSet<String>,
+    /// Monitors whose last change was vetoed by the `pre_change` hook
+    pub vetoed: BTreeSet<String>,
+    /// Whether swww was started by [`init_sww`](crate::init_sww) of this process
+    pub started_swww: bool,
+    /// Whether the system runs on battery, checked by the daemon before every update
+    pub power: PowerProfile,
+    /// Monitors whose update was deferred because of a fullscreen window or a preview
+    pub deferred: BTreeSet<String>,
+    /// Map from monitor to the end of its preview, only known to the daemon
+    pub previews: BTreeMap<String, std::time::Instant>,
+    /// Map from not connected monitor to its update when it appears, only known to the daemon
+    pub pending: BTreeMap<String, Pending>,
+    /// The images found in the `image_sources` by [`State::refresh_sources`]
+    pub source_images: BTreeMap<PathBuf, ImageEntry>,
+    /// Map from monitor to how its image was chosen in the last update, only known to the daemon
+    pub traces: BTreeMap<String, SelectionTrace>,
+    /// The interval updates of the monitors, only known to the daemon
+    pub schedule: Schedule,
+    listings: Listings,
+    last_loaded_cache_hash: u64,
+    last_loaded_config_hash: u64,
+    /// Hash of the bytes of the cache file, as last read or written
+    cache_file_hash: u64,
+}
+
+impl State {
+    pub fn project_dirs() -> anyhow::Result<ProjectDirs> {
+        ProjectDirs::from("", "akida", "wallpaper")
+            .ok_or_else(|| anyhow!("can't find project directories"))
+    }
+
+    /// Makes the random choices reproducible, e.g. for tests or to debug a crash loop
+    pub fn seed(&mut self, seed: u64) {
+        self.rng = rand::SeedableRng::seed_from_u64(seed);
+    }
+
+    pub fn load() -> anyhow::Result<Self> {
+        let mut s = Self::new(Config::default(), Cache::default())?;
+        s.reload()?;
+        Ok(s)
     }
 
-    pub fn load() -> anyhow::Result<Self> {
-        let config = Config::default();
-        let cache = Cache::default();
+    /// A state with the config and cache, which are not read from their files
+    pub fn new(config: Config, cache: Cache) -> anyhow::Result<Self> {
         let last_loaded_cache_hash = Self::hash_cache(&cache);
         let last_loaded_config_hash = Self::hash_config(&config);
 
-        let mut s = Self {
+        Ok(Self {
             config,
             cache,
             project_dirs: Self::project_dirs()?,
-            rng: rand::thread_rng(),
+            rng: rand::SeedableRng::from_entropy(),
+            workspaces: BTreeMap::new(),
+            fullscreen: BTreeSet::new(),
+            off: BTreeSet::new(),
+            vetoed: BTreeSet::new(),
+            started_swww: false,
+            power: crate::power::current_profile(),
+            deferred: BTreeSet::new(),
+            previews: BTreeMap::new(),
+            pending: BTreeMap::new(),
+            source_images: BTreeMap::new(),
+            traces: BTreeMap::new(),
+            schedule: Schedule::default(),
+            listings: Listings::default(),
             last_loaded_cache_hash,
             last_loaded_config_hash,
-        };
-        s.reload()?;
-        Ok(s)
+            cache_file_hash: 0,
+        })
     }
 
     fn hash_cache(cache: &Cache) -> u64 {
@@ -131,15 +2080,94 @@ impl State {
         let Cache {
             version: _,
             last_update: _,
+            last_updates,
             last_transitions,
             last_images,
+            transition_indices,
+            history,
+            shuffle_bags,
+            cursors,
+            recently_shown,
+            mode,
+            mirror,
+            favorites_only,
+            shown_since,
+            brightness,
+            dominant_colors,
+            monitor_colors,
+            dimensions,
+            content_hashes,
+            ratings,
+            display_stats,
+            // only changes when monitors are queried
+            monitors_seen: _,
         } = cache;
+        for (monitor, update) in last_updates {
+            monitor.hash(&mut s);
+            (**update).hash(&mut s);
+        }
         last_transitions.hash(&mut s);
+        mode.hash(&mut s);
+        mirror.hash(&mut s);
+        favorites_only.hash(&mut s);
+        for (monitor, since) in shown_since {
+            monitor.hash(&mut s);
+            (**since).hash(&mut s);
+        }
+        for (image, brightness) in brightness {
+            image.hash(&mut s);
+            (*brightness.modified).hash(&mut s);
+            brightness.luminance.to_bits().hash(&mut s);
+        }
+        for (image, color) in dominant_colors {
+            image.hash(&mut s);
+            (*color.modified).hash(&mut s);
+            color.color.hash(&mut s);
+        }
+        monitor_colors.hash(&mut s);
+        for (image, dimensions) in dimensions {
+            image.hash(&mut s);
+            (*dimensions.modified).hash(&mut s);
+            dimensions.width.hash(&mut s);
+            dimensions.height.hash(&mut s);
+        }
+        ratings.hash(&mut s);
+        for (image, stats) in display_stats {
+            image.hash(&mut s);
+            stats.count.hash(&mut s);
+            (*stats.last_shown).hash(&mut s);
+        }
+        for (image, hash) in content_hashes {
+            image.hash(&mut s);
+            (*hash.modified).hash(&mut s);
+            hash.hash.hash(&mut s);
+        }
         last_images.hash(&mut s);
+        transition_indices.hash(&mut s);
+        for (monitor, history) in history {
+            monitor.hash(&mut s);
+            for entry in history {
+                entry.image.hash(&mut s);
+                entry.transition.hash(&mut s);
+                entry.shown_at.as_deref().hash(&mut s);
+            }
+        }
+        shuffle_bags.hash(&mut s);
+        cursors.hash(&mut s);
+        for (image, shown) in recently_shown {
+            image.hash(&mut s);
+            (**shown).hash(&mut s);
+        }
 
         s.finish()
     }
 
+    fn hash_bytes(bytes: &[u8]) -> u64 {
+        let mut s = DefaultHasher::new();
+        bytes.hash(&mut s);
+        s.finish()
+    }
+
     fn hash_config(config: &Config) -> u64 {
         let mut s = DefaultHasher::new();
         config.hash(&mut s);
@@ -155,8 +2183,25 @@ impl State {
         let cache_file = cache_dir.join("cache.json");
         if cache_file.is_file() {
             debug!("reading cache file");
-            let file = std::fs::File::open(&cache_file).context("while opening cache file")?;
-            let cache: Cache = serde_json::from_reader(file).context("while parsing cache file")?;
+            let bytes = std::fs::read(&cache_file).context("while reading cache file")?;
+            self.cache_file_hash = Self::hash_bytes(&bytes);
+            let header: CacheHeader =
+                serde_json::from_slice(&bytes).context("while parsing cache file")?;
+            if !Cache::is_known_version(header.version) {
+                error!(
+                    "read cache with incompatible version. Expected version {} but got {}",
+                    CACHE_VERSION, header.version
+                );
+                // the next save replaces the file
+                let backup = cache_file.with_extension("json.bak");
+                match std::fs::copy(&cache_file, &backup) {
+                    Ok(_) => warn!("backed up the cache file to {}", backup.display()),
+                    Err(e) => error!("can't back up the cache file: {}", e),
+                }
+                return Ok(None);
+            }
+            let cache: Cache =
+                serde_json::from_slice(&bytes).context("while parsing cache file")?;
             Ok(Some(cache))
         } else {
             info!(
@@ -178,7 +2223,9 @@ impl State {
         if config_file.is_file() {
             debug!("reading config file");
             let file = std::fs::File::open(&config_file).context("while opening config file")?;
-            let config = serde_json::from_reader(file).context("while parsing config file")?;
+            let mut config: Config =
+                serde_json::from_reader(file).context("while parsing config file")?;
+            config.resolve()?;
             Ok(Some(config))
         } else {
             info!(
@@ -193,107 +2240,852 @@ impl State {
         }
     }
 
+    /// Takes over the values of a loaded cache for the configured monitors
+    fn merge_cache(&mut self, cache: Cache) {
+        for (monitor, image) in cache.last_images {
+            if self.config.monitors.includes(&monitor) {
+                self.cache.last_images.insert(monitor, image);
+            }
+        }
+        for (monitor, transition) in cache.last_transitions {
+            if self.config.monitors.includes(&monitor) {
+                self.cache.last_transitions.insert(monitor, transition);
+            }
+        }
+        for (monitor, index) in cache.transition_indices {
+            if self.config.monitors.includes(&monitor) {
+                self.cache.transition_indices.insert(monitor, index);
+            }
+        }
+        for (monitor, history) in cache.history {
+            if self.config.monitors.includes(&monitor) {
+                self.cache.history.insert(monitor, history);
+            }
+        }
+        for (monitor, bag) in cache.shuffle_bags {
+            if self.config.monitors.includes(&monitor) {
+                self.cache.shuffle_bags.insert(monitor, bag);
+            }
+        }
+        for (monitor, cursor) in cache.cursors {
+            if self.config.monitors.includes(&monitor) {
+                self.cache.cursors.insert(monitor, cursor);
+            }
+        }
+        for (monitor, since) in cache.shown_since {
+            if self.config.monitors.includes(&monitor) {
+                self.cache.shown_since.insert(monitor, since);
+            }
+        }
+        for (monitor, update) in cache.last_updates {
+            if self.config.monitors.includes(&monitor) {
+                self.cache.last_updates.insert(monitor, update);
+            }
+        }
+        self.cache.recently_shown.extend(cache.recently_shown);
+        self.cache.last_update = cache.last_update;
+        self.cache.mode = cache.mode;
+        self.cache.mirror = cache.mirror;
+        self.cache.favorites_only = cache.favorites_only;
+        self.cache.brightness.extend(cache.brightness);
+        self.cache.dominant_colors.extend(cache.dominant_colors);
+        self.cache.dimensions.extend(cache.dimensions);
+        self.cache.content_hashes.extend(cache.content_hashes);
+        self.cache.ratings = cache.ratings;
+        self.cache.display_stats = cache.display_stats;
+        for (monitor, seen) in cache.monitors_seen {
+            let known = self
+                .cache
+                .monitors_seen
+                .entry(monitor)
+                .or_insert(seen.clone());
+            if *seen > **known {
+                *known = seen;
+            }
+        }
+        for (monitor, color) in cache.monitor_colors {
+            if self.config.monitors.includes(&monitor) {
+                self.cache.monitor_colors.insert(monitor, color);
+            }
+        }
+    }
+
     pub fn force_reload(&mut self) -> anyhow::Result<()> {
         debug!("force reload");
         if let Some(cache) = self.reload_cache()? {
-            if cache.version != CACHE_VERSION {
-                error!(
-                    "read cache with incompatible version. Expected version {} but got {}",
-                    CACHE_VERSION, cache.version
-                );
-            } else {
-                self.last_loaded_cache_hash = Self::hash_cache(&cache);
-                for (monitor, image) in cache.last_images {
-                    if self.config.monitors.includes(&monitor) {
-                        self.cache.last_images.insert(monitor, image);
-                    }
-                }
-                for (monitor, transition) in cache.last_transitions {
-                    if self.config.monitors.includes(&monitor) {
-                        self.cache.last_transitions.insert(monitor, transition);
-                    }
-                }
-                self.cache.last_update = cache.last_update;
+            let hash = Self::hash_cache(&cache);
+            if hash != self.last_loaded_cache_hash {
+                info!("cache file changed");
+            }
+            self.last_loaded_cache_hash = hash;
+            match cache.migrate() {
+                Ok(cache) => self.merge_cache(cache),
+                Err(e) => error!("{}", e),
             }
         }
 
         if let Some(config) = self.reload_config()? {
-            self.last_loaded_config_hash = Self::hash_config(&config);
+            let hash = Self::hash_config(&config);
+            if hash != self.last_loaded_config_hash {
+                info!("config file changed");
+            }
+            self.last_loaded_config_hash = hash;
             self.config = config;
         }
 
         Ok(())
     }
 
-    pub fn reload(&mut self) -> anyhow::Result<()> {
+    /// Reloads the config and the cache if their files changed since they were last loaded
+    ///
+    /// Returns whether one of them changed.
+    pub fn reload(&mut self) -> anyhow::Result<bool> {
+        let mut changed = false;
         if let Some(cache) = self.reload_cache()? {
             if Self::hash_cache(&cache) == self.last_loaded_cache_hash {
                 debug!("not reloading cache as it stayed the same");
-                return Ok(());
-            }
-            debug!("reloading cache for real");
-            self.last_loaded_cache_hash = Self::hash_cache(&cache);
-            if cache.version != CACHE_VERSION {
-                error!(
-                    "read cache with incompatible version. Expected version {} but got {}",
-                    CACHE_VERSION, cache.version
-                );
             } else {
-                for (monitor, image) in cache.last_images {
-                    if self.config.monitors.includes(&monitor) {
-                        self.cache.last_images.insert(monitor, image);
-                    }
-                }
-                for (monitor, transition) in cache.last_transitions {
-                    if self.config.monitors.includes(&monitor) {
-                        self.cache.last_transitions.insert(monitor, transition);
-                    }
+                debug!("reloading cache for real");
+                self.last_loaded_cache_hash = Self::hash_cache(&cache);
+                match cache.migrate() {
+                    Ok(cache) => self.merge_cache(cache),
+                    Err(e) => error!("{}", e),
                 }
-                self.cache.last_update = cache.last_update;
+                changed = true;
             }
         }
 
         if let Some(config) = self.reload_config()? {
             if Self::hash_config(&config) == self.last_loaded_config_hash {
                 debug!("not reloading config as it stayed the same");
-                return Ok(());
+            } else {
+                debug!("reloading config for real");
+                self.last_loaded_config_hash = Self::hash_config(&config);
+                self.config = config;
+                changed = true;
             }
-            debug!("reloading config for real");
-            self.last_loaded_config_hash = Self::hash_config(&config);
-            self.config = config;
         }
 
-        Ok(())
+        Ok(changed)
     }
 
-    pub fn save(&self) -> anyhow::Result<()> {
+    /// Writes the cache file, unless it already has the same content
+    ///
+    /// The file is replaced atomically, so a crash never leaves half of it behind.
+    pub fn save(&mut self) -> anyhow::Result<()> {
+        self.prune_monitors(false);
+        self.prune_images(false);
+        self.cache.prune_recently_shown(self.config.max_cooldown());
+        self.cache
+            .prune_history(self.config.history_size.max(self.config.history_limit));
+        let bytes = serde_json::to_vec(&self.cache).context("while serializing cache")?;
+        let hash = Self::hash_bytes(&bytes);
+        // the daemon doesn't have to reload its own changes
+        self.last_loaded_cache_hash = Self::hash_cache(&self.cache);
+        if hash == self.cache_file_hash {
+            debug!("not saving cache file as it stayed the same");
+            return Ok(());
+        }
+
         debug!("saving cache file");
         let cache_file = self.project_dirs.cache_dir().join("cache.json");
-        let file =
-            std::fs::File::create(cache_file).context("while opening cache file for write")?;
-        serde_json::to_writer(file, &self.cache).context("while writing cache file")?;
+        let tmp_file = cache_file.with_extension("json.tmp");
+        std::fs::write(&tmp_file, &bytes).context("while writing cache file")?;
+        std::fs::rename(&tmp_file, &cache_file).context("while replacing cache file")?;
+        self.cache_file_hash = hash;
         debug!("saved cache file");
 
         Ok(())
     }
+
+    /// Changes the config file with `f` and reloads the config
+    ///
+    /// Only the config file is changed, so options which were changed at runtime are lost.
+    /// The file is replaced atomically, so the daemon never reads half of it.
+    pub fn edit_config(&mut self, f: impl FnOnce(&mut Config)) -> anyhow::Result<()> {
+        let mut config = self.read_config_file()?;
+        f(&mut config);
+        let resolved = Self::write_config(&config)?;
+
+        self.last_loaded_config_hash = Self::hash_config(&resolved);
+        self.config = resolved;
+
+        Ok(())
+    }
+
+    /// Reads the config file without resolving it, or returns the current config if there is none
+    pub fn read_config_file(&self) -> anyhow::Result<Config> {
+        let config_file = self.project_dirs.config_dir().join("config.json");
+        if !config_file.is_file() {
+            return Ok(self.config.clone());
+        }
+        let file = std::fs::File::open(&config_file).context("while opening config file")?;
+        serde_json::from_reader(file).context("while parsing config file")
+    }
+
+    /// Replaces the config file and the cache with imported ones
+    ///
+    /// With `merge` only the images, time presets and cache entries which are missing here are
+    /// added. The config is checked before anything is written.
+    pub fn import(&mut self, config: Config, cache: Cache, merge: bool) -> anyhow::Result<()> {
+        let (config, cache) = if merge {
+            let mut current = self.read_config_file()?;
+            for (image, entry) in config.images {
+                let key = current.image_key(&config.image_dir.join(image));
+                current.images.entry(key).or_insert(entry);
+            }
+            for (name, times) in config.time_presets {
+                current.time_presets.entry(name).or_insert(times);
+            }
+            let mut current_cache = self.cache.clone();
+            current_cache.merge_missing(cache);
+            (current, current_cache)
+        } else {
+            (config, cache)
+        };
+        let resolved = Self::write_config(&config).context("while writing imported config")?;
+        self.last_loaded_config_hash = Self::hash_config(&resolved);
+        self.config = resolved;
+
+        self.cache = cache;
+        self.save().context("while writing imported cache")?;
+        self.last_loaded_cache_hash = Self::hash_cache(&self.cache);
+        info!("imported config and cache");
+
+        Ok(())
+    }
+
+    /// The path of the config file
+    pub fn config_file() -> anyhow::Result<PathBuf> {
+        Ok(Self::project_dirs()?.config_dir().join("config.json"))
+    }
+
+    /// The path of the cache file
+    pub fn cache_file() -> anyhow::Result<PathBuf> {
+        Ok(Self::project_dirs()?.cache_dir().join("cache.json"))
+    }
+
+    /// The directory of the log files, unless it is overridden with `--log-dir`
+    ///
+    /// Only `log_dir` is read from the config file, as logging starts before the config is loaded
+    /// and checked. Without it, the logs are in the state dir, or in the cache dir if there is
+    /// no state dir.
+    pub fn log_dir() -> anyhow::Result<PathBuf> {
+        #[derive(Deserialize)]
+        struct LogConfig {
+            #[serde(default)]
+            log_dir: Option<PathBuf>,
+        }
+
+        let configured = std::fs::read(Self::config_file()?)
+            .ok()
+            .and_then(|bytes| serde_json::from_slice::<LogConfig>(&bytes).ok())
+            .and_then(|config| config.log_dir);
+        if let Some(log_dir) = configured {
+            return Ok(log_dir);
+        }
+        let project_dirs = Self::project_dirs()?;
+        let dir = project_dirs
+            .state_dir()
+            .unwrap_or_else(|| project_dirs.cache_dir());
+        Ok(dir.join("logs"))
+    }
+
+    /// Where the logs were written before they moved to the state dir
+    pub fn old_log_dir() -> anyhow::Result<PathBuf> {
+        Ok(Self::project_dirs()?.cache_dir().join("logs"))
+    }
+
+    /// Replaces the config file atomically and returns the resolved config
+    ///
+    /// The config is checked before, so a broken config is never saved.
+    pub fn write_config(config: &Config) -> anyhow::Result<Config> {
+        let mut resolved = config.clone();
+        resolved.resolve()?;
+
+        let config_file = Self::config_file()?;
+        if let Some(config_dir) = config_file.parent() {
+            std::fs::create_dir_all(config_dir).context("while creating config dir")?;
+        }
+        let tmp_file = config_file.with_extension("json.tmp");
+        let file =
+            std::fs::File::create(&tmp_file).context("while opening config file for write")?;
+        serde_json::to_writer_pretty(file, config).context("while writing config file")?;
+        std::fs::rename(&tmp_file, &config_file).context("while replacing config file")?;
+        debug!("saved config file");
+
+        Ok(resolved)
+    }
+
+    /// Adds the image to the config file and reloads the config
+    ///
+    /// New images are shown at all times if no times are given. Returns whether the image is new.
+    pub fn add_image(
+        &mut self,
+        image: &Path,
+        times: Option<TimeRefs>,
+        weight: Option<u32>,
+    ) -> anyhow::Result<bool> {
+        let mut new = false;
+        self.edit_config(|config| {
+            let entry = config
+                .images
+                .entry(config.image_key(image))
+                .or_insert_with(|| {
+                    new = true;
+                    ImageEntry::new(vec![ValidTime::ALL])
+                });
+            if let Some(times) = times {
+                entry.time_refs = times;
+            }
+            if weight.is_some() {
+                entry.weight = weight;
+            }
+        })?;
+        Ok(new)
+    }
+
+    /// Removes the images which match the path or glob from the config file and reloads the config
+    ///
+    /// Returns the full paths of the removed images.
+    pub fn remove_images(&mut self, pattern: &str) -> anyhow::Result<BTreeSet<PathBuf>> {
+        let pattern = ImagePattern::new(pattern)?;
+        let mut removed = BTreeSet::new();
+        self.edit_config(|config| {
+            let image_dir = config.image_dir.clone();
+            let mut retain = |images: &mut BTreeMap<PathBuf, ImageEntry>| {
+                images.retain(|key, _entry| {
+                    let matches = pattern.matches(&image_dir, key);
+                    if matches {
+                        removed.insert(image_dir.join(key));
+                    }
+                    !matches
+                });
+            };
+            retain(&mut config.images);
+            for settings in config.monitor_settings.values_mut() {
+                retain(&mut settings.images);
+            }
+        })?;
+        Ok(removed)
+    }
+
+    /// Replaces the times of the images which match the path or glob, or appends to them
+    ///
+    /// Fails if no image matches. Returns the full paths of the changed images.
+    pub fn set_times(
+        &mut self,
+        pattern: &str,
+        times: &TimeRefs,
+        append: bool,
+    ) -> anyhow::Result<BTreeSet<PathBuf>> {
+        let glob = pattern;
+        let pattern = ImagePattern::new(glob)?;
+        // the config file stays untouched if the pattern is wrong
+        let mut configured = self.config.images.keys().chain(
+            self.config
+                .monitor_settings
+                .values()
+                .flat_map(|settings| settings.images.keys()),
+        );
+        if !configured.any(|key| pattern.matches(&self.config.image_dir, key)) {
+            anyhow::bail!("no configured image matches {}", glob);
+        }
+        let mut changed = BTreeSet::new();
+        self.edit_config(|config| {
+            let image_dir = config.image_dir.clone();
+            let images = config.images.iter_mut().chain(
+                config
+                    .monitor_settings
+                    .values_mut()
+                    .flat_map(|settings| settings.images.iter_mut()),
+            );
+            for (key, entry) in images {
+                if !pattern.matches(&image_dir, key) {
+                    continue;
+                }
+                let old = entry.time_refs.clone();
+                if append {
+                    entry.time_refs.append(times);
+                } else {
+                    entry.time_refs = times.clone();
+                }
+                if entry.time_refs != old {
+                    changed.insert(image_dir.join(key));
+                }
+            }
+        })?;
+        Ok(changed)
+    }
+
+    /// Moves the entries of missing images to a file with the same name inside `image_dir`
+    /// or removes them if there is none
+    ///
+    /// All fixes are planned before the config file is replaced once. Nothing is written in a dry run.
+    pub fn fix_missing_images(&mut self, dry_run: bool) -> anyhow::Result<Vec<ImageFix>> {
+        let image_dir = self.config.image_dir.clone();
+        let mut by_name: BTreeMap<_, Vec<PathBuf>> = BTreeMap::new();
+        if image_dir.is_dir() {
+            for file in self.listings.files(&image_dir, true)? {
+                let (Some(name), Ok(key)) = (file.file_name(), file.strip_prefix(&image_dir))
+                else {
+                    continue;
+                };
+                by_name
+                    .entry(name.to_owned())
+                    .or_default()
+                    .push(key.to_owned());
+            }
+        }
+
+        let config = self.read_config_file()?;
+        let maps = std::iter::once((None, &config.images)).chain(
+            config
+                .monitor_settings
+                .iter()
+                .map(|(monitor, settings)| (Some(monitor), &settings.images)),
+        );
+        let mut fixes = Vec::new();
+        for (monitor, images) in maps {
+            let mut claimed = HashSet::new();
+            for key in images.keys() {
+                if image_dir.join(key).is_file() {
+                    continue;
+                }
+                let candidates: Vec<_> = key
+                    .file_name()
+                    .and_then(|name| by_name.get(name))
+                    .into_iter()
+                    .flatten()
+                    // an image which is configured already would lose its entry
+                    .filter(|candidate| {
+                        !images.contains_key(*candidate) && !claimed.contains(*candidate)
+                    })
+                    .cloned()
+                    .collect();
+                let action = match <[_; 1]>::try_from(candidates) {
+                    Ok([candidate]) => {
+                        claimed.insert(candidate.clone());
+                        FixAction::Move(candidate)
+                    }
+                    Err(candidates) if candidates.is_empty() => FixAction::Remove,
+                    Err(candidates) => FixAction::Ambiguous(candidates),
+                };
+                fixes.push(ImageFix {
+                    monitor: monitor.cloned(),
+                    key: key.clone(),
+                    action,
+                });
+            }
+        }
+
+        let changes = fixes
+            .iter()
+            .any(|fix| !matches!(fix.action, FixAction::Ambiguous(_)));
+        if dry_run || !changes {
+            return Ok(fixes);
+        }
+        self.edit_config(|config| {
+            for fix in &fixes {
+                let images = match &fix.monitor {
+                    Some(monitor) => match config.monitor_settings.get_mut(monitor) {
+                        Some(settings) => &mut settings.images,
+                        None => continue,
+                    },
+                    None => &mut config.images,
+                };
+                match &fix.action {
+                    FixAction::Move(key) => {
+                        if let Some(entry) = images.remove(&fix.key) {
+                            images.insert(key.clone(), entry);
+                        }
+                    }
+                    FixAction::Remove => {
+                        images.remove(&fix.key);
+                    }
+                    FixAction::Ambiguous(_) => {}
+                }
+            }
+        })?;
+        Ok(fixes)
+    }
+
+    /// The directory with the downloaded images of the remote source
+    pub fn remote_dir(&self, source: &RemoteSource) -> PathBuf {
+        let mut s = DefaultHasher::new();
+        source.url.hash(&mut s);
+        self.project_dirs
+            .cache_dir()
+            .join("remote")
+            .join(format!("{:016x}", s.finish()))
+    }
+
+    /// The directory with the tiles of spanned images
+    pub fn tile_dir(&self) -> PathBuf {
+        self.project_dirs.cache_dir().join("tiles")
+    }
+
+    /// The directory with the copies of images with effects
+    pub fn effects_dir(&self) -> PathBuf {
+        self.project_dirs.cache_dir().join("effects")
+    }
+
+    /// The directory with the scaled copies of images
+    pub fn scaled_dir(&self) -> PathBuf {
+        self.project_dirs.cache_dir().join("scaled")
+    }
+
+    /// The directory with the symlinks to the shown images
+    pub fn links_dir(&self) -> PathBuf {
+        self.project_dirs.cache_dir().to_path_buf()
+    }
+
+    /// Returns why updates of the monitor are deferred, apart from a preview
+    pub fn inhibition(&self, monitor: &str) -> Option<&'static str> {
+        if self.config.skip_when_off && self.off.contains(monitor) {
+            Some("powered off")
+        } else if self.config.inhibit_on_fullscreen && self.fullscreen.contains(monitor) {
+            Some("fullscreen window")
+        } else {
+            None
+        }
+    }
+
+    /// Returns whether all monitors show the same image
+    pub fn mirrors(&self) -> bool {
+        self.cache.mirror.unwrap_or(self.config.mirror)
+    }
+
+    /// Lists the images of the `image_sources` and the downloaded images of the
+    /// `remote_sources` again, if their directories were modified
+    pub fn refresh_sources(&mut self) {
+        let local = self.config.image_sources.iter().map(|source| {
+            let dir = self.config.image_dir.join(&source.dir);
+            (dir, source.recursive, source.times.clone())
+        });
+        // nothing was downloaded yet if the directory doesn't exist
+        let remote = self
+            .config
+            .remote_sources
+            .iter()
+            .map(|source| self.remote_dir(source))
+            .filter(|dir| dir.is_dir())
+            .map(|dir| (dir, false, vec![ValidTime::ALL]));
+        let dirs: Vec<_> = local.chain(remote).collect();
+
+        let mut images = BTreeMap::new();
+        for (dir, recursive, times) in dirs {
+            let found = match self.listings.files(&dir, recursive) {
+                Ok(found) => found,
+                Err(e) => {
+                    error!("can't list images of {}: {:#}", dir.display(), e);
+                    continue;
+                }
+            };
+            for image in found {
+                if !self.config.has_allowed_extension(&image) {
+                    continue;
+                }
+                // the first source which contains an image decides its times
+                images
+                    .entry(image)
+                    .or_insert_with(|| ImageEntry::new(times.clone()));
+            }
+        }
+        self.source_images = images;
+    }
+
+    /// Takes over the changes of the files in the `image_dir`
+    ///
+    /// The images of the `image_sources` are listed again and the deleted images are
+    /// forgotten in the cache.
+    pub fn images_changed(&mut self, added: &[PathBuf], removed: &[PathBuf]) -> anyhow::Result<()> {
+        info!(
+            "{} files were added to and {} files or directories removed from the image dir",
+            added.len(),
+            removed.len()
+        );
+        self.refresh_sources();
+        // removed directories contain removed images as well
+        let forgotten = self
+            .cache
+            .forget_images(|image| removed.iter().any(|removed| image.starts_with(removed)));
+        if forgotten > 0 {
+            debug!("forgot {} cache entries of removed images", forgotten);
+            self.save().context("while saving cache")?;
+        }
+        Ok(())
+    }
+
+    /// Returns all images which may be shown, including those of workspaces and static images
+    ///
+    /// The images of the `image_sources` are only included after [`State::refresh_sources`].
+    pub fn configured_images(&self) -> BTreeSet<PathBuf> {
+        let mut images = self.config.configured_images();
+        images.extend(
+            self.source_images
+                .keys()
+                .map(|image| self.config.image_dir.join(image)),
+        );
+        images
+    }
+
+    /// Forgets the cache entries of monitors which were not connected for `monitor_retention`
+    ///
+    /// Monitors in `keep_monitors` are never forgotten.
+    /// Returns the forgotten monitors, or the ones which would be forgotten if `dry_run` is set.
+    pub fn prune_monitors(&mut self, dry_run: bool) -> Vec<String> {
+        let stale = self
+            .cache
+            .stale_monitors(*self.config.monitor_retention, &self.config.keep_monitors);
+        if !dry_run {
+            for monitor in &stale {
+                info!(
+                    "forgetting monitor {}, as it was not connected for {}",
+                    monitor, self.config.monitor_retention
+                );
+                self.cache.forget_monitor(monitor);
+            }
+        }
+        stale
+    }
+
+    /// Forgets the cache entries of images which are neither configured nor exist anymore
+    ///
+    /// Returns the number of forgotten entries, or of those which would be forgotten if `dry_run`
+    /// is set.
+    pub fn prune_images(&mut self, dry_run: bool) -> usize {
+        let stale = stale_images(&self.config, &self.cache, Path::exists);
+        if stale.is_empty() {
+            return 0;
+        }
+        if dry_run {
+            return self.cache.clone().forget_stale_images(&stale);
+        }
+        let forgotten = self.cache.forget_stale_images(&stale);
+        info!(
+            "forgot {} cache entries of {} images which don't exist anymore",
+            forgotten,
+            stale.len()
+        );
+        forgotten
+    }
+
+    /// Forgets the display statistics of images which are not configured anymore
+    pub fn prune_display_stats(&mut self) {
+        let images = self.configured_images();
+        self.cache
+            .display_stats
+            .retain(|image, _stats| images.contains(image));
+    }
+
+    /// Returns whether the image is rated high enough for favorites-only mode
+    pub fn is_favorite(&self, image: &Path) -> bool {
+        self.cache
+            .ratings
+            .get(image)
+            .is_some_and(|rating| *rating >= self.config.favorites_threshold)
+    }
+
+    /// Writes the dominant colors of the current images to `colors.json` for other programs
+    pub fn save_colors(&self) -> anyhow::Result<()> {
+        let colors_file = self.project_dirs.cache_dir().join("colors.json");
+        let file =
+            std::fs::File::create(colors_file).context("while opening colors file for write")?;
+        serde_json::to_writer_pretty(file, &self.cache.monitor_colors)
+            .context("while writing colors file")?;
+
+        Ok(())
+    }
+}
+
+fn zero_duration() -> Duration {
+    std::time::Duration::ZERO.into()
+}
+
+fn default_startup_timeout() -> Duration {
+    std::time::Duration::from_secs(10).into()
+}
+
+/// Resolves the time presets of the images and sets the location of their times
+fn resolve_images(
+    images: &mut BTreeMap<PathBuf, ImageEntry>,
+    presets: &BTreeMap<String, Vec<ValidTime>>,
+    location: Option<Location>,
+) -> anyhow::Result<()> {
+    for (image, entry) in images {
+        entry.times.clear();
+        for time in &entry.time_refs {
+            match time {
+                TimeRef::Time(time) => entry.times.push(time.clone()),
+                TimeRef::Preset(name) => {
+                    let preset = presets.get(name).ok_or_else(|| {
+                        anyhow!("image {}: unknown time preset @{}", image.display(), name)
+                    })?;
+                    entry.times.extend(preset.iter().cloned());
+                }
+            }
+        }
+        for time in &mut entry.times {
+            time.set_location(location)
+                .map_err(|e| anyhow!("image {}: {}", image.display(), e))?;
+        }
+    }
+
+    Ok(())
+}
+
+/// A repair of the entry of a missing image, planned by `wallpaper check --fix`
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImageFix {
+    /// `None` for the global `images`
+    pub monitor: Option<String>,
+    pub key: PathBuf,
+    pub action: FixAction,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FixAction {
+    /// A file with the same name was found at the new key
+    Move(PathBuf),
+    Remove,
+    /// Several files with the same name were found, so the entry is kept
+    Ambiguous(Vec<PathBuf>),
+}
+
+/// A path or glob of configured images, in which `*` matches any characters and `?` one
+struct ImagePattern(Regex);
+
+impl ImagePattern {
+    fn new(glob: &str) -> anyhow::Result<Self> {
+        let mut pattern = String::from("^");
+        for (i, part) in glob.split('*').enumerate() {
+            if i > 0 {
+                pattern.push_str(".*");
+            }
+            let part: Vec<_> = part.split('?').map(regex::escape).collect();
+            pattern.push_str(&part.join("."));
+        }
+        pattern.push('$');
+        Regex::new(&pattern)
+            .map(Self)
+            .with_context(|| format!("invalid pattern {}", glob))
+    }
+
+    /// Matches the path in the config and the full and canonical path of the image
+    fn matches(&self, image_dir: &Path, key: &Path) -> bool {
+        let image = image_dir.join(key);
+        self.0.is_match(&key.to_string_lossy())
+            || self.0.is_match(&image.to_string_lossy())
+            || image
+                .canonicalize()
+                .is_ok_and(|image| self.0.is_match(&image.to_string_lossy()))
+    }
+}
+
+fn default_pending_expiry() -> Duration {
+    std::time::Duration::from_secs(60 * 60).into()
+}
+
+fn default_monitor_retention() -> Duration {
+    std::time::Duration::from_secs(60 * 60 * 24 * 30).into()
+}
+
+fn default_select_max_depth() -> usize {
+    32
+}
+
+fn default_name_rules() -> Vec<NameRule> {
+    [
+        ("hours", r"[_-](\d{1,2})-(\d{1,2})$", "$1-$2"),
+        ("night", r"(?i)(^|[_-])night([_-]|$)", "20-6"),
+        ("day", r"(?i)(^|[_-])day([_-]|$)", "6-20"),
+    ]
+    .into_iter()
+    .map(|(name, pattern, times)| NameRule {
+        name: name.to_owned(),
+        pattern: pattern.to_owned(),
+        times: times.to_owned(),
+    })
+    .collect()
+}
+
+fn default_history_size() -> usize {
+    5
+}
+
+fn default_history_limit() -> usize {
+    100
+}
+
+fn default_allowed_extensions() -> Vec<String> {
+    ["jpg", "jpeg", "png", "webp", "gif", "bmp"]
+        .into_iter()
+        .map(String::from)
+        .collect()
+}
+
+fn default_favorites_threshold() -> i32 {
+    1
+}
+
+fn default_prescale_cache_mb() -> u64 {
+    512
+}
+
+fn default_selector_timeout() -> Duration {
+    std::time::Duration::from_secs(2).into()
+}
+
+fn default_switch_debounce() -> Duration {
+    std::time::Duration::from_millis(500).into()
+}
+
+fn default_current_links() -> bool {
+    true
+}
+
+fn default_hook_timeout() -> Duration {
+    std::time::Duration::from_secs(30).into()
+}
+
+fn default_pre_change_timeout() -> Duration {
+    std::time::Duration::from_secs(1).into()
+}
+
+fn ser_duration<S>(val: &Duration, ser: S) -> Result<S::Ok, S::Error>
+where
+    S: serde::Serializer,
+{
+    let v = humantime::format_duration(**val).to_string();
+    ser.serialize_str(&v)
+}
+
+fn deser_duration<'de, D>(deser: D) -> Result<Duration, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let s = String::deserialize(deser)?;
+    let duration = s.parse();
+
+    duration.map_err(|e| D::Error::custom(format!("can't parse duration: {}", e)))
 }
 
-fn ser_duration<S>(val: &Duration, ser: S) -> Result<S::Ok, S::Error>
+fn ser_opt_duration<S>(val: &Option<Duration>, ser: S) -> Result<S::Ok, S::Error>
 where
     S: serde::Serializer,
 {
-    let v = humantime::format_duration(**val).to_string();
-    ser.serialize_str(&v)
+    match val {
+        Some(val) => ser_duration(val, ser),
+        None => ser.serialize_none(),
+    }
 }
 
-fn deser_duration<'de, D>(deser: D) -> Result<Duration, D::Error>
+fn deser_opt_duration<'de, D>(deser: D) -> Result<Option<Duration>, D::Error>
 where
     D: serde::Deserializer<'de>,
 {
-    let s = String::deserialize(deser)?;
-    let duration = s.parse();
-
-    duration.map_err(|e| D::Error::custom(format!("can't parse duration: {}", e)))
+    deser_duration(deser).map(Some)
 }
 
 fn ser_timestamp<S>(val: &Timestamp, ser: S) -> Result<S::Ok, S::Error>
@@ -304,6 +3096,16 @@ where
     ser.serialize_str(&v)
 }
 
+fn ser_opt_timestamp<S>(val: &Option<Timestamp>, ser: S) -> Result<S::Ok, S::Error>
+where
+    S: serde::Serializer,
+{
+    match val {
+        Some(val) => ser_timestamp(val, ser),
+        None => ser.serialize_none(),
+    }
+}
+
 fn deser_timestamp<'de, D>(deser: D) -> Result<Timestamp, D::Error>
 where
     D: serde::Deserializer<'de>,
@@ -314,78 +3116,590 @@ where
     timestamp.map_err(|e| D::Error::custom(format!("can't parse timestamp: {}", e)))
 }
 
-fn deser_images<'de, D>(deser: D) -> Result<BTreeMap<String, Vec<ValidTime>>, D::Error>
+fn ser_timestamps<K, S>(val: &BTreeMap<K, Timestamp>, ser: S) -> Result<S::Ok, S::Error>
 where
+    K: Serialize,
+    S: serde::Serializer,
+{
+    ser.collect_map(
+        val.iter()
+            .map(|(k, v)| (k, humantime::format_rfc3339(**v).to_string())),
+    )
+}
+
+fn deser_timestamps<'de, K, D>(deser: D) -> Result<BTreeMap<K, Timestamp>, D::Error>
+where
+    K: Deserialize<'de> + Ord,
     D: serde::Deserializer<'de>,
 {
-    #[derive(Deserialize, Debug)]
+    let s: BTreeMap<K, String> = BTreeMap::deserialize(deser)?;
+    s.into_iter()
+        .map(|(k, v)| {
+            let timestamp = v
+                .parse()
+                .map_err(|e| D::Error::custom(format!("can't parse timestamp: {}", e)))?;
+            Ok((k, timestamp))
+        })
+        .collect()
+}
+
+/// A set of days of the week
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Weekdays(u8);
+
+impl Weekdays {
+    pub fn contains(&self, day: Weekday) -> bool {
+        self.0 & (1 << day.num_days_from_monday()) != 0
+    }
+
+    pub fn insert(&mut self, day: Weekday) {
+        self.0 |= 1 << day.num_days_from_monday();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+}
+
+impl std::fmt::Display for Weekdays {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let days: Vec<_> = std::iter::successors(Some(Weekday::Mon), |day| {
+            Some(day.succ()).filter(|day| *day != Weekday::Mon)
+        })
+        .collect();
+        let mut parts = Vec::new();
+        let mut i = 0;
+        while i < days.len() {
+            if !self.contains(days[i]) {
+                i += 1;
+                continue;
+            }
+            let start = i;
+            while i + 1 < days.len() && self.contains(days[i + 1]) {
+                i += 1;
+            }
+            match i - start {
+                0 => parts.push(format!("{}", days[start])),
+                1 => parts.push(format!("{},{}", days[start], days[i])),
+                _ => parts.push(format!("{}-{}", days[start], days[i])),
+            }
+            i += 1;
+        }
+        write!(f, "{}", parts.join(","))
+    }
+}
+
+impl std::str::FromStr for Weekdays {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parse_day = |day: &str| {
+            day.trim()
+                .parse::<Weekday>()
+                .map_err(|_| format!("invalid weekday {}", day))
+        };
+
+        let mut weekdays = Self::default();
+        for part in s.split(',').filter(|part| !part.trim().is_empty()) {
+            if let Some((start, end)) = part.split_once('-') {
+                let (start, end) = (parse_day(start)?, parse_day(end)?);
+                let mut day = start;
+                weekdays.insert(day);
+                while day != end {
+                    day = day.succ();
+                    weekdays.insert(day);
+                }
+            } else {
+                weekdays.insert(parse_day(part)?);
+            }
+        }
+
+        Ok(weekdays)
+    }
+}
+
+/// Marks the keys of images which are not valid utf-8
+const FILE_URL_PREFIX: &str = "file://";
+
+/// Writes the path as string, or as `file://` url with percent-encoded bytes if it is not utf-8
+fn path_to_key(path: &Path) -> Cow<'_, str> {
+    use std::os::unix::ffi::OsStrExt;
+
+    if let Some(path) = path.to_str() {
+        return Cow::Borrowed(path);
+    }
+    let mut key = String::from(FILE_URL_PREFIX);
+    for byte in path.as_os_str().as_bytes() {
+        match byte {
+            b'%' | 0x80.. | ..0x20 => key.push_str(&format!("%{:02X}", byte)),
+            byte => key.push(char::from(*byte)),
+        }
+    }
+    Cow::Owned(key)
+}
+
+/// Reads a path written by [`path_to_key`]
+fn key_to_path(key: &str) -> Result<PathBuf, String> {
+    use std::{ffi::OsString, os::unix::ffi::OsStringExt};
+
+    let Some(encoded) = key.strip_prefix(FILE_URL_PREFIX) else {
+        return Ok(PathBuf::from(key));
+    };
+    let mut bytes = Vec::with_capacity(encoded.len());
+    let mut rest = encoded.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        if byte == b'%' {
+            let hex = tail
+                .get(..2)
+                .and_then(|hex| std::str::from_utf8(hex).ok())
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+                .ok_or_else(|| format!("invalid percent-encoding in {}", key))?;
+            bytes.push(hex);
+            rest = &tail[2..];
+        } else {
+            bytes.push(byte);
+            rest = tail;
+        }
+    }
+    Ok(PathBuf::from(OsString::from_vec(bytes)))
+}
+
+fn ser_images<S>(val: &BTreeMap<PathBuf, ImageEntry>, ser: S) -> Result<S::Ok, S::Error>
+where
+    S: serde::Serializer,
+{
+    #[derive(Serialize)]
     #[serde(untagged)]
-    enum OneOrMany {
-        One(ValidTime),
-        Vec(Vec<ValidTime>),
+    enum Repr<'a> {
+        Times(&'a TimeRefs),
+        Rich(&'a ImageEntry),
     }
-    let s: BTreeMap<String, OneOrMany> = BTreeMap::deserialize(deser)?;
-    Ok(s.into_iter()
+
+    ser.collect_map(val.iter().map(|(k, v)| {
+        let v = if v.is_simple() {
+            Repr::Times(&v.time_refs)
+        } else {
+            Repr::Rich(v)
+        };
+        (path_to_key(k), v)
+    }))
+}
+
+fn deser_images<'de, D>(deser: D) -> Result<BTreeMap<PathBuf, ImageEntry>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    // don't use an untagged enum here, so that the errors of the entries don't get lost
+    let s: BTreeMap<String, serde_json::Value> = BTreeMap::deserialize(deser)?;
+    s.into_iter()
         .map(|(k, v)| {
-            (
-                k,
-                match v {
-                    OneOrMany::Vec(v) => v,
-                    OneOrMany::One(v) => vec![v],
-                },
-            )
+            let entry = if v.is_object() {
+                ImageEntry::deserialize(v)
+            } else {
+                TimeRefs::deserialize(v).map(|time_refs| ImageEntry {
+                    time_refs,
+                    ..ImageEntry::new(Vec::new())
+                })
+            };
+            let path = key_to_path(&k).map_err(D::Error::custom)?;
+            entry
+                .map(|entry| (path, entry))
+                .map_err(|e| D::Error::custom(format!("image {}: {}", k, e)))
         })
-        .collect())
+        .collect()
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+/// Geographic location used to calculate sunrise and sunset
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub struct Location {
+    pub latitude: f64,
+    pub longitude: f64,
+}
+
+impl Eq for Location {}
+
+impl Hash for Location {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.latitude.to_bits().hash(state);
+        self.longitude.to_bits().hash(state);
+    }
+}
+
+impl Location {
+    /// Returns the local times of sunrise and sunset on the given date
+    pub fn sun_times(&self, date: NaiveDate) -> Option<(NaiveTime, NaiveTime)> {
+        let (sunrise, sunset) = crate::sun::sun_times(date, self.latitude, self.longitude)?;
+        Some((
+            sunrise.with_timezone(&Local).time(),
+            sunset.with_timezone(&Local).time(),
+        ))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum SunEvent {
+    Sunrise,
+    Sunset,
+}
+
+/// A time of day, either fixed or relative to the sun
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum TimeSpec {
+    Fixed(NaiveTime),
+    Sun {
+        event: SunEvent,
+        /// Offset in seconds from the event
+        offset: i64,
+        /// Gets set after loading the config
+        location: Option<Location>,
+    },
+}
+
+impl TimeSpec {
+    /// Returns the time of day on the given date
+    fn resolve(&self, date: NaiveDate) -> NaiveTime {
+        match self {
+            Self::Fixed(time) => *time,
+            Self::Sun {
+                event,
+                offset,
+                location,
+            } => {
+                let time = location
+                    .and_then(|location| location.sun_times(date))
+                    .map(|(sunrise, sunset)| match event {
+                        SunEvent::Sunrise => sunrise,
+                        SunEvent::Sunset => sunset,
+                    })
+                    .unwrap_or_else(|| {
+                        // the sun doesn't rise or set today, so use some sensible default
+                        trace!("no {:?} on {}", event, date);
+                        match event {
+                            SunEvent::Sunrise => NaiveTime::from_hms_opt(6, 0, 0),
+                            SunEvent::Sunset => NaiveTime::from_hms_opt(18, 0, 0),
+                        }
+                        .expect("valid time")
+                    });
+                time + chrono::Duration::seconds(*offset)
+            }
+        }
+    }
+
+    fn add_hour(self) -> Self {
+        match self {
+            Self::Fixed(time) => {
+                let time = time + chrono::Duration::hours(1);
+                // `23` should end at the end of the day instead of wrapping around midnight
+                if time == ValidTime::MIN {
+                    Self::Fixed(ValidTime::MAX)
+                } else {
+                    Self::Fixed(time)
+                }
+            }
+            Self::Sun {
+                event,
+                offset,
+                location,
+            } => Self::Sun {
+                event,
+                offset: offset + 60 * 60,
+                location,
+            },
+        }
+    }
+
+    fn parse(s: &str, what: &str) -> Result<Self, String> {
+        let s = s.trim();
+        for (keyword, event) in [("sunrise", SunEvent::Sunrise), ("sunset", SunEvent::Sunset)] {
+            let Some(offset) = s.strip_prefix(keyword) else {
+                continue;
+            };
+            let offset = offset.trim();
+            let offset = if offset.is_empty() {
+                0
+            } else {
+                let (sign, duration) = if let Some(duration) = offset.strip_prefix('+') {
+                    (1, duration)
+                } else if let Some(duration) = offset.strip_prefix('-') {
+                    (-1, duration)
+                } else {
+                    return Err(format!("invalid offset for {} in {}", what, s));
+                };
+                let duration = humantime::parse_duration(duration.trim())
+                    .map_err(|e| format!("invalid offset for {} in {}: {}", what, s, e))?;
+                sign * i64::try_from(duration.as_secs())
+                    .map_err(|_| format!("offset for {} in {} is too large", what, s))?
+            };
+            return Ok(Self::Sun {
+                event,
+                offset,
+                location: None,
+            });
+        }
+
+        if let Ok(t) = NaiveTime::parse_from_str(s, "%H:%M:%S") {
+            Ok(Self::Fixed(t))
+        } else if let Ok(t) = NaiveTime::parse_from_str(s, "%H:%M") {
+            Ok(Self::Fixed(t))
+        } else if let Ok(v) = s.parse() {
+            if v == 24 {
+                Ok(Self::Fixed(ValidTime::MAX))
+            } else {
+                NaiveTime::from_hms_opt(v, 0, 0)
+                    .map(Self::Fixed)
+                    .ok_or_else(|| format!("invalid hour for {} in {}", what, s))
+            }
+        } else {
+            Err(format!("invalid time for {} in {}", what, s))
+        }
+    }
+}
+
+impl std::fmt::Display for TimeSpec {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Fixed(time) if *time == ValidTime::MAX => write!(f, "24"),
+            Self::Fixed(time) => {
+                if time.second() != 0 {
+                    write!(f, "{}", time.format("%H:%M:%S"))
+                } else if time.minute() != 0 {
+                    write!(f, "{}", time.format("%H:%M"))
+                } else {
+                    write!(f, "{}", time.format("%H"))
+                }
+            }
+            Self::Sun { event, offset, .. } => {
+                match event {
+                    SunEvent::Sunrise => write!(f, "sunrise")?,
+                    SunEvent::Sunset => write!(f, "sunset")?,
+                }
+                if *offset != 0 {
+                    let sign = if *offset < 0 { '-' } else { '+' };
+                    let duration = std::time::Duration::from_secs(offset.unsigned_abs());
+                    write!(f, "{}{}", sign, humantime::format_duration(duration))?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// A range of times of day, optionally only on some weekdays
+///
+/// Two ranges are equal if they match the same times, regardless of how they were written.
+#[derive(Debug, Clone)]
 pub struct ValidTime {
-    start: NaiveTime,
-    end: NaiveTime,
+    start: TimeSpec,
+    end: TimeSpec,
+    /// Only match on these days, all days if `None`
+    weekdays: Option<Weekdays>,
+    /// The string this range was parsed from, used to write it back as the user wrote it
+    source: Option<String>,
+}
+
+impl PartialEq for ValidTime {
+    fn eq(&self, other: &Self) -> bool {
+        (self.start, self.end, self.weekdays) == (other.start, other.end, other.weekdays)
+    }
+}
+
+impl Eq for ValidTime {}
+
+impl Hash for ValidTime {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        (self.start, self.end, self.weekdays).hash(state);
+    }
 }
 
 impl ValidTime {
-    pub fn matches(&self, time: &NaiveTime) -> bool {
-        (self.start..=self.end).contains(time)
+    /// Returns whether the time is inside this range
+    ///
+    /// The start is included in the range, but the end is not,
+    /// so adjacent ranges like `8` and `9` don't overlap.
+    /// A range whose start is after its end wraps around midnight.
+    /// The part after midnight then belongs to the day on which the range started.
+    pub fn matches(&self, time: &NaiveDateTime) -> bool {
+        let day_matches = |day| self.weekdays.is_none_or(|weekdays| weekdays.contains(day));
+        let (start, end) = self.resolve(time.date());
+        let (day, time) = (time.weekday(), time.time());
+        if start <= end {
+            (start..end).contains(&time) && day_matches(day)
+        } else {
+            (time >= start && day_matches(day)) || (time < end && day_matches(day.pred()))
+        }
+    }
+
+    /// Returns the start and end of the range on the given date
+    pub fn resolve(&self, date: NaiveDate) -> (NaiveTime, NaiveTime) {
+        (self.start.resolve(date), self.end.resolve(date))
+    }
+
+    /// Returns the parts of the given day which are covered by this range
+    ///
+    /// The parts are given as half open ranges of seconds since midnight.
+    pub fn covered_seconds(&self, date: NaiveDate) -> Vec<(u32, u32)> {
+        const DAY: u32 = 24 * 60 * 60;
+        let day_matches =
+            |day: Weekday| self.weekdays.is_none_or(|weekdays| weekdays.contains(day));
+        let to_seconds = |time: NaiveTime| {
+            if time == Self::MAX {
+                DAY
+            } else {
+                time.num_seconds_from_midnight()
+            }
+        };
+        let (start, end) = self.resolve(date);
+        let (start, end) = (to_seconds(start), to_seconds(end));
+        let day = date.weekday();
+
+        let mut res = Vec::new();
+        if start <= end {
+            if day_matches(day) {
+                res.push((start, end));
+            }
+        } else {
+            if day_matches(day.pred()) {
+                res.push((0, end));
+            }
+            if day_matches(day) {
+                res.push((start, DAY));
+            }
+        }
+        res
+    }
+
+    /// Returns whether this range depends on sunrise or sunset
+    pub fn uses_sun(&self) -> bool {
+        [self.start, self.end]
+            .iter()
+            .any(|time| matches!(time, TimeSpec::Sun { .. }))
+    }
+
+    /// Sets the location used to calculate sunrise and sunset
+    pub fn set_location(&mut self, location: Option<Location>) -> Result<(), String> {
+        if location.is_none() && self.uses_sun() {
+            return Err(format!(
+                "{} uses sunrise or sunset, but no `location` is configured",
+                self
+            ));
+        }
+        for time in [&mut self.start, &mut self.end] {
+            if let TimeSpec::Sun {
+                location: time_location,
+                ..
+            } = time
+            {
+                *time_location = location;
+            }
+        }
+
+        Ok(())
     }
 
     pub fn check(&self) -> Result<(), String> {
-        if self.start > self.end {
+        // every other combination of start and end is valid, as ranges may wrap around midnight
+        if self.weekdays.is_some_and(|weekdays| weekdays.is_empty()) {
+            Err(format!("invalid time: {} never matches any weekday", self))
+        } else if matches!((self.start, self.end), (TimeSpec::Fixed(start), TimeSpec::Fixed(end)) if start == end)
+        {
             Err(format!(
-                "invalid time: {} must be before {}",
-                Self::to_s(&self.start),
-                Self::to_s(&self.end)
+                "invalid time: {} is empty, as the end is not included",
+                self
             ))
         } else {
             Ok(())
         }
     }
 
-    fn to_s(date: &NaiveTime) -> impl std::fmt::Display {
-        if date.second() != 0 {
-            date.format("%H:%M:%S")
-        } else if date.minute() != 0 {
-            date.format("%H:%M")
-        } else {
-            date.format("%H")
-        }
+    /// Returns the times of day at which `matches` changes its result on the given date
+    pub fn boundaries(&self, date: NaiveDate) -> [NaiveTime; 2] {
+        let (start, end) = self.resolve(date);
+        let end = if end == Self::MAX { Self::MIN } else { end };
+        [start, end]
     }
 
     const MIN: NaiveTime = NaiveTime::MIN;
+    /// The end of the day, written as `24`
+    ///
+    /// This is a leap second, so it is after every regular time of the day.
     const MAX: NaiveTime = match NaiveTime::from_hms_nano_opt(23, 59, 59, 1_999_999_999) {
         Some(v) => v,
         None => panic!("max time is valid"),
     };
 
     pub const ALL: Self = Self {
-        start: Self::MIN,
-        end: Self::MAX,
+        start: TimeSpec::Fixed(Self::MIN),
+        end: TimeSpec::Fixed(Self::MAX),
+        weekdays: None,
+        source: None,
     };
 }
 
 impl std::fmt::Display for ValidTime {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}-{}", Self::to_s(&self.start), Self::to_s(&self.end))
+        if let Some(source) = &self.source {
+            return write!(f, "{}", source);
+        }
+        if let Some(weekdays) = self.weekdays {
+            write!(f, "{} ", weekdays)?;
+        }
+        write!(f, "{}-{}", self.start, self.end)
+    }
+}
+
+impl std::str::FromStr for ValidTime {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let source = Some(s.to_owned());
+
+        // an optional list of weekdays can precede the time
+        let (weekdays, s) = match s.split_once(char::is_whitespace) {
+            Some((weekdays, rest)) => match weekdays.parse::<Weekdays>() {
+                Ok(weekdays) => (Some(weekdays), rest.trim()),
+                Err(_) => (None, s),
+            },
+            None => (None, s),
+        };
+
+        let (start, end) = if s == "*" {
+            (TimeSpec::Fixed(Self::MIN), TimeSpec::Fixed(Self::MAX))
+        } else if s.contains('-') {
+            // the offsets of sunrise and sunset may contain a dash too,
+            // so try every dash as separator
+            let mut splits = s
+                .match_indices('-')
+                .map(|(i, _)| (&s[..i], &s[i + 1..]))
+                .map(|(start_s, end_s)| -> Result<_, String> {
+                    Ok((
+                        TimeSpec::parse(start_s, "start")?,
+                        TimeSpec::parse(end_s, "end")?,
+                    ))
+                });
+            let first = splits.next().expect("s contains a dash");
+            match first {
+                Ok(v) => v,
+                Err(e) => match splits.find_map(Result::ok) {
+                    Some(v) => v,
+                    // a single time with a negative offset, like `sunrise-30m`
+                    None => {
+                        let v = TimeSpec::parse(s, "single time").map_err(|_| e)?;
+                        (v, v.add_hour())
+                    }
+                },
+            }
+        } else {
+            let v = TimeSpec::parse(s, "single time")?;
+            (v, v.add_hour())
+        };
+
+        Ok(Self {
+            start,
+            end,
+            weekdays,
+            source,
+        })
     }
 }
 
@@ -404,46 +3718,392 @@ impl<'de> serde::Deserialize<'de> for ValidTime {
     where
         D: serde::Deserializer<'de>,
     {
-        let s = String::deserialize(deserializer)?.trim().to_owned();
-
-        fn from_s<'de, D: serde::Deserializer<'de>>(
-            s: &str,
-            what: &str,
-        ) -> Result<NaiveTime, D::Error> {
-            if let Ok(t) = NaiveTime::parse_from_str(s, "%H:%M:%S") {
-                Ok(t)
-            } else if let Ok(t) = NaiveTime::parse_from_str(s, "%H:%M") {
-                Ok(t)
-            } else if let Ok(v) = s.parse() {
-                if v == 24 {
-                    Ok(ValidTime::MAX)
-                } else {
-                    NaiveTime::from_hms_opt(v, 0, 0).ok_or_else(|| {
-                        D::Error::custom(format!("invalid hour for {} in {}", what, s))
-                    })
-                }
-            } else {
-                Err(D::Error::custom(format!(
-                    "invalid time for {} in {}",
-                    what, s
-                )))
-            }
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(D::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{seq::SliceRandom, Rng};
+
+    use super::*;
+
+    /// The caches written by every older version
+    const CACHE_FIXTURES: [&str; 7] = [
+        include_str!("../tests/fixtures/cache/v0.json"),
+        include_str!("../tests/fixtures/cache/v1.json"),
+        include_str!("../tests/fixtures/cache/v2.json"),
+        include_str!("../tests/fixtures/cache/v3.json"),
+        include_str!("../tests/fixtures/cache/v4.json"),
+        include_str!("../tests/fixtures/cache/v5.json"),
+        include_str!("../tests/fixtures/cache/v6.json"),
+    ];
+
+    fn migrated(version: usize) -> Cache {
+        let cache: Cache = serde_json::from_str(CACHE_FIXTURES[version]).unwrap();
+        assert_eq!(cache.version, version);
+        cache.migrate().unwrap()
+    }
+
+    fn history_images(cache: &Cache, monitor: &str) -> Vec<PathBuf> {
+        cache.history[monitor]
+            .iter()
+            .map(|entry| entry.image.clone())
+            .collect()
+    }
+
+    #[test]
+    fn migrates_every_version() {
+        let last_update: Timestamp = "2024-05-01T10:00:00Z".parse().unwrap();
+        for version in 0..CACHE_FIXTURES.len() {
+            let cache = migrated(version);
+            assert_eq!(cache.version, CACHE_VERSION, "version {}", version);
+            assert_eq!(
+                cache.last_images,
+                BTreeMap::from([
+                    (String::from("DP-1"), PathBuf::from("/images/a.png")),
+                    (String::from("HDMI-A-1"), PathBuf::from("/images/b.png")),
+                ]),
+                "version {}",
+                version
+            );
+            assert_eq!(cache.last_transitions["HDMI-A-1"], "wipe");
+            assert_eq!(cache.last_update, last_update);
+            assert_eq!(
+                history_images(&cache, "HDMI-A-1"),
+                [PathBuf::from("/images/b.png")],
+                "version {}",
+                version
+            );
+            // version 6 seeds the last update of every monitor
+            assert_eq!(
+                cache.last_updates,
+                BTreeMap::from([
+                    (String::from("DP-1"), last_update.clone()),
+                    (String::from("HDMI-A-1"), last_update.clone()),
+                ]),
+                "version {}",
+                version
+            );
         }
+    }
 
-        let (start, end) = if s == "*" {
-            (Self::MIN, Self::MAX)
-        } else if s.contains('-') {
-            let (start_s, end_s) = s.split_once('-').unwrap();
+    #[test]
+    fn keeps_the_history_of_versions_with_history() {
+        for version in 3..CACHE_FIXTURES.len() {
+            let cache = migrated(version);
+            assert_eq!(
+                history_images(&cache, "DP-1"),
+                [
+                    PathBuf::from("/images/a.png"),
+                    PathBuf::from("/images/c.png")
+                ],
+                "version {}",
+                version
+            );
+        }
+        assert_eq!(
+            migrated(4).history["DP-1"][0].transition.as_deref(),
+            Some("fade")
+        );
+        assert_eq!(
+            migrated(5).display_stats[Path::new("/images/a.png")].count,
+            3
+        );
+    }
+
+    #[test]
+    fn seeds_the_history_of_version_0() {
+        let cache = migrated(0);
+        assert_eq!(
+            history_images(&cache, "DP-1"),
+            [PathBuf::from("/images/a.png")]
+        );
+        assert_eq!(
+            history_images(&cache, "HDMI-A-1"),
+            [PathBuf::from("/images/b.png")]
+        );
+        assert_eq!(cache.history["DP-1"][0].shown_at, None);
+    }
 
-            let start = from_s::<D>(start_s, "start")?;
-            let end = from_s::<D>(end_s, "end")?;
+    #[test]
+    fn keeps_the_fields_added_later() {
+        assert_eq!(migrated(1).transition_indices["DP-1"], 2);
+        assert!(migrated(2)
+            .recently_shown
+            .contains_key(Path::new("/images/a.png")));
+        assert_eq!(migrated(6).monitors_seen.len(), 2);
+    }
 
-            (start, end)
-        } else {
-            let v = from_s::<D>(&s, "single time")?;
-            (v, v + chrono::Duration::hours(1))
+    #[test]
+    fn finds_stale_images() {
+        let config = Config {
+            image_dir: PathBuf::from("/images"),
+            images: BTreeMap::from([(PathBuf::from("a.png"), ImageEntry::new(Vec::new()))]),
+            ..Config::default()
+        };
+        let mut cache = Cache::default();
+        let shown: Timestamp = std::time::UNIX_EPOCH.into();
+        // neither configured nor existing, but shown
+        cache
+            .last_images
+            .insert(String::from("DP-1"), PathBuf::from("/gone/shown.png"));
+        cache.history.insert(
+            String::from("DP-1"),
+            [
+                "/gone/shown.png",
+                "/images/a.png",
+                "/disk/b.png",
+                "/gone/x.png",
+            ]
+            .into_iter()
+            .map(|image| HistoryEntry {
+                image: PathBuf::from(image),
+                transition: None,
+                shown_at: None,
+            })
+            .collect(),
+        );
+        cache.ratings.insert(PathBuf::from("/gone/x.png"), 2);
+        cache.ratings.insert(PathBuf::from("/images/a.png"), 2);
+        cache.display_stats.insert(
+            PathBuf::from("/gone/y.png"),
+            DisplayStats {
+                count: 1,
+                last_shown: shown.clone(),
+            },
+        );
+        cache
+            .recently_shown
+            .insert(PathBuf::from("/disk/b.png"), shown);
+
+        let exists = |image: &Path| image.starts_with("/disk");
+        let stale = stale_images(&config, &cache, exists);
+        assert_eq!(
+            stale,
+            BTreeSet::from([PathBuf::from("/gone/x.png"), PathBuf::from("/gone/y.png")])
+        );
+
+        assert_eq!(cache.forget_stale_images(&stale), 3);
+        assert!(stale_images(&config, &cache, exists).is_empty());
+        assert_eq!(cache.history["DP-1"].len(), 3);
+        assert_eq!(cache.last_images.len(), 1);
+    }
+
+    #[test]
+    fn rejects_newer_versions() {
+        let cache = Cache {
+            version: CACHE_VERSION + 1,
+            ..Cache::default()
+        };
+        assert!(cache.migrate().is_err());
+        assert!(!Cache::is_known_version(CACHE_VERSION + 1));
+        assert!(Cache::is_known_version(0));
+    }
+
+    /// The time on Wednesday, 2024-05-01
+    fn at(hour: u32, minute: u32, second: u32, milli: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2024, 5, 1)
+            .unwrap()
+            .and_hms_milli_opt(hour, minute, second, milli)
+            .unwrap()
+    }
+
+    fn time(s: &str) -> ValidTime {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn overnight_ranges_wrap_around_midnight() {
+        let night = time("22-6");
+        assert!(night.check().is_ok());
+        assert!(!night.matches(&at(21, 59, 59, 0)));
+        assert!(night.matches(&at(22, 0, 0, 0)));
+        assert!(night.matches(&at(23, 59, 59, 0)));
+        assert!(night.matches(&at(0, 0, 0, 0)));
+        assert!(night.matches(&at(5, 59, 59, 0)));
+        assert!(!night.matches(&at(6, 0, 0, 0)));
+        assert!(!night.matches(&at(12, 0, 0, 0)));
+        assert_eq!(night.to_string(), "22-6");
+    }
+
+    #[test]
+    fn overnight_ranges_belong_to_the_day_they_start() {
+        // 2024-05-01 is a Wednesday, so the morning belongs to the night of Tuesday
+        let night = time("Tue 22-6");
+        assert!(night.matches(&at(0, 0, 0, 0)));
+        assert!(night.matches(&at(5, 59, 59, 0)));
+        assert!(!night.matches(&at(23, 59, 59, 0)));
+        let night = time("Wed 22-6");
+        assert!(!night.matches(&at(0, 0, 0, 0)));
+        assert!(night.matches(&at(23, 59, 59, 0)));
+    }
+
+    #[test]
+    fn adjacent_hours_do_not_overlap() {
+        let (eight, nine) = (time("8"), time("9"));
+        assert!(eight.matches(&at(8, 59, 59, 999)));
+        assert!(!eight.matches(&at(9, 0, 0, 0)));
+        assert!(nine.matches(&at(9, 0, 0, 0)));
+        assert!(!nine.matches(&at(8, 59, 59, 999)));
+    }
+
+    #[test]
+    fn ranges_until_24_include_the_end_of_the_day() {
+        for s in ["23", "22-24", "*", "0-24"] {
+            let time = time(s);
+            assert!(time.check().is_ok(), "{}", s);
+            assert!(time.matches(&at(23, 59, 59, 999)), "{}", s);
+        }
+        assert!(ValidTime::ALL.matches(&at(0, 0, 0, 0)));
+        assert!(ValidTime::ALL.matches(&at(23, 59, 59, 999)));
+        assert!(!time("22-23").matches(&at(23, 0, 0, 0)));
+        assert!(time("9-9").check().is_err());
+    }
+
+    #[test]
+    fn single_times_may_have_negative_offsets() {
+        let location = Some(Location {
+            latitude: 52.5,
+            longitude: 13.4,
+        });
+        let mut single = time("sunrise-30m");
+        let mut range = time("sunrise-30m-sunrise+30m");
+        single.set_location(location).unwrap();
+        range.set_location(location).unwrap();
+        let date = at(0, 0, 0, 0).date();
+        assert_eq!(single.resolve(date), range.resolve(date));
+        assert_eq!(single, range);
+        assert_eq!(single.to_string(), "sunrise-30m");
+
+        // ranges are still preferred
+        assert_eq!(time("sunset-2h-23"), time("sunset-2h-23:00"));
+        assert_eq!(
+            time("Mon sunset-1h 30m"),
+            time("Mon sunset-1h 30m-sunset-30m")
+        );
+        assert!("sunrise-30".parse::<ValidTime>().is_err());
+        assert!("sunrise-soon".parse::<ValidTime>().is_err());
+    }
+
+    /// A random time as it may be written in the config
+    fn random_time_spec(rng: &mut impl Rng) -> String {
+        match rng.gen_range(0..6) {
+            0 => rng.gen_range(0..24).to_string(),
+            1 => format!("{:02}:{:02}", rng.gen_range(0..24), rng.gen_range(0..60)),
+            2 => format!(
+                "{}:{:02}:{:02}",
+                rng.gen_range(0..24),
+                rng.gen_range(0..60),
+                rng.gen_range(0..60)
+            ),
+            3 => String::from(if rng.gen() { "sunrise" } else { "sunset" }),
+            4 => format!("sunset+{}m", rng.gen_range(1..120)),
+            _ => format!("sunrise-{}h", rng.gen_range(1..3)),
+        }
+    }
+
+    /// A random range as it may be written in the config
+    fn random_valid_time(rng: &mut impl Rng) -> String {
+        let weekdays = ["", "Mon ", "Mon-Fri ", "Sat,Sun ", "Tue,Thu-Sat "]
+            .choose(rng)
+            .unwrap();
+        let range = match rng.gen_range(0..4) {
+            0 => String::from("*"),
+            1 => random_time_spec(rng),
+            2 => format!("{}-24", random_time_spec(rng)),
+            _ => format!("{}-{}", random_time_spec(rng), random_time_spec(rng)),
         };
+        format!("{}{}", weekdays, range)
+    }
+
+    fn rng() -> rand::rngs::StdRng {
+        rand::SeedableRng::seed_from_u64(840)
+    }
+
+    #[test]
+    fn time_specs_round_trip() {
+        let mut rng = rng();
+        for _ in 0..1000 {
+            let s = random_time_spec(&mut rng);
+            let spec = TimeSpec::parse(&s, "time").unwrap();
+            assert_eq!(
+                TimeSpec::parse(&spec.to_string(), "time"),
+                Ok(spec),
+                "{}",
+                s
+            );
+        }
+    }
+
+    #[test]
+    fn valid_times_round_trip() {
+        let mut rng = rng();
+        for _ in 0..1000 {
+            let s = random_valid_time(&mut rng);
+            let time: ValidTime = s.parse().unwrap_or_else(|e| panic!("{}: {}", s, e));
+            // the spelling is kept
+            let json = serde_json::to_string(&time).unwrap();
+            assert_eq!(json, serde_json::to_string(&s).unwrap());
+            assert_eq!(time.to_string(), s);
+            assert_eq!(serde_json::from_str::<ValidTime>(&json).unwrap(), time);
+
+            // the formatted form is used without the spelling and means the same
+            let formatted = ValidTime {
+                source: None,
+                ..time.clone()
+            };
+            let json = serde_json::to_string(&formatted).unwrap();
+            assert_eq!(
+                serde_json::from_str::<ValidTime>(&json).unwrap(),
+                time,
+                "{}",
+                s
+            );
+        }
+    }
+
+    #[test]
+    fn time_refs_round_trip() {
+        let mut rng = rng();
+        for _ in 0..1000 {
+            let parts: Vec<_> = (0..rng.gen_range(1..4))
+                .map(|_| {
+                    if rng.gen_ratio(1, 5) {
+                        String::from("@night")
+                    } else {
+                        random_valid_time(&mut rng)
+                    }
+                })
+                .collect();
+            let s = parts.join(",");
+            let refs: TimeRefs = s.parse().unwrap_or_else(|e| panic!("{}: {}", s, e));
+            assert_eq!(refs.iter().count(), parts.len(), "{}", s);
+            let json = serde_json::to_string(&refs).unwrap();
+            assert_eq!(json, serde_json::to_string(&s).unwrap());
+            assert_eq!(serde_json::from_str::<TimeRefs>(&json).unwrap(), refs);
 
-        Ok(Self { start, end })
+            // a list stays a list
+            let list = TimeRefs::new(refs.iter().cloned().collect());
+            let json = serde_json::to_string(&list).unwrap();
+            assert_eq!(json, serde_json::to_string(&parts).unwrap());
+            assert_eq!(serde_json::from_str::<TimeRefs>(&json).unwrap(), list);
+        }
+    }
+
+    #[test]
+    fn equality_ignores_the_spelling() {
+        assert_eq!(time("08:30-10"), time("8:30-10:00"));
+        assert_eq!(time("8"), time("8-9"));
+        assert_eq!(time("*"), ValidTime::ALL);
+        assert_ne!(time("8"), time("Mon 8"));
+        let hash = |time: &ValidTime| {
+            let mut hasher = DefaultHasher::new();
+            time.hash(&mut hasher);
+            hasher.finish()
+        };
+        assert_eq!(hash(&time("08:30-10")), hash(&time("8:30-10:00")));
     }
 }
@@ -0,0 +1,920 @@
+//! `wallpaper daemon`, which updates the wallpapers on time and answers the other commands
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::PathBuf,
+    sync::mpsc::RecvTimeoutError,
+    time::{Duration, Instant, SystemTime},
+};
+
+use anyhow::Context;
+use tracing::{debug, error, info, warn};
+use wallpaper::{
+    brightness, check_wayland_display, dedupe, exit_sww,
+    explain::SelectionTrace,
+    hotplug, init_sww,
+    ipc::{self, IpcEvent, IpcRequest, IpcResponse},
+    links, power, preview, remote, restore,
+    scheduler::{self, next_wakeup, ClockJumpDetector, Reason},
+    show_image, signals, systemd, undo, update_wallpapers, watch, Monitors, OnStart, Pending,
+    State, UpdateOptions,
+};
+
+use crate::{
+    add_image, list_images, print, rate, remove_images, reset_stats, select, set_favorites,
+    set_image, set_mirror, set_mode, set_times, switch,
+};
+
+/// Forgets the updates of monitors which didn't appear in time
+fn expire_pending(state: &mut State) {
+    let now = Instant::now();
+    state.pending.retain(|monitor, pending| {
+        if pending.until <= now {
+            info!(
+                "forgetting update of monitor {}, as it didn't appear",
+                monitor
+            );
+        }
+        pending.until > now
+    });
+}
+
+/// Updates the monitors which just got connected, with their pending update if they have one
+fn update_connected(state: &mut State, monitors: Vec<String>) {
+    for monitor in monitors {
+        let res = match state.pending.remove(&monitor) {
+            Some(Pending {
+                image: Some(image),
+                options,
+                ..
+            }) => show_image(state, &image, Some(&monitor), options.transition.as_deref()),
+            Some(Pending { options, .. }) => {
+                update_wallpapers(state, Monitors::Some(vec![monitor.clone()]), options)
+                    .map(|_changes| ())
+            }
+            None => update_wallpapers(
+                state,
+                Monitors::Some(vec![monitor.clone()]),
+                UpdateOptions::default(),
+            )
+            .map(|_changes| ()),
+        };
+        if let Err(e) = res {
+            error!("can't update monitor {}: {:#}", monitor, e);
+        }
+    }
+}
+
+/// How long the focus has to stay on a workspace before its images are shown
+const WORKSPACE_DEBOUNCE: Duration = Duration::from_millis(300);
+
+/// How often the daemon looks for new monitors while a not connected monitor has a pending update
+const PENDING_POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How often the daemon looks for new monitors
+///
+/// There are no events for new monitors, so they are polled.
+fn hotplug_interval(state: &State) -> Duration {
+    if state.pending.is_empty() {
+        *state.config.check_interval
+    } else {
+        PENDING_POLL_INTERVAL
+    }
+}
+
+/// Schedules the connected monitors, or the monitors of the cache if they are unknown
+fn sync_schedule(state: &mut State, detector: &hotplug::Detector) {
+    let monitors: Vec<String> = match detector.connected() {
+        Some(connected) => connected.iter().cloned().collect(),
+        None => state.cache.last_images.keys().cloned().collect(),
+    };
+    state.schedule.sync(
+        &state.config,
+        state.power,
+        &state.cache,
+        &monitors,
+        &mut state.rng,
+    );
+}
+
+/// The longest time the daemon sleeps without checking whether the system was suspended
+const SUSPEND_POLL_INTERVAL: Duration = Duration::from_secs(60);
+
+pub fn run(state: &mut State) -> anyhow::Result<()> {
+    // before any thread or swww is started, as it changes the environment
+    let mut notifier = systemd::Notifier::from_env();
+    // before any thread is started, so all of them inherit the blocked signals
+    let signals = signals::Signals::block()?;
+    check_wayland_display()?;
+    // the compositor may still be starting, so don't fail permanently
+    let mut swww_ready = match init_sww(state, *state.config.startup_timeout) {
+        Ok(()) => true,
+        Err(e) => {
+            error!("can't initialize swww, retrying later: {:#}", e);
+            false
+        }
+    };
+
+    let listener = ipc::Listener::bind().context("while starting ipc server")?;
+    signals.spawn(listener.sender());
+    let project_dirs = State::project_dirs()?;
+    let watcher = match watch::Watcher::spawn(
+        &[project_dirs.config_dir(), project_dirs.cache_dir()],
+        listener.sender(),
+    ) {
+        Ok(watcher) => Some(watcher),
+        Err(e) => {
+            warn!("can't watch the files, polling them instead: {:#}", e);
+            None
+        }
+    };
+    #[cfg(feature = "sway-ipc")]
+    if let Err(e) = wallpaper::sway::spawn(listener.sender()) {
+        warn!("can't follow the workspaces of sway: {:#}", e);
+    }
+    // map from monitor to the time at which it gets updated after its workspace changed
+    let mut pending_workspaces: BTreeMap<String, Instant> = BTreeMap::new();
+
+    info!("starting mainloop");
+
+    match state.config.on_start {
+        OnStart::Always if !swww_ready => {
+            info!("not updating wallpaper on start, because swww is not ready");
+        }
+        OnStart::Always => {
+            info!("updating wallpaper on start");
+            if let Err(e) = update_wallpapers(state, Monitors::All, UpdateOptions::default()) {
+                // swww may still be starting, so it is initialized again on the next tick
+                error!("can't update wallpaper on start: {:#}", e);
+                swww_ready = false;
+            }
+        }
+        OnStart::IfDue => {
+            info!("updating wallpaper on start only if an update is due");
+        }
+        OnStart::Never => {
+            info!("not updating wallpaper on start");
+            state.schedule.start_at(SystemTime::now());
+        }
+    }
+
+    let mut analyzer = brightness::Analyzer::new();
+    let mut hasher = dedupe::Hasher::new();
+    let mut fetcher = remote::Fetcher::new();
+    let mut detector = hotplug::Detector::new();
+
+    // the `image_dir` which is watched, if it could be watched
+    let mut image_watcher: Option<(PathBuf, Option<watch::ImageDirWatcher>)> = None;
+    let mut subscribers = ipc::Subscribers::default();
+    // the last switch which was handled and when it was received
+    let mut last_switch: Option<(Instant, IpcEvent)> = None;
+    // the images which the subscribers know about
+    let mut notified_images = state.cache.last_images.clone();
+
+    loop {
+        notifier.ping();
+        if image_watcher.as_ref().map(|(dir, _)| dir) != Some(&state.config.image_dir) {
+            image_watcher = Some(watch_image_dir(state, listener.sender()));
+        }
+        if state.cache.last_images != notified_images {
+            notified_images.clone_from(&state.cache.last_images);
+            subscribers.notify();
+        }
+        let power = power::current_profile();
+        if power != state.power {
+            // only the settings of the next update change, switching doesn't cause an update
+            info!("power profile changed to {}", power);
+            state.power = power;
+            state
+                .schedule
+                .recompute(&state.config, state.power, &mut state.rng);
+        }
+        if analyzer.poll(state) | hasher.poll(state) {
+            state.save().context("while saving cache")?;
+        }
+        fetcher.poll(state);
+
+        let previewed: Vec<_> = state
+            .previews
+            .iter()
+            .filter(|(_, end)| **end <= Instant::now())
+            .map(|(monitor, _)| monitor.clone())
+            .collect();
+        if !previewed.is_empty() {
+            info!("preview on {} is over", previewed.join(", "));
+            state
+                .previews
+                .retain(|monitor, _| !previewed.contains(monitor));
+            // deferred monitors get a new image right away
+            let previewed: Vec<_> = previewed
+                .into_iter()
+                .filter(|monitor| !state.deferred.contains(monitor))
+                .collect();
+            if let Err(e) = restore(state, &previewed) {
+                error!("can't restore wallpaper after preview: {:#}", e);
+            }
+            continue;
+        }
+
+        expire_pending(state);
+        match detector.poll(hotplug_interval(state)) {
+            Ok(Some(changes)) => {
+                // the socket is bound and the monitors can be queried
+                notifier.ready();
+                if let Some(connected) = detector.connected() {
+                    state.cache.saw_monitors(connected);
+                    // also after a restart, as the links may be stale
+                    if let Err(e) = links::update(state, connected) {
+                        error!("can't update the links to the current images: {:#}", e);
+                    }
+                }
+                for monitor in &changes.removed {
+                    info!("monitor {} was disconnected", monitor);
+                    state.deferred.remove(monitor);
+                    state.previews.remove(monitor);
+                    state.fullscreen.remove(monitor);
+                    state.off.remove(monitor);
+                    pending_workspaces.remove(monitor);
+                }
+                // a pending monitor may have been connected when the update was queued
+                let mut connected: BTreeSet<_> = changes.added.into_iter().collect();
+                if let Some(known) = detector.connected() {
+                    connected.extend(
+                        state
+                            .pending
+                            .keys()
+                            .filter(|monitor| known.contains(*monitor))
+                            .cloned(),
+                    );
+                }
+                let connected: Vec<_> = connected.into_iter().collect();
+                if !connected.is_empty() {
+                    info!("updating wallpaper of {}: connected", connected.join(", "));
+                    update_connected(state, connected);
+                    continue;
+                }
+            }
+            Ok(None) => {}
+            Err(e) => warn!("can't look for new monitors: {:#}", e),
+        }
+
+        let workspace_changed: Vec<_> = pending_workspaces
+            .iter()
+            .filter(|(_, at)| **at <= Instant::now())
+            .map(|(monitor, _)| monitor.clone())
+            .collect();
+        let resumed: Vec<_> = state
+            .deferred
+            .iter()
+            .filter(|monitor| state.inhibition(monitor).is_none())
+            .filter(|monitor| !state.previews.contains_key(*monitor))
+            .filter(|monitor| !workspace_changed.contains(monitor))
+            .cloned()
+            .collect();
+        if !workspace_changed.is_empty() || !resumed.is_empty() {
+            pending_workspaces.retain(|monitor, _| !workspace_changed.contains(monitor));
+            if !workspace_changed.is_empty() {
+                info!(
+                    "updating wallpaper of {}: workspace changed",
+                    workspace_changed.join(", ")
+                );
+            }
+            if !resumed.is_empty() {
+                info!(
+                    "updating wallpaper of {}: deferred update",
+                    resumed.join(", ")
+                );
+            }
+            // monitors which still can't be updated get deferred again
+            for monitor in &resumed {
+                state.deferred.remove(monitor);
+            }
+            let monitors = workspace_changed.into_iter().chain(resumed).collect();
+            if let Err(e) =
+                update_wallpapers(state, Monitors::Some(monitors), UpdateOptions::default())
+            {
+                error!("can't update wallpaper: {:#}", e);
+            }
+            continue;
+        }
+
+        // the monitors which got a new image, e.g. from a switch, restart their interval
+        sync_schedule(state, &detector);
+
+        let now = SystemTime::now();
+        // without monitors nothing is due, but the config is checked
+        let (last_update, next_update) = state
+            .schedule
+            .next()
+            .unwrap_or((now, now + *state.config.check_interval));
+        let (mut wakeup, mut reason) =
+            next_wakeup(now, last_update, next_update, &state.config, &state.cache);
+
+        if !swww_ready {
+            match init_sww(state, Duration::ZERO) {
+                Ok(()) => {
+                    info!("swww is ready now");
+                    swww_ready = true;
+                }
+                Err(e) => {
+                    warn!("swww is still not ready: {:#}", e);
+                    (wakeup, reason) = (now + *state.config.check_interval, Reason::Check);
+                }
+            }
+        }
+
+        if reason == Reason::Expired && wakeup <= now {
+            let expired = scheduler::expired_monitors(&state.config, &state.cache, now);
+            info!("updating wallpaper of {}: {}", expired.join(", "), reason);
+            if let Err(e) =
+                update_wallpapers(state, Monitors::Some(expired), UpdateOptions::default())
+            {
+                error!("can't update expired images: {:#}", e);
+            }
+            // e.g. disconnected monitors can't be updated, so forget their images to not retry
+            for monitor in scheduler::expired_monitors(&state.config, &state.cache, now) {
+                debug!("image of monitor {} can't be updated", monitor);
+                state.cache.shown_since.remove(&monitor);
+            }
+            state.save().context("while saving cache")?;
+            continue;
+        }
+
+        if reason.is_update() && wakeup <= now {
+            // a time window of the images concerns all monitors
+            let due = match reason {
+                Reason::Interval => state.schedule.due(now),
+                _ => state.schedule.monitors(),
+            };
+            let all_off = detector.connected().filter(|connected| {
+                state.config.skip_when_off
+                    && !connected.is_empty()
+                    && connected.is_subset(&state.off)
+            });
+            if let Some(connected) = all_off {
+                // swww isn't even asked, they get one update when they are powered on
+                info!("deferring update: all monitors are powered off");
+                state.deferred.extend(connected.iter().cloned());
+            } else {
+                let res = if due.len() == state.schedule.monitors().len() {
+                    info!("updating wallpaper: {}", reason);
+                    update_wallpapers(state, Monitors::All, UpdateOptions::default())
+                } else {
+                    info!("updating wallpaper of {}: {}", due.join(", "), reason);
+                    update_wallpapers(state, Monitors::Some(due.clone()), UpdateOptions::default())
+                };
+                if let Err(e) = res {
+                    // e.g. swww died, so it is initialized again on the next tick
+                    error!("can't update wallpaper: {:#}", e);
+                    swww_ready = false;
+                }
+            }
+            // also if they were not updated, e.g. because they were inhibited
+            state
+                .schedule
+                .restart(&due, &state.config, state.power, now, &mut state.rng);
+            continue;
+        }
+
+        debug!(
+            "waiting until {} ({})",
+            humantime::format_rfc3339_seconds(wakeup),
+            reason
+        );
+        notifier.status(format!(
+            "waiting until {} ({})",
+            humantime::format_rfc3339_seconds(wakeup),
+            reason
+        ));
+        let sleep_duration = wakeup.duration_since(now).unwrap_or_default();
+        // new previews break the wait, so the known ones are enough
+        let preview_end = state.previews.values().min().copied();
+        let hotplug_check = detector.next_poll(hotplug_interval(state));
+
+        // the sleep doesn't advance while the system is suspended,
+        // so wake up regularly to check whether the wall clock jumped
+        let deadline = preview_end
+            .into_iter()
+            .chain([hotplug_check])
+            .chain(notifier.next_ping())
+            .fold(Instant::now() + sleep_duration, Instant::min);
+        let mut clock = ClockJumpDetector::new(
+            SystemTime::now(),
+            Instant::now(),
+            *state.config.check_interval,
+        );
+        loop {
+            let deadline = pending_workspaces
+                .values()
+                .copied()
+                .fold(deadline, Instant::min);
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                break;
+            }
+            match listener.recv_timeout(remaining.min(SUSPEND_POLL_INTERVAL)) {
+                Ok(msg) => {
+                    // process pending messages
+                    let requests = std::iter::once(msg).chain(listener.try_iter()).collect();
+                    let (requests, merged) =
+                        coalesce(requests, *state.config.switch_debounce, &mut last_switch);
+                    if !merged.is_empty() {
+                        info!("merged {} repeated switch or reload requests", merged.len());
+                    }
+                    for request in merged {
+                        let response = match request.event {
+                            IpcEvent::Switch { .. } => IpcResponse::Changes(Vec::new()),
+                            _ => IpcResponse::Ok,
+                        };
+                        request.reply(response);
+                    }
+                    let mut changed = false;
+                    for msg in requests {
+                        changed |=
+                            handle_request(state, msg, &mut subscribers, &mut pending_workspaces);
+                    }
+                    if changed {
+                        break;
+                    }
+                }
+                Err(e) => match e {
+                    RecvTimeoutError::Timeout => {}
+                    RecvTimeoutError::Disconnected => todo!(),
+                },
+            }
+            if let Some(jump) = clock.check(SystemTime::now(), Instant::now()) {
+                info!(
+                    "wall clock jumped by {}, probably after a suspend",
+                    humantime::format_duration(Duration::from_secs(jump.as_secs()))
+                );
+                break;
+            }
+        }
+
+        // updates run in this loop, so none is interrupted
+        if signals::shutdown_requested() {
+            break;
+        }
+
+        // changes are only noticed by polling if the files are not watched
+        if !watcher.as_ref().is_some_and(watch::Watcher::is_active) {
+            debug!("reloading state");
+            state.reload().context("while reloading state")?;
+            debug!("reloaded state");
+        }
+    }
+
+    notifier.stopping();
+    drop(image_watcher);
+    state.save().context("while saving cache")?;
+    exit_sww(state);
+    // removes the socket
+    drop(listener);
+    info!("goodbye");
+
+    Ok(())
+}
+
+/// Answers a request to the daemon
+///
+/// Returns whether the state may have changed.
+fn handle_request(
+    state: &mut State,
+    request: IpcRequest,
+    subscribers: &mut ipc::Subscribers,
+    pending_workspaces: &mut BTreeMap<String, Instant>,
+) -> bool {
+    if let IpcEvent::Subscribe = request.event {
+        subscribers.add(request);
+        return false;
+    }
+    let mut files_changed = false;
+    let result = match &request.event {
+        IpcEvent::Reload => {
+            signals::reload_started();
+            debug!("reloading state (ipc)");
+            let res = state.force_reload().context("can't reload state");
+            debug!("reloaded state (ipc)");
+            res.map(|()| IpcResponse::Ok)
+        }
+        IpcEvent::Switch {
+            monitor,
+            force,
+            dry_run,
+            transition,
+            fps,
+            now_or_fail,
+        } => switch(
+            state,
+            monitor.clone(),
+            *force,
+            *dry_run,
+            transition.clone(),
+            *fps,
+            *now_or_fail,
+        )
+        .context("can't switch wallpaper")
+        .map(|changes| {
+            if *dry_run {
+                IpcResponse::Changes(changes)
+            } else {
+                IpcResponse::Ok
+            }
+        }),
+        IpcEvent::Select {
+            path,
+            keep_old,
+            monitor,
+            times,
+            all_files,
+            import,
+            parse_names,
+            dry_run,
+        } => select::run(
+            state,
+            path,
+            monitor.as_deref(),
+            times.as_deref(),
+            select::SelectOptions {
+                keep_old: *keep_old,
+                all_files: *all_files,
+                import: *import,
+                parse_names: *parse_names,
+                dry_run: *dry_run,
+            },
+        )
+        .context("can't select wallpaper")
+        .map(IpcResponse::Selected),
+        IpcEvent::Preview {
+            path,
+            monitor,
+            duration,
+        } => preview(state, path, monitor.as_deref())
+            .context("can't preview image")
+            .map(|monitors| {
+                let end = Instant::now() + *duration;
+                for monitor in monitors {
+                    state.previews.insert(monitor, end);
+                }
+                IpcResponse::Ok
+            }),
+        IpcEvent::SetImage {
+            path,
+            monitor,
+            transition,
+            add,
+            now_or_fail,
+        } => set_image(
+            state,
+            path,
+            monitor.as_deref(),
+            transition.as_deref(),
+            *add,
+            *now_or_fail,
+        )
+        .context("can't set image")
+        .map(|()| IpcResponse::Ok),
+        IpcEvent::AddImage {
+            path,
+            times,
+            weight,
+        } => add_image(state, path, times.as_deref(), *weight)
+            .context("can't add image")
+            .map(|new| IpcResponse::Added { new }),
+        IpcEvent::RemoveImages { pattern, switch } => remove_images(state, pattern, *switch)
+            .context("can't remove images")
+            .map(|(images, monitors)| IpcResponse::Removed { images, monitors }),
+        IpcEvent::SetTimes {
+            pattern,
+            times,
+            append,
+        } => set_times(state, pattern, times, *append)
+            .context("can't set times")
+            .map(IpcResponse::TimesSet),
+        IpcEvent::ListImages { valid_now } => {
+            Ok(IpcResponse::Images(list_images(state, *valid_now)))
+        }
+        IpcEvent::Undo { monitor } => undo(state, monitor.as_deref())
+            .context("can't undo wallpaper change")
+            .map(|()| IpcResponse::Ok),
+        IpcEvent::SetMode { mode } => set_mode(state, *mode)
+            .context("can't set mode")
+            .map(|()| IpcResponse::Ok),
+        IpcEvent::SetMirror { enabled } => set_mirror(state, *enabled)
+            .context("can't set mirror mode")
+            .map(|()| IpcResponse::Ok),
+        IpcEvent::Favorites { action } => set_favorites(state, *action)
+            .context("can't set favorites-only mode")
+            .map(|()| IpcResponse::Ok),
+        IpcEvent::Rate { monitor, delta } => rate(state, monitor.as_deref(), *delta)
+            .context("can't rate wallpaper")
+            .map(|()| IpcResponse::Ok),
+        IpcEvent::NextChange => {
+            let now = SystemTime::now();
+            let (last_update, next_update) = state
+                .schedule
+                .next()
+                .unwrap_or((now, now + *state.config.check_interval));
+            let (at, reason) =
+                scheduler::next_change(now, last_update, next_update, &state.config, &state.cache);
+            Ok(IpcResponse::NextChange {
+                at,
+                reason: reason.to_string(),
+            })
+        }
+        IpcEvent::WorkspaceFocus { output, workspace } => {
+            let old = state.workspaces.insert(output.clone(), workspace.clone());
+            let config = &state.config;
+            if old.as_ref() != Some(workspace)
+                && (config.has_workspace_images(old.as_deref())
+                    || config.has_workspace_images(Some(workspace)))
+            {
+                // wait a bit, as the next workspace may be focused right after this one
+                pending_workspaces.insert(output.clone(), Instant::now() + WORKSPACE_DEBOUNCE);
+            }
+            Ok(IpcResponse::Ok)
+        }
+        IpcEvent::Fullscreen { outputs } => {
+            debug!("fullscreen outputs: {:?}", outputs);
+            state.fullscreen = outputs.clone();
+            Ok(IpcResponse::Ok)
+        }
+        IpcEvent::OutputsOff { outputs } => {
+            for output in outputs.difference(&state.off) {
+                info!("monitor {} was powered off", output);
+            }
+            for output in state.off.difference(outputs) {
+                info!("monitor {} was powered on", output);
+            }
+            state.off = outputs.clone();
+            Ok(IpcResponse::Ok)
+        }
+        IpcEvent::Status => Ok(IpcResponse::Status(print::daemon_status(state))),
+        IpcEvent::State => print::state_summary(state, true)
+            .context("can't summarize state")
+            .map(|summary| IpcResponse::State(Box::new(summary))),
+        IpcEvent::ResetStats => reset_stats(state)
+            .context("can't reset statistics")
+            .map(|()| IpcResponse::Ok),
+        IpcEvent::PruneCache { dry_run: true } => Ok(IpcResponse::Pruned {
+            monitors: state.prune_monitors(true),
+            entries: state.prune_images(true),
+        }),
+        IpcEvent::PruneCache { dry_run: false } => {
+            let monitors = state.prune_monitors(false);
+            let entries = state.prune_images(false);
+            state
+                .save()
+                .context("can't save cache")
+                .map(|()| IpcResponse::Pruned { monitors, entries })
+        }
+        IpcEvent::History => Ok(IpcResponse::History(state.cache.history.clone())),
+        IpcEvent::Explain { monitor } => SelectionTrace::find(&state.traces, monitor.as_deref())
+            .map(|trace| IpcResponse::Explain(trace.clone())),
+        IpcEvent::FilesChanged => state.reload().context("can't reload state").map(|changed| {
+            files_changed = changed;
+            IpcResponse::Ok
+        }),
+        IpcEvent::ImagesChanged { added, removed } => state
+            .images_changed(added, removed)
+            .map(|()| IpcResponse::Ok),
+        IpcEvent::Shutdown => {
+            signals::request_shutdown();
+            Ok(IpcResponse::Ok)
+        }
+        IpcEvent::Import {
+            config,
+            cache,
+            merge,
+        } => state
+            .import((**config).clone(), (**cache).clone(), *merge)
+            .context("can't import")
+            .map(|()| IpcResponse::Ok),
+        IpcEvent::Subscribe => unreachable!("subscriptions are kept above"),
+    };
+    let changes_state = match &request.event {
+        IpcEvent::FilesChanged => files_changed,
+        IpcEvent::NextChange
+        | IpcEvent::WorkspaceFocus { .. }
+        | IpcEvent::Status
+        | IpcEvent::State
+        | IpcEvent::History
+        | IpcEvent::Explain { .. }
+        | IpcEvent::PruneCache { .. }
+        | IpcEvent::ListImages { .. }
+        | IpcEvent::Switch { dry_run: true, .. }
+        | IpcEvent::Select { dry_run: true, .. } => false,
+        // the deferred updates have to happen now
+        IpcEvent::Fullscreen { .. } | IpcEvent::OutputsOff { .. } => state
+            .deferred
+            .iter()
+            .any(|monitor| state.inhibition(monitor).is_none()),
+        _ => true,
+    };
+    match result {
+        Ok(response) => request.reply(response),
+        Err(e) => {
+            error!("{:#}", e);
+            request.reply(IpcResponse::Error(format!("{:#}", e)));
+        }
+    }
+    changes_state
+}
+
+/// Drops the `Switch` requests which repeat the previous one within `window`,
+/// and every `Reload` request after the first one
+///
+/// `last_switch` is the previous switch which was kept, also by an earlier call, so a
+/// switch which arrives while the previous one runs is dropped too.
+/// Returns the kept and the dropped requests, both in order.
+fn coalesce(
+    requests: Vec<IpcRequest>,
+    window: Duration,
+    last_switch: &mut Option<(Instant, IpcEvent)>,
+) -> (Vec<IpcRequest>, Vec<IpcRequest>) {
+    let mut kept = Vec::new();
+    let mut dropped = Vec::new();
+    let mut reloading = false;
+    for request in requests {
+        match &request.event {
+            IpcEvent::Reload if reloading => {
+                dropped.push(request);
+                continue;
+            }
+            IpcEvent::Reload => reloading = true,
+            // a dry run changes nothing, so it is always answered
+            IpcEvent::Switch { dry_run: false, .. } => {
+                let repeated = last_switch.as_ref().is_some_and(|(at, event)| {
+                    *event == request.event && request.received.duration_since(*at) < window
+                });
+                if repeated {
+                    dropped.push(request);
+                    continue;
+                }
+                *last_switch = Some((request.received, request.event.clone()));
+            }
+            _ => {}
+        }
+        kept.push(request);
+    }
+    (kept, dropped)
+}
+
+/// Watches the `image_dir` of the config
+///
+/// Without a watcher, the images of the `image_sources` are only listed again on updates.
+fn watch_image_dir(
+    state: &State,
+    sender: std::sync::mpsc::Sender<IpcRequest>,
+) -> (PathBuf, Option<watch::ImageDirWatcher>) {
+    let dir = state.config.image_dir.clone();
+    if dir.as_os_str().is_empty() {
+        return (dir, None);
+    }
+    match watch::ImageDirWatcher::spawn(&dir, sender) {
+        Ok(watcher) => (dir, Some(watcher)),
+        Err(e) => {
+            warn!(
+                "can't watch {}, its images are only listed again on updates: {:#}",
+                dir.display(),
+                e
+            );
+            (dir, None)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WINDOW: Duration = Duration::from_millis(500);
+
+    fn switch(monitor: Option<&str>, dry_run: bool) -> IpcEvent {
+        IpcEvent::Switch {
+            monitor: monitor.map(String::from),
+            force: false,
+            dry_run,
+            transition: None,
+            fps: None,
+            now_or_fail: false,
+        }
+    }
+
+    fn request(event: IpcEvent, received: Instant) -> IpcRequest {
+        let mut request = IpcRequest::internal(event);
+        request.received = received;
+        request
+    }
+
+    fn events(requests: &[IpcRequest]) -> Vec<&IpcEvent> {
+        requests.iter().map(|request| &request.event).collect()
+    }
+
+    #[test]
+    fn coalesces_identical_switches_within_the_window() {
+        let start = Instant::now();
+        let requests = vec![
+            request(switch(None, false), start),
+            request(switch(None, false), start + Duration::from_millis(100)),
+            request(switch(None, false), start + Duration::from_millis(400)),
+        ];
+        let mut last_switch = None;
+        let (kept, dropped) = coalesce(requests, WINDOW, &mut last_switch);
+        assert_eq!(events(&kept), [&switch(None, false)]);
+        assert_eq!(dropped.len(), 2);
+    }
+
+    #[test]
+    fn keeps_identical_switches_outside_the_window() {
+        let start = Instant::now();
+        let requests = vec![
+            request(switch(None, false), start),
+            request(switch(None, false), start + WINDOW),
+        ];
+        let mut last_switch = None;
+        let (kept, dropped) = coalesce(requests, WINDOW, &mut last_switch);
+        assert_eq!(kept.len(), 2);
+        assert!(dropped.is_empty());
+    }
+
+    #[test]
+    fn keeps_switches_of_different_monitors() {
+        let start = Instant::now();
+        let requests = vec![
+            request(switch(Some("DP-1"), false), start),
+            request(switch(Some("DP-2"), false), start),
+            request(switch(None, false), start),
+        ];
+        let mut last_switch = None;
+        let (kept, dropped) = coalesce(requests, WINDOW, &mut last_switch);
+        assert_eq!(kept.len(), 3);
+        assert!(dropped.is_empty());
+    }
+
+    #[test]
+    fn keeps_dry_run_switches() {
+        let start = Instant::now();
+        let requests = vec![
+            request(switch(None, true), start),
+            request(switch(None, true), start),
+            request(switch(None, false), start),
+            request(switch(None, true), start),
+        ];
+        let mut last_switch = None;
+        let (kept, dropped) = coalesce(requests, WINDOW, &mut last_switch);
+        assert_eq!(kept.len(), 4);
+        assert!(dropped.is_empty());
+    }
+
+    #[test]
+    fn collapses_a_reload_storm() {
+        let start = Instant::now();
+        let requests = (0..10)
+            .map(|i| request(IpcEvent::Reload, start + Duration::from_secs(i)))
+            .chain([request(IpcEvent::Status, start)])
+            .collect();
+        let mut last_switch = None;
+        let (kept, dropped) = coalesce(requests, WINDOW, &mut last_switch);
+        assert_eq!(events(&kept), [&IpcEvent::Reload, &IpcEvent::Status]);
+        assert_eq!(dropped.len(), 9);
+    }
+
+    #[test]
+    fn remembers_the_last_switch_between_calls() {
+        let start = Instant::now();
+        let mut last_switch = None;
+        let (kept, _) = coalesce(
+            vec![request(switch(Some("DP-1"), false), start)],
+            WINDOW,
+            &mut last_switch,
+        );
+        assert_eq!(kept.len(), 1);
+        assert_eq!(
+            last_switch.as_ref().map(|(_, event)| event),
+            Some(&switch(Some("DP-1"), false))
+        );
+
+        let (kept, dropped) = coalesce(
+            vec![request(
+                switch(Some("DP-1"), false),
+                start + Duration::from_millis(200),
+            )],
+            WINDOW,
+            &mut last_switch,
+        );
+        assert!(kept.is_empty());
+        assert_eq!(dropped.len(), 1);
+
+        // the window starts at the kept switch, not at the dropped one
+        let (kept, _) = coalesce(
+            vec![request(
+                switch(Some("DP-1"), false),
+                start + Duration::from_millis(600),
+            )],
+            WINDOW,
+            &mut last_switch,
+        );
+        assert_eq!(kept.len(), 1);
+    }
+}
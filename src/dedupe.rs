@@ -0,0 +1,155 @@
+//! Finds images with the same content
+//!
+//! Hashing needs the `dedupe` feature. Without it, no image gets hashed,
+//! so every image counts as unique.
+
+use std::{
+    collections::{BTreeMap, HashSet},
+    path::{Path, PathBuf},
+    sync::mpsc::{channel, Receiver, TryRecvError},
+};
+
+use tracing::{debug, error};
+
+use crate::config::{ContentHash, State};
+
+/// Hashes images which are not hashed yet in a background thread
+#[derive(Debug, Default)]
+pub struct Hasher {
+    results: Option<Receiver<(PathBuf, anyhow::Result<ContentHash>)>>,
+    /// Images which can't be hashed, so they are not retried
+    failed: HashSet<PathBuf>,
+}
+
+impl Hasher {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Stores the finished hashes in the cache and starts hashing new images
+    ///
+    /// This never blocks. Returns whether the cache changed.
+    pub fn poll(&mut self, state: &mut State) -> bool {
+        let mut changed = false;
+        if let Some(results) = &self.results {
+            loop {
+                match results.try_recv() {
+                    Ok((image, Ok(hash))) => {
+                        debug!("{} has the content hash {}", image.display(), hash.hash);
+                        state.cache.content_hashes.insert(image, hash);
+                        changed = true;
+                    }
+                    Ok((image, Err(e))) => {
+                        error!("can't hash {}: {:#}", image.display(), e);
+                        self.failed.insert(image);
+                    }
+                    Err(TryRecvError::Empty) => return changed,
+                    Err(TryRecvError::Disconnected) => {
+                        self.results = None;
+                        break;
+                    }
+                }
+            }
+        }
+
+        if !state.config.dedupe || !cfg!(feature = "dedupe") {
+            return changed;
+        }
+        let pending: Vec<_> = configured_images(state)
+            .into_iter()
+            .filter(|image| image.is_file())
+            .filter(|image| !self.failed.contains(image))
+            .filter(|image| state.cache.content_hash_of(image).is_none())
+            .collect();
+        if pending.is_empty() {
+            return changed;
+        }
+
+        debug!("hashing {} images", pending.len());
+        let (sender, results) = channel();
+        std::thread::spawn(move || {
+            for image in pending {
+                let hash = hash(&image);
+                if sender.send((image, hash)).is_err() {
+                    break;
+                }
+            }
+        });
+        self.results = Some(results);
+
+        changed
+    }
+}
+
+/// Returns the images of the config, of the image sources and of all workspaces
+fn configured_images(state: &State) -> Vec<PathBuf> {
+    let mut images: Vec<_> = state
+        .config
+        .images
+        .keys()
+        .chain(state.source_images.keys())
+        .map(PathBuf::as_path)
+        .chain(
+            state
+                .config
+                .workspace_images
+                .values()
+                .flat_map(|images| images.keys())
+                .map(Path::new),
+        )
+        .map(|path| state.config.image_dir.join(path))
+        .collect();
+    images.sort();
+    images.dedup();
+    images
+}
+
+/// Returns the groups of configured images which have the same content
+///
+/// Images which are not hashed yet get hashed now, so this may block for a while.
+pub fn duplicates(state: &State) -> Vec<Vec<PathBuf>> {
+    let mut groups: BTreeMap<String, Vec<PathBuf>> = BTreeMap::new();
+    for image in configured_images(state) {
+        if !image.is_file() {
+            continue;
+        }
+        let hash = match state.cache.content_hash_of(&image) {
+            Some(hash) => hash.to_owned(),
+            None => match hash(&image) {
+                Ok(hash) => hash.hash,
+                Err(e) => {
+                    error!("can't hash {}: {:#}", image.display(), e);
+                    continue;
+                }
+            },
+        };
+        groups.entry(hash).or_default().push(image);
+    }
+
+    groups
+        .into_values()
+        .filter(|images| images.len() > 1)
+        .collect()
+}
+
+#[cfg(feature = "dedupe")]
+fn hash(image: &Path) -> anyhow::Result<ContentHash> {
+    use anyhow::Context;
+
+    let modified = std::fs::metadata(image)
+        .and_then(|m| m.modified())
+        .context("while reading modification time")?;
+    let mut hasher = blake3::Hasher::new();
+    let mut file = std::fs::File::open(image).context("while opening image")?;
+    std::io::copy(&mut file, &mut hasher).context("while reading image")?;
+
+    Ok(ContentHash {
+        modified: modified.into(),
+        hash: hasher.finalize().to_hex().to_string(),
+    })
+}
+
+#[cfg(not(feature = "dedupe"))]
+fn hash(_image: &Path) -> anyhow::Result<ContentHash> {
+    anyhow::bail!("hashing images needs the `dedupe` feature")
+}
@@ -0,0 +1,124 @@
+//! `wallpaper doctor`, which diagnoses swww, the daemon and the files
+
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use wallpaper::{
+    ipc::{self, IpcEvent, IpcResponse},
+    Config, State,
+};
+
+/// Prints whether every part of the environment works, with a hint how to fix it if not
+///
+/// Returns whether all critical parts work.
+pub fn run(log_dir: &Path) -> anyhow::Result<bool> {
+    let mut healthy = true;
+    let mut diagnose =
+        |name: &str, critical: bool, result: anyhow::Result<String>, hint: &str| match result {
+            Ok(details) => println!("\u{2713} {}: {}", name, details),
+            Err(e) => {
+                println!("\u{2717} {}: {:#}", name, e);
+                println!("  hint: {}", hint);
+                healthy &= !critical;
+            }
+        };
+    let run = |args: &[&str]| -> anyhow::Result<String> {
+        let output = std::process::Command::new("swww")
+            .args(args)
+            .output()
+            .context("can't execute swww")?;
+        if !output.status.success() {
+            anyhow::bail!("{}", String::from_utf8_lossy(&output.stderr).trim());
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).trim().to_owned())
+    };
+
+    diagnose(
+        "swww",
+        true,
+        run(&["--version"]),
+        "install swww and make sure it is in the PATH of the daemon",
+    );
+    diagnose(
+        "swww daemon",
+        true,
+        run(&["query"]).map(|monitors| format!("{} monitors", monitors.lines().count())),
+        "start it with `swww init`, the daemon does it on its start",
+    );
+    diagnose(
+        "WAYLAND_DISPLAY",
+        true,
+        std::env::var("WAYLAND_DISPLAY")
+            .map_err(|_| anyhow::anyhow!("not set, so the compositor can't be reached")),
+        "start the daemon from your compositor or pass WAYLAND_DISPLAY to its systemd unit",
+    );
+    let ping = || -> anyhow::Result<String> {
+        let socket_path = ipc::socket_path();
+        let mut client = ipc::Client::connect()
+            .with_context(|| format!("no daemon listens on {}", socket_path.display()))?;
+        match client.send(IpcEvent::Status)? {
+            IpcResponse::Status(_) => Ok(format!("listening on {}", socket_path.display())),
+            IpcResponse::Error(e) => anyhow::bail!("daemon returned error: {}", e),
+            response => anyhow::bail!("unexpected response from daemon: {:?}", response),
+        }
+    };
+    diagnose(
+        "wallpaper daemon",
+        false,
+        ping(),
+        "start it with `wallpaper daemon`. It and the commands need the same XDG_RUNTIME_DIR",
+    );
+
+    let config = || -> anyhow::Result<String> {
+        let config_file = State::config_file()?;
+        if !config_file.is_file() {
+            return Ok(format!("{} does not exist yet", config_file.display()));
+        }
+        let file = std::fs::File::open(&config_file).context("can't open config file")?;
+        let mut config: Config =
+            serde_json::from_reader(file).context("can't parse config file")?;
+        config.resolve()?;
+        Ok(format!(
+            "{}, {} images",
+            config_file.display(),
+            config.images.len()
+        ))
+    };
+    diagnose(
+        "config",
+        true,
+        config(),
+        "fix the config file, `wallpaper init --force` writes a new one",
+    );
+    let writable = |dir: PathBuf| -> anyhow::Result<String> {
+        std::fs::create_dir_all(&dir).with_context(|| format!("can't create {}", dir.display()))?;
+        let probe = dir.join(".doctor");
+        std::fs::write(&probe, b"").with_context(|| format!("can't write to {}", dir.display()))?;
+        std::fs::remove_file(&probe).ok();
+        Ok(dir.display().to_string())
+    };
+    diagnose(
+        "cache",
+        true,
+        writable(State::project_dirs()?.cache_dir().to_owned()),
+        "check the permissions of the directory or set XDG_CACHE_HOME",
+    );
+    diagnose(
+        "logs",
+        false,
+        writable(log_dir.to_owned()),
+        "check the permissions of the directory, set XDG_STATE_HOME, `log_dir` in the config or \
+        pass --log-dir",
+    );
+    let old_logs = State::old_log_dir()?;
+    if old_logs != log_dir && old_logs.is_dir() {
+        diagnose(
+            "old logs",
+            false,
+            Ok(format!("{} is not written to anymore", old_logs.display())),
+            "",
+        );
+    }
+
+    Ok(healthy)
+}
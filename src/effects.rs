@@ -0,0 +1,84 @@
+//! Blurs and dims images before they are shown
+//!
+//! The effects need the `effects` feature. Without it, the images are shown unchanged.
+
+use std::path::{Path, PathBuf};
+
+use crate::config::Effects;
+
+/// Returns a copy of the image with the effects applied
+///
+/// The copies are stored in `dir` and reused until the image is modified.
+/// Animated GIFs are returned unchanged, as only their first frame would be kept.
+#[cfg(feature = "effects")]
+pub fn apply(dir: &Path, image: &Path, effects: &Effects) -> anyhow::Result<PathBuf> {
+    use std::hash::{DefaultHasher, Hash, Hasher};
+
+    use anyhow::Context;
+    use tracing::{debug, warn};
+
+    if is_animated(image)? {
+        warn!(
+            "not applying effects to {}, because it is animated",
+            image.display()
+        );
+        return Ok(image.to_owned());
+    }
+
+    let modified = std::fs::metadata(image)
+        .and_then(|m| m.modified())
+        .context("while reading modification time")?;
+    let mut s = DefaultHasher::new();
+    image.hash(&mut s);
+    modified.hash(&mut s);
+    effects.hash(&mut s);
+    let path = dir.join(format!("{:016x}.png", s.finish()));
+    if path.is_file() {
+        return Ok(path);
+    }
+
+    debug!("applying {:?} to {}", effects, image.display());
+    std::fs::create_dir_all(dir).context("while creating directory for effects")?;
+    let mut processed = image::open(image).context("while decoding image")?;
+    if let Some(sigma) = effects.blur.filter(|sigma| *sigma > 0.0) {
+        processed = processed.blur(sigma);
+    }
+    let mut processed = processed.into_rgb8();
+    if let Some(dim) = effects.dim {
+        let factor = 1.0 - dim.clamp(0.0, 1.0);
+        for pixel in processed.pixels_mut() {
+            for c in &mut pixel.0 {
+                *c = (f32::from(*c) * factor).round() as u8;
+            }
+        }
+    }
+    processed
+        .save(&path)
+        .with_context(|| format!("while saving {}", path.display()))?;
+
+    Ok(path)
+}
+
+#[cfg(not(feature = "effects"))]
+pub fn apply(_dir: &Path, _image: &Path, _effects: &Effects) -> anyhow::Result<PathBuf> {
+    anyhow::bail!("applying effects needs the `effects` feature")
+}
+
+/// Returns whether the image is a GIF with more than one frame
+#[cfg(feature = "effects")]
+fn is_animated(image: &Path) -> anyhow::Result<bool> {
+    use anyhow::Context;
+    use image::AnimationDecoder;
+
+    let is_gif = image
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("gif"));
+    if !is_gif {
+        return Ok(false);
+    }
+    let file = std::fs::File::open(image).context("while opening image")?;
+    let decoder = image::codecs::gif::GifDecoder::new(std::io::BufReader::new(file))
+        .context("while decoding gif")?;
+
+    Ok(decoder.into_frames().take(2).count() > 1)
+}
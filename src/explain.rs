@@ -0,0 +1,83 @@
+//! Records why an image was chosen, for `wallpaper explain`
+
+use std::{collections::BTreeMap, path::PathBuf, time::SystemTime};
+
+/// How the image of a group of monitors was chosen in the last update
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct SelectionTrace {
+    pub at: SystemTime,
+    /// The monitors which show the image together, e.g. in mirror mode
+    pub monitors: Vec<String>,
+    /// Number of images configured for the monitors
+    pub images: usize,
+    /// Map from filter to the number of images it removed before the first stage
+    pub filtered: BTreeMap<String, usize>,
+    /// The stages which were tried, in order. The last one chose the image
+    pub stages: Vec<Stage>,
+    /// `None` if no image was found
+    pub image: Option<PathBuf>,
+    /// `None` if the monitors showed the image already
+    pub transition: Option<String>,
+    /// The monitors whose change was vetoed by the `pre_change` hook
+    #[serde(default)]
+    pub vetoed: Vec<String>,
+}
+
+/// One try to find an image in a set of candidates
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Stage {
+    pub name: String,
+    pub candidates: usize,
+    /// Map from filter to the number of candidates it removed
+    pub removed: BTreeMap<String, usize>,
+    pub chosen: bool,
+}
+
+impl Stage {
+    pub fn new(name: &str, candidates: usize, chosen: bool) -> Self {
+        Self {
+            name: name.to_owned(),
+            candidates,
+            removed: BTreeMap::new(),
+            chosen,
+        }
+    }
+}
+
+/// Keeps only the candidates for which `f` returns true and counts the removed ones as `filter`
+pub(crate) fn retain(
+    candidates: &mut Vec<PathBuf>,
+    removed: &mut BTreeMap<String, usize>,
+    filter: &str,
+    f: impl FnMut(&PathBuf) -> bool,
+) {
+    let before = candidates.len();
+    candidates.retain(f);
+    if candidates.len() < before {
+        *removed.entry(filter.to_owned()).or_default() += before - candidates.len();
+    }
+}
+
+impl SelectionTrace {
+    /// Returns the trace of the monitor, or of all monitors if they were updated together
+    pub fn find<'a>(
+        traces: &'a BTreeMap<String, SelectionTrace>,
+        monitor: Option<&str>,
+    ) -> anyhow::Result<&'a SelectionTrace> {
+        match monitor {
+            Some(monitor) => traces
+                .get(monitor)
+                .ok_or_else(|| anyhow::anyhow!("monitor {} was not updated yet", monitor)),
+            None => {
+                let mut traces = traces.values();
+                let Some(first) = traces.next() else {
+                    anyhow::bail!("no monitor was updated yet");
+                };
+                if traces.any(|trace| trace != first) {
+                    anyhow::bail!("the monitors were updated separately, choose a monitor");
+                }
+                Ok(first)
+            }
+        }
+    }
+}
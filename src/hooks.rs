@@ -0,0 +1,162 @@
+//! Runs the commands of [`Hooks`] around changes of the wallpaper
+//!
+//! The `pre_change` hook runs before every change and may veto it by exiting with
+//! [`VETO_EXIT_CODE`]. It is waited for at most `pre_change_timeout`.
+//!
+//! The other hooks run after the wallpaper changed, every command with `sh -c` in its own
+//! thread, so a slow hook can't delay the daemon.
+//! A hook which runs longer than the `timeout` of the hooks gets killed.
+//! Failures are only logged, as the wallpaper already changed.
+
+use std::{
+    io::Write,
+    path::Path,
+    process::{Command, Stdio},
+    time::{Duration, Instant},
+};
+
+use tracing::{debug, error, warn};
+
+use crate::{Change, Hooks};
+
+/// The exit code of the `pre_change` hook which keeps the old image
+pub const VETO_EXIT_CODE: i32 = 10;
+
+/// Runs the `pre_change` hook and returns whether it vetoed the change of the monitor
+///
+/// If the hook fails in any other way, the change happens anyway.
+pub fn pre_change(hooks: &Hooks, monitor: &str, image: &Path) -> bool {
+    let Some(command) = &hooks.pre_change else {
+        return false;
+    };
+    debug!("running pre_change hook for monitor {}", monitor);
+    let child = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .env("WALLPAPER_MONITOR", monitor)
+        .env("WALLPAPER_IMAGE", image)
+        .stdin(Stdio::null())
+        .spawn();
+    let mut child = match child {
+        Ok(child) => child,
+        Err(e) => {
+            warn!("can't start pre_change hook, changing anyway: {}", e);
+            return false;
+        }
+    };
+
+    let timeout = *hooks.pre_change_timeout;
+    let deadline = Instant::now() + timeout;
+    let status = loop {
+        match child.try_wait() {
+            Ok(Some(status)) => break status,
+            Ok(None) => {}
+            Err(e) => {
+                warn!("can't wait for pre_change hook, changing anyway: {}", e);
+                return false;
+            }
+        }
+        if Instant::now() >= deadline {
+            let _ = child.kill();
+            let _ = child.wait();
+            warn!(
+                "pre_change hook timed out after {}, changing anyway",
+                humantime::format_duration(timeout)
+            );
+            return false;
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    };
+    match status.code() {
+        Some(0) => false,
+        Some(VETO_EXIT_CODE) => true,
+        _ => {
+            warn!("pre_change hook failed with {}, changing anyway", status);
+            false
+        }
+    }
+}
+
+/// Runs the `post_change` hooks for a monitor which shows a new image
+pub fn post_change(hooks: &Hooks, monitor: &str, image: &Path, transition: &str) {
+    for command in &hooks.post_change {
+        let mut child = Command::new("sh");
+        child
+            .arg("-c")
+            .arg(command)
+            .env("WALLPAPER_MONITOR", monitor)
+            .env("WALLPAPER_IMAGE", image)
+            .env("WALLPAPER_TRANSITION", transition);
+        spawn(command.clone(), child, *hooks.timeout, None);
+    }
+}
+
+/// Runs the `post_update` hooks with the changes of an update
+pub fn post_update(hooks: &Hooks, changes: &[Change]) {
+    if hooks.post_update.is_empty() {
+        return;
+    }
+    let input = match serde_json::to_string(changes) {
+        Ok(input) => input,
+        Err(e) => {
+            error!("can't serialize changes for the post_update hooks: {}", e);
+            return;
+        }
+    };
+    for command in &hooks.post_update {
+        let mut child = Command::new("sh");
+        child.arg("-c").arg(command);
+        spawn(command.clone(), child, *hooks.timeout, Some(input.clone()));
+    }
+}
+
+/// Runs the command in a background thread and kills it after the timeout
+pub fn spawn(name: String, mut command: Command, timeout: Duration, input: Option<String>) {
+    std::thread::spawn(move || {
+        debug!("running hook {}", name);
+        let stdin = if input.is_some() {
+            Stdio::piped()
+        } else {
+            Stdio::null()
+        };
+        let mut child = match command.stdin(stdin).spawn() {
+            Ok(child) => child,
+            Err(e) => {
+                error!("can't start hook {}: {}", name, e);
+                return;
+            }
+        };
+        if let (Some(input), Some(mut stdin)) = (input, child.stdin.take()) {
+            // a hook which doesn't read its input can't block the timeout
+            std::thread::spawn(move || {
+                let _ = stdin.write_all(input.as_bytes());
+            });
+        }
+
+        let deadline = Instant::now() + timeout;
+        let status = loop {
+            match child.try_wait() {
+                Ok(Some(status)) => break status,
+                Ok(None) => {}
+                Err(e) => {
+                    error!("can't wait for hook {}: {}", name, e);
+                    return;
+                }
+            }
+            if Instant::now() >= deadline {
+                let _ = child.kill();
+                let _ = child.wait();
+                warn!(
+                    "killed hook {} after {}",
+                    name,
+                    humantime::format_duration(timeout)
+                );
+                return;
+            }
+            std::thread::sleep(Duration::from_millis(50));
+        };
+        if !status.success() {
+            warn!("hook {} failed with {}", name, status);
+        }
+    });
+}
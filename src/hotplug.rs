@@ -0,0 +1,70 @@
+//! Notices when monitors get connected or disconnected
+
+use std::{
+    collections::BTreeSet,
+    time::{Duration, Instant},
+};
+
+use crate::query_monitors;
+
+/// The monitors which got connected or disconnected between two polls
+#[derive(Debug, Default, PartialEq)]
+pub struct Changes {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+}
+
+/// Returns the monitors which are only in `new` as added and the ones only in `old` as removed
+pub fn diff(old: &BTreeSet<String>, new: &BTreeSet<String>) -> Changes {
+    Changes {
+        added: new.difference(old).cloned().collect(),
+        removed: old.difference(new).cloned().collect(),
+    }
+}
+
+/// Queries the monitors regularly and compares them with the last known ones
+#[derive(Debug, Default)]
+pub struct Detector {
+    /// `None` until the monitors were queried successfully
+    connected: Option<BTreeSet<String>>,
+    last_poll: Option<Instant>,
+}
+
+impl Detector {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The monitors which were connected at the last poll
+    pub fn connected(&self) -> Option<&BTreeSet<String>> {
+        self.connected.as_ref()
+    }
+
+    /// When the monitors have to be queried again
+    pub fn next_poll(&self, interval: Duration) -> Instant {
+        self.last_poll
+            .map_or_else(Instant::now, |last_poll| last_poll + interval)
+    }
+
+    /// Queries the monitors if `interval` elapsed since the last poll
+    ///
+    /// Returns `None` if the monitors weren't queried.
+    /// The monitors of the first successful poll are known already, so they are no change.
+    pub fn poll(&mut self, interval: Duration) -> anyhow::Result<Option<Changes>> {
+        if self.next_poll(interval) > Instant::now() {
+            return Ok(None);
+        }
+        self.last_poll = Some(Instant::now());
+        let connected: BTreeSet<_> = query_monitors()?
+            .into_iter()
+            .map(|monitor| monitor.name)
+            .collect();
+        let changes = match &self.connected {
+            Some(old) => diff(old, &connected),
+            None => Changes::default(),
+        };
+        self.connected = Some(connected);
+
+        Ok(Some(changes))
+    }
+}
@@ -0,0 +1,119 @@
+//! `wallpaper init`, which writes a first config
+
+use std::{collections::BTreeMap, path::PathBuf};
+
+use anyhow::Context;
+use tracing::debug;
+use wallpaper::{
+    check_transition,
+    ipc::{self, IpcEvent, IpcResponse},
+    Config, State, TimeRef, TimeRefs, ValidTime,
+};
+
+use crate::{prompt, select::find_images};
+
+/// The transitions which `wallpaper init` proposes
+const TRANSITIONS: &[&str] = &["fade", "wipe", "grow"];
+
+/// Writes a config which shows all images of the directory
+///
+/// Fails if a config exists already, unless `force` is set.
+pub fn run(dir: Option<PathBuf>, force: bool, yes: bool) -> anyhow::Result<()> {
+    let config_file = State::config_file()?;
+    if config_file.is_file() && !force {
+        anyhow::bail!(
+            "config file {} exists already, use --force to overwrite it",
+            config_file.display()
+        );
+    }
+    let dir = match dir {
+        Some(dir) => dir,
+        None => PathBuf::from(prompt("directory with your wallpapers: ")?),
+    };
+    if dir.as_os_str().is_empty() {
+        anyhow::bail!("no directory given");
+    }
+    let dir = dir
+        .canonicalize()
+        .with_context(|| format!("can't read {}", dir.display()))?;
+
+    let mut config = Config {
+        image_dir: dir.clone(),
+        transitions: TRANSITIONS.iter().map(ToString::to_string).collect(),
+        ..Config::default()
+    };
+    let mut skipped = BTreeMap::new();
+    let times = TimeRefs::new(vec![TimeRef::Time(ValidTime::ALL)]);
+    let images = find_images(&config, &dir, &times, false, &mut skipped)?;
+    if images.is_empty() {
+        anyhow::bail!("no images found in {}", dir.display());
+    }
+    println!("found {} images in {}", images.len(), dir.display());
+    for (reason, count) in skipped {
+        println!("skipped {} files: {}", count, reason);
+    }
+    config.images = images
+        .into_iter()
+        .map(|(image, entry)| (config.image_key(&image), entry))
+        .collect();
+
+    if !yes {
+        let interval = prompt(&format!(
+            "change the wallpaper every [{}]: ",
+            config.update_interval
+        ))?;
+        if !interval.is_empty() {
+            config.update_interval = interval
+                .parse()
+                .with_context(|| format!("invalid interval {}", interval))?;
+        }
+        let transitions = prompt(&format!(
+            "transitions, separated by spaces [{}]: ",
+            config.transitions.join(" ")
+        ))?;
+        if !transitions.is_empty() {
+            let transitions: Vec<_> = transitions.split_whitespace().map(String::from).collect();
+            for transition in &transitions {
+                check_transition(transition)?;
+            }
+            config.transitions = transitions;
+        }
+    }
+
+    State::write_config(&config)?;
+    println!(
+        "wrote {}, it shows the images at all times and changes them every {}",
+        config_file.display(),
+        config.update_interval
+    );
+    println!("run `wallpaper check` after editing it to find mistakes");
+
+    match ipc::Client::connect() {
+        Ok(mut client) => {
+            match client.send(IpcEvent::Reload)? {
+                IpcResponse::Ok => {}
+                IpcResponse::Error(e) => anyhow::bail!("daemon returned error: {}", e),
+                response => anyhow::bail!("unexpected response from daemon: {:?}", response),
+            }
+            if yes || prompt("show a wallpaper now? [Y/n] ")?.to_lowercase() != "n" {
+                let event = IpcEvent::Switch {
+                    monitor: None,
+                    force: false,
+                    dry_run: false,
+                    transition: None,
+                    fps: None,
+                    now_or_fail: false,
+                };
+                if let IpcResponse::Error(e) = client.send(event)? {
+                    anyhow::bail!("daemon returned error: {}", e);
+                }
+            }
+        }
+        Err(e) => {
+            debug!("can't connect to daemon: {:#}", e);
+            println!("start `wallpaper daemon` to show the wallpapers");
+        }
+    }
+
+    Ok(())
+}
@@ -1,28 +1,128 @@
 use std::{
-    io::{BufRead, BufReader, Write},
+    collections::{BTreeMap, BTreeSet, VecDeque},
+    fs::File,
+    io::{BufRead, BufReader, ErrorKind, Read, Write},
     net::Shutdown,
-    os::unix::net::{UnixListener, UnixStream},
+    os::{
+        fd::AsRawFd,
+        unix::{
+            fs::FileExt,
+            net::{UnixListener, UnixStream},
+        },
+    },
     path::PathBuf,
     sync::mpsc::{channel, Receiver, Sender},
     thread,
+    time::{Duration, Instant, SystemTime},
 };
 
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
 use tracing::{debug, error, warn};
 
-#[must_use]
-fn get_socket_path() -> PathBuf {
-    let mut socket_path = if let Ok(dir) = std::env::var("XDG_RUNTIME_DIR") {
+use crate::{
+    explain::SelectionTrace, power::PowerProfile, Cache, Change, Config, HistoryEntry, Mode,
+};
+
+fn runtime_dir() -> PathBuf {
+    if let Ok(dir) = std::env::var("XDG_RUNTIME_DIR") {
         PathBuf::from(dir)
     } else {
         PathBuf::from("/tmp/wallpaper")
-    };
-    socket_path.push("wallpaper.socket");
+    }
+}
 
-    socket_path
+/// The socket on which the daemon listens
+#[must_use]
+pub fn socket_path() -> PathBuf {
+    runtime_dir().join("wallpaper.socket")
 }
 
-#[derive(Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+fn pid_path() -> PathBuf {
+    runtime_dir().join("wallpaper.pid")
+}
+
+/// Locks the file without waiting, returns `false` if another process holds the lock
+fn try_lock(file: &File) -> Result<bool> {
+    // SAFETY: the file descriptor is valid as long as `file` lives
+    if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } == 0 {
+        return Ok(true);
+    }
+    let error = std::io::Error::last_os_error();
+    if error.kind() == std::io::ErrorKind::WouldBlock {
+        Ok(false)
+    } else {
+        Err(error).context("can't lock the pid file")
+    }
+}
+
+/// Ensures that only one daemon runs at a time
+///
+/// The daemon holds an advisory lock on its pid file in the runtime dir.
+/// The kernel releases the lock when the file is closed, so also if the daemon panics or
+/// gets killed. A pid file which nobody locks is stale.
+#[derive(Debug)]
+pub struct InstanceLock {
+    file: File,
+}
+
+impl InstanceLock {
+    /// Takes the lock and writes the pid, or fails if another daemon holds the lock
+    pub fn acquire() -> Result<Self> {
+        let dir = runtime_dir();
+        std::fs::create_dir_all(&dir).context("while creating runtime dir")?;
+        let pid_path = pid_path();
+        let file = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&pid_path)
+            .with_context(|| format!("can't open pid file {}", pid_path.display()))?;
+        if !try_lock(&file)? {
+            match read_pid(&file) {
+                Some(pid) => bail!("daemon already running (pid {})", pid),
+                None => bail!("daemon already running"),
+            }
+        }
+        let lock = Self { file };
+        lock.write_pid()?;
+        Ok(lock)
+    }
+
+    /// Writes the pid of this process, which changes when the daemon detaches
+    pub fn write_pid(&self) -> Result<()> {
+        let pid = std::process::id().to_string();
+        self.file
+            .set_len(0)
+            .and_then(|()| self.file.write_all_at(pid.as_bytes(), 0))
+            .context("while writing pid file")
+    }
+
+    /// Returns the pid of the running daemon, or `None` if no daemon holds the lock
+    pub fn holder() -> Result<Option<u32>> {
+        let pid_path = pid_path();
+        let file = match File::open(&pid_path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e).with_context(|| format!("can't open {}", pid_path.display())),
+        };
+        if try_lock(&file)? {
+            debug!("ignoring stale pid file {}", pid_path.display());
+            return Ok(None);
+        }
+        read_pid(&file)
+            .map(Some)
+            .with_context(|| format!("daemon is running, but {} has no pid", pid_path.display()))
+    }
+}
+
+fn read_pid(mut file: &File) -> Option<u32> {
+    let mut pid = String::new();
+    file.read_to_string(&mut pid).ok()?;
+    pid.trim().parse().ok()
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
 pub enum IpcEvent {
     /// Reload the state
     Reload,
@@ -30,30 +130,452 @@ pub enum IpcEvent {
     Switch {
         /// Only switch the wallpaper for this monitor
         monitor: Option<String>,
+        /// Also switch monitors with a static wallpaper
+        #[serde(default)]
+        force: bool,
+        /// Only return the changes, without showing the images
+        #[serde(default)]
+        dry_run: bool,
+        /// Use this transition instead of the configured ones
+        #[serde(default)]
+        transition: Option<String>,
+        /// Use this fps instead of the configured one
+        #[serde(default)]
+        fps: Option<u8>,
+        /// Fail if the monitor is not connected instead of switching it when it appears
+        #[serde(default)]
+        now_or_fail: bool,
     },
     /// Select an image (or folder of images) which will be shown
     Select {
         path: String,
         /// whether to keep the old images
         keep_old: bool,
+        /// Only select the images for this monitor
+        #[serde(default)]
+        monitor: Option<String>,
+        /// The times of the images as in the config, always if `None`
+        #[serde(default)]
+        times: Option<String>,
+        /// Also select hidden files and files without an allowed extension
+        #[serde(default)]
+        all_files: bool,
+        /// How images outside of `image_dir` are brought into it, they are kept in place if `None`
+        #[serde(default)]
+        import: Option<ImportMode>,
+        /// Derive the times of the images from their names
+        #[serde(default)]
+        parse_names: bool,
+        /// Only return what would change
+        #[serde(default)]
+        dry_run: bool,
+    },
+    /// Set the mode and update all monitors
+    SetMode { mode: Mode },
+    /// Enable or disable the mirror mode and update all monitors
+    SetMirror { enabled: bool },
+    /// Change whether only favorites are shown and update all monitors
+    Favorites { action: FavoritesAction },
+    /// Show an image for a while, then the current image again
+    Preview {
+        /// Absolute path of the image
+        path: PathBuf,
+        /// Only show the image on this monitor
+        monitor: Option<String>,
+        duration: Duration,
+    },
+    /// Show an image now and remember it like a normal change
+    SetImage {
+        /// Absolute path of the image
+        path: PathBuf,
+        /// Only show the image on this monitor
+        monitor: Option<String>,
+        /// Use this transition instead of the configured ones
+        transition: Option<String>,
+        /// Also add the image to the config file
+        #[serde(default)]
+        add: bool,
+        /// Fail if the monitor is not connected instead of showing the image when it appears
+        #[serde(default)]
+        now_or_fail: bool,
+    },
+    /// Add an image to the config or change its times and weight
+    AddImage {
+        /// Absolute path of the image
+        path: PathBuf,
+        /// The times as in the config, always for new images if `None`
+        times: Option<String>,
+        weight: Option<u32>,
+    },
+    /// Remove the images which match a path or glob from the config
+    RemoveImages {
+        pattern: String,
+        /// Switch the monitors which show one of the images
+        switch: bool,
+    },
+    /// Replace the times of the configured images which match a path or glob
+    SetTimes {
+        pattern: String,
+        /// The times as in the config
+        times: String,
+        /// Add the times instead of replacing the old ones
+        append: bool,
+    },
+    /// Ask for the configured images
+    ListImages {
+        /// Only list the images which may be shown now
+        valid_now: bool,
+    },
+    /// Show the previous image again
+    Undo {
+        /// Only undo the change of this monitor
+        monitor: Option<String>,
+    },
+    /// Change the rating of the shown image by `delta`
+    Rate {
+        /// The monitor whose image is rated, only needed if the monitors show different images
+        monitor: Option<String>,
+        delta: i32,
+    },
+    /// Ask when the wallpaper changes next
+    NextChange,
+    /// A workspace got focused on an output
+    WorkspaceFocus { output: String, workspace: String },
+    /// These outputs show a fullscreen window now
+    Fullscreen { outputs: BTreeSet<String> },
+    /// These outputs are powered off now, e.g. by DPMS
+    OutputsOff { outputs: BTreeSet<String> },
+    /// Ask for the state of the daemon
+    Status,
+    /// Forget how often the images were shown
+    ResetStats,
+    /// Forget the cache entries of monitors which were not connected for long and of images
+    /// which don't exist anymore
+    PruneCache {
+        /// Only return what would be forgotten
+        #[serde(default)]
+        dry_run: bool,
+    },
+    /// Ask for the last images of all monitors
+    History,
+    /// Ask how the image of a monitor was chosen in the last update
+    Explain {
+        /// Only needed if the monitors were updated separately
+        monitor: Option<String>,
+    },
+    /// Ask for the state as the daemon holds it, for `wallpaper print`
+    State,
+    /// Save the cache and stop the daemon
+    Shutdown,
+    /// The config or cache file changed on disk
+    FilesChanged,
+    /// Files in the `image_dir` changed on disk
+    ImagesChanged {
+        /// The files which were written or moved into the `image_dir`
+        added: Vec<PathBuf>,
+        /// The files and directories which were deleted or moved away
+        removed: Vec<PathBuf>,
+    },
+    /// Replace the config and the cache with the ones of `wallpaper import`
+    Import {
+        config: Box<Config>,
+        /// The images are moved into the `image_dir` of the config already
+        cache: Box<Cache>,
+        /// Only add what is missing instead of replacing everything
+        merge: bool,
+    },
+    /// Keep the connection open and get [`IpcResponse::Changed`] after every change
+    Subscribe,
+}
+
+/// The answer of the daemon to an [`IpcEvent`]
+#[derive(Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub enum IpcResponse {
+    /// The event was handled
+    Ok,
+    /// Handling the event failed
+    Error(String),
+    /// The next time the wallpaper changes
+    NextChange {
+        at: SystemTime,
+        /// Why the wallpaper changes then
+        reason: String,
     },
+    Status(Status),
+    /// The changes a switch would make
+    Changes(Vec<Change>),
+    Explain(SelectionTrace),
+    /// Map from monitor to its last images, the newest first
+    History(BTreeMap<String, VecDeque<HistoryEntry>>),
+    /// The monitors which were forgotten and the number of forgotten cache entries of images,
+    /// or what would be forgotten in a dry run
+    Pruned {
+        monitors: Vec<String>,
+        entries: usize,
+    },
+    /// What a selection changed, or would change in a dry run
+    Selected(Selection),
+    /// Whether the added image was not configured before
+    Added {
+        new: bool,
+    },
+    Removed {
+        /// The full paths of the removed images
+        images: Vec<PathBuf>,
+        /// The monitors which were switched, or still show a removed image if they weren't
+        monitors: Vec<String>,
+    },
+    /// The full paths of the images whose times changed
+    TimesSet(Vec<PathBuf>),
+    Images(Vec<ImageInfo>),
+    State(Box<StateSummary>),
+    /// Pushed to subscribed clients after the wallpaper changed
+    Changed,
+}
+
+/// The result of `wallpaper select`
+#[derive(Debug, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Selection {
+    /// The number of selected images
+    pub images: usize,
+    /// The images which were not configured before
+    pub added: Vec<PathBuf>,
+    /// The number of configured images which were replaced by the selection
+    pub dropped: usize,
+    /// Map from reason to the number of files which were skipped because of it
+    pub skipped: BTreeMap<String, usize>,
+    /// Map from image to the name rule which gave its times
+    #[serde(default)]
+    pub matched: BTreeMap<PathBuf, String>,
+}
+
+/// How the favorites-only mode gets changed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FavoritesAction {
+    On,
+    Off,
+    Toggle,
+}
+
+impl std::str::FromStr for FavoritesAction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "on" => Ok(Self::On),
+            "off" => Ok(Self::Off),
+            "toggle" => Ok(Self::Toggle),
+            _ => Err(format!("invalid action {}, expected on, off or toggle", s)),
+        }
+    }
+}
+
+/// Changes when fields of [`StateSummary`] are renamed or removed
+pub const STATE_SCHEMA_VERSION: u32 = 1;
+
+/// Everything `wallpaper print` shows, so the json and the human output can't differ
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct StateSummary {
+    pub schema_version: u32,
+    /// Whether this is the state of the running daemon instead of the one in the files
+    pub live: bool,
+    pub config_file: PathBuf,
+    pub cache_file: PathBuf,
+    pub config: Config,
+    /// The update interval which applies now
+    pub effective_update_interval: String,
+    /// Map from configured image to its analyzed luminance
+    pub luminance: BTreeMap<PathBuf, f32>,
+    /// Sunrise and sunset of today, if a location is configured and the sun rises
+    pub sun_times: Option<(String, String)>,
+    pub cache: CacheSummary,
+    /// What only the daemon knows, `None` if the files were read
+    pub daemon: Option<DaemonSummary>,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct CacheSummary {
+    pub last_update: String,
+    pub mode: Option<Mode>,
+    pub mirror: Option<bool>,
+    pub favorites_only: bool,
+    pub monitors: BTreeMap<String, MonitorSummary>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct MonitorSummary {
+    pub image: Option<PathBuf>,
+    pub transition: Option<String>,
+    /// The last images, the newest first
+    pub history: Vec<PathBuf>,
+    pub dominant_color: Option<String>,
+    pub expires_at: Option<String>,
+    /// The last update of the monitor
+    pub last_update: Option<String>,
+    /// When the update interval of the monitor elapses
+    pub next_update: Option<String>,
+    /// The last image in sequential mode
+    pub cursor: Option<PathBuf>,
+    /// The number of remaining and of all images of this round in shuffle mode
+    pub shuffle_bag: Option<(usize, usize)>,
+}
+
+/// The state which the daemon only holds in memory
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct DaemonSummary {
+    pub status: Status,
+    /// Map from monitor to its focused workspace
+    pub workspaces: BTreeMap<String, String>,
+    /// The number of images found in the `image_sources`
+    pub source_images: usize,
+}
+
+/// A configured image, as listed by `wallpaper list-images`
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct ImageInfo {
+    /// The absolute path of the image
+    pub path: PathBuf,
+    /// The times as written in the config
+    pub times: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub weight: Option<u32>,
+    /// The monitor which has this image in its own set, `None` if all monitors may show it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub monitor: Option<String>,
+    pub exists: bool,
+}
+
+/// How selected images outside of `image_dir` are brought into it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ImportMode {
+    Copy,
+    Symlink,
+}
+
+impl std::str::FromStr for ImportMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "copy" => Ok(Self::Copy),
+            "symlink" => Ok(Self::Symlink),
+            _ => Err(format!(
+                "invalid import mode {}, expected copy or symlink",
+                s
+            )),
+        }
+    }
+}
+
+/// The state of the daemon
+#[derive(Debug, Clone, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Status {
+    /// Map from output to the reason why it isn't updated currently
+    pub inhibited: BTreeMap<String, String>,
+    /// Outputs which get updated when they are not inhibited anymore
+    pub deferred: BTreeSet<String>,
+    /// Whether only favorites are shown
+    #[serde(default)]
+    pub favorites_only: bool,
+    /// Not connected outputs which get updated when they appear
+    #[serde(default)]
+    pub pending: BTreeSet<String>,
+    /// Whether the overrides for running on battery apply
+    #[serde(default)]
+    pub power: PowerProfile,
+    /// Outputs whose last change was vetoed by the `pre_change` hook
+    #[serde(default)]
+    pub vetoed: BTreeSet<String>,
+    /// Map from output to when its update interval elapses
+    #[serde(default)]
+    pub next_updates: BTreeMap<String, String>,
+}
+
+/// An event received by the daemon, which has to be answered
+#[derive(Debug)]
+pub struct IpcRequest {
+    pub event: IpcEvent,
+    /// `None` for requests from inside the daemon
+    reply: Option<Sender<IpcResponse>>,
+    pub received: Instant,
+}
+
+impl IpcRequest {
+    /// Creates a request from inside the daemon, which doesn't need a reply
+    pub fn internal(event: IpcEvent) -> Self {
+        Self {
+            event,
+            reply: None,
+            received: Instant::now(),
+        }
+    }
+
+    pub fn reply(self, response: IpcResponse) {
+        let Some(reply) = self.reply else {
+            return;
+        };
+        if let Err(e) = reply.send(response) {
+            warn!("can't reply to client: {}", e);
+        }
+    }
+}
+
+/// The clients which subscribed with [`IpcEvent::Subscribe`]
+#[derive(Debug, Default)]
+pub struct Subscribers {
+    replies: Vec<Sender<IpcResponse>>,
+}
+
+impl Subscribers {
+    /// Confirms the subscription and keeps the reply channel of the request
+    pub fn add(&mut self, request: IpcRequest) {
+        let Some(reply) = request.reply else {
+            return;
+        };
+        if reply.send(IpcResponse::Ok).is_ok() {
+            self.replies.push(reply);
+        }
+    }
+
+    /// Tells every client about a change and forgets the disconnected ones
+    pub fn notify(&mut self) {
+        self.replies
+            .retain(|reply| reply.send(IpcResponse::Changed).is_ok());
+        debug!("notified {} subscribers", self.replies.len());
+    }
 }
 
 #[derive(Debug)]
 pub struct Listener {
-    inner: Receiver<IpcEvent>,
+    inner: Receiver<IpcRequest>,
+    sender: Sender<IpcRequest>,
     socket_path: PathBuf,
 }
 
 impl Listener {
+    /// Listens on the socket
+    ///
+    /// Only the daemon which holds the [`InstanceLock`] may bind, so a socket which is left
+    /// belongs to a killed daemon and is removed.
     pub fn bind() -> Result<Self> {
-        let socket_path = get_socket_path();
+        let socket_path = socket_path();
+        if socket_path.exists() {
+            warn!(
+                "removing the socket {} of a daemon which didn't stop cleanly",
+                socket_path.display()
+            );
+            std::fs::remove_file(&socket_path).context("while removing old socket")?;
+        }
         debug!("connecting listener to {}", socket_path.display());
         let listener = UnixListener::bind(&socket_path).context("connecting listener to socket")?;
 
         let (sender, recv) = channel();
 
+        let client_sender = sender.clone();
         thread::spawn(move || {
+            let sender = client_sender;
             for stream in listener.incoming() {
                 match stream {
                     Ok(stream) => {
@@ -67,13 +589,19 @@ impl Listener {
 
         Ok(Self {
             inner: recv,
+            sender,
             socket_path,
         })
     }
+
+    /// Returns a sender for requests from inside the daemon
+    pub fn sender(&self) -> Sender<IpcRequest> {
+        self.sender.clone()
+    }
 }
 
 impl std::ops::Deref for Listener {
-    type Target = Receiver<IpcEvent>;
+    type Target = Receiver<IpcRequest>;
 
     fn deref(&self) -> &Self::Target {
         &self.inner
@@ -87,7 +615,14 @@ impl Drop for Listener {
     }
 }
 
-fn handle_client(stream: UnixStream, sender: Sender<IpcEvent>) {
+fn handle_client(stream: UnixStream, sender: Sender<IpcRequest>) {
+    let mut writer = match stream.try_clone() {
+        Ok(writer) => writer,
+        Err(e) => {
+            error!("can't clone client stream: {}", e);
+            return;
+        }
+    };
     let mut buf = String::new();
     let mut stream = BufReader::new(stream);
     loop {
@@ -95,7 +630,7 @@ fn handle_client(stream: UnixStream, sender: Sender<IpcEvent>) {
             Ok(read) => {
                 if read == 0 {
                     // EOF
-                    continue;
+                    break;
                 }
             }
             Err(e) => {
@@ -103,80 +638,132 @@ fn handle_client(stream: UnixStream, sender: Sender<IpcEvent>) {
                 break;
             }
         };
-        if buf.is_empty() {
-            // TODO enable this
-            // debug!("empty message");
+        if buf.trim().is_empty() {
+            buf.clear();
             continue;
         }
-        match serde_json::from_str(&buf) {
-            Ok(msg) => {
-                if let Err(e) = sender.send(msg) {
+        // the responses of a subscription are forwarded until the daemon drops the sender
+        let mut subscription = None;
+        let response = match serde_json::from_str(&buf) {
+            Ok(event) => {
+                let subscribe = matches!(event, IpcEvent::Subscribe);
+                let (reply, reply_recv) = channel();
+                let request = IpcRequest {
+                    event,
+                    reply: Some(reply),
+                    received: Instant::now(),
+                };
+                if let Err(e) = sender.send(request) {
                     error!("can't send message to daemon receiver: {}", e);
                     return;
                 }
+                let response = match reply_recv.recv() {
+                    Ok(response) => response,
+                    Err(e) => {
+                        error!("daemon didn't reply: {}", e);
+                        return;
+                    }
+                };
+                if subscribe {
+                    subscription = Some(reply_recv);
+                }
+                response
             }
             Err(e) => {
                 error!("invalid ipc message: {}", e);
                 warn!("message was: {}", buf);
-                // TODO remove this
-                return;
+                IpcResponse::Error(format!("invalid ipc message: {}", e))
             }
         };
         buf.clear();
+
+        if let Err(e) = write_response(&mut writer, &response) {
+            error!("{:#}", e);
+            return;
+        }
+        if let Some(subscription) = subscription {
+            for response in subscription {
+                if let Err(e) = write_response(&mut writer, &response) {
+                    debug!("subscriber disconnected: {:#}", e);
+                    return;
+                }
+            }
+            // closing the connection tells the client that the daemon stopped
+            return;
+        }
     }
 }
 
+fn write_response(writer: &mut UnixStream, response: &IpcResponse) -> Result<()> {
+    let mut buf = serde_json::to_vec(response)
+        .with_context(|| format!("can't serialize response {:?}", response))?;
+    buf.push(b'\n');
+    writer
+        .write_all(&buf)
+        .context("can't send response to client")
+}
+
 pub struct Client {
-    inner: Sender<IpcEvent>,
-    // wait that the sended message gets actually send
-    fin_recv: Receiver<()>,
+    writer: UnixStream,
+    reader: BufReader<UnixStream>,
 }
 
 impl Client {
     pub fn connect() -> Result<Self> {
-        let socket_path = get_socket_path();
+        let socket_path = socket_path();
         debug!("connecting sender to {}", socket_path.display());
-        let mut stream = UnixStream::connect(socket_path).context("connecting sender to socket")?;
-
-        let (sender, recv) = channel();
-        let (fin_sender, fin_recv) = channel();
+        let writer = UnixStream::connect(socket_path).context("connecting sender to socket")?;
+        let reader = BufReader::new(writer.try_clone().context("cloning socket")?);
+        debug!("connected sender");
 
-        thread::spawn(move || {
-            for event in &recv {
-                debug!("received event");
-                let mut buf = match serde_json::to_vec(&event) {
-                    Ok(b) => b,
-                    Err(e) => {
-                        error!(
-                            "can't serialize event {:?} before sending it to socket: {}",
-                            event, e
-                        );
-                        continue;
-                    }
-                };
-                buf.push(b'\n');
-                debug!("sending message to daemon");
-                if let Err(e) = stream.write_all(&buf) {
-                    error!("can't send event {:?} to socket: {}", event, e);
-                }
-                let _ = fin_sender.send(());
-            }
-            warn!("ipc sender disconnected");
-            let _ = stream.shutdown(Shutdown::Write);
-        });
+        Ok(Self { writer, reader })
+    }
 
-        debug!("connected sender");
+    /// Sends the event to the daemon and waits for its response
+    pub fn send(&mut self, event: IpcEvent) -> Result<IpcResponse> {
+        let mut buf = serde_json::to_vec(&event)
+            .with_context(|| format!("can't serialize event {:?}", event))?;
+        buf.push(b'\n');
+        debug!("sending message to daemon");
+        self.writer
+            .write_all(&buf)
+            .with_context(|| format!("can't send event {:?} to socket", event))?;
 
-        Ok(Self {
-            inner: sender,
-            fin_recv,
-        })
+        let mut line = String::new();
+        if self
+            .reader
+            .read_line(&mut line)
+            .context("can't read response from socket")?
+            == 0
+        {
+            bail!("daemon closed the connection without responding");
+        }
+        serde_json::from_str(&line).context("invalid response from daemon")
     }
 
-    pub fn send(&self, event: IpcEvent) -> Result<()> {
-        self.inner.send(event)?;
-        self.fin_recv.recv()?;
+    /// Waits until the subscribed daemon pushes a change or the timeout elapsed
+    ///
+    /// Returns false if the daemon closed the connection, e.g. because it stopped.
+    pub fn wait_for_change(&mut self, timeout: Duration) -> Result<bool> {
+        self.reader
+            .get_ref()
+            .set_read_timeout(Some(timeout))
+            .context("can't set timeout of socket")?;
+        let mut line = String::new();
+        match self.reader.read_line(&mut line) {
+            Ok(0) => Ok(false),
+            Ok(_) => match serde_json::from_str(&line).context("invalid response from daemon")? {
+                IpcResponse::Changed => Ok(true),
+                response => bail!("unexpected response from daemon: {:?}", response),
+            },
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => Ok(true),
+            Err(e) => Err(e).context("can't read from socket"),
+        }
+    }
+}
 
-        Ok(())
+impl Drop for Client {
+    fn drop(&mut self) {
+        let _ = self.writer.shutdown(Shutdown::Write);
     }
 }
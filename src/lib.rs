@@ -1,30 +1,155 @@
+pub mod aspect;
+pub mod backup;
+pub mod brightness;
+mod colors;
 mod config;
+pub mod dedupe;
+mod effects;
+pub mod explain;
+mod hooks;
+pub mod hotplug;
 pub mod ipc;
+pub mod links;
+mod lockscreen;
+pub mod power;
+pub mod remote;
+mod scale;
+pub mod scheduler;
+mod selector;
+pub mod signals;
+pub mod sources;
+pub mod span;
+mod sun;
+#[cfg(feature = "sway-ipc")]
+pub mod sway;
+pub mod systemd;
+pub mod term;
+#[cfg(test)]
+mod test_util;
+pub mod validate;
+pub mod watch;
 
-use std::{collections::HashSet, path::PathBuf};
+use std::{
+    cell::RefCell,
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque},
+    path::{Path, PathBuf},
+    sync::{Mutex, PoisonError},
+    time::{Duration, Instant, SystemTime},
+};
 
 use anyhow::{bail, Context};
-use rand::{
-    rngs::ThreadRng,
-    seq::{IteratorRandom, SliceRandom},
+use rand::{rngs::StdRng, seq::SliceRandom, Rng};
+use tracing::{debug, error, info, trace, warn};
+
+use crate::{
+    explain::{SelectionTrace, Stage},
+    span::SpanLayout,
+};
+
+pub use crate::config::{
+    AspectTolerance, Brightness, Cache, Config, ContentHash, DateRange, DisplayStats, Effects,
+    FixAction, HistoryEntry, Hooks, ImageEntry, ImageFix, ImageSource, Location, Lockscreen,
+    LockscreenMode, Mode, Monitors, NightMaxBrightness, OnExit, OnStart, RatingFactor,
+    RemoteSource, SelectionMode, ShuffleBag, SortOrder, SpanGroup, SpanMonitor, State, TimeRef,
+    TimeRefs, TransitionOrder, ValidTime,
 };
-use tracing::{debug, error, info, trace};
 
-pub use crate::config::{Monitors, State, ValidTime};
+/// Fails if no wayland compositor can be reached
+pub fn check_wayland_display() -> anyhow::Result<()> {
+    if std::env::var_os("WAYLAND_DISPLAY").is_none() {
+        bail!(
+            "WAYLAND_DISPLAY is not set, so the compositor can't be reached. \
+            Start the daemon from your compositor, e.g. with `exec wallpaper daemon` in the sway config"
+        );
+    }
+    Ok(())
+}
 
-pub fn init_sww() -> anyhow::Result<()> {
+/// Starts swww if it isn't running and waits until it answers queries, but at most for `timeout`
+///
+/// Remembers in [`State::started_swww`] whether swww was started by this process.
+pub fn init_sww(state: &mut State, timeout: Duration) -> anyhow::Result<()> {
     debug!("initializing swww");
-    std::process::Command::new("swww")
-        .arg("init")
+    let running = std::process::Command::new("swww")
+        .arg("query")
         .output()
-        .context("while initializing swww")?;
+        .is_ok_and(|cmd| cmd.status.success());
+    if !running {
+        std::process::Command::new("swww")
+            .arg("init")
+            .output()
+            .context("while initializing swww")?;
+        state.started_swww = true;
+    }
+
+    let deadline = Instant::now() + timeout;
+    loop {
+        let cmd = std::process::Command::new("swww")
+            .arg("query")
+            .output()
+            .context("while waiting for swww")?;
+        if cmd.status.success() {
+            break;
+        }
+        if Instant::now() >= deadline {
+            bail!(
+                "swww is not ready after {}: {}",
+                humantime::format_duration(timeout),
+                String::from_utf8_lossy(&cmd.stderr).trim()
+            );
+        }
+        info!("waiting for swww to be ready");
+        std::thread::sleep(Duration::from_millis(500));
+    }
     debug!("initialized swww");
 
     Ok(())
 }
 
+/// Clears or kills swww as configured in `on_exit`, if it was started by this process
+pub fn exit_sww(state: &State) {
+    let command = match state.config.on_exit {
+        OnExit::Keep => return,
+        OnExit::Clear => "clear",
+        OnExit::Kill => "kill",
+    };
+    if !state.started_swww {
+        debug!(
+            "not running swww {}, as swww was started by someone else",
+            command
+        );
+        return;
+    }
+    info!("running swww {}", command);
+    match std::process::Command::new("swww").arg(command).output() {
+        Ok(cmd) if cmd.status.success() => {}
+        Ok(cmd) => error!(
+            "swww {} failed with {}: {}",
+            command,
+            cmd.status,
+            String::from_utf8_lossy(&cmd.stderr).trim()
+        ),
+        Err(e) => error!("can't run swww {}: {}", command, e),
+    }
+}
+
 pub fn get_monitors() -> anyhow::Result<HashSet<String>> {
-    info!("trying to query monitors");
+    Ok(query_monitors()?
+        .into_iter()
+        .map(|monitor| monitor.name)
+        .collect())
+}
+
+/// A monitor reported by `swww query`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MonitorInfo {
+    pub name: String,
+    /// Width and height in pixels, if swww reported them
+    pub size: Option<(u32, u32)>,
+}
+
+pub fn query_monitors() -> anyhow::Result<Vec<MonitorInfo>> {
+    debug!("trying to query monitors");
     let cmd = std::process::Command::new("swww")
         .arg("query")
         .output()
@@ -41,32 +166,114 @@ pub fn get_monitors() -> anyhow::Result<HashSet<String>> {
     stdout
         .lines()
         .map(|line| {
-            let (name, _rest) = line
+            // e.g. `DP-1: 1920x1080, scale: 1, currently displaying: image: /path/to/image`
+            let (name, rest) = line
                 .split_once(':')
                 .ok_or_else(|| anyhow::anyhow!("invalid line in output: {}", line))?;
-            Ok(name.to_owned())
+            let size = rest
+                .split(',')
+                .next()
+                .and_then(|size| size.trim().split_once('x'))
+                .and_then(|(width, height)| Some((width.parse().ok()?, height.parse().ok()?)));
+            Ok(MonitorInfo {
+                name: name.to_owned(),
+                size,
+            })
         })
         .collect()
 }
 
-pub fn update_wallpapers(state: &mut State, monitors: Monitors) -> anyhow::Result<()> {
-    let get_image = |mut images: HashSet<PathBuf>, rng: &mut ThreadRng| loop {
-        let image = images.iter().choose(rng).cloned();
-        if let Some(image) = image {
-            images.remove(&image);
-            if image.is_file() {
-                break Some(image);
-            } else {
-                error!("image {} does not exist!", image.to_string_lossy());
-            }
-        } else {
-            // imagees is empty
-            break None;
-        }
-    };
-    let connected_monitors = get_monitors()?;
-    let monitors = match monitors {
-        Monitors::All => connected_monitors.clone(),
+/// Returns the first candidate after `cursor` in the given order, wrapping around
+///
+/// If `cursor` isn't one of the `images`, the first candidate is returned.
+fn next_in_order(
+    mut images: Vec<PathBuf>,
+    sort: SortOrder,
+    cursor: Option<&Path>,
+    is_candidate: impl Fn(&PathBuf) -> bool,
+) -> Option<PathBuf> {
+    match sort {
+        SortOrder::Name => images.sort(),
+        SortOrder::Mtime => images.sort_by_cached_key(|image| {
+            let modified = image.metadata().and_then(|meta| meta.modified()).ok();
+            (modified, image.clone())
+        }),
+    }
+    let start = cursor
+        .and_then(|cursor| images.iter().position(|image| image == cursor))
+        .map_or(0, |i| i + 1);
+    images
+        .iter()
+        .cycle()
+        .skip(start)
+        .take(images.len())
+        .find(|image| is_candidate(image))
+        .cloned()
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct UpdateOptions {
+    /// Also change monitors which are configured with a static image
+    pub force: bool,
+    /// Also change monitors with a fullscreen window or which are powered off,
+    /// if `inhibit_on_fullscreen` or `skip_when_off` is set
+    pub ignore_inhibition: bool,
+    /// Only return the changes, without showing the images or changing the state
+    pub dry_run: bool,
+    /// Use this transition instead of the configured ones
+    pub transition: Option<String>,
+    /// Use this fps instead of the configured one
+    pub fps: Option<u8>,
+}
+
+/// An update of a monitor which happens as soon as the monitor is connected
+#[derive(Debug, Clone)]
+pub struct Pending {
+    /// The update is forgotten after this
+    pub until: Instant,
+    /// The image to show, or `None` to choose one
+    pub image: Option<PathBuf>,
+    pub options: UpdateOptions,
+}
+
+/// A change of the image of some monitors
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Change {
+    pub monitors: Vec<String>,
+    pub image: PathBuf,
+    pub transition: String,
+}
+
+/// Chooses new images for the monitors and shows them
+///
+/// Returns the monitors whose image changed.
+pub fn update_wallpapers(
+    state: &mut State,
+    monitors: Monitors,
+    options: UpdateOptions,
+) -> anyhow::Result<Vec<Change>> {
+    if options.dry_run {
+        // the clone chooses the same images, as it has the same random generator
+        return update(&mut state.clone(), monitors, options);
+    }
+    update(state, monitors, options)
+}
+
+fn update(
+    state: &mut State,
+    monitors: Monitors,
+    options: UpdateOptions,
+) -> anyhow::Result<Vec<Change>> {
+    let monitor_infos = query_monitors()?;
+    state.refresh_sources();
+    state.prune_display_stats();
+    let connected_monitors: HashSet<_> = monitor_infos
+        .iter()
+        .map(|monitor| monitor.name.clone())
+        .collect();
+    state.cache.saw_monitors(&connected_monitors);
+    let monitors: BTreeSet<_> = match monitors {
+        Monitors::All => connected_monitors.iter().cloned().collect(),
         Monitors::Some(monitors) => monitors
             .into_iter()
             .filter(|monitor| {
@@ -94,99 +301,1285 @@ pub fn update_wallpapers(state: &mut State, monitors: Monitors) -> anyhow::Resul
         }
     }
 
-    let last_images: HashSet<_> = state.cache.last_images.values().cloned().collect();
+    let mut targets = Vec::new();
     for monitor in monitors {
-        let last_image = state.cache.last_images.get(&monitor).cloned();
+        if state.previews.contains_key(&monitor) {
+            info!(
+                "deferring update of monitor {} because of a preview",
+                monitor
+            );
+            state.deferred.insert(monitor);
+            continue;
+        }
+        if let Some(reason) = state
+            .inhibition(&monitor)
+            .filter(|_| !options.ignore_inhibition)
+        {
+            info!("deferring update of monitor {}: {}", monitor, reason);
+            state.deferred.insert(monitor);
+            continue;
+        }
+        state.deferred.remove(&monitor);
+
+        if let Some(static_image) = state
+            .config
+            .static_image(&monitor)
+            .filter(|_| !options.force)
+        {
+            if Some(&static_image) == state.cache.last_images.get(&monitor) {
+                debug!("monitor {} already shows its static image", monitor);
+                continue;
+            }
+            if !static_image.is_file() {
+                error!(
+                    "static image {} for monitor {} does not exist!",
+                    static_image.to_string_lossy(),
+                    monitor
+                );
+                continue;
+            }
+        }
+        targets.push(monitor);
+    }
+
+    // the monitors of a span group show one panoramic image together, if all of them get updated
+    let mut groups: Vec<(Vec<String>, Option<SpanLayout>)> = Vec::new();
+    if cfg!(feature = "span") {
+        for span_group in &state.config.span_groups {
+            let complete = span_group.monitors.iter().all(|monitor| {
+                targets.contains(&monitor.name)
+                    && (options.force || state.config.static_image(&monitor.name).is_none())
+            });
+            if !complete {
+                continue;
+            }
+            let Some(layout) = SpanLayout::new(span_group, &monitor_infos) else {
+                warn!("the size of a monitor in a span group is unknown, updating them separately");
+                continue;
+            };
+            targets.retain(|monitor| !span_group.contains(monitor));
+            groups.push((layout.monitors().map(String::from).collect(), Some(layout)));
+        }
+    }
+    // in mirror mode all monitors without a static image show the same image
+    if state.mirrors() {
+        let (static_monitors, mirrored): (Vec<_>, Vec<_>) = targets
+            .into_iter()
+            .partition(|monitor| !options.force && state.config.static_image(monitor).is_some());
+        groups.extend(
+            static_monitors
+                .into_iter()
+                .map(|monitor| vec![monitor])
+                .chain(Some(mirrored).filter(|mirrored| !mirrored.is_empty()))
+                .map(|group| (group, None)),
+        );
+    } else {
+        groups.extend(targets.into_iter().map(|monitor| (vec![monitor], None)));
+    }
+    // strict monitors choose first, as they can show less images
+    groups.sort_by_key(|(group, _span)| {
+        !group
+            .iter()
+            .any(|monitor| state.config.is_orientation_strict(monitor))
+    });
+    let mut groups = VecDeque::from(groups);
+
+    let last_images: HashSet<_> = state.cache.last_images.values().cloned().collect();
+    // the images chosen in this update, which other monitors should not show too
+    let mut chosen = HashSet::new();
+    let mut changes = Vec::new();
+    // the error which stopped the update, the images shown until then are saved anyway
+    let mut error = None;
+    while let Some((group, span)) = groups.pop_front() {
+        let group_infos: Vec<_> = monitor_infos
+            .iter()
+            .filter(|info| group.contains(&info.name))
+            .cloned()
+            .collect();
+        let image = match choose_image(
+            state,
+            &group,
+            &options,
+            &group_infos,
+            span.as_ref(),
+            &last_images,
+            &chosen,
+        ) {
+            Ok(image) => image,
+            Err(e) => {
+                error = Some(e);
+                break;
+            }
+        };
+        let Some(image) = image else {
+            if span.is_some() {
+                debug!(
+                    "no panoramic image for {}, updating them separately",
+                    group.join(", ")
+                );
+                groups.extend(group.into_iter().map(|monitor| (vec![monitor], None)));
+            }
+            continue;
+        };
+
+        // a dry run doesn't run the hook, as it may have side effects
+        let vetoed: Vec<_> = group
+            .iter()
+            .filter(|monitor| {
+                !options.dry_run && state.cache.last_images.get(*monitor) != Some(&image)
+            })
+            .filter(|monitor| hooks::pre_change(&state.config.hooks, monitor, &image))
+            .cloned()
+            .collect();
+        for monitor in &vetoed {
+            info!("pre_change hook vetoed the change of monitor {}", monitor);
+            if let Some(trace) = state.traces.get_mut(monitor) {
+                trace.vetoed.clone_from(&vetoed);
+            }
+        }
+
+        // the monitors which don't show the image yet, with the file they have to show
+        let outdated = |monitor: &String| {
+            !vetoed.contains(monitor) && state.cache.last_images.get(monitor) != Some(&image)
+        };
+        let files: Vec<(Vec<String>, PathBuf)> = match &span {
+            // a dry run doesn't write the tiles and scaled images
+            _ if options.dry_run => {
+                let outputs: Vec<_> = group.iter().filter(|m| outdated(m)).cloned().collect();
+                if outputs.is_empty() {
+                    Vec::new()
+                } else {
+                    vec![(outputs, image.clone())]
+                }
+            }
+            Some(layout) => match span::tiles(&state.tile_dir(), &image, layout) {
+                Ok(tiles) => tiles
+                    .into_iter()
+                    .filter(|(monitor, _tile)| outdated(monitor))
+                    // the tiles have the size of their monitor already
+                    .flat_map(|(monitor, tile)| {
+                        prepare(state, &monitor_infos, vec![monitor], &tile, false)
+                    })
+                    .collect(),
+                Err(e) => {
+                    error!(
+                        "can't split {} into tiles: {:#}",
+                        image.to_string_lossy(),
+                        e
+                    );
+                    groups.extend(group.into_iter().map(|monitor| (vec![monitor], None)));
+                    continue;
+                }
+            },
+            None => {
+                let outputs: Vec<_> = group.iter().filter(|m| outdated(m)).cloned().collect();
+                if outputs.is_empty() {
+                    Vec::new()
+                } else {
+                    prepare(state, &monitor_infos, outputs, &image, true)
+                }
+            }
+        };
+        chosen.insert(image.clone());
+
+        // the cursors and bags of a group belong to its first monitor
+        let transition = match &options.transition {
+            Some(transition) => transition.clone(),
+            None => next_transition(state, &group[0]),
+        };
 
-        let now = chrono::offset::Local::now().time();
-        let valid_images = || {
+        if files.is_empty() && vetoed.is_empty() {
+            info!("not changing wallpaper because it is the same");
+        } else if !files.is_empty() {
+            for monitor in &group {
+                if let Some(trace) = state.traces.get_mut(monitor) {
+                    trace.transition = Some(transition.clone());
+                }
+            }
+            changes.push(Change {
+                monitors: files
+                    .iter()
+                    .flat_map(|(outputs, _file)| outputs.clone())
+                    .collect(),
+                image: image.clone(),
+                transition: transition.clone(),
+            });
+        }
+        if options.dry_run {
+            continue;
+        }
+        let fps = options.fps.unwrap_or(state.config.fps_in(state.power));
+        let sent: BTreeSet<String> = files
+            .iter()
+            .flat_map(|(outputs, _file)| outputs.clone())
+            .collect();
+        // the monitors which show the image now
+        let mut shown = BTreeSet::new();
+        for (outputs, file) in files {
+            match set_image(fps, &transition, &outputs, &file) {
+                Ok(true) => {}
+                Ok(false) => continue,
+                Err(e) => {
+                    error = Some(e);
+                    break;
+                }
+            }
+            for monitor in &outputs {
+                hooks::post_change(&state.config.hooks, monitor, &image, &transition);
+                lockscreen::update(state, &monitor_infos, monitor, &image);
+            }
+            shown.extend(outputs);
+        }
+        if shown.len() < sent.len() {
+            // the change of this group was pushed above
+            if let Some(change) = changes.last_mut() {
+                change.monitors.retain(|monitor| shown.contains(monitor));
+            }
+            changes.retain(|change| !change.monitors.is_empty());
+        }
+        if state.config.prescale {
+            // only after swww read the scaled images
+            let max_bytes = state.config.prescale_cache_mb * 1024 * 1024;
+            if let Err(e) = scale::evict(&state.scaled_dir(), max_bytes) {
+                error!("can't evict scaled images: {:#}", e);
+            }
+        }
+
+        // only after swww returned, so the change isn't delayed
+        let color = colors::dominant_color(&mut state.cache, &image);
+        for monitor in group {
+            if vetoed.contains(&monitor) {
+                state.vetoed.insert(monitor);
+                continue;
+            }
+            state.vetoed.remove(&monitor);
+            // the monitors which show the image already get updated as well
+            if sent.contains(&monitor) && !shown.contains(&monitor) {
+                continue;
+            }
             state
-                .config
-                .images
-                .iter()
-                .filter(|(path, time)| {
-                    let res = time.iter().any(|t| t.matches(&now));
-                    trace!("{} is valid? {}", path, res);
-                    res
-                })
-                .map(|(path, _time)| state.config.image_dir.join(path))
+                .cache
+                .update(monitor, image.clone(), transition.clone(), color.clone());
+        }
+        if error.is_some() {
+            break;
+        }
+    }
+    if !options.dry_run {
+        // once for all monitors, so an update with many monitors writes the cache only once
+        state.save().context("while saving cache")?;
+        if cfg!(feature = "colors") {
+            state.save_colors().context("while saving colors")?;
+        }
+        let connected = connected_monitors.into_iter().collect();
+        if let Err(e) = links::update(state, &connected) {
+            error!("can't update the links to the current images: {:#}", e);
+        }
+    }
+    if let Some(e) = error {
+        return Err(e);
+    }
+    if !options.dry_run && !changes.is_empty() {
+        hooks::post_update(&state.config.hooks, &changes);
+    }
+
+    Ok(changes)
+}
+
+/// The transition types which swww knows
+pub const TRANSITIONS: &[&str] = &[
+    "none", "simple", "fade", "left", "right", "top", "bottom", "wipe", "wave", "grow", "center",
+    "any", "outer", "random",
+];
+
+/// Fails if swww doesn't know the transition
+pub fn check_transition(transition: &str) -> anyhow::Result<()> {
+    if !TRANSITIONS.contains(&transition) {
+        bail!(
+            "unknown transition {}, expected one of {}",
+            transition,
+            TRANSITIONS.join(", ")
+        );
+    }
+    Ok(())
+}
+
+/// Chooses the transition for the next change of the monitor
+fn next_transition(state: &mut State, monitor: &str) -> String {
+    let transitions = state.config.transitions_in(state.power);
+    match state.config.transition_order {
+        TransitionOrder::Random => transitions.choose(&mut state.rng).cloned(),
+        TransitionOrder::Cycle => {
+            let index = state
+                .cache
+                .transition_indices
+                .entry(monitor.to_owned())
+                .or_default();
+            let transition = transitions.get(*index % transitions.len().max(1)).cloned();
+            *index = (*index + 1) % transitions.len().max(1);
+            transition
+        }
+    }
+    .unwrap_or_else(|| String::from("simple"))
+}
+
+/// Returns the monitor if it is connected, or all connected monitors if `monitor` is `None`
+fn connected(monitor_infos: &[MonitorInfo], monitor: Option<&str>) -> anyhow::Result<Vec<String>> {
+    Ok(match monitor {
+        Some(monitor) if !monitor_infos.iter().any(|info| info.name == monitor) => {
+            bail!("monitor {} is not connected", monitor)
+        }
+        Some(monitor) => vec![monitor.to_owned()],
+        None => monitor_infos
+            .iter()
+            .map(|monitor| monitor.name.clone())
+            .collect(),
+    })
+}
+
+/// Shows the previous image of the monitor again, or of all monitors if `monitor` is `None`
+///
+/// The previous image is shown with the transition which was used for the current one.
+/// Fails if no monitor has a previous image.
+pub fn undo(state: &mut State, monitor: Option<&str>) -> anyhow::Result<()> {
+    let monitor_infos = query_monitors()?;
+    let monitors: Vec<_> = match monitor {
+        Some(monitor) => vec![monitor.to_owned()],
+        None => monitor_infos
+            .iter()
+            .map(|monitor| monitor.name.clone())
+            .collect(),
+    };
+    // monitors with the same previous image and transition change together
+    let mut changes: BTreeMap<(PathBuf, String), Vec<String>> = BTreeMap::new();
+    for monitor in monitors {
+        let Some(image) = state
+            .cache
+            .history
+            .get(&monitor)
+            .and_then(|history| history.get(1))
+            .map(|entry| &entry.image)
+        else {
+            continue;
         };
-        let image = get_image(
-            // try valid images which were not used before first
-            valid_images()
-                .filter(|path| !last_images.contains(path))
-                .collect(),
-            &mut state.rng,
+        if !image.is_file() {
+            bail!(
+                "previous image {} of monitor {} does not exist",
+                image.display(),
+                monitor
+            );
+        }
+        let transition = state
+            .cache
+            .last_transitions
+            .get(&monitor)
+            .cloned()
+            .unwrap_or_else(|| String::from("none"));
+        changes
+            .entry((image.clone(), transition))
+            .or_default()
+            .push(monitor);
+    }
+    if changes.is_empty() {
+        match monitor {
+            Some(monitor) => bail!("monitor {} has no previous image", monitor),
+            None => bail!("no monitor has a previous image"),
+        }
+    }
+
+    for ((image, transition), monitors) in changes {
+        let mut shown = Vec::new();
+        for (outputs, file) in prepare(state, &monitor_infos, monitors, &image, true) {
+            if set_image(
+                state.config.fps_in(state.power),
+                &transition,
+                &outputs,
+                &file,
+            )? {
+                shown.extend(outputs);
+            }
+        }
+        let color = colors::dominant_color(&mut state.cache, &image);
+        for monitor in shown {
+            state.cache.undo(&monitor, color.clone());
+        }
+    }
+    state.save().context("while saving cache")?;
+    if cfg!(feature = "colors") {
+        state.save_colors().context("while saving colors")?;
+    }
+
+    Ok(())
+}
+
+/// Shows the image on the monitor, or on all monitors if `monitor` is `None`, without changing the cache
+///
+/// Returns the monitors which show the image now.
+/// Fails if the image doesn't exist or can't be decoded.
+pub fn preview(state: &State, image: &Path, monitor: Option<&str>) -> anyhow::Result<Vec<String>> {
+    if !image.is_file() {
+        bail!("image {} does not exist", image.display());
+    }
+    validate::check(image).with_context(|| format!("can't decode {}", image.display()))?;
+    let monitor_infos = query_monitors()?;
+    let monitors = connected(&monitor_infos, monitor)?;
+
+    let mut shown = Vec::new();
+    for (outputs, file) in prepare(state, &monitor_infos, monitors, image, true) {
+        if set_image(state.config.fps_in(state.power), "simple", &outputs, &file)? {
+            shown.extend(outputs);
+        }
+    }
+
+    Ok(shown)
+}
+
+/// Shows the image on the monitor, or on all monitors if `monitor` is `None`
+///
+/// Uses the `transition` or the next configured one.
+/// Unlike [`preview`], the image is remembered like a normal change, so it can be undone.
+/// The image is not added to the config.
+pub fn show_image(
+    state: &mut State,
+    image: &Path,
+    monitor: Option<&str>,
+    transition: Option<&str>,
+) -> anyhow::Result<()> {
+    if !image.is_file() {
+        bail!("image {} does not exist", image.display());
+    }
+    validate::check(image).with_context(|| format!("can't decode {}", image.display()))?;
+    if let Some(transition) = transition {
+        check_transition(transition)?;
+    }
+    let monitor_infos = query_monitors()?;
+    let monitors = connected(&monitor_infos, monitor)?;
+    let Some(first) = monitors.first() else {
+        bail!("no monitor is connected");
+    };
+    let transition = match transition {
+        Some(transition) => transition.to_owned(),
+        None => next_transition(state, first),
+    };
+
+    let mut shown = Vec::new();
+    for (outputs, file) in prepare(state, &monitor_infos, monitors, image, true) {
+        if set_image(
+            state.config.fps_in(state.power),
+            &transition,
+            &outputs,
+            &file,
+        )? {
+            shown.extend(outputs);
+        }
+    }
+    let color = colors::dominant_color(&mut state.cache, image);
+    for monitor in shown {
+        state
+            .cache
+            .update(monitor, image.to_owned(), transition.clone(), color.clone());
+    }
+    state.save().context("while saving cache")?;
+    if cfg!(feature = "colors") {
+        state.save_colors().context("while saving colors")?;
+    }
+
+    Ok(())
+}
+
+/// Shows the images of the cache on the monitors again, e.g. after a preview
+pub fn restore(state: &State, monitors: &[String]) -> anyhow::Result<()> {
+    let monitor_infos = query_monitors()?;
+    for monitor in monitors {
+        let Some(image) = state.cache.last_images.get(monitor) else {
+            continue;
+        };
+        for (outputs, file) in prepare(state, &monitor_infos, vec![monitor.clone()], image, true) {
+            set_image(state.config.fps_in(state.power), "simple", &outputs, &file)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Returns the files which the outputs have to show for the image
+///
+/// The image gets scaled to the size of the outputs if `prescale` is set and `scale` is true,
+/// then the effects of the outputs get applied.
+/// Outputs with the same size and effects are returned together.
+/// If this fails, the outputs get the original image.
+fn prepare(
+    state: &State,
+    monitors: &[MonitorInfo],
+    outputs: Vec<String>,
+    image: &Path,
+    scale: bool,
+) -> Vec<(Vec<String>, PathBuf)> {
+    let scale = scale && state.config.prescale && cfg!(feature = "prescale");
+    let mut variants: Vec<(_, Vec<String>)> = Vec::new();
+    for output in outputs {
+        let size = monitors
+            .iter()
+            .find(|monitor| monitor.name == output)
+            .and_then(|monitor| monitor.size)
+            .filter(|_| scale);
+        let effects = state
+            .config
+            .effects_for(&output)
+            .filter(|_| cfg!(feature = "effects"));
+        match variants
+            .iter_mut()
+            .find(|(other, _)| *other == (size, effects))
+        {
+            Some((_, outputs)) => outputs.push(output),
+            None => variants.push(((size, effects), vec![output])),
+        }
+    }
+
+    variants
+        .into_iter()
+        .map(|((size, effects), outputs)| {
+            let mut file = image.to_owned();
+            if let Some(size) = size {
+                match scale::scaled(&state.scaled_dir(), &file, size) {
+                    Ok(scaled) => file = scaled,
+                    Err(e) => error!("can't scale {}: {:#}", image.to_string_lossy(), e),
+                }
+            }
+            if let Some(effects) = effects {
+                match effects::apply(&state.effects_dir(), &file, &effects) {
+                    Ok(processed) => file = processed,
+                    Err(e) => error!(
+                        "can't apply effects to {}: {:#}",
+                        image.to_string_lossy(),
+                        e
+                    ),
+                }
+            }
+            (outputs, file)
+        })
+        .collect()
+}
+
+/// Shows the image on the outputs with swww
+///
+/// A single swww command is used for all outputs, so the transitions are synchronized.
+/// Returns whether swww succeeded.
+fn set_image(fps: u8, transition: &str, outputs: &[String], image: &Path) -> anyhow::Result<bool> {
+    info!(
+        "updating {} to {} with transition {}",
+        outputs.join(", "),
+        image.to_string_lossy(),
+        transition
+    );
+    // swww img --transition-step=2 --transition-fps=60 --transition-type any --outputs a,b image_path.jpg
+    let cmd = std::process::Command::new("swww")
+        .args(["img", "--transition-step=2", "--transition-fps"])
+        .arg(fps.to_string())
+        .arg("--transition-type")
+        .arg(transition)
+        .arg("--outputs")
+        .arg(outputs.join(","))
+        .arg(image)
+        .output()
+        .context("while executing swww")?;
+
+    if !cmd.status.success() {
+        error!(
+            "swww returned error. Exit Code: {}.\nStdout: {}\n\nStderr:{}",
+            cmd.status,
+            String::from_utf8_lossy(&cmd.stdout),
+            String::from_utf8_lossy(&cmd.stderr)
+        );
+    }
+
+    Ok(cmd.status.success())
+}
+
+/// Chooses one of the candidates randomly, with a probability proportional to its weight
+///
+/// All candidates are equally likely if no weight is positive.
+pub fn choose_weighted(
+    candidates: &[PathBuf],
+    weight: impl Fn(&PathBuf) -> f64,
+    rng: &mut impl Rng,
+) -> Option<PathBuf> {
+    candidates
+        .choose_weighted(rng, weight)
+        .ok()
+        .or_else(|| candidates.choose(rng))
+        .cloned()
+}
+
+/// Returns whether the image may be passed to swww
+///
+/// Why an image is filtered out gets logged only once per run.
+fn is_allowed(config: &Config, image: &Path) -> bool {
+    static LOGGED: Mutex<BTreeSet<PathBuf>> = Mutex::new(BTreeSet::new());
+
+    let Some(reason) = config.rejection(image) else {
+        return true;
+    };
+    let mut logged = LOGGED.lock().unwrap_or_else(PoisonError::into_inner);
+    if logged.insert(image.to_owned()) {
+        warn!("not showing {}, because {}", image.display(), reason);
+    }
+    false
+}
+
+/// Images with this rating or a lower one are only shown if no other image is left
+pub const BLACKLIST_RATING: i32 = -3;
+
+/// Chooses the next image for a group of monitors which show the same image
+///
+/// The group contains more than one monitor only in mirror mode,
+/// then the image has to fit the sizes of all of them, or for a span group,
+/// then only panoramic images which fit the `span` layout are chosen.
+/// The `chosen` images are only used if no other image is left,
+/// unless `allow_duplicate_across_monitors` is set.
+/// Images with a higher rating are chosen more often, see [`RatingFactor`].
+/// Returns `None` if no image has the orientation of a strict monitor
+/// or no panoramic image was found for the span group.
+fn choose_image(
+    state: &mut State,
+    group: &[String],
+    options: &UpdateOptions,
+    monitors: &[MonitorInfo],
+    span: Option<&SpanLayout>,
+    last_images: &HashSet<PathBuf>,
+    chosen: &HashSet<PathBuf>,
+) -> anyhow::Result<Option<PathBuf>> {
+    let selector_command = state.config.selector_command.clone();
+    let selector_timeout = *state.config.selector_timeout;
+    let selector_monitor = group.join(",");
+    let no_images = HashSet::new();
+    let excluded = if state.config.allow_duplicate_across_monitors {
+        &no_images
+    } else {
+        chosen
+    };
+    // images which are not hashed yet count as unique
+    let content_hashes: HashMap<PathBuf, String> = if state.config.dedupe {
+        state
+            .cache
+            .content_hashes
+            .keys()
+            .filter_map(|image| {
+                let hash = state.cache.content_hash_of(image)?;
+                Some((image.clone(), hash.to_owned()))
+            })
+            .collect()
+    } else {
+        HashMap::new()
+    };
+    let config = &state.config;
+    let ratings = state.cache.ratings.clone();
+    let rating = |image: &PathBuf| ratings.get(image).copied().unwrap_or(0);
+    let rating_factor = state.config.rating_factor;
+    // images without an entry, e.g. of the image sources, have a weight of 1
+    let weights: HashMap<PathBuf, f64> = config
+        .images
+        .iter()
+        .chain(
+            config
+                .monitor_settings
+                .values()
+                .flat_map(|settings| &settings.images),
         )
+        .filter_map(|(path, entry)| Some((config.image_dir.join(path), f64::from(entry.weight?))))
+        .collect();
+    let weight = |image: &PathBuf| {
+        rating_factor.weight(rating(image)) * weights.get(image).copied().unwrap_or(1.0)
+    };
+    // the stages which were tried, for `wallpaper explain`
+    let stages = RefCell::new(Vec::new());
+    let pick = |stage: &str,
+                images: HashSet<PathBuf>,
+                rng: &mut StdRng,
+                monitor: &str,
+                excluded: &HashSet<PathBuf>| {
+        let excluded_hashes: HashSet<_> = excluded
+            .iter()
+            .filter_map(|image| content_hashes.get(image))
+            .collect();
+        let considered = images.len();
+        let mut removed = BTreeMap::new();
+        let mut candidates: Vec<_> = images.into_iter().collect();
+        explain::retain(&mut candidates, &mut removed, "other monitor", |image| {
+            !excluded.contains(image)
+        });
+        explain::retain(&mut candidates, &mut removed, "other monitor", |image| {
+            content_hashes
+                .get(image)
+                .is_none_or(|hash| !excluded_hashes.contains(hash))
+        });
+        explain::retain(&mut candidates, &mut removed, "missing", |image| {
+            let exists = image.is_file();
+            if !exists {
+                error!("image {} does not exist!", image.to_string_lossy());
+            }
+            exists
+        });
+        explain::retain(
+            &mut candidates,
+            &mut removed,
+            "file type or size",
+            |image| is_allowed(config, image),
+        );
+        // duplicates are collapsed to the image with the smallest path
+        candidates.sort();
+        let mut seen = HashSet::new();
+        explain::retain(&mut candidates, &mut removed, "duplicate", |image| {
+            content_hashes
+                .get(image)
+                .is_none_or(|hash| seen.insert(hash))
+        });
+        // disliked images are only shown if no other image is left
+        if candidates
+            .iter()
+            .any(|image| rating(image) > BLACKLIST_RATING)
+        {
+            explain::retain(&mut candidates, &mut removed, "blacklist", |image| {
+                rating(image) > BLACKLIST_RATING
+            });
+        }
+        let selected = selector_command
+            .as_ref()
+            .filter(|_| !candidates.is_empty())
+            .and_then(|command| {
+                match selector::select(command, selector_timeout, monitor, &candidates) {
+                    Ok(image) => Some(image),
+                    Err(e) => {
+                        warn!("{:#}, choosing a random image instead", e);
+                        None
+                    }
+                }
+            });
+        let image = selected.or_else(|| choose_weighted(&candidates, weight, rng));
+        stages.borrow_mut().push(Stage {
+            removed,
+            ..Stage::new(stage, considered, image.is_some())
+        });
+        image
+    };
+    let get_image = |stage: &str, images: HashSet<PathBuf>, rng: &mut StdRng, monitor: &str| {
+        pick(stage, images, rng, monitor, excluded)
+    };
+
+    let monitor = group[0].clone();
+    let last_image = state.cache.last_images.get(&monitor).cloned();
+    let static_image = if group.len() == 1 {
+        state.config.static_image(&monitor)
+    } else {
+        None
+    };
+
+    // mirrored monitors can't show the images of their own workspaces
+    let workspace = state
+        .workspaces
+        .get(&monitor)
+        .map(String::as_str)
+        .filter(|_| group.len() == 1);
+    // mirrored monitors can't show their own images either
+    let own_monitor = Some(monitor.as_str()).filter(|_| group.len() == 1);
+    let images = state
+        .config
+        .images_for(workspace, own_monitor, &state.source_images);
+    let monitor_sizes: Vec<_> = match span {
+        Some(layout) => vec![(layout.width, layout.height)],
+        None => monitors.iter().filter_map(|monitor| monitor.size).collect(),
+    };
+    // the orientation of monitors with an unknown size is unknown too, so they can't be strict
+    let strict_orientations: Vec<_> = monitors
+        .iter()
+        .filter(|monitor| state.config.is_orientation_strict(&monitor.name))
+        .filter_map(|monitor| monitor.size)
+        .map(aspect::orientation)
+        .collect();
+    let strict = !strict_orientations.is_empty() && span.is_none();
+    // images with unknown dimensions fit every monitor
+    let fitting: Option<HashSet<_>> = match state.config.aspect_tolerance {
+        Some(AspectTolerance(tolerance)) if !monitor_sizes.is_empty() => Some(
+            images
+                .keys()
+                .map(|path| state.config.image_dir.join(path))
+                .filter(
+                    |image| match aspect::dimensions_of(&mut state.cache, image) {
+                        Some(dimensions) => monitor_sizes
+                            .iter()
+                            .all(|size| aspect::fits(dimensions, *size, tolerance)),
+                        None => true,
+                    },
+                )
+                .collect(),
+        ),
+        _ => None,
+    };
+    let fitting = fitting.as_ref();
+    let oriented: Option<HashSet<_>> = strict.then(|| {
+        images
+            .keys()
+            .map(|path| state.config.image_dir.join(path))
+            .filter(
+                |image| match aspect::dimensions_of(&mut state.cache, image) {
+                    Some(dimensions) => strict_orientations
+                        .iter()
+                        .all(|orientation| aspect::orientation(dimensions) == *orientation),
+                    None => true,
+                },
+            )
+            .collect()
+    });
+    let oriented = oriented.as_ref();
+    // images with unknown dimensions are never panoramic
+    let panoramic: Option<HashSet<_>> = span.map(|layout| {
+        images
+            .keys()
+            .map(|path| state.config.image_dir.join(path))
+            .filter(|image| {
+                aspect::dimensions_of(&mut state.cache, image).is_some_and(|dimensions| {
+                    aspect::fits(dimensions, (layout.width, layout.height), layout.tolerance)
+                })
+            })
+            .collect()
+    });
+    let panoramic = panoramic.as_ref();
+    // don't fall back to images which don't fit
+    let hard = strict || span.is_some();
+
+    let now = chrono::offset::Local::now().naive_local();
+    let mode = state.cache.mode;
+    let cache = &state.cache;
+    let night = state
+        .config
+        .night_max_brightness
+        .as_ref()
+        .filter(|night| night.times.matches(&now));
+    // the mode, brightness and aspect ratio get ignored if no image fits them
+    // in favorites-only mode the other images are only shown if no favorite is valid now
+    let favorites: Option<HashSet<_>> = state.cache.favorites_only.then(|| {
+        images
+            .iter()
+            .filter(|(_path, entry)| entry.is_valid_at(&now))
+            .map(|(path, _entry)| state.config.image_dir.join(path))
+            .filter(|image| state.is_favorite(image))
+            .collect()
+    });
+    let favorites = match favorites {
+        Some(favorites) if favorites.is_empty() => {
+            warn!("no favorite is valid now, showing the other images too");
+            None
+        }
+        favorites => favorites,
+    };
+    let favorites = favorites.as_ref();
+    // returns the first filter which removes the image
+    let filtered_by = |path: &Path,
+                       entry: &ImageEntry,
+                       image: &PathBuf,
+                       respect_filters: bool,
+                       respect_aspect: bool| {
+        if !entry.is_valid_at(&now) {
+            return Some("time");
+        }
+        if respect_filters && !entry.fits_mode(mode) {
+            return Some("mode");
+        }
+        if favorites.is_some_and(|favorites| !favorites.contains(image)) {
+            return Some("favorites");
+        }
+        if oriented.is_some_and(|oriented| !oriented.contains(image)) {
+            return Some("orientation");
+        }
+        if panoramic.is_some_and(|panoramic| !panoramic.contains(image)) {
+            return Some("panorama");
+        }
+        if respect_aspect && fitting.is_some_and(|fitting| !fitting.contains(image)) {
+            return Some("aspect ratio");
+        }
+        if let Some(night) = night.filter(|_| respect_filters) {
+            match cache.luminance_of(image) {
+                Some(luminance) if luminance > night.max => return Some("brightness"),
+                Some(_) => {}
+                None => debug!("brightness of {} is not analyzed yet", path.display()),
+            }
+        }
+        None
+    };
+    let valid_images = |respect_filters: bool, respect_aspect: bool| {
+        images
+            .iter()
+            .map(|(path, entry)| (state.config.image_dir.join(path), path, entry))
+            .filter(move |(image, path, entry)| {
+                match filtered_by(path, entry, image, respect_filters, respect_aspect) {
+                    Some(filter) => {
+                        trace!("{} is filtered out by {}", path.display(), filter);
+                        false
+                    }
+                    None => true,
+                }
+            })
+            .map(|(image, path, _entry)| (image, path))
+    };
+    let not_cooling_down = |(image, path): &(PathBuf, &PathBuf)| {
+        let res = !state
+            .cache
+            .is_cooling_down(image, state.config.cooldown_of(path));
+        trace!("{} is not cooling down? {}", path.display(), res);
+        res
+    };
+    // the current images of all monitors and the last images of the monitors in the group
+    let mut recent_images: HashSet<_> = group
+        .iter()
+        .filter_map(|monitor| state.cache.history.get(monitor))
+        .flat_map(|history| history.iter().take(state.config.history_size))
+        .map(|entry| &entry.image)
+        .chain(last_images)
+        .cloned()
+        .collect();
+    // images with the same content as a recent image are recent too
+    let recent_hashes: HashSet<_> = recent_images
+        .iter()
+        .filter_map(|image| content_hashes.get(image))
+        .collect();
+    let duplicates: Vec<_> = content_hashes
+        .iter()
+        .filter(|(_image, hash)| recent_hashes.contains(hash))
+        .map(|(image, _hash)| image.clone())
+        .collect();
+    recent_images.extend(duplicates);
+    let mut filtered: BTreeMap<String, usize> = BTreeMap::new();
+    for (path, entry) in images.iter() {
+        let image = state.config.image_dir.join(path);
+        let filter = match filtered_by(path, entry, &image, true, true) {
+            Some(filter) => filter,
+            None if !not_cooling_down(&(image.clone(), path)) => "cooldown",
+            None if recent_images.contains(&image) => "recently shown",
+            None => continue,
+        };
+        *filtered.entry(filter.to_owned()).or_default() += 1;
+    }
+    let mut bag = None;
+    if state.config.selection_mode == SelectionMode::Shuffle {
+        let pool: BTreeSet<_> = images
+            .keys()
+            .map(|path| state.config.image_dir.join(path))
+            .collect();
+        let mut current = state
+            .cache
+            .shuffle_bags
+            .get(&monitor)
+            .cloned()
+            .unwrap_or_default();
+        if current.pool != pool {
+            debug!(
+                "images of monitor {} changed, starting a new round",
+                monitor
+            );
+            current = ShuffleBag {
+                remaining: pool.clone(),
+                pool,
+            };
+        } else if !valid_images(true, true).any(|(image, _path)| current.remaining.contains(&image))
+        {
+            // images which are not valid now stay in the bag until the round is over
+            debug!(
+                "all valid images of monitor {} were shown, starting a new round",
+                monitor
+            );
+            current.remaining = current.pool.clone();
+        }
+        bag = Some(current);
+    }
+    let image = static_image
+        .filter(|_| !options.force)
+        .inspect(|_| stages.borrow_mut().push(Stage::new("static", 1, true)))
+        .or_else(|| {
+            // in sequential mode show the next valid image after the current one
+            if state.config.selection_mode != SelectionMode::Sequential {
+                return None;
+            }
+            let cursor = state.cache.cursors.get(&monitor).map(PathBuf::as_path);
+            let all: Vec<_> = images
+                .keys()
+                .map(|path| state.config.image_dir.join(path))
+                .collect();
+            let next = [(true, true), (true, false), (false, false)]
+                .into_iter()
+                .find_map(|(respect_filters, respect_aspect)| {
+                    let valid: HashSet<_> = valid_images(respect_filters, respect_aspect)
+                        .map(|(image, _path)| image)
+                        .collect();
+                    next_in_order(all.clone(), state.config.sort, cursor, |image| {
+                        valid.contains(image)
+                            && !excluded.contains(image)
+                            && image.is_file()
+                            && is_allowed(&state.config, image)
+                    })
+                });
+            stages
+                .borrow_mut()
+                .push(Stage::new("sequence", all.len(), next.is_some()));
+            next
+        })
+        .or_else(|| {
+            // in shuffle mode try valid images which were not shown in this round first
+            let bag = bag.as_ref()?;
+            get_image(
+                "shuffle bag",
+                valid_images(true, true)
+                    .filter(not_cooling_down)
+                    .map(|(image, _path)| image)
+                    .filter(|image| {
+                        bag.remaining.contains(image) && Some(image) != last_image.as_ref()
+                    })
+                    .collect(),
+                &mut state.rng,
+                &selector_monitor,
+            )
+        })
+        .or_else(|| {
+            // try valid images which were not used recently first
+            get_image(
+                "unused",
+                valid_images(true, true)
+                    .filter(not_cooling_down)
+                    .map(|(image, _path)| image)
+                    .filter(|image| !recent_images.contains(image))
+                    .collect(),
+                &mut state.rng,
+                &selector_monitor,
+            )
+        })
+        .or_else(|| {
+            // try valid images which were used before but whose cooldown is over next
+            get_image(
+                "not cooling down",
+                valid_images(true, true)
+                    .filter(not_cooling_down)
+                    .map(|(image, _path)| image)
+                    .collect(),
+                &mut state.rng,
+                &selector_monitor,
+            )
+        })
         .or_else(|| {
-            // try valid images which were used before next
-            get_image(valid_images().collect(), &mut state.rng)
+            // try all valid images which fit the mode, brightness and aspect ratio next
+            get_image(
+                "valid",
+                valid_images(true, true)
+                    .map(|(image, _path)| image)
+                    .collect(),
+                &mut state.rng,
+                &selector_monitor,
+            )
+        })
+        .or_else(|| {
+            // try all valid images which fit the mode and brightness next
+            get_image(
+                "valid in any aspect ratio",
+                valid_images(true, false)
+                    .map(|(image, _path)| image)
+                    .collect(),
+                &mut state.rng,
+                &selector_monitor,
+            )
+        })
+        .or_else(|| {
+            // try all valid images regardless of mode and brightness next
+            get_image(
+                "valid in any mode",
+                valid_images(false, false)
+                    .map(|(image, _path)| image)
+                    .collect(),
+                &mut state.rng,
+                &selector_monitor,
+            )
         })
         .or_else(|| {
-            // try all images next
+            // try all images next, but never with the wrong orientation or for a span group
+            if hard {
+                return None;
+            }
             get_image(
-                state
-                    .config
-                    .images
+                "all",
+                images
+                    .keys()
+                    .map(|path| state.config.image_dir.join(path))
+                    .collect(),
+                &mut state.rng,
+                &selector_monitor,
+            )
+        })
+        .or_else(|| {
+            // try valid images which other monitors show already next
+            pick(
+                "valid on other monitors",
+                valid_images(false, false)
+                    .map(|(image, _path)| image)
+                    .collect(),
+                &mut state.rng,
+                &selector_monitor,
+                &no_images,
+            )
+        })
+        .or_else(|| {
+            // try all images which other monitors show already next
+            if hard {
+                return None;
+            }
+            pick(
+                "all on other monitors",
+                images
                     .keys()
                     .map(|path| state.config.image_dir.join(path))
                     .collect(),
                 &mut state.rng,
+                &selector_monitor,
+                &no_images,
             )
         })
         .or_else(|| {
-            // try default image
+            // try default image, which is landscape
+            if hard {
+                return None;
+            }
             let default =
                 PathBuf::from("/usr/share/backgrounds/sway/Sway_Wallpaper_Blue_1920x1080.png");
-            if default.is_file() {
-                Some(default)
-            } else {
-                None
-            }
+            let exists = default.is_file();
+            stages
+                .borrow_mut()
+                .push(Stage::new("default image", 1, exists));
+            exists.then_some(default)
         });
-        let Some(image) = image else {
-            bail!("no valid image found")
+    let trace = SelectionTrace {
+        at: SystemTime::now(),
+        monitors: group.to_vec(),
+        images: images.len(),
+        filtered,
+        stages: stages.into_inner(),
+        image: image.clone(),
+        transition: None,
+        vetoed: Vec::new(),
+    };
+    for monitor in group {
+        state.traces.insert(monitor.clone(), trace.clone());
+    }
+    let Some(image) = image else {
+        if span.is_some() {
+            return Ok(None);
+        }
+        if strict {
+            error!(
+                "no valid image has the orientation of {}, keeping the current image",
+                selector_monitor
+            );
+            return Ok(None);
+        }
+        bail!("no valid image found")
+    };
+    if state.config.selection_mode == SelectionMode::Sequential {
+        state.cache.cursors.insert(monitor.clone(), image.clone());
+    }
+    if let Some(mut bag) = bag {
+        bag.remaining.remove(&image);
+        state.cache.shuffle_bags.insert(monitor, bag);
+    }
+
+    Ok(Some(image))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    /// A state with the images in a temporary image dir, valid at all times
+    fn state(dir: &TempDir, images: &[&str]) -> State {
+        let images = images
+            .iter()
+            .map(|image| {
+                dir.touch(image);
+                (PathBuf::from(image), ImageEntry::new(vec![ValidTime::ALL]))
+            })
+            .collect();
+        let config = Config {
+            image_dir: dir.path().to_owned(),
+            images,
+            ..Config::default()
         };
-        let transition = state
-            .config
-            .transitions
-            .choose(&mut state.rng)
-            .cloned()
-            .unwrap_or_else(|| String::from("simple"));
+        let mut state = State::new(config, Cache::default()).unwrap();
+        state.seed(0);
+        state
+    }
 
-        // swww img --transition-step=2 --transition-fps=60 --transition-type any --output monitor image_path.jpg
-        if Some(&image) != last_image.as_ref() {
-            info!(
-                "updating to {} with transition {}",
-                image.to_string_lossy(),
-                &transition
-            );
-            let cmd = std::process::Command::new("swww")
-                .args(["img", "--transition-step=2", "--transition-fps"])
-                .arg(state.config.fps.to_string())
-                .arg("--transition-type")
-                .arg(&transition)
-                .arg("--outputs")
-                .arg(&monitor)
-                .arg(&image)
-                .output()
-                .context("while executing swww")?;
-
-            if !cmd.status.success() {
-                error!(
-                    "swww returned error. Exit Code: {}.\nStdout: {}\n\nStderr:{}",
-                    cmd.status,
-                    String::from_utf8_lossy(&cmd.stdout),
-                    String::from_utf8_lossy(&cmd.stderr)
-                );
+    /// Chooses an image for each of the monitors, like one update without mirroring
+    fn choose(state: &mut State, monitors: &[&str]) -> Vec<PathBuf> {
+        let mut chosen = HashSet::new();
+        let mut images = Vec::new();
+        for monitor in monitors {
+            let image = choose_image(
+                state,
+                &[monitor.to_string()],
+                &UpdateOptions::default(),
+                &[],
+                None,
+                &HashSet::new(),
+                &chosen,
+            )
+            .unwrap()
+            .unwrap();
+            state
+                .cache
+                .update(monitor.to_string(), image.clone(), String::new(), None);
+            chosen.insert(image.clone());
+            images.push(image);
+        }
+        images
+    }
+
+    #[test]
+    fn shuffle_shows_every_image_once_per_round() {
+        let dir = TempDir::new();
+        let names = ["a.png", "b.png", "c.png", "d.png", "e.png"];
+        for seed in 0..20 {
+            let mut state = state(&dir, &names);
+            state.config.selection_mode = SelectionMode::Shuffle;
+            state.seed(seed);
+            let all: BTreeSet<_> = names.iter().map(|name| dir.path().join(name)).collect();
+            let mut last = None;
+            for round in 0..3 {
+                let shown: Vec<_> = (0..names.len())
+                    .map(|_| choose(&mut state, &["DP-1"]).remove(0))
+                    .collect();
+                let unique: BTreeSet<_> = shown.iter().cloned().collect();
+                assert_eq!(unique, all, "seed {} round {}", seed, round);
+                // a new round doesn't start with the last image of the previous one
+                assert_ne!(last.as_ref(), shown.first(), "seed {}", seed);
+                last = shown.last().cloned();
             }
-        } else {
-            info!("not changing wallpaper because it is the same");
         }
+    }
 
-        state.cache.update(monitor, image, transition);
-        state.save().context("while saving cache")?;
+    #[test]
+    fn monitors_get_distinct_images_while_possible() {
+        let dir = TempDir::new();
+        for seed in 0..20 {
+            let mut state = state(&dir, &["a.png", "b.png"]);
+            state.seed(seed);
+            let images = choose(&mut state, &["DP-1", "DP-2", "DP-3"]);
+            assert_ne!(images[0], images[1], "seed {}", seed);
+            // the third monitor reuses one of the images instead of failing
+            assert!(images[..2].contains(&images[2]), "seed {}", seed);
+        }
     }
 
-    Ok(())
+    #[test]
+    fn monitors_may_share_images_if_allowed() {
+        let dir = TempDir::new();
+        let mut state = state(&dir, &["a.png"]);
+        state.config.allow_duplicate_across_monitors = true;
+        let images = choose(&mut state, &["DP-1", "DP-2", "DP-3"]);
+        assert_eq!(images, vec![dir.path().join("a.png"); 3]);
+    }
 }
@@ -0,0 +1,203 @@
+//! Keeps symlinks to the shown images in the cache directory
+//!
+//! `current-<monitor>` points at the image of every connected monitor and `current` at the one
+//! of the `primary_monitor`, so e.g. screen lockers have a stable path to the wallpaper.
+//! The links are replaced atomically by renaming, so readers never see a missing link.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+use tracing::debug;
+
+use crate::State;
+
+const PREFIX: &str = "current-";
+const PRIMARY: &str = "current";
+
+/// Points the links at the images of the connected monitors and removes the other links
+///
+/// Does nothing if `current_links` is disabled.
+pub fn update(state: &State, connected: &BTreeSet<String>) -> anyhow::Result<()> {
+    if !state.config.current_links {
+        return Ok(());
+    }
+    update_dir(
+        &state.links_dir(),
+        &state.cache.last_images,
+        state.config.primary_monitor.as_deref(),
+        connected,
+    )
+}
+
+/// Updates the links in `dir`, see [`update`]
+fn update_dir(
+    dir: &Path,
+    last_images: &BTreeMap<String, PathBuf>,
+    primary_monitor: Option<&str>,
+    connected: &BTreeSet<String>,
+) -> anyhow::Result<()> {
+    std::fs::create_dir_all(dir).with_context(|| format!("while creating {}", dir.display()))?;
+
+    let images: Vec<(&String, &PathBuf)> = last_images
+        .iter()
+        .filter(|(monitor, _image)| connected.contains(*monitor))
+        .collect();
+    for (monitor, image) in &images {
+        link(&dir.join(format!("{}{}", PREFIX, monitor)), image)?;
+    }
+    let primary = primary_monitor
+        .and_then(|primary| images.iter().find(|(monitor, _image)| *monitor == primary))
+        .or_else(|| images.first());
+    match primary {
+        Some((_monitor, image)) => link(&dir.join(PRIMARY), image)?,
+        None => remove(&dir.join(PRIMARY))?,
+    }
+
+    for entry in
+        std::fs::read_dir(dir).with_context(|| format!("while reading {}", dir.display()))?
+    {
+        let entry = entry.context("while reading directory entry")?;
+        let name = entry.file_name();
+        let Some(monitor) = name.to_str().and_then(|name| name.strip_prefix(PREFIX)) else {
+            continue;
+        };
+        if !images
+            .iter()
+            .any(|(connected, _image)| *connected == monitor)
+        {
+            debug!("removing link of monitor {}", monitor);
+            remove(&entry.path())?;
+        }
+    }
+    Ok(())
+}
+
+/// Replaces the link atomically, if it doesn't point at the image yet
+pub(crate) fn link(path: &Path, image: &Path) -> anyhow::Result<()> {
+    if std::fs::read_link(path).is_ok_and(|target| target == image) {
+        return Ok(());
+    }
+    let tmp = tmp_path(path);
+    remove(&tmp)?;
+    std::os::unix::fs::symlink(image, &tmp)
+        .with_context(|| format!("while creating link {}", tmp.display()))?;
+    std::fs::rename(&tmp, path)
+        .with_context(|| format!("while replacing link {}", path.display()))?;
+    debug!("linked {} to {}", path.display(), image.display());
+    Ok(())
+}
+
+/// A hidden file next to the path, which replaces it by renaming
+pub(crate) fn tmp_path(path: &Path) -> PathBuf {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    path.with_file_name(format!(".{}.tmp", name))
+}
+
+pub(crate) fn remove(path: &Path) -> anyhow::Result<()> {
+    match std::fs::remove_file(path) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+            Err(e).with_context(|| format!("while removing {}", path.display()))
+        }
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    fn images(dir: &TempDir, monitors: &[(&str, &str)]) -> BTreeMap<String, PathBuf> {
+        monitors
+            .iter()
+            .map(|(monitor, image)| (monitor.to_string(), dir.touch(image)))
+            .collect()
+    }
+
+    fn connected(monitors: &[&str]) -> BTreeSet<String> {
+        monitors.iter().map(|monitor| monitor.to_string()).collect()
+    }
+
+    fn target(dir: &Path, name: &str) -> Option<PathBuf> {
+        std::fs::read_link(dir.join(name)).ok()
+    }
+
+    #[test]
+    fn links_the_connected_monitors() {
+        let dir = TempDir::new();
+        let links = dir.path().join("links");
+        let images = images(&dir, &[("DP-1", "a.png"), ("HDMI-A-1", "b.png")]);
+
+        update_dir(&links, &images, None, &connected(&["DP-1", "HDMI-A-1"])).unwrap();
+
+        assert_eq!(target(&links, "current-DP-1").as_ref(), images.get("DP-1"));
+        assert_eq!(
+            target(&links, "current-HDMI-A-1").as_ref(),
+            images.get("HDMI-A-1")
+        );
+    }
+
+    #[test]
+    fn replaces_links_to_old_images() {
+        let dir = TempDir::new();
+        let links = dir.path().join("links");
+        let old = images(&dir, &[("DP-1", "a.png")]);
+        update_dir(&links, &old, None, &connected(&["DP-1"])).unwrap();
+
+        let new = images(&dir, &[("DP-1", "b.png")]);
+        update_dir(&links, &new, None, &connected(&["DP-1"])).unwrap();
+
+        assert_eq!(target(&links, "current-DP-1").as_ref(), new.get("DP-1"));
+        assert_eq!(target(&links, PRIMARY).as_ref(), new.get("DP-1"));
+        assert!(!tmp_path(&links.join("current-DP-1")).exists());
+    }
+
+    #[test]
+    fn current_points_at_the_primary_monitor() {
+        let dir = TempDir::new();
+        let links = dir.path().join("links");
+        let images = images(&dir, &[("DP-1", "a.png"), ("HDMI-A-1", "b.png")]);
+        let connected = connected(&["DP-1", "HDMI-A-1"]);
+
+        update_dir(&links, &images, Some("HDMI-A-1"), &connected).unwrap();
+        assert_eq!(target(&links, PRIMARY).as_ref(), images.get("HDMI-A-1"));
+
+        // without a connected primary monitor the first one is used
+        update_dir(&links, &images, Some("eDP-1"), &connected).unwrap();
+        assert_eq!(target(&links, PRIMARY).as_ref(), images.get("DP-1"));
+    }
+
+    #[test]
+    fn removes_links_of_disconnected_monitors() {
+        let dir = TempDir::new();
+        let links = dir.path().join("links");
+        let images = images(&dir, &[("DP-1", "a.png"), ("HDMI-A-1", "b.png")]);
+        update_dir(&links, &images, None, &connected(&["DP-1", "HDMI-A-1"])).unwrap();
+
+        update_dir(&links, &images, None, &connected(&["HDMI-A-1"])).unwrap();
+        assert_eq!(target(&links, "current-DP-1"), None);
+        assert_eq!(
+            target(&links, "current-HDMI-A-1").as_ref(),
+            images.get("HDMI-A-1")
+        );
+        assert_eq!(target(&links, PRIMARY).as_ref(), images.get("HDMI-A-1"));
+
+        update_dir(&links, &images, None, &connected(&[])).unwrap();
+        assert_eq!(target(&links, "current-HDMI-A-1"), None);
+        assert_eq!(target(&links, PRIMARY), None);
+    }
+
+    #[test]
+    fn keeps_other_files() {
+        let dir = TempDir::new();
+        let links = dir.path().join("links");
+        std::fs::create_dir_all(&links).unwrap();
+        std::fs::write(links.join("cache.json"), "{}").unwrap();
+
+        update_dir(&links, &BTreeMap::new(), None, &connected(&[])).unwrap();
+        assert!(links.join("cache.json").exists());
+    }
+}
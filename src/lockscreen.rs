@@ -0,0 +1,83 @@
+//! Keeps the image of the lock screen in sync with the wallpaper
+//!
+//! Failures are only logged, as the wallpaper already changed.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use tracing::{debug, warn};
+
+use crate::{hooks, links, scale, LockscreenMode, MonitorInfo, State};
+
+const MONITOR: &str = "{monitor}";
+
+/// Updates the lock screen after the monitor got the image
+pub fn update(state: &State, monitor_infos: &[MonitorInfo], monitor: &str, image: &Path) {
+    let Some(lockscreen) = &state.config.lockscreen else {
+        return;
+    };
+    let per_monitor = lockscreen.target.contains(MONITOR);
+    if !per_monitor
+        && state
+            .config
+            .primary_monitor
+            .as_ref()
+            .is_some_and(|primary| primary != monitor)
+    {
+        return;
+    }
+    let target = lockscreen.target.replace(MONITOR, monitor);
+
+    let result = match lockscreen.mode {
+        LockscreenMode::Symlink => {
+            prepare_dir(Path::new(&target)).and_then(|()| links::link(Path::new(&target), image))
+        }
+        LockscreenMode::Copy => {
+            let size = monitor_infos
+                .iter()
+                .find(|info| info.name == monitor)
+                .and_then(|info| info.size)
+                .filter(|_| lockscreen.scale && cfg!(feature = "prescale"));
+            copy(state, image, size, Path::new(&target))
+        }
+        LockscreenMode::Command => {
+            let mut command = std::process::Command::new("sh");
+            command
+                .arg("-c")
+                .arg(&target)
+                .env("WALLPAPER_MONITOR", monitor)
+                .env("WALLPAPER_IMAGE", image);
+            hooks::spawn(target.clone(), command, *state.config.hooks.timeout, None);
+            Ok(())
+        }
+    };
+    match result {
+        Ok(()) => debug!("updated lock screen image of monitor {}", monitor),
+        Err(e) => warn!("can't update the lock screen image: {:#}", e),
+    }
+}
+
+/// Replaces the target atomically with a copy of the image, scaled to the size if it is known
+fn copy(
+    state: &State,
+    image: &Path,
+    size: Option<(u32, u32)>,
+    target: &Path,
+) -> anyhow::Result<()> {
+    let source: PathBuf = match size {
+        Some(size) => scale::scaled(&state.scaled_dir(), image, size)?,
+        None => image.to_owned(),
+    };
+    prepare_dir(target)?;
+    let tmp = links::tmp_path(target);
+    std::fs::copy(&source, &tmp)
+        .with_context(|| format!("while copying {} to {}", source.display(), tmp.display()))?;
+    std::fs::rename(&tmp, target).with_context(|| format!("while replacing {}", target.display()))
+}
+
+fn prepare_dir(target: &Path) -> anyhow::Result<()> {
+    let Some(dir) = target.parent().filter(|dir| !dir.as_os_str().is_empty()) else {
+        return Ok(());
+    };
+    std::fs::create_dir_all(dir).with_context(|| format!("while creating {}", dir.display()))
+}
@@ -1,40 +1,53 @@
-use std::{collections::BTreeMap, path::Path, sync::mpsc::RecvTimeoutError};
+mod check;
+mod daemon;
+mod doctor;
+mod init;
+mod print;
+mod select;
+mod transfer;
+mod waybar;
+
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, BTreeSet, VecDeque},
+    io::Write,
+    os::fd::AsRawFd,
+    path::{Path, PathBuf},
+    time::{Duration, Instant, SystemTime},
+};
 
 use anyhow::Context;
-use clap::{Parser, Subcommand};
-use tracing::{debug, error, info, warn, Level};
+use clap::{CommandFactory, Parser, Subcommand};
+use tracing::{debug, info, Level};
 use tracing_subscriber::{
     filter::LevelFilter, fmt::writer::MakeWriterExt, layer::SubscriberExt, util::SubscriberInitExt,
     Layer,
 };
 
 use wallpaper::{
-    get_monitors, init_sww,
-    ipc::{self, IpcEvent},
-    update_wallpapers, Monitors, State, ValidTime,
+    check_transition, check_wayland_display, init_sww,
+    ipc::{self, FavoritesAction, ImageInfo, ImportMode, IpcEvent, IpcResponse},
+    query_monitors,
+    scheduler::{self, estimate_next_update, next_change},
+    show_image, term, update_wallpapers, Change, Config, HistoryEntry, Mode, Monitors, Pending,
+    State, TimeRef, TimeRefs, UpdateOptions, BLACKLIST_RATING,
 };
 
-fn init_logging() -> anyhow::Result<()> {
-    let project_dirs = State::project_dirs()?;
+/// Logs into the log file in `log_dir` and, unless the daemon is detached, to stdout
+fn init_logging(stdout: bool, log_dir: &Path) -> anyhow::Result<()> {
     tracing_subscriber::registry()
-        .with(
-            tracing_subscriber::fmt::layer().with_writer(
-                tracing_appender::rolling::daily(
-                    project_dirs.cache_dir().join("logs"),
-                    "wallpaper.log",
-                )
-                .with_max_level(Level::TRACE),
-            ),
-        )
-        .with(
+        .with(tracing_subscriber::fmt::layer().with_writer(
+            tracing_appender::rolling::daily(log_dir, "wallpaper.log").with_max_level(Level::TRACE),
+        ))
+        .with(stdout.then(|| {
             tracing_subscriber::fmt::layer().with_filter(
                 tracing_subscriber::EnvFilter::builder()
                     .with_default_directive(tracing_subscriber::filter::Directive::from(
                         LevelFilter::INFO,
                     ))
                     .from_env_lossy(),
-            ),
-        )
+            )
+        }))
         .init();
     debug!("hello world, logging initialized :)");
 
@@ -43,6 +56,9 @@ fn init_logging() -> anyhow::Result<()> {
 
 #[derive(Parser, Debug)]
 struct Args {
+    /// The directory of the log files, overrides `log_dir` of the config
+    #[arg(long, global = true, env = "WALLPAPER_LOG_DIR")]
+    log_dir: Option<PathBuf>,
     /// Subcommand to run
     #[command(subcommand)]
     command: Command,
@@ -51,244 +67,1749 @@ struct Args {
 #[derive(Subcommand, Debug)]
 enum Command {
     /// Run the daemon which changes the wallpaper at specific times
-    Daemon,
+    Daemon {
+        /// Seed for the random choices, which makes them the same in every run
+        #[arg(long, env = "WALLPAPER_SEED")]
+        seed: Option<u64>,
+        /// Run in the background and write the output into the log file
+        #[arg(long)]
+        detach: bool,
+    },
+    /// Stop the daemon
+    Quit,
+    /// Update the wallpapers once if an update is due, e.g. from a systemd timer
+    ///
+    /// Exits with 2 if no wallpaper changed and with 3 if a daemon runs, which has to be used
+    /// with `wallpaper switch` instead.
+    Update {
+        /// Update even if no update is due
+        #[arg(long)]
+        force: bool,
+    },
+    /// Write a config for the images of a directory
+    Init {
+        /// The directory with the images, asked for if missing
+        dir: Option<PathBuf>,
+        /// Overwrite an existing config
+        #[arg(long)]
+        force: bool,
+        /// Take the proposed settings without asking
+        #[arg(long)]
+        yes: bool,
+    },
+    /// Check whether swww, the compositor, the daemon and the files can be reached
+    Doctor,
     /// Set a new image now
     Switch {
         /// Only switch the wallpaper for this monitor
         monitor: Option<String>,
+        /// Also switch monitors with a static wallpaper
+        #[arg(long)]
+        force: bool,
+        /// Only print the images which would be shown. Exits with 2 if nothing would change
+        #[arg(long)]
+        dry_run: bool,
+        /// Use this transition instead of the configured ones
+        #[arg(long)]
+        transition: Option<String>,
+        /// Use this fps instead of the configured one
+        #[arg(long)]
+        fps: Option<u8>,
+        /// Fail if the monitor is not connected instead of switching it when it appears
+        #[arg(long)]
+        now_or_fail: bool,
     },
     /// Select an image (or folder of images) which will be shown
     Select {
         path: String,
         /// whether to keep the old images
-        #[arg(default_value_t = false)]
+        #[arg(default_value_t = false, action = clap::ArgAction::Set)]
         keep_old: bool,
+        /// Only select the images for this monitor, the other monitors keep theirs
+        #[arg(long)]
+        monitor: Option<String>,
+        /// Show the images only at these times, e.g. "20-6" or "@night", instead of always
+        #[arg(long)]
+        times: Option<String>,
+        /// Also select hidden files, junk like Thumbs.db and files without an allowed extension
+        #[arg(long)]
+        all_files: bool,
+        /// Copy or symlink images outside of the image directory into it, "copy" or "symlink"
+        #[arg(long)]
+        import: Option<ImportMode>,
+        /// Derive the times of the images from their names with the `name_rules` of the config
+        #[arg(long)]
+        parse_names: bool,
+        /// Only print what would change
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Show an image for a while without remembering it, then the current image again
+    Preview {
+        path: PathBuf,
+        /// Only show the image on this monitor
+        #[arg(long)]
+        monitor: Option<String>,
+        /// How long the image is shown
+        #[arg(long = "for", default_value = "10s")]
+        duration: humantime::Duration,
+    },
+    /// Show a specific image now
+    Set {
+        path: PathBuf,
+        /// Only show the image on this monitor
+        #[arg(long)]
+        monitor: Option<String>,
+        /// Use this transition instead of the configured ones
+        #[arg(long)]
+        transition: Option<String>,
+        /// Also add the image to the config, so it is shown at all times
+        #[arg(long)]
+        add: bool,
+        /// Fail if the monitor is not connected instead of showing the image when it appears
+        #[arg(long)]
+        now_or_fail: bool,
+    },
+    /// Add an image to the config, or change the times and weight of a configured one
+    Add {
+        path: PathBuf,
+        /// Show the image only at these times, e.g. "18-23" or "@night", instead of always
+        #[arg(long)]
+        times: Option<String>,
+        /// Make the image this many times as likely to be chosen
+        #[arg(long)]
+        weight: Option<u32>,
+    },
+    /// Remove the images from the config which match a path or a glob like "beach/*.jpg"
+    Remove {
+        pattern: String,
+        /// Switch the monitors which show one of the removed images
+        #[arg(long)]
+        switch: bool,
+    },
+    /// Replace the times of the configured images which match a path or a glob
+    SetTime {
+        pattern: String,
+        /// The new times, e.g. "6-9,17-20" or "@night"
+        times: String,
+        /// Add the times instead of replacing the old ones
+        #[arg(long)]
+        append: bool,
+    },
+    /// Print the configured images with their times, one per line separated by tabs
+    ListImages {
+        /// Print the images as json
+        #[arg(long)]
+        json: bool,
+        /// Only print the images which may be shown now
+        #[arg(long)]
+        valid_now: bool,
+    },
+    /// Show the previous image again
+    Undo {
+        /// Only undo the change of this monitor
+        monitor: Option<String>,
+    },
+    /// Tell the daemon that a workspace got focused, e.g. from a script of the compositor
+    Workspace { output: String, workspace: String },
+    /// Switch between light and dark images
+    Mode { mode: Mode },
+    /// Show the same image on all monitors or not, overriding the config
+    Mirror {
+        #[arg(action = clap::ArgAction::Set)]
+        enabled: bool,
+    },
+    /// Show the current image more often
+    Like {
+        /// The monitor whose image is liked, only needed if the monitors show different images
+        monitor: Option<String>,
+    },
+    /// Show the current image less often, or not at all after disliking it often enough
+    Dislike {
+        /// The monitor whose image is disliked, only needed if the monitors show different images
+        monitor: Option<String>,
+    },
+    /// Only show images rated at least `favorites_threshold` or all images again
+    Favorites {
+        /// `on`, `off` or `toggle`
+        action: FavoritesAction,
+    },
+    /// Print the rated images, the best ones first
+    Ratings,
+    /// Print how often and when every image was shown
+    Stats {
+        /// `count` to print the most shown images first or `recent` for the last shown ones
+        #[arg(long, default_value = "count")]
+        sort: StatsSort,
+        /// Print the statistics as json
+        #[arg(long)]
+        json: bool,
+        /// Forget the statistics instead of printing them
+        #[arg(long, conflicts_with_all = ["sort", "json"])]
+        reset: bool,
+    },
+    /// Print the time until the wallpaper changes next
+    Countdown {
+        /// Print the time again every second until the wallpaper changes
+        #[arg(long)]
+        watch: bool,
+        /// Print the time as json
+        #[arg(long)]
+        json: bool,
+    },
+    /// Print the current images and the next change as json for a custom module of waybar
+    Waybar {
+        /// Print them again after every change, until the daemon stops
+        #[arg(long)]
+        watch: bool,
+    },
+    /// Print the state of the running daemon
+    Status,
+    /// Print the last images, the newest first
+    History {
+        /// Only print the images of this monitor
+        monitor: Option<String>,
+        /// How many images are printed at most
+        #[arg(long, default_value_t = 20)]
+        limit: usize,
+        /// Print the images as json
+        #[arg(long)]
+        json: bool,
+    },
+    /// Print why the current image of a monitor was chosen
+    Explain {
+        /// Only needed if the monitors were updated separately
+        monitor: Option<String>,
+        /// Print the explanation as json
+        #[arg(long)]
+        json: bool,
     },
     /// Check the config for errors
-    Check,
+    Check {
+        /// Also decode the header of every image, which needs the `image-validate` feature
+        #[arg(long)]
+        deep: bool,
+        /// Decode the whole images instead of only their headers
+        #[arg(long, requires = "deep")]
+        full: bool,
+        /// Also fail if there are warnings
+        #[arg(long)]
+        strict: bool,
+        /// Print the errors and warnings as json
+        #[arg(long)]
+        json: bool,
+        /// Remove the entries of missing images, or move them to a file with the same name
+        /// inside `image_dir`
+        #[arg(long)]
+        fix: bool,
+        /// Only print the fixes
+        #[arg(long, requires = "fix")]
+        dry_run: bool,
+    },
+    /// Manage the cache
+    Cache {
+        #[command(subcommand)]
+        command: CacheCommand,
+    },
     /// Print the current state and config
-    Print,
+    Print {
+        /// Print a stable json structure for scripts instead
+        #[arg(long)]
+        json: bool,
+        /// Print long lists completely on a terminal
+        #[arg(long)]
+        full: bool,
+    },
+    /// Print the config and the cache as one document, to move them to another machine
+    ///
+    /// e.g. `wallpaper export > backup.json`
+    Export,
+    /// Restore the config and the cache from a document of `wallpaper export`
+    Import {
+        file: PathBuf,
+        /// Only add the images and cache entries which are missing here instead of replacing all
+        #[arg(long)]
+        merge: bool,
+        /// The directory with the images on this machine, asked for if it differs from the backup
+        #[arg(long)]
+        image_dir: Option<PathBuf>,
+    },
+    /// Print the completions for the shell
+    ///
+    /// e.g. `wallpaper completions fish > ~/.config/fish/completions/wallpaper.fish`
+    Completions { shell: clap_complete::Shell },
+    /// Print the candidates for a dynamic completion, one per line
+    #[command(name = "__complete", hide = true)]
+    Complete { kind: CompletionKind },
+}
+
+/// What `wallpaper __complete` prints
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CompletionKind {
+    /// The connected monitors
+    Monitors,
+    /// The keys of the configured images
+    Images,
 }
 
-fn print_state(state: &State) -> anyhow::Result<()> {
-    println!("last update: {}", state.cache.last_update);
-    for (monitor, transition) in &state.cache.last_transitions {
-        println!("last transition for monitor {}: {}", monitor, transition);
+impl std::str::FromStr for CompletionKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "monitors" => Ok(Self::Monitors),
+            "images" => Ok(Self::Images),
+            _ => Err(format!("invalid kind {}, expected monitors or images", s)),
+        }
     }
-    for (monitor, image) in &state.cache.last_images {
-        println!(
-            "last image for monitor {}: {}",
-            monitor,
-            image.to_string_lossy()
+}
+
+#[derive(Subcommand, Debug)]
+enum CacheCommand {
+    /// Forget the monitors which were not connected for `monitor_retention` and the images
+    /// which are neither configured nor exist anymore
+    Prune {
+        /// Only print what would be forgotten
+        #[arg(long)]
+        dry_run: bool,
+    },
+}
+
+/// The order of the images printed by `wallpaper stats`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum StatsSort {
+    Count,
+    Recent,
+}
+
+impl std::str::FromStr for StatsSort {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "count" => Ok(Self::Count),
+            "recent" => Ok(Self::Recent),
+            _ => Err(format!("invalid order {}, expected count or recent", s)),
+        }
+    }
+}
+
+fn check_not_static(state: &State, monitor: &str) -> anyhow::Result<()> {
+    if state.config.static_image(monitor).is_some() {
+        anyhow::bail!(
+            "monitor {} always shows a static image. Use --force to switch it anyway",
+            monitor
         );
     }
-    println!("check interval: {}", state.config.check_interval);
-    println!("update interval: {}", state.config.update_interval);
-    println!("transitions: {:#?}", state.config.transitions);
-    let images: Vec<_> = state
-        .config
-        .images
-        .iter()
-        .map(|(name, times)| {
-            let times = times
-                .iter()
-                .map(ToString::to_string)
-                .collect::<Vec<_>>()
-                .join(", ");
-            format!("{}: [{}]", name, times)
-        })
-        .collect();
-    println!("images: {:#?}", images);
-    println!(
-        "image directory: {}",
-        state.config.image_dir.to_string_lossy()
-    );
-    println!("fps: {}", state.config.fps);
 
     Ok(())
 }
 
-fn check(state: &State) -> anyhow::Result<()> {
-    info!("checking the config for errors");
-
-    for (file_path, times) in &state.config.images {
-        let image = state.config.image_dir.join(file_path);
-        if !image.is_file() {
-            error!("image {} does not exist!", image.to_string_lossy());
-        }
-        for time in times {
-            if let Err(e) = time.check() {
-                error!(
-                    "image {}: {}. Consider creating multiple time slots",
-                    image.to_string_lossy(),
-                    e
-                );
-            }
-        }
+fn switch(
+    state: &mut State,
+    monitor: Option<String>,
+    force: bool,
+    dry_run: bool,
+    transition: Option<String>,
+    fps: Option<u8>,
+    now_or_fail: bool,
+) -> anyhow::Result<Vec<Change>> {
+    if let Some(transition) = &transition {
+        check_transition(transition)?;
+    }
+    let options = UpdateOptions {
+        force,
+        ignore_inhibition: true,
+        dry_run,
+        transition,
+        fps,
+    };
+    if dry_run {
+        info!("choosing images without switching");
+    } else {
+        info!("switching one time");
     }
 
-    let monitors = get_monitors()?;
-    match &state.config.monitors {
-        Monitors::Some(list) => {
-            for monitor in list {
-                if !monitors.contains(monitor) {
-                    warn!("monitor {} not available", monitor);
-                }
+    let monitor = match monitor {
+        Some(monitor) => {
+            if !force {
+                check_not_static(state, &monitor)?;
+            }
+            // a dry run has nothing to remember
+            if queue_if_absent(state, &monitor, None, &options, now_or_fail || dry_run)? {
+                return Ok(Vec::new());
+            }
+            if state.mirrors() && state.config.static_image(&monitor).is_none() {
+                info!("switching all monitors, as they are mirrored");
+                Monitors::All
+            } else {
+                Monitors::Some(vec![monitor])
             }
         }
-        Monitors::All => {}
+        None => Monitors::All,
+    };
+
+    let changes = update_wallpapers(state, monitor, options).context("while updating state")?;
+
+    if !dry_run {
+        info!("switched one time");
     }
+    Ok(changes)
+}
 
-    info!("checked the config for errors");
+fn set_mode(state: &mut State, mode: Mode) -> anyhow::Result<()> {
+    info!("setting mode to {}", mode);
+
+    state.cache.mode = Some(mode);
+    state.save().context("while saving cache")?;
+    update_wallpapers(state, Monitors::All, UpdateOptions::default())
+        .context("while updating state")?;
 
     Ok(())
 }
 
-fn switch(state: &mut State, monitor: Option<String>) -> anyhow::Result<()> {
-    info!("switching one time");
+fn set_mirror(state: &mut State, enabled: bool) -> anyhow::Result<()> {
+    info!("setting mirror mode to {}", enabled);
 
-    let monitor = match monitor {
-        Some(monitor) => Monitors::Some(vec![monitor]),
-        None => Monitors::All,
+    state.cache.mirror = Some(enabled);
+    state.save().context("while saving cache")?;
+    update_wallpapers(state, Monitors::All, UpdateOptions::default())
+        .context("while updating state")?;
+
+    Ok(())
+}
+
+fn set_favorites(state: &mut State, action: FavoritesAction) -> anyhow::Result<()> {
+    let enabled = match action {
+        FavoritesAction::On => true,
+        FavoritesAction::Off => false,
+        FavoritesAction::Toggle => !state.cache.favorites_only,
     };
+    info!("setting favorites-only mode to {}", enabled);
 
-    update_wallpapers(state, monitor).context("while updating state")?;
+    state.cache.favorites_only = enabled;
+    state.save().context("while saving cache")?;
+    update_wallpapers(state, Monitors::All, UpdateOptions::default())
+        .context("while updating state")?;
 
-    info!("switched one time");
     Ok(())
 }
 
-fn select(state: &mut State, path: &str, keep_old: bool) -> anyhow::Result<()> {
-    fn get_images_rec(path: &Path) -> anyhow::Result<BTreeMap<String, Vec<ValidTime>>> {
-        let mut res = BTreeMap::new();
-        if path.is_file() {
-            let path_s = path
-                .to_str()
-                .ok_or_else(|| anyhow::anyhow!("path {} is not valid utf-8", path.display()))?
-                .to_string();
-            res.insert(path_s, vec![ValidTime::ALL]);
-        } else {
-            for entry in std::fs::read_dir(path).context("reading image directory")? {
-                let entry = entry.context("getting image directory entry")?;
-                res.extend(get_images_rec(&entry.path())?);
+fn rate(state: &mut State, monitor: Option<&str>, delta: i32) -> anyhow::Result<()> {
+    let image = match monitor {
+        Some(monitor) => state
+            .cache
+            .last_images
+            .get(monitor)
+            .cloned()
+            .ok_or_else(|| anyhow::anyhow!("monitor {} shows no image yet", monitor))?,
+        None => {
+            let images: BTreeSet<_> = state.cache.last_images.values().cloned().collect();
+            let mut images = images.into_iter();
+            match (images.next(), images.next()) {
+                (Some(image), None) => image,
+                (None, _) => anyhow::bail!("no image is shown yet"),
+                (Some(_), Some(_)) => {
+                    anyhow::bail!("the monitors show different images, choose a monitor")
+                }
             }
         }
-        Ok(res)
+    };
+
+    let rating = state.cache.ratings.entry(image.clone()).or_default();
+    *rating = rating.saturating_add(delta);
+    info!("rating of {} is now {}", image.display(), rating);
+    state.save().context("while saving cache")?;
+
+    Ok(())
+}
+
+fn print_ratings(state: &State) {
+    let mut ratings: Vec<_> = state
+        .cache
+        .ratings
+        .iter()
+        .filter(|(_image, rating)| **rating != 0)
+        .collect();
+    ratings.sort_by(|(a_image, a), (b_image, b)| b.cmp(a).then(a_image.cmp(b_image)));
+    for (image, rating) in ratings {
+        if *rating <= BLACKLIST_RATING {
+            println!(
+                "{:+} {} (only shown if no other image is left)",
+                rating,
+                image.display()
+            );
+        } else {
+            println!("{:+} {}", rating, image.display());
+        }
     }
+}
 
-    let new_images = get_images_rec(path.as_ref())?;
+fn print_stats(state: &mut State, sort: StatsSort, json: bool) -> anyhow::Result<()> {
+    state.refresh_sources();
+    let never_shown: Vec<_> = state
+        .configured_images()
+        .into_iter()
+        .filter(|image| !state.cache.display_stats.contains_key(image))
+        .collect();
+    let mut stats: Vec<_> = state.cache.display_stats.iter().collect();
+    match sort {
+        StatsSort::Count => stats
+            .sort_by(|(a_image, a), (b_image, b)| b.count.cmp(&a.count).then(a_image.cmp(b_image))),
+        StatsSort::Recent => stats.sort_by(|(a_image, a), (b_image, b)| {
+            b.last_shown.cmp(&a.last_shown).then(a_image.cmp(b_image))
+        }),
+    }
+    let total: u64 = stats.iter().map(|(_image, stats)| stats.count).sum();
 
-    info!(
-        "selected image path {} with {} images",
-        path,
-        new_images.len()
+    if json {
+        let images: Vec<_> = stats
+            .iter()
+            .map(|(image, stats)| {
+                serde_json::json!({
+                    "image": image,
+                    "count": stats.count,
+                    "last_shown": humantime::format_rfc3339_seconds(*stats.last_shown).to_string(),
+                })
+            })
+            .collect();
+        println!(
+            "{}",
+            serde_json::json!({
+                "images": images,
+                "total": total,
+                "never_shown": never_shown,
+            })
+        );
+        return Ok(());
+    }
+
+    for (image, stats) in &stats {
+        let last_shown = chrono::DateTime::<chrono::Local>::from(*stats.last_shown);
+        println!(
+            "{}\t{}\t{}",
+            stats.count,
+            last_shown.format("%F %H:%M:%S"),
+            image.display()
+        );
+    }
+    println!(
+        "{} images were shown {} times, {} configured images were never shown",
+        stats.len(),
+        total,
+        never_shown.len()
     );
 
-    if keep_old {
-        state.config.images.extend(new_images);
+    Ok(())
+}
+
+/// Forgets the monitors which were not connected for long and the images which don't exist
+/// anymore and prints what was forgotten
+fn prune_cache(state: &mut State, dry_run: bool) -> anyhow::Result<()> {
+    let (monitors, entries) = match ipc::Client::connect() {
+        // the daemon would write its monitors to the cache again
+        Ok(mut client) => match client.send(IpcEvent::PruneCache { dry_run })? {
+            IpcResponse::Pruned { monitors, entries } => (monitors, entries),
+            IpcResponse::Error(e) => anyhow::bail!("daemon returned error: {}", e),
+            response => anyhow::bail!("unexpected response from daemon: {:?}", response),
+        },
+        Err(e) => {
+            debug!("can't connect to daemon, pruning the cache: {:#}", e);
+            let monitors = state.prune_monitors(dry_run);
+            let entries = state.prune_images(dry_run);
+            if !dry_run {
+                state.save().context("while saving cache")?;
+            }
+            (monitors, entries)
+        }
+    };
+
+    if monitors.is_empty() {
+        println!("no monitor to forget");
+    }
+    for monitor in monitors {
+        if dry_run {
+            println!("would forget monitor {}", monitor);
+        } else {
+            println!("forgot monitor {}", monitor);
+        }
+    }
+    if dry_run {
+        println!("would forget {} cache entries of images", entries);
     } else {
-        state.config.images = new_images;
+        println!("forgot {} cache entries of images", entries);
+    }
+
+    Ok(())
+}
+
+/// Returns the configured images, the ones of the image sources and the ones of the monitors
+fn list_images(state: &State, valid_now: bool) -> Vec<ImageInfo> {
+    let now = chrono::Local::now().naive_local();
+    let config = &state.config;
+    config
+        .images
+        .iter()
+        .chain(&state.source_images)
+        .map(|(path, entry)| (path, entry, None))
+        .chain(
+            config
+                .monitor_settings
+                .iter()
+                .flat_map(|(monitor, settings)| {
+                    settings
+                        .images
+                        .iter()
+                        .map(move |(path, entry)| (path, entry, Some(monitor)))
+                }),
+        )
+        .filter(|(_path, entry, _monitor)| !valid_now || entry.is_valid_at(&now))
+        .map(|(path, entry, monitor)| {
+            let path = config.image_dir.join(path);
+            ImageInfo {
+                path: std::path::absolute(&path).unwrap_or(path.clone()),
+                times: entry.time_refs.to_string(),
+                weight: entry.weight,
+                monitor: monitor.cloned(),
+                exists: path.is_file(),
+            }
+        })
+        .collect()
+}
+
+/// Prints the images of the daemon, or of the config if the daemon doesn't run
+fn print_images(state: &mut State, json: bool, valid_now: bool) -> anyhow::Result<()> {
+    let images = match ipc::Client::connect() {
+        Ok(mut client) => match client.send(IpcEvent::ListImages { valid_now })? {
+            IpcResponse::Images(images) => images,
+            IpcResponse::Error(e) => anyhow::bail!("daemon returned error: {}", e),
+            response => anyhow::bail!("unexpected response from daemon: {:?}", response),
+        },
+        Err(e) => {
+            debug!("can't connect to daemon, reading the config: {:#}", e);
+            state.refresh_sources();
+            list_images(state, valid_now)
+        }
+    };
+
+    if json {
+        println!(
+            "{}",
+            serde_json::to_string(&images).context("while serializing images")?
+        );
+        return Ok(());
+    }
+    for image in images {
+        println!(
+            "{}\t{}\t{}\t{}\t{}",
+            image.path.display(),
+            image.times,
+            image
+                .weight
+                .map_or(String::from("-"), |weight| weight.to_string()),
+            image.monitor.as_deref().unwrap_or("-"),
+            if image.exists { "exists" } else { "missing" }
+        );
     }
 
-    update_wallpapers(state, Monitors::All).context("while updating state")?;
+    Ok(())
+}
+
+fn reset_stats(state: &mut State) -> anyhow::Result<()> {
+    info!("resetting the display statistics");
+    state.cache.display_stats.clear();
+    state.save().context("while saving cache")?;
 
     Ok(())
 }
 
-fn daemon(state: &mut State) -> anyhow::Result<()> {
-    init_sww()?;
+fn set_image(
+    state: &mut State,
+    path: &Path,
+    monitor: Option<&str>,
+    transition: Option<&str>,
+    add: bool,
+    now_or_fail: bool,
+) -> anyhow::Result<()> {
+    let options = UpdateOptions {
+        transition: transition.map(str::to_owned),
+        ..UpdateOptions::default()
+    };
+    let queued = match monitor {
+        Some(monitor) => queue_if_absent(state, monitor, Some(path), &options, now_or_fail)?,
+        None => false,
+    };
+    if !queued {
+        show_image(state, path, monitor, transition)?;
+    }
+    if add {
+        state
+            .add_image(path, None, None)
+            .context("while adding image to config")?;
+        info!("added {} to the config", path.display());
+    }
 
-    let listener = ipc::Listener::bind().context("while starting ipc server")?;
+    Ok(())
+}
 
-    info!("starting mainloop");
+/// Adds the image to the config and returns whether it is new
+fn add_image(
+    state: &mut State,
+    path: &Path,
+    times: Option<&str>,
+    weight: Option<u32>,
+) -> anyhow::Result<bool> {
+    if !path.is_file() {
+        anyhow::bail!("image {} does not exist", path.display());
+    }
+    let times = times.map(parse_times).transpose()?;
+    if weight == Some(0) {
+        anyhow::bail!("the weight has to be at least 1");
+    }
+    let new = state
+        .add_image(path, times, weight)
+        .context("while adding image to config")?;
+    info!("added {} to the config", path.display());
 
-    loop {
-        let check_interval = state.config.check_interval.as_nanos();
-        let update_interval = state.config.update_interval.as_nanos();
+    Ok(new)
+}
 
-        let current_time = std::time::UNIX_EPOCH
-            .elapsed()
-            .context("after unix epoch")?
-            .as_nanos();
+/// Removes the images which match the pattern from the config and forgets them
+///
+/// Returns the removed images and the monitors which were switched,
+/// or which still show one of them if `switch` is false.
+fn remove_images(
+    state: &mut State,
+    pattern: &str,
+    switch: bool,
+) -> anyhow::Result<(Vec<PathBuf>, Vec<String>)> {
+    let removed = state
+        .remove_images(pattern)
+        .context("while removing images from config")?;
+    let monitors: BTreeSet<_> = removed
+        .iter()
+        .flat_map(|image| state.cache.monitors_showing(image))
+        .collect();
+    let monitors: Vec<_> = monitors.into_iter().collect();
+    if switch && !monitors.is_empty() {
+        info!(
+            "switching {}, as they show a removed image",
+            monitors.join(", ")
+        );
+        update_wallpapers(
+            state,
+            Monitors::Some(monitors.clone()),
+            UpdateOptions::default(),
+        )
+        .context("while switching monitors")?;
+    }
+    for image in &removed {
+        info!("removed {} from the config", image.display());
+        state.cache.forget_image(image);
+    }
+    state.save().context("while saving cache")?;
 
-        let last_time = state
-            .cache
-            .last_update
-            .duration_since(std::time::UNIX_EPOCH)
-            .context("after unix epoch")?
-            .as_nanos();
+    Ok((removed.into_iter().collect(), monitors))
+}
 
-        if last_time / update_interval < current_time / update_interval {
-            info!("updating wallpaper");
-            // FIXME: allow setting only some monitors?
-            update_wallpapers(state, Monitors::All).context("while updating state")?;
-        }
+/// Parses and checks the times of an image
+fn parse_times(times: &str) -> anyhow::Result<TimeRefs> {
+    let times = times
+        .parse::<TimeRefs>()
+        .map_err(|e| anyhow::anyhow!("invalid times {}: {}", times, e))?;
+    check_times(&times)?;
+    Ok(times)
+}
 
-        let to_sleep = check_interval - (current_time % check_interval);
+/// Replaces the times of the images which match the pattern, or appends to them
+///
+/// Returns the images whose times changed.
+fn set_times(
+    state: &mut State,
+    pattern: &str,
+    times: &str,
+    append: bool,
+) -> anyhow::Result<Vec<PathBuf>> {
+    let times = parse_times(times)?;
+    let changed = state
+        .set_times(pattern, &times, append)
+        .context("while changing the config")?;
+    for image in &changed {
+        info!("changed the times of {}", image.display());
+    }
 
-        debug!("waiting for next time :)");
-        let sleep_duration =
-            std::time::Duration::from_nanos(to_sleep.try_into().context("can't sleep that long")?);
+    Ok(changed.into_iter().collect())
+}
 
-        let mut handle_msg = |msg| match msg {
-            IpcEvent::Reload => {
-                debug!("reloading state (ipc)");
-                if let Err(e) = state.force_reload() {
-                    error!("can't reload state: {}", e);
+/// Lets the daemon edit the configured images and prints what changed
+///
+/// Without daemon the config is changed directly.
+fn edit_images(state: &mut State, event: IpcEvent) -> anyhow::Result<()> {
+    let switch = matches!(event, IpcEvent::RemoveImages { switch: true, .. });
+    let response = match ipc::Client::connect() {
+        Ok(mut client) => client.send(event)?,
+        Err(e) => {
+            debug!("can't connect to daemon, changing the config: {:#}", e);
+            match &event {
+                IpcEvent::AddImage {
+                    path,
+                    times,
+                    weight,
+                } => add_image(state, path, times.as_deref(), *weight)
+                    .map(|new| IpcResponse::Added { new })?,
+                IpcEvent::SetTimes {
+                    pattern,
+                    times,
+                    append,
+                } => set_times(state, pattern, times, *append).map(IpcResponse::TimesSet)?,
+                IpcEvent::RemoveImages { pattern, switch } => {
+                    if *switch {
+                        anyhow::bail!("can't switch monitors without daemon");
+                    }
+                    remove_images(state, pattern, false)
+                        .map(|(images, monitors)| IpcResponse::Removed { images, monitors })?
                 }
-                debug!("reloaded state (ipc)");
+                event => anyhow::bail!("can't edit images with {:?}", event),
             }
-            IpcEvent::Switch { monitor } => {
-                if let Err(e) = switch(state, monitor) {
-                    error!("can't switch wallpaper: {}", e);
-                }
+        }
+    };
+
+    match response {
+        IpcResponse::Added { new: true } => println!("added image"),
+        IpcResponse::Added { new: false } => println!("changed configured image"),
+        IpcResponse::Removed { images, monitors } => {
+            if images.is_empty() {
+                println!("no configured image matches");
             }
-            IpcEvent::Select { path, keep_old } => {
-                if let Err(e) = select(state, &path, keep_old) {
-                    error!("can't select wallpaper: {}", e);
+            for image in &images {
+                println!("removed {}", image.display());
+            }
+            for monitor in monitors {
+                if switch {
+                    println!("switched monitor {}", monitor);
+                } else {
+                    println!("monitor {} still shows a removed image", monitor);
                 }
             }
-        };
+        }
+        IpcResponse::TimesSet(images) => {
+            println!("changed the times of {} images", images.len());
+            for image in images {
+                println!("changed {}", image.display());
+            }
+        }
+        IpcResponse::Error(e) => anyhow::bail!("daemon returned error: {}", e),
+        response => anyhow::bail!("unexpected response from daemon: {:?}", response),
+    }
 
-        match listener.recv_timeout(sleep_duration) {
-            Ok(msg) => {
-                handle_msg(msg);
-                // process pending messages
-                while let Ok(msg) = listener.try_recv() {
-                    handle_msg(msg);
-                }
+    Ok(())
+}
+
+/// Remembers the update of a not connected monitor, so it happens when the monitor appears
+///
+/// Returns whether the monitor was not connected.
+/// Fails instead if `now_or_fail` is set.
+fn queue_if_absent(
+    state: &mut State,
+    monitor: &str,
+    image: Option<&Path>,
+    options: &UpdateOptions,
+    now_or_fail: bool,
+) -> anyhow::Result<bool> {
+    let monitor_infos = query_monitors()?;
+    if monitor_infos.iter().any(|info| info.name == monitor) {
+        return Ok(false);
+    }
+    if now_or_fail {
+        anyhow::bail!("monitor {} is not connected", monitor);
+    }
+    info!(
+        "monitor {} is not connected, updating it when it appears",
+        monitor
+    );
+    state.pending.insert(
+        monitor.to_owned(),
+        Pending {
+            until: Instant::now() + *state.config.pending_expiry,
+            image: image.map(Path::to_owned),
+            options: options.clone(),
+        },
+    );
+
+    Ok(true)
+}
+
+/// Prints the completions of clap and the functions which complete monitors and images
+fn completions(shell: clap_complete::Shell) -> anyhow::Result<()> {
+    print!("{}", completion_script(shell)?);
+    Ok(())
+}
+
+/// Returns the completions of clap and the functions which complete monitors and images
+///
+/// Only zsh and fish complete the monitors and images, with `wallpaper __complete`.
+fn completion_script(shell: clap_complete::Shell) -> anyhow::Result<String> {
+    use std::fmt::Write;
+
+    let mut command = Args::command();
+    let mut script = Vec::new();
+    clap_complete::generate(shell, &mut command, "wallpaper", &mut script);
+    let script = String::from_utf8(script).context("invalid completion script")?;
+
+    // the arguments which name a monitor or match configured images
+    let mut dynamic = Vec::new();
+    for subcommand in command.get_subcommands() {
+        for arg in subcommand.get_arguments() {
+            let kind = match arg.get_id().as_str() {
+                "monitor" => "monitors",
+                "pattern" => "images",
+                _ => continue,
+            };
+            dynamic.push((subcommand.get_name(), arg.get_long(), kind));
+        }
+    }
+
+    let mut out = String::new();
+    match shell {
+        clap_complete::Shell::Zsh => {
+            let (compdef, rest) = script.split_once('\n').unwrap_or((&script, ""));
+            writeln!(out, "{}", compdef)?;
+            for kind in ["monitors", "images"] {
+                writeln!(
+                    out,
+                    "\n_wallpaper_{kind}() {{\n    \
+                    local -a candidates\n    \
+                    candidates=(${{(f)\"$(wallpaper __complete {kind} 2>/dev/null)\"}})\n    \
+                    compadd -a candidates\n}}",
+                    kind = kind
+                )?;
             }
-            Err(e) => match e {
-                RecvTimeoutError::Timeout => {}
-                RecvTimeoutError::Disconnected => todo!(),
-            },
+            let monitor = regex::Regex::new(
+                r"^('(::?monitor -- [^']*|--monitor=\[[^']*\]:MONITOR)):_default'",
+            )
+            .context("invalid regex")?;
+            let pattern = regex::Regex::new(r"^':pattern:_default'").context("invalid regex")?;
+            for line in rest.lines() {
+                let line = monitor.replace(line, "$1:_wallpaper_monitors'");
+                let line = pattern.replace(&line, "':pattern:_wallpaper_images'");
+                writeln!(out, "{}", line)?;
+            }
+        }
+        clap_complete::Shell::Fish => {
+            out.push_str(&script);
+            for (subcommand, long, kind) in dynamic {
+                let condition = match long {
+                    Some(long) => format!(
+                        "__fish_wallpaper_using_subcommand {}; and string match -q -- --{} (commandline -opc)[-1]",
+                        subcommand, long
+                    ),
+                    None => format!("__fish_wallpaper_using_subcommand {}", subcommand),
+                };
+                writeln!(
+                    out,
+                    "complete -c wallpaper -n \"{}\" -f -a \"(wallpaper __complete {} 2>/dev/null)\"",
+                    condition, kind
+                )?;
+            }
+        }
+        _ => out = script,
+    }
+    Ok(out)
+}
+
+/// Prints the candidates for `wallpaper __complete`, which the completion scripts call
+fn complete(kind: CompletionKind) -> anyhow::Result<()> {
+    let candidates = match kind {
+        CompletionKind::Monitors => query_monitors()?
+            .into_iter()
+            .map(|monitor| monitor.name)
+            .collect(),
+        CompletionKind::Images => {
+            let config_file = State::config_file()?;
+            // nothing is configured yet
+            if !config_file.is_file() {
+                return Ok(());
+            }
+            let file = std::fs::File::open(&config_file).context("while opening config file")?;
+            let config: Config =
+                serde_json::from_reader(file).context("while parsing config file")?;
+            image_keys(&config)
+        }
+    };
+    for candidate in candidates {
+        println!("{}", candidate);
+    }
+    Ok(())
+}
+
+/// The keys of the images of the config and of the monitors, sorted and without duplicates
+fn image_keys(config: &Config) -> Vec<String> {
+    let keys: BTreeSet<_> = config
+        .images
+        .keys()
+        .chain(
+            config
+                .monitor_settings
+                .values()
+                .flat_map(|settings| settings.images.keys()),
+        )
+        .collect();
+    keys.into_iter()
+        .map(|key| key.display().to_string())
+        .collect()
+}
+
+/// Tells where the logs are now, if there are logs at the old location in the cache dir
+fn notice_old_logs(log_dir: &Path) {
+    let Ok(old_logs) = State::old_log_dir() else {
+        return;
+    };
+    if old_logs != log_dir && old_logs.is_dir() {
+        info!(
+            "the logs are written to {} now, the old logs in {} can be removed",
+            log_dir.display(),
+            old_logs.display()
+        );
+    }
+}
+
+/// Forks the daemon into the background and lets the parent exit
+///
+/// Only the forking thread exists in the child, so no other thread may be started before.
+/// The child keeps the lock, writes its pid and writes stdout and stderr into the log file
+/// of the day.
+fn detach_daemon(lock: &ipc::InstanceLock, log_dir: &Path) -> anyhow::Result<()> {
+    std::fs::create_dir_all(log_dir).context("while creating logs dir")?;
+    // the name which the daily log file of tracing_appender gets
+    let log_file = log_dir.join(format!(
+        "wallpaper.log.{}",
+        chrono::Utc::now().format("%Y-%m-%d")
+    ));
+    let log = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&log_file)
+        .with_context(|| format!("can't open log file {}", log_file.display()))?;
+    let null = std::fs::File::open("/dev/null").context("can't open /dev/null")?;
+
+    // SAFETY: no other thread runs, so the child can continue normally
+    match unsafe { libc::fork() } {
+        -1 => return Err(std::io::Error::last_os_error()).context("can't fork"),
+        0 => {}
+        pid => {
+            println!("daemon started with pid {}", pid);
+            std::process::exit(0);
+        }
+    }
+    // SAFETY: the file descriptors are valid and the child is no process group leader
+    let ret = unsafe {
+        libc::setsid();
+        libc::dup2(null.as_raw_fd(), 0)
+            .max(libc::dup2(log.as_raw_fd(), 1))
+            .max(libc::dup2(log.as_raw_fd(), 2))
+    };
+    if ret == -1 {
+        return Err(std::io::Error::last_os_error()).context("can't redirect the output");
+    }
+    lock.write_pid()
+}
+
+/// Stops the daemon with SIGTERM and waits until it is gone
+fn quit() -> anyhow::Result<()> {
+    let Some(pid) = ipc::InstanceLock::holder()? else {
+        anyhow::bail!("daemon is not running");
+    };
+    let pid_t = libc::pid_t::try_from(pid).context("invalid pid")?;
+    // SAFETY: kill has no memory safety requirements
+    if unsafe { libc::kill(pid_t, libc::SIGTERM) } != 0 {
+        return Err(std::io::Error::last_os_error())
+            .with_context(|| format!("can't stop daemon (pid {})", pid));
+    }
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while ipc::InstanceLock::holder()?.is_some() {
+        if Instant::now() > deadline {
+            anyhow::bail!("daemon (pid {}) didn't stop within 10s", pid);
+        }
+        std::thread::sleep(Duration::from_millis(50));
+    }
+    println!("stopped daemon (pid {})", pid);
+    Ok(())
+}
+
+/// Prints the question and returns the trimmed answer
+fn prompt(question: &str) -> anyhow::Result<String> {
+    print!("{}", question);
+    std::io::stdout()
+        .flush()
+        .context("while writing question")?;
+    let mut answer = String::new();
+    std::io::stdin()
+        .read_line(&mut answer)
+        .context("while reading answer")?;
+    Ok(answer.trim().to_owned())
+}
+
+/// Fails if one of the times is invalid
+fn check_times(times: &TimeRefs) -> anyhow::Result<()> {
+    for time in times {
+        if let TimeRef::Time(time) = time {
+            time.check().map_err(|e| anyhow::anyhow!(e))?;
+        }
+    }
+    Ok(())
+}
+
+/// Updates the wallpapers if an update is due, or always with `force`
+///
+/// Returns whether a wallpaper changed.
+fn update_once(state: &mut State, force: bool) -> anyhow::Result<bool> {
+    check_wayland_display()?;
+    if force {
+        info!("updating wallpaper: forced");
+    } else {
+        let now = SystemTime::now();
+        let (last_update, next_update) =
+            estimate_next_update(&state.config, state.power, &state.cache, &mut state.rng);
+        let (at, reason) = next_change(now, last_update, next_update, &state.config, &state.cache);
+        if at > now {
+            info!(
+                "no update is due, the next one is at {} ({})",
+                humantime::format_rfc3339_seconds(at),
+                reason
+            );
+            return Ok(false);
         }
+        info!("updating wallpaper: {}", reason);
+    }
+    init_sww(state, *state.config.startup_timeout)?;
 
-        debug!("reloading state");
-        state.reload().context("while reloading state")?;
-        debug!("reloaded state");
+    let changes = update_wallpapers(state, Monitors::All, UpdateOptions::default())
+        .context("while updating state")?;
+    state.save().context("while saving cache")?;
+    for change in &changes {
+        println!(
+            "{} shows {}",
+            change.monitors.join(", "),
+            change.image.display()
+        );
     }
+
+    Ok(!changes.is_empty())
 }
 
 fn run_ipc(msg: IpcEvent) -> anyhow::Result<()> {
-    let sender = ipc::Client::connect()?;
-    sender.send(msg)?;
+    let mut sender = ipc::Client::connect()?;
+    match sender.send(msg)? {
+        IpcResponse::Error(e) => Err(anyhow::anyhow!("daemon returned error: {}", e)),
+        _ => Ok(()),
+    }
+}
+
+/// Prints the images which the daemon would show after a switch
+///
+/// Exits with 2 if no image would change.
+fn dry_switch(
+    monitor: Option<String>,
+    force: bool,
+    transition: Option<String>,
+) -> anyhow::Result<()> {
+    let mut client = ipc::Client::connect()?;
+    let event = IpcEvent::Switch {
+        monitor,
+        force,
+        dry_run: true,
+        transition,
+        fps: None,
+        now_or_fail: true,
+    };
+    let changes = match client.send(event)? {
+        IpcResponse::Changes(changes) => changes,
+        IpcResponse::Error(e) => anyhow::bail!("daemon returned error: {}", e),
+        response => anyhow::bail!("unexpected response from daemon: {:?}", response),
+    };
+
+    if changes.is_empty() {
+        println!("nothing would change");
+        std::process::exit(2);
+    }
+    for change in changes {
+        println!(
+            "{} would show {} with transition {}",
+            change.monitors.join(", "),
+            change.image.display(),
+            change.transition
+        );
+    }
 
     Ok(())
 }
 
-fn main() -> anyhow::Result<()> {
-    init_logging()?;
+/// Prints the state of the running daemon
+fn status() -> anyhow::Result<()> {
+    let mut client = ipc::Client::connect()?;
+    let status = match client.send(IpcEvent::Status)? {
+        IpcResponse::Status(status) => status,
+        IpcResponse::Error(e) => anyhow::bail!("daemon returned error: {}", e),
+        response => anyhow::bail!("unexpected response from daemon: {:?}", response),
+    };
+
+    if status.inhibited.is_empty() {
+        println!("no monitor is inhibited");
+    }
+    for (monitor, reason) in &status.inhibited {
+        println!("monitor {} is inhibited: {}", monitor, reason);
+    }
+    for monitor in &status.deferred {
+        println!("update of monitor {} is deferred", monitor);
+    }
+    for monitor in &status.pending {
+        println!("monitor {} gets updated when it is connected", monitor);
+    }
+    for monitor in &status.vetoed {
+        println!(
+            "last change of monitor {} was vetoed by the pre_change hook",
+            monitor
+        );
+    }
+    for (monitor, at) in &status.next_updates {
+        println!("next update of monitor {} at {}", monitor, at);
+    }
+    if status.favorites_only {
+        println!("only favorites are shown");
+    }
+    println!("power profile: {}", status.power);
+
+    Ok(())
+}
+
+/// Prints the last images of the monitor or of all monitors, the newest first
+fn history(state: &State, monitor: Option<&str>, limit: usize, json: bool) -> anyhow::Result<()> {
+    let history: BTreeMap<String, VecDeque<HistoryEntry>> = match ipc::Client::connect() {
+        Ok(mut client) => match client.send(IpcEvent::History)? {
+            IpcResponse::History(history) => history,
+            IpcResponse::Error(e) => anyhow::bail!("daemon returned error: {}", e),
+            response => anyhow::bail!("unexpected response from daemon: {:?}", response),
+        },
+        Err(e) => {
+            debug!("can't connect to daemon, reading the cache: {:#}", e);
+            state.cache.history.clone()
+        }
+    };
+    if let Some(monitor) = monitor.filter(|monitor| !history.contains_key(*monitor)) {
+        anyhow::bail!("monitor {} has no history", monitor);
+    }
+
+    let mut rows: Vec<_> = history
+        .iter()
+        .filter(|(name, _history)| monitor.is_none_or(|monitor| monitor == *name))
+        .flat_map(|(name, history)| history.iter().map(move |entry| (name, entry)))
+        .collect();
+    // entries without a time are older than all others
+    rows.sort_by_key(|(_name, entry)| Reverse(entry.shown_at.as_deref().copied()));
+    rows.truncate(limit);
+
+    // absolute paths can be passed to other programs
+    let absolute = |image: &Path| std::path::absolute(image).unwrap_or_else(|_| image.to_owned());
+    if json {
+        let rows: Vec<_> = rows
+            .iter()
+            .map(|(name, entry)| {
+                serde_json::json!({
+                    "monitor": name,
+                    "image": absolute(&entry.image),
+                    "transition": entry.transition,
+                    "shown_at": entry
+                        .shown_at
+                        .as_ref()
+                        .map(|shown_at| humantime::format_rfc3339_seconds(**shown_at).to_string()),
+                })
+            })
+            .collect();
+        println!("{}", serde_json::Value::Array(rows));
+        return Ok(());
+    }
 
+    for (name, entry) in rows {
+        let shown_at = match &entry.shown_at {
+            Some(shown_at) => chrono::DateTime::<chrono::Local>::from(**shown_at)
+                .format("%F %H:%M:%S")
+                .to_string(),
+            None => String::from("unknown time"),
+        };
+        println!(
+            "{}\t{}\t{}\t{}",
+            shown_at,
+            name,
+            absolute(&entry.image).display(),
+            entry.transition.as_deref().unwrap_or("unknown transition")
+        );
+    }
+
+    Ok(())
+}
+
+/// Prints how the daemon chose the current image of the monitor
+fn explain(monitor: Option<String>, json: bool) -> anyhow::Result<()> {
+    let mut client = ipc::Client::connect()?;
+    let trace = match client.send(IpcEvent::Explain { monitor })? {
+        IpcResponse::Explain(trace) => trace,
+        IpcResponse::Error(e) => anyhow::bail!("daemon returned error: {}", e),
+        response => anyhow::bail!("unexpected response from daemon: {:?}", response),
+    };
+
+    if json {
+        let mut value = serde_json::to_value(&trace).context("while serializing explanation")?;
+        value["at"] = humantime::format_rfc3339_seconds(trace.at)
+            .to_string()
+            .into();
+        println!("{}", value);
+        return Ok(());
+    }
+
+    let at = chrono::DateTime::<chrono::Local>::from(trace.at);
+    println!(
+        "{} updated at {}",
+        trace.monitors.join(", "),
+        at.format("%F %H:%M:%S")
+    );
+    println!("{} images are configured", trace.images);
+    for (filter, count) in &trace.filtered {
+        println!("  {} removed by {}", count, filter);
+    }
+    for stage in &trace.stages {
+        let removed: Vec<_> = stage
+            .removed
+            .iter()
+            .map(|(filter, count)| format!("{} by {}", count, filter))
+            .collect();
+        let mut line = format!("stage {}: {} candidates", stage.name, stage.candidates);
+        if !removed.is_empty() {
+            line.push_str(&format!(", removed {}", removed.join(", ")));
+        }
+        if stage.chosen {
+            line.push_str(", chose the image");
+        }
+        println!("{}", line);
+    }
+    let Some(image) = &trace.image else {
+        println!("no image was found");
+        return Ok(());
+    };
+    println!("image: {}", image.display());
+    match &trace.transition {
+        Some(transition) => println!("transition: {}", transition),
+        None if !trace.vetoed.is_empty() => {}
+        None => println!("transition: none, the image was shown already"),
+    }
+    if !trace.vetoed.is_empty() {
+        println!("vetoed by the pre_change hook: {}", trace.vetoed.join(", "));
+    }
+
+    Ok(())
+}
+
+/// Prints the time until the wallpaper changes next
+fn countdown(state: &mut State, watch: bool, json: bool) -> anyhow::Result<()> {
+    loop {
+        let (at, reason, estimated) = match ipc::Client::connect() {
+            Ok(mut client) => match client.send(IpcEvent::NextChange)? {
+                IpcResponse::NextChange { at, reason } => (at, reason, false),
+                IpcResponse::Error(e) => anyhow::bail!("daemon returned error: {}", e),
+                response => anyhow::bail!("unexpected response from daemon: {:?}", response),
+            },
+            Err(e) => {
+                debug!("can't connect to daemon, estimating from cache: {:#}", e);
+                // the jitter is random, so leave it out of the estimate
+                let mut no_jitter = rand::rngs::mock::StepRng::new(0, 0);
+                let (last_update, next_update) =
+                    estimate_next_update(&state.config, state.power, &state.cache, &mut no_jitter);
+                let (at, reason) = scheduler::next_change(
+                    SystemTime::now(),
+                    last_update,
+                    next_update,
+                    &state.config,
+                    &state.cache,
+                );
+                (at, reason.to_string(), true)
+            }
+        };
+
+        // an overdue change happens as soon as the daemon wakes up
+        let at = at.max(SystemTime::now());
+        let remaining = at.duration_since(SystemTime::now()).unwrap_or_default();
+        // round up, so the countdown doesn't show 0s before the change
+        let remaining =
+            Duration::from_secs(remaining.as_secs() + u64::from(remaining.subsec_nanos() > 0));
+        let at_local = chrono::DateTime::<chrono::Local>::from(at);
+        if json {
+            println!(
+                "{}",
+                serde_json::json!({
+                    "remaining_secs": remaining.as_secs(),
+                    "at": humantime::format_rfc3339_seconds(at).to_string(),
+                    "reason": reason,
+                    "estimated": estimated,
+                })
+            );
+        } else {
+            let mut line = format!(
+                "{} (at {})",
+                humantime::format_duration(remaining),
+                at_local.format("%H:%M:%S")
+            );
+            if estimated {
+                line.push_str(" [estimated, daemon not running]");
+            }
+            println!("{}", line);
+        }
+
+        if !watch || remaining.is_zero() {
+            return Ok(());
+        }
+        std::thread::sleep(Duration::from_secs(1));
+        if estimated {
+            state.reload().context("while reloading state")?;
+        }
+    }
+}
+
+fn main() -> anyhow::Result<()> {
     let args = Args::parse();
+    let log_dir = match &args.log_dir {
+        Some(log_dir) => log_dir.clone(),
+        None => State::log_dir()?,
+    };
+
+    // forking is only safe before any thread is started
+    let _lock = match args.command {
+        Command::Daemon { detach, .. } => {
+            // a second daemon must not touch swww or the cache
+            let lock = ipc::InstanceLock::acquire()?;
+            if detach {
+                detach_daemon(&lock, &log_dir)?;
+            }
+            Some(lock)
+        }
+        // the running daemon owns swww and the cache
+        Command::Update { .. } => {
+            if let Some(pid) = ipc::InstanceLock::holder()? {
+                eprintln!(
+                    "daemon is running (pid {}), use `wallpaper switch` instead",
+                    pid
+                );
+                std::process::exit(3);
+            }
+            Some(ipc::InstanceLock::acquire()?)
+        }
+        _ => None,
+    };
+    init_logging(
+        !matches!(args.command, Command::Daemon { detach: true, .. }),
+        &log_dir,
+    )?;
+    if let Command::Daemon { .. } = args.command {
+        notice_old_logs(&log_dir);
+    }
+
+    // loading the state writes a default config, so an existing config is only seen before
+    if let Command::Init { dir, force, yes } = args.command {
+        return init::run(dir, force, yes);
+    }
+    match args.command {
+        Command::Completions { shell } => return completions(shell),
+        Command::Complete { kind } => return complete(kind),
+        _ => {}
+    }
+    // the backup is checked before the state is loaded, which writes a default config
+    if let Command::Import {
+        file,
+        merge,
+        image_dir,
+    } = args.command
+    {
+        return transfer::import(&file, merge, image_dir);
+    }
+    // a broken config is one of the things to diagnose
+    if let Command::Doctor = args.command {
+        if !doctor::run(&log_dir)? {
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
+    // stopping the daemon only needs its pid, not the config
+    if let Command::Quit = args.command {
+        return quit();
+    }
 
     let mut state = State::load().context("while loading state")?;
 
     match args.command {
-        Command::Daemon => daemon(&mut state),
-        Command::Switch { monitor } => run_ipc(IpcEvent::Switch { monitor }),
-        Command::Select { path, keep_old } => run_ipc(IpcEvent::Select { path, keep_old }),
-        Command::Check => check(&state),
-        Command::Print => print_state(&state),
+        Command::Daemon { seed, .. } => {
+            if let Some(seed) = seed {
+                info!("using seed {} for the random choices", seed);
+                state.seed(seed);
+            }
+            daemon::run(&mut state)
+        }
+        Command::Update { force } => {
+            if !update_once(&mut state, force)? {
+                std::process::exit(2);
+            }
+            Ok(())
+        }
+        Command::Switch {
+            monitor,
+            force,
+            dry_run,
+            transition,
+            fps,
+            now_or_fail,
+        } => {
+            if let Some(monitor) = monitor.as_deref().filter(|_| !force) {
+                check_not_static(&state, monitor)?;
+            }
+            if let Some(transition) = &transition {
+                check_transition(transition)?;
+            }
+            if dry_run {
+                return dry_switch(monitor, force, transition);
+            }
+            run_ipc(IpcEvent::Switch {
+                monitor,
+                force,
+                dry_run,
+                transition,
+                fps,
+                now_or_fail,
+            })
+        }
+        Command::Select {
+            path,
+            keep_old,
+            monitor,
+            times,
+            all_files,
+            import,
+            parse_names,
+            dry_run,
+        } => select::request(IpcEvent::Select {
+            path,
+            keep_old,
+            monitor,
+            times,
+            all_files,
+            import,
+            parse_names,
+            dry_run,
+        }),
+        Command::Preview {
+            path,
+            monitor,
+            duration,
+        } => {
+            // the daemon may run in another directory
+            let path = std::path::absolute(&path).context("while resolving path")?;
+            run_ipc(IpcEvent::Preview {
+                path,
+                monitor,
+                duration: *duration,
+            })
+        }
+        Command::Set {
+            path,
+            monitor,
+            transition,
+            add,
+            now_or_fail,
+        } => {
+            if let Some(transition) = &transition {
+                check_transition(transition)?;
+            }
+            // the daemon may run in another directory
+            let path = std::path::absolute(&path).context("while resolving path")?;
+            run_ipc(IpcEvent::SetImage {
+                path,
+                monitor,
+                transition,
+                add,
+                now_or_fail,
+            })
+        }
+        Command::Add {
+            path,
+            times,
+            weight,
+        } => {
+            // the daemon may run in another directory
+            let path = std::path::absolute(&path).context("while resolving path")?;
+            edit_images(
+                &mut state,
+                IpcEvent::AddImage {
+                    path,
+                    times,
+                    weight,
+                },
+            )
+        }
+        Command::Remove { pattern, switch } => {
+            // paths of existing files are matched in full, as the daemon may run in another directory
+            let pattern = match Path::new(&pattern).canonicalize() {
+                Ok(path) => path.to_string_lossy().into_owned(),
+                Err(_) => pattern,
+            };
+            edit_images(&mut state, IpcEvent::RemoveImages { pattern, switch })
+        }
+        Command::SetTime {
+            pattern,
+            times,
+            append,
+        } => {
+            // the times are checked here, so the error doesn't need the daemon
+            parse_times(&times)?;
+            let pattern = match Path::new(&pattern).canonicalize() {
+                Ok(path) => path.to_string_lossy().into_owned(),
+                Err(_) => pattern,
+            };
+            edit_images(
+                &mut state,
+                IpcEvent::SetTimes {
+                    pattern,
+                    times,
+                    append,
+                },
+            )
+        }
+        Command::ListImages { json, valid_now } => print_images(&mut state, json, valid_now),
+        Command::Init { .. }
+        | Command::Import { .. }
+        | Command::Quit
+        | Command::Doctor
+        | Command::Completions { .. }
+        | Command::Complete { .. } => unreachable!("runs without state"),
+        Command::Undo { monitor } => run_ipc(IpcEvent::Undo { monitor }),
+        Command::Mode { mode } => run_ipc(IpcEvent::SetMode { mode }),
+        Command::Mirror { enabled } => run_ipc(IpcEvent::SetMirror { enabled }),
+        Command::Like { monitor } => run_ipc(IpcEvent::Rate { monitor, delta: 1 }),
+        Command::Dislike { monitor } => run_ipc(IpcEvent::Rate { monitor, delta: -1 }),
+        Command::Favorites { action } => run_ipc(IpcEvent::Favorites { action }),
+        Command::Ratings => {
+            print_ratings(&state);
+            Ok(())
+        }
+        Command::Workspace { output, workspace } => {
+            run_ipc(IpcEvent::WorkspaceFocus { output, workspace })
+        }
+        Command::Stats { reset: true, .. } => match ipc::Client::connect() {
+            Ok(_) => run_ipc(IpcEvent::ResetStats),
+            Err(e) => {
+                debug!("can't connect to daemon, resetting the cache: {:#}", e);
+                reset_stats(&mut state)
+            }
+        },
+        Command::Stats { sort, json, .. } => print_stats(&mut state, sort, json),
+        Command::Countdown { watch, json } => countdown(&mut state, watch, json),
+        Command::Waybar { watch } => waybar::run(watch),
+        Command::Status => status(),
+        Command::History {
+            monitor,
+            limit,
+            json,
+        } => history(&state, monitor.as_deref(), limit, json),
+        Command::Explain { monitor, json } => explain(monitor, json),
+        Command::Check {
+            deep,
+            full,
+            strict,
+            json,
+            fix,
+            dry_run,
+        } => {
+            if fix {
+                check::fix_missing_images(&mut state, dry_run)?;
+            }
+            // on a terminal the findings are printed together, after the progress
+            let pretty = !json && term::is_terminal();
+            let report = check::run(&state, deep, full, !pretty)?;
+            if json {
+                println!(
+                    "{}",
+                    serde_json::to_string(&report).context("while serializing report")?
+                );
+            } else if pretty {
+                report.print();
+            } else {
+                println!("{}", report.summary());
+            }
+            match report.exit_code(strict) {
+                0 => Ok(()),
+                code => std::process::exit(code),
+            }
+        }
+        Command::Cache {
+            command: CacheCommand::Prune { dry_run },
+        } => prune_cache(&mut state, dry_run),
+        Command::Print { json, full } => print::run(&state, json, full),
+        Command::Export => transfer::export(&state),
+    }
+}
+
+#[cfg(test)]
+#[path = "test_util.rs"]
+mod test_util;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn image_keys_are_sorted_and_unique() {
+        let config: Config = serde_json::from_value(serde_json::json!({
+            "check_interval": "5m",
+            "update_interval": "1h",
+            "transitions": [],
+            "image_dir": "/images",
+            "fps": 30,
+            "images": { "b c.png": "*", "a.png": "*" },
+            "monitor_settings": {
+                "DP-1": { "images": { "a.png": "*", "d.png": "*" } },
+            },
+        }))
+        .unwrap();
+        assert_eq!(image_keys(&config), ["a.png", "b c.png", "d.png"]);
+    }
+
+    #[test]
+    fn zsh_completes_monitors_and_images() {
+        let script = completion_script(clap_complete::Shell::Zsh).unwrap();
+        assert!(script.starts_with("#compdef wallpaper\n"));
+        for kind in ["monitors", "images"] {
+            assert!(script.contains(&format!("_wallpaper_{}() {{", kind)));
+            assert!(script.contains(&format!("wallpaper __complete {} ", kind)));
+        }
+        assert!(script.contains(
+            "'::monitor -- Only switch the wallpaper for this monitor:_wallpaper_monitors'"
+        ));
+        assert!(script.contains("':pattern:_wallpaper_images'"));
+        assert!(!script.contains(":pattern:_default'"));
+    }
+
+    #[test]
+    fn fish_completes_monitors_and_images() {
+        let script = completion_script(clap_complete::Shell::Fish).unwrap();
+        for (subcommand, kind) in [("switch", "monitors"), ("remove", "images")] {
+            let line = format!(
+                "complete -c wallpaper -n \"__fish_wallpaper_using_subcommand {}\" -f -a \"(wallpaper __complete {} 2>/dev/null)\"",
+                subcommand, kind
+            );
+            assert!(script.lines().any(|other| other == line), "{}", line);
+        }
+    }
+
+    #[test]
+    fn parses_completion_kinds() {
+        assert_eq!("monitors".parse(), Ok(CompletionKind::Monitors));
+        assert_eq!("images".parse(), Ok(CompletionKind::Images));
+        assert!("files".parse::<CompletionKind>().is_err());
     }
 }
@@ -0,0 +1,57 @@
+//! Finds out whether the system runs on battery
+
+use std::path::Path;
+
+use tracing::debug;
+
+const POWER_SUPPLY_DIR: &str = "/sys/class/power_supply";
+
+/// Which overrides of the `power` config apply
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum PowerProfile {
+    /// Connected to the power supply, or a system without battery
+    #[default]
+    Ac,
+    /// Running on battery, the `power.on_battery` overrides apply
+    Battery,
+}
+
+impl std::fmt::Display for PowerProfile {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Ac => write!(f, "ac"),
+            Self::Battery => write!(f, "battery"),
+        }
+    }
+}
+
+/// Reads the power profile from the batteries in `/sys/class/power_supply`
+///
+/// The system runs on battery if one of its batteries is discharging.
+/// Systems without batteries or whose power supplies can't be read run on AC.
+pub fn current_profile() -> PowerProfile {
+    let Ok(supplies) = std::fs::read_dir(POWER_SUPPLY_DIR) else {
+        debug!("can't read {}, assuming AC", POWER_SUPPLY_DIR);
+        return PowerProfile::Ac;
+    };
+    let discharging = supplies
+        .flatten()
+        .any(|supply| is_discharging_battery(&supply.path()));
+    if discharging {
+        PowerProfile::Battery
+    } else {
+        PowerProfile::Ac
+    }
+}
+
+fn is_discharging_battery(supply: &Path) -> bool {
+    let read = |name: &str| {
+        std::fs::read_to_string(supply.join(name))
+            .map(|value| value.trim().to_owned())
+            .unwrap_or_default()
+    };
+    read("type") == "Battery" && read("status") == "Discharging"
+}
@@ -0,0 +1,778 @@
+//! `wallpaper print`, which shows the state of the daemon or of the files
+
+use std::{collections::BTreeMap, fmt::Display};
+
+use anyhow::Context;
+use wallpaper::{
+    ipc::{self, CacheSummary, IpcEvent, IpcResponse, MonitorSummary, StateSummary},
+    scheduler::{self, next_update_time},
+    term, AspectTolerance, SelectionMode, State,
+};
+
+/// Returns why outputs aren't updated currently
+pub fn daemon_status(state: &State) -> ipc::Status {
+    let mut inhibited: BTreeMap<_, _> = state
+        .fullscreen
+        .iter()
+        .chain(&state.off)
+        .filter_map(|output| Some((output.clone(), state.inhibition(output)?.to_owned())))
+        .collect();
+    for monitor in state.previews.keys() {
+        inhibited.insert(monitor.clone(), String::from("preview"));
+    }
+    ipc::Status {
+        inhibited,
+        deferred: state.deferred.clone(),
+        favorites_only: state.cache.favorites_only,
+        pending: state.pending.keys().cloned().collect(),
+        power: state.power,
+        vetoed: state.vetoed.clone(),
+        next_updates: state
+            .schedule
+            .monitors()
+            .into_iter()
+            .filter_map(|monitor| {
+                let at = state.schedule.next_update(&monitor)?;
+                Some((monitor, humantime::format_rfc3339_seconds(at).to_string()))
+            })
+            .collect(),
+    }
+}
+
+/// Returns the state for `wallpaper print`, `live` if it is the one of the daemon
+pub fn state_summary(state: &State, live: bool) -> anyhow::Result<StateSummary> {
+    let (config, cache) = (&state.config, &state.cache);
+    let mut monitors: BTreeMap<String, MonitorSummary> = BTreeMap::new();
+    for (monitor, transition) in &cache.last_transitions {
+        monitors.entry(monitor.clone()).or_default().transition = Some(transition.clone());
+    }
+    for (monitor, image) in &cache.last_images {
+        let summary = monitors.entry(monitor.clone()).or_default();
+        summary.image = Some(image.clone());
+        if let Some(history) = cache.history.get(monitor) {
+            summary.history = history.iter().map(|entry| entry.image.clone()).collect();
+        }
+        summary.dominant_color = cache.monitor_colors.get(monitor).cloned();
+        summary.expires_at = scheduler::image_expiry(config, cache, monitor)
+            .map(|expiry| humantime::format_rfc3339_seconds(expiry).to_string());
+        summary.last_update = cache
+            .last_updates
+            .get(monitor)
+            .map(|update| humantime::format_rfc3339_seconds(**update).to_string());
+        let next_update = if live {
+            state.schedule.next_update(monitor)
+        } else {
+            // the jitter is random, so leave it out of the estimate
+            let mut no_jitter = rand::rngs::mock::StepRng::new(0, 0);
+            Some(next_update_time(
+                config,
+                state.power,
+                cache.last_update_of(monitor),
+                &mut no_jitter,
+            ))
+        };
+        summary.next_update =
+            next_update.map(|at| humantime::format_rfc3339_seconds(at).to_string());
+    }
+    for (monitor, cursor) in &cache.cursors {
+        monitors.entry(monitor.clone()).or_default().cursor = Some(cursor.clone());
+    }
+    for (monitor, bag) in &cache.shuffle_bags {
+        monitors.entry(monitor.clone()).or_default().shuffle_bag =
+            Some((bag.remaining.len(), bag.pool.len()));
+    }
+    let luminance = config
+        .images
+        .keys()
+        .filter_map(|name| {
+            let luminance = cache.luminance_of(&config.image_dir.join(name))?;
+            Some((name.clone(), luminance))
+        })
+        .collect();
+    let sun_times = config
+        .location
+        .as_ref()
+        .and_then(|location| location.sun_times(chrono::Local::now().date_naive()))
+        .map(|(sunrise, sunset)| {
+            (
+                sunrise.format("%H:%M:%S").to_string(),
+                sunset.format("%H:%M:%S").to_string(),
+            )
+        });
+    let daemon = live.then(|| ipc::DaemonSummary {
+        status: daemon_status(state),
+        workspaces: state.workspaces.clone(),
+        source_images: state.source_images.len(),
+    });
+
+    Ok(StateSummary {
+        schema_version: ipc::STATE_SCHEMA_VERSION,
+        live,
+        config_file: State::config_file()?,
+        cache_file: State::cache_file()?,
+        config: config.clone(),
+        effective_update_interval: config
+            .update_interval_at(&chrono::Local::now().naive_local(), state.power)
+            .to_string(),
+        luminance,
+        sun_times,
+        cache: CacheSummary {
+            last_update: cache.last_update.to_string(),
+            mode: cache.mode,
+            mirror: cache.mirror,
+            favorites_only: cache.favorites_only,
+            monitors,
+        },
+        daemon,
+    })
+}
+
+/// Asks the daemon for its state, or reads the files if no daemon responds
+pub fn run(state: &State, json: bool, full: bool) -> anyhow::Result<()> {
+    let live = ipc::Client::connect()
+        .ok()
+        .map(|mut client| client.send(IpcEvent::State))
+        .transpose()?;
+    let summary = match live {
+        Some(IpcResponse::State(summary)) => {
+            let mut summary = *summary;
+            // the resolved times aren't sent
+            summary
+                .config
+                .resolve()
+                .context("can't resolve config of daemon")?;
+            summary
+        }
+        Some(IpcResponse::Error(e)) => anyhow::bail!("daemon returned error: {}", e),
+        Some(response) => anyhow::bail!("unexpected response from daemon: {:?}", response),
+        None => state_summary(state, false)?,
+    };
+    if json {
+        println!(
+            "{}",
+            serde_json::to_string(&summary).context("while serializing state")?
+        );
+    } else {
+        print_state(&summary, full);
+    }
+    Ok(())
+}
+
+/// The sections in which `wallpaper print` groups its lines on a terminal
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Section {
+    State,
+    Monitors,
+    Config,
+    Images,
+}
+
+impl Section {
+    fn title(self) -> &'static str {
+        match self {
+            Self::State => "State",
+            Self::Monitors => "Monitors",
+            Self::Config => "Config",
+            Self::Images => "Images",
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Line {
+    Pair(String, String),
+    Text(String),
+    List(String, Vec<String>),
+}
+
+/// Prints the lines of `wallpaper print` as they come when piped, so scripts can parse them,
+/// or collects them to print aligned sections on a terminal
+#[derive(Debug)]
+struct StatePrinter {
+    /// `None` if the lines are printed as they come
+    sections: Option<BTreeMap<Section, Vec<Line>>>,
+    /// Print long lists completely on a terminal
+    full: bool,
+}
+
+impl StatePrinter {
+    /// The number of items of a list which are printed on a terminal without `--full`
+    const SHORT_LIST: usize = 10;
+
+    fn new(full: bool) -> Self {
+        Self {
+            sections: term::is_terminal().then(BTreeMap::new),
+            full,
+        }
+    }
+
+    fn push(&mut self, section: Section, line: Line) {
+        match &mut self.sections {
+            Some(sections) => sections.entry(section).or_default().push(line),
+            None => match line {
+                Line::Pair(label, value) => println!("{}: {}", label, value),
+                Line::Text(text) => println!("{}", text),
+                Line::List(label, items) => println!("{}: {:#?}", label, items),
+            },
+        }
+    }
+
+    fn line(&mut self, section: Section, label: impl Into<String>, value: impl Display) {
+        self.push(section, Line::Pair(label.into(), value.to_string()));
+    }
+
+    fn text(&mut self, section: Section, text: impl Into<String>) {
+        self.push(section, Line::Text(text.into()));
+    }
+
+    fn list(&mut self, section: Section, label: impl Into<String>, items: Vec<String>) {
+        self.push(section, Line::List(label.into(), items));
+    }
+
+    /// Prints the collected sections, with the monitors as a table
+    fn finish(mut self, monitors: &BTreeMap<String, MonitorSummary>) {
+        let Some(mut sections) = self.sections.take() else {
+            return;
+        };
+        if !monitors.is_empty() {
+            sections.entry(Section::Monitors).or_default();
+        }
+        for (section, lines) in sections {
+            println!("{}", term::paint(section.title(), term::Style::Bold));
+            if section == Section::Monitors {
+                self.print_monitors(monitors);
+            }
+            let width = lines
+                .iter()
+                .filter_map(|line| match line {
+                    Line::Pair(label, _) => Some(label.chars().count()),
+                    _ => None,
+                })
+                .max()
+                .unwrap_or(0);
+            for line in lines {
+                match line {
+                    Line::Pair(label, value) => {
+                        println!("  {:width$}  {}", label, value, width = width);
+                    }
+                    Line::Text(text) => println!("  {}", text),
+                    Line::List(label, items) => self.print_list(&label, &items),
+                }
+            }
+            println!();
+        }
+    }
+
+    fn print_monitors(&self, monitors: &BTreeMap<String, MonitorSummary>) {
+        let header = [
+            "monitor",
+            "transition",
+            "last update",
+            "next update",
+            "expires at",
+            "image",
+        ];
+        let mut rows = vec![header.iter().map(ToString::to_string).collect()];
+        for (monitor, status) in monitors {
+            rows.push(vec![
+                monitor.clone(),
+                status.transition.clone().unwrap_or_default(),
+                status.last_update.clone().unwrap_or_default(),
+                status.next_update.clone().unwrap_or_default(),
+                status.expires_at.clone().unwrap_or_default(),
+                status
+                    .image
+                    .as_ref()
+                    .map(|image| image.display().to_string())
+                    .unwrap_or_default(),
+            ]);
+        }
+        for (i, row) in term::table(&rows).into_iter().enumerate() {
+            if i == 0 {
+                println!("  {}", term::paint(row, term::Style::Dim));
+            } else {
+                println!("  {}", row);
+            }
+        }
+        if self.full {
+            for (monitor, status) in monitors {
+                let history = status
+                    .history
+                    .iter()
+                    .map(|image| image.display().to_string())
+                    .collect::<Vec<_>>();
+                if !history.is_empty() {
+                    self.print_list(&format!("history of {}", monitor), &history);
+                }
+            }
+        }
+    }
+
+    fn print_list(&self, label: &str, items: &[String]) {
+        println!("  {} ({})", label, items.len());
+        let shown = if self.full {
+            items.len()
+        } else {
+            items.len().min(Self::SHORT_LIST)
+        };
+        for item in &items[..shown] {
+            println!("    {}", item);
+        }
+        if shown < items.len() {
+            let more = format!("... and {} more, see --full", items.len() - shown);
+            println!("    {}", term::paint(more, term::Style::Dim));
+        }
+    }
+}
+
+fn print_state(summary: &StateSummary, full: bool) {
+    use Section::{Config as Cfg, Images, Monitors, State as St};
+
+    let (config, cache) = (&summary.config, &summary.cache);
+    let mut out = StatePrinter::new(full);
+    if summary.live {
+        out.line(St, "state", "live (from the running daemon)");
+    } else {
+        out.line(
+            St,
+            "state",
+            "offline (read from the files, no daemon responded)",
+        );
+    }
+    out.line(St, "config file", summary.config_file.display());
+    out.line(St, "cache file", summary.cache_file.display());
+    out.line(St, "last update", &cache.last_update);
+    match cache.mode {
+        Some(mode) => out.line(St, "mode", mode),
+        None => out.line(St, "mode", "none (images of all modes are shown)"),
+    }
+    if let Some(daemon) = &summary.daemon {
+        for (monitor, reason) in &daemon.status.inhibited {
+            out.line(
+                St,
+                format!("monitor {} is not updated because of", monitor),
+                reason,
+            );
+        }
+        for monitor in &daemon.status.deferred {
+            out.text(
+                St,
+                format!("monitor {} gets updated when it is not inhibited", monitor),
+            );
+        }
+        for monitor in &daemon.status.pending {
+            out.text(
+                St,
+                format!("monitor {} gets updated when it is connected", monitor),
+            );
+        }
+        for (monitor, workspace) in &daemon.workspaces {
+            out.line(
+                St,
+                format!("focused workspace on monitor {}", monitor),
+                workspace,
+            );
+        }
+        out.line(
+            St,
+            "images found in the image sources",
+            daemon.source_images,
+        );
+    }
+    // on a terminal the monitors are printed as a table
+    if out.sections.is_none() {
+        for (monitor, status) in &cache.monitors {
+            if let Some(transition) = &status.transition {
+                out.line(
+                    Monitors,
+                    format!("last transition for monitor {}", monitor),
+                    transition,
+                );
+            }
+            let Some(image) = &status.image else {
+                continue;
+            };
+            out.line(
+                Monitors,
+                format!("last image for monitor {}", monitor),
+                image.display(),
+            );
+            if !status.history.is_empty() {
+                let history = status
+                    .history
+                    .iter()
+                    .map(|image| image.to_string_lossy().into_owned())
+                    .collect();
+                out.list(
+                    Monitors,
+                    format!("history for monitor {}", monitor),
+                    history,
+                );
+            }
+            if let Some(color) = &status.dominant_color {
+                out.line(
+                    Monitors,
+                    format!("dominant color for monitor {}", monitor),
+                    color,
+                );
+            }
+            if let Some(expiry) = &status.expires_at {
+                out.text(
+                    Monitors,
+                    format!("image of monitor {} expires at {}", monitor, expiry),
+                );
+            }
+            if let Some(update) = &status.last_update {
+                out.line(
+                    Monitors,
+                    format!("last update for monitor {}", monitor),
+                    update,
+                );
+            }
+            if let Some(update) = &status.next_update {
+                out.line(
+                    Monitors,
+                    format!("next update for monitor {}", monitor),
+                    update,
+                );
+            }
+        }
+    } else {
+        for (monitor, status) in &cache.monitors {
+            if let Some(color) = &status.dominant_color {
+                out.line(
+                    Monitors,
+                    format!("dominant color for monitor {}", monitor),
+                    color,
+                );
+            }
+        }
+    }
+    out.line(Cfg, "check interval", config.check_interval);
+    out.line(Cfg, "pending expiry", config.pending_expiry);
+    out.line(Cfg, "monitor retention", config.monitor_retention);
+    if !config.keep_monitors.is_empty() {
+        out.line(Cfg, "kept monitors", config.keep_monitors.join(", "));
+    }
+    out.line(Cfg, "update interval", config.update_interval);
+    out.line(Cfg, "interval jitter", config.interval_jitter);
+    for entry in &config.interval_schedule {
+        out.line(
+            Cfg,
+            format!("update interval during {}", entry.times),
+            entry.update_interval,
+        );
+    }
+    out.line(
+        Cfg,
+        "currently effective update interval",
+        &summary.effective_update_interval,
+    );
+    out.list(Cfg, "transitions", config.transitions.clone());
+    out.line(
+        Cfg,
+        "transition order",
+        format!("{:?}", config.transition_order),
+    );
+    out.line(Cfg, "on start", format!("{:?}", config.on_start));
+    out.line(Cfg, "on exit", format!("{:?}", config.on_exit));
+    out.line(Cfg, "startup timeout", config.startup_timeout);
+    out.line(Cfg, "inhibit on fullscreen", config.inhibit_on_fullscreen);
+    out.line(Cfg, "skip when off", config.skip_when_off);
+    out.line(Cfg, "current links", config.current_links);
+    if let Some(log_dir) = &config.log_dir {
+        out.line(Cfg, "log dir", log_dir.display());
+    }
+    if let Some(lockscreen) = &config.lockscreen {
+        out.line(
+            Cfg,
+            "lock screen",
+            format!("{:?} {}", lockscreen.mode, lockscreen.target),
+        );
+    }
+    if let Some(monitor) = &config.primary_monitor {
+        out.line(Cfg, "primary monitor", monitor);
+    }
+    if let Some(command) = &config.hooks.pre_change {
+        out.line(
+            Cfg,
+            "pre change hook",
+            format!("{} (timeout {})", command, config.hooks.pre_change_timeout),
+        );
+    }
+    if !config.hooks.post_change.is_empty() {
+        out.list(Cfg, "post change hooks", config.hooks.post_change.clone());
+    }
+    if !config.hooks.post_update.is_empty() {
+        out.list(Cfg, "post update hooks", config.hooks.post_update.clone());
+    }
+    if let Some(command) = &config.selector_command {
+        out.line(
+            Cfg,
+            "selector command",
+            format!("{} (timeout {})", command, config.selector_timeout),
+        );
+    }
+    if let Some(night) = &config.night_max_brightness {
+        out.line(
+            Cfg,
+            "night max brightness",
+            format!("{} during {}", night.max, night.times),
+        );
+    }
+    let images: Vec<_> = config
+        .images
+        .iter()
+        .map(|(name, entry)| {
+            let times = entry
+                .times
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join(", ");
+            let mut s = format!("{}: [{}]", name.display(), times);
+            if let Some(dates) = &entry.dates {
+                s.push_str(&format!(" (dates {})", dates));
+            }
+            if let Some(cooldown) = entry.cooldown {
+                s.push_str(&format!(" (cooldown {})", cooldown));
+            }
+            if let Some(mode) = entry.mode {
+                s.push_str(&format!(" (mode {})", mode));
+            }
+            if let Some(max_duration) = entry.max_duration {
+                s.push_str(&format!(" (max duration {})", max_duration));
+            }
+            if let Some(weight) = entry.weight {
+                s.push_str(&format!(" (weight {})", weight));
+            }
+            if let Some(luminance) = summary.luminance.get(name) {
+                s.push_str(&format!(" (luminance {:.2})", luminance));
+            }
+            s
+        })
+        .collect();
+    for (name, times) in &config.time_presets {
+        let times = times
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join(", ");
+        out.line(
+            Images,
+            format!("time preset @{}", name),
+            format!("[{}]", times),
+        );
+    }
+    out.list(Images, "images", images);
+    for source in &config.image_sources {
+        let times = source
+            .times
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join(", ");
+        out.line(
+            Images,
+            format!(
+                "image source {}{}",
+                source.dir.display(),
+                if source.recursive { " (recursive)" } else { "" }
+            ),
+            format!("[{}]", times),
+        );
+    }
+    for source in &config.remote_sources {
+        out.line(
+            Images,
+            format!("remote source {}", source.url),
+            format!("refreshed every {}", source.refresh),
+        );
+    }
+    for (workspace, images) in &config.workspace_images {
+        let images: Vec<_> = images
+            .iter()
+            .map(|(name, times)| {
+                let times = times
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                format!("{}: [{}]", name, times)
+            })
+            .collect();
+        out.list(
+            Images,
+            format!("images for workspace {}", workspace),
+            images,
+        );
+    }
+    out.line(Cfg, "cooldown", config.cooldown);
+    out.line(Cfg, "history size", config.history_size);
+    out.line(Cfg, "history limit", config.history_limit);
+    out.line(Cfg, "select max depth", config.select_max_depth);
+    for rule in &config.name_rules {
+        out.line(
+            Images,
+            format!("name rule {}", rule.name),
+            format!("{} -> {}", rule.pattern, rule.times),
+        );
+    }
+    out.line(
+        Cfg,
+        "selection mode",
+        format!("{:?}", config.selection_mode),
+    );
+    out.line(
+        Cfg,
+        "allow duplicate images across monitors",
+        config.allow_duplicate_across_monitors,
+    );
+    if let Some(AspectTolerance(tolerance)) = config.aspect_tolerance {
+        out.line(Cfg, "aspect tolerance", tolerance);
+    }
+    if let Some(effects) = &config.effects {
+        out.line(Cfg, "effects", format!("{:?}", effects));
+    }
+    for (monitor, settings) in &config.monitor_settings {
+        if let Some(effects) = &settings.effects {
+            out.line(
+                Cfg,
+                format!("effects for monitor {}", monitor),
+                format!("{:?}", effects),
+            );
+        }
+    }
+    out.line(Cfg, "rating factor", config.rating_factor.0);
+    out.line(
+        St,
+        "favorites only",
+        format!(
+            "{} (threshold {})",
+            cache.favorites_only, config.favorites_threshold
+        ),
+    );
+    if config.dedupe {
+        out.line(Cfg, "dedupe", "images with the same content count as one");
+    }
+    if config.prescale {
+        out.line(
+            Cfg,
+            "prescale",
+            format!("up to {} MiB of scaled images", config.prescale_cache_mb),
+        );
+    }
+    for group in &config.span_groups {
+        let monitors: Vec<_> = group
+            .monitors
+            .iter()
+            .map(|monitor| match (monitor.x, monitor.y) {
+                (None, None) => monitor.name.clone(),
+                (x, y) => format!(
+                    "{} at {},{}",
+                    monitor.name,
+                    x.map_or_else(|| String::from("auto"), |x| x.to_string()),
+                    y.unwrap_or(0)
+                ),
+            })
+            .collect();
+        out.line(
+            Cfg,
+            "span group",
+            format!("{} (tolerance {})", monitors.join(", "), group.tolerance()),
+        );
+    }
+    match cache.mirror {
+        Some(mirror) => out.line(
+            St,
+            "mirror",
+            format!("{} (set with `wallpaper mirror`)", mirror),
+        ),
+        None => out.line(Cfg, "mirror", config.mirror),
+    }
+    if config.selection_mode == SelectionMode::Sequential {
+        out.line(Cfg, "sort", format!("{:?}", config.sort));
+        for (monitor, status) in &cache.monitors {
+            if let Some(cursor) = &status.cursor {
+                out.line(
+                    St,
+                    format!("sequence position on {}", monitor),
+                    cursor.display(),
+                );
+            }
+        }
+    }
+    for (monitor, status) in &cache.monitors {
+        if let Some((remaining, pool)) = status.shuffle_bag {
+            out.line(
+                St,
+                format!("images left in this round on {}", monitor),
+                format!("{}/{}", remaining, pool),
+            );
+        }
+    }
+    out.line(Cfg, "image directory", config.image_dir.to_string_lossy());
+    out.line(Cfg, "fps", config.fps);
+    if let Some(on_battery) = &config.power.on_battery {
+        out.line(Cfg, "fps on battery", on_battery.fps);
+        if !on_battery.transitions.is_empty() {
+            out.list(
+                Cfg,
+                "transitions on battery",
+                on_battery.transitions.clone(),
+            );
+        }
+        if let Some(interval) = on_battery.update_interval {
+            out.line(Cfg, "update interval on battery", interval);
+        }
+    }
+    if let Some(location) = &config.location {
+        out.line(
+            Cfg,
+            "location",
+            format!("{}, {}", location.latitude, location.longitude),
+        );
+        match &summary.sun_times {
+            Some((sunrise, sunset)) => {
+                out.line(Cfg, "sunrise today", sunrise);
+                out.line(Cfg, "sunset today", sunset);
+            }
+            None => out.text(Cfg, "the sun doesn't rise or set today"),
+        }
+    }
+    out.finish(&cache.monitors);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use wallpaper::{power, Config};
+
+    use super::*;
+
+    #[test]
+    fn print_json_matches_the_snapshot() {
+        let mut config: Config =
+            serde_json::from_str(include_str!("../tests/fixtures/print/config.json")).unwrap();
+        config.resolve().unwrap();
+        let cache =
+            serde_json::from_str(include_str!("../tests/fixtures/print/cache.json")).unwrap();
+        let mut state = State::new(config, cache).unwrap();
+        state.power = power::PowerProfile::Ac;
+
+        let mut summary = state_summary(&state, false).unwrap();
+        // the files depend on the home directory
+        summary.config_file = PathBuf::from("/config/wallpaper/config.json");
+        summary.cache_file = PathBuf::from("/cache/wallpaper/cache.json");
+        let json = serde_json::to_value(&summary).unwrap();
+        let snapshot: serde_json::Value =
+            serde_json::from_str(include_str!("../tests/fixtures/print/summary.json")).unwrap();
+        assert_eq!(
+            json,
+            snapshot,
+            "{}",
+            serde_json::to_string_pretty(&json).unwrap()
+        );
+    }
+}
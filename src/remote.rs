@@ -0,0 +1,334 @@
+//! Downloads the images of `remote_sources` in the background
+//!
+//! Downloading needs the `remote` feature. Without it, only the images which were
+//! downloaded before are shown.
+
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+    sync::mpsc::{channel, Receiver, Sender},
+    time::Instant,
+};
+
+use tracing::{debug, warn};
+
+use crate::config::State;
+
+type FetchResult = (String, PathBuf, anyhow::Result<usize>);
+
+/// Refreshes the remote sources in background threads whenever their refresh interval is over
+#[derive(Debug)]
+pub struct Fetcher {
+    sender: Sender<FetchResult>,
+    results: Receiver<FetchResult>,
+    /// Map from URL to the time at which its last refresh started
+    started: HashMap<String, Instant>,
+    /// URLs which are refreshed now
+    running: HashSet<String>,
+}
+
+impl Default for Fetcher {
+    fn default() -> Self {
+        let (sender, results) = channel();
+        Self {
+            sender,
+            results,
+            started: HashMap::new(),
+            running: HashSet::new(),
+        }
+    }
+}
+
+impl Fetcher {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Logs the finished refreshes and starts the ones which are due
+    ///
+    /// This never blocks. Failed refreshes are retried after the refresh interval,
+    /// until then the images which were downloaded before are used.
+    pub fn poll(&mut self, state: &State) {
+        while let Ok((url, dir, result)) = self.results.try_recv() {
+            self.running.remove(&url);
+            match result {
+                Ok(count) => debug!("remote source {} has {} images", url, count),
+                Err(e) => warn!(
+                    "can't refresh remote source {}, using the images in {}: {:#}",
+                    url,
+                    dir.display(),
+                    e
+                ),
+            }
+        }
+
+        if !cfg!(feature = "remote") {
+            return;
+        }
+        for source in &state.config.remote_sources {
+            let due = self
+                .started
+                .get(&source.url)
+                .is_none_or(|started| started.elapsed() >= *source.refresh);
+            if !due || self.running.contains(&source.url) {
+                continue;
+            }
+            debug!("refreshing remote source {}", source.url);
+            self.started.insert(source.url.clone(), Instant::now());
+            self.running.insert(source.url.clone());
+
+            let url = source.url.clone();
+            let dir = state.remote_dir(source);
+            let extensions = state.config.allowed_extensions.clone();
+            let sender = self.sender.clone();
+            std::thread::spawn(move || {
+                let result = fetch(&url, &dir, &extensions);
+                // the daemon may have stopped already
+                let _ = sender.send((url, dir, result));
+            });
+        }
+    }
+}
+
+/// Name of the file which stores the downloaded URLs and their validators
+#[cfg(feature = "remote")]
+const META_FILE: &str = "meta.json";
+
+/// What was downloaded from a remote source
+#[cfg(feature = "remote")]
+#[derive(Debug, Default, serde::Serialize, serde::Deserialize)]
+struct Meta {
+    index: Validators,
+    /// Map from the URL of an image to its file
+    files: std::collections::BTreeMap<String, Download>,
+}
+
+/// Lets the server answer that a file wasn't modified since it was downloaded
+#[cfg(feature = "remote")]
+#[derive(Debug, Default, Clone, serde::Serialize, serde::Deserialize)]
+struct Validators {
+    etag: Option<String>,
+    last_modified: Option<String>,
+}
+
+#[cfg(feature = "remote")]
+impl Validators {
+    fn of(response: &ureq::Response) -> Self {
+        Self {
+            etag: response.header("ETag").map(String::from),
+            last_modified: response.header("Last-Modified").map(String::from),
+        }
+    }
+}
+
+#[cfg(feature = "remote")]
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+struct Download {
+    file: String,
+    validators: Validators,
+}
+
+/// Downloads the list of images and all images which were modified into `dir`
+///
+/// Images which are not listed anymore get removed. Returns the number of images.
+#[cfg(feature = "remote")]
+fn fetch(url: &str, dir: &Path, extensions: &[String]) -> anyhow::Result<usize> {
+    use std::collections::BTreeMap;
+
+    use anyhow::Context;
+
+    let meta_path = dir.join(META_FILE);
+    let mut meta: Meta = std::fs::read(&meta_path)
+        .ok()
+        .and_then(|meta| serde_json::from_slice(&meta).ok())
+        .unwrap_or_default();
+
+    let agent = ureq::AgentBuilder::new()
+        .timeout_connect(std::time::Duration::from_secs(10))
+        .timeout_read(std::time::Duration::from_secs(60))
+        .build();
+    let base = url::Url::parse(url).context("while parsing url")?;
+    let urls: Vec<String> = match get(&agent, url, &meta.index)? {
+        None => {
+            debug!("list of {} was not modified", url);
+            meta.files.keys().cloned().collect()
+        }
+        Some(response) => {
+            let validators = Validators::of(&response);
+            let body = response
+                .into_string()
+                .context("while reading list of images")?;
+            meta.index = validators;
+            image_urls(&base, &body, extensions)
+        }
+    };
+    std::fs::create_dir_all(dir).context("while creating download directory")?;
+
+    let mut files = BTreeMap::new();
+    for image_url in urls {
+        let previous = meta
+            .files
+            .get(&image_url)
+            .filter(|download| dir.join(&download.file).is_file());
+        let file = match previous {
+            Some(download) => download.file.clone(),
+            None => file_name(&image_url),
+        };
+        let validators = previous
+            .map(|download| download.validators.clone())
+            .unwrap_or_default();
+        match download(&agent, &image_url, &dir.join(&file), validators) {
+            Ok(validators) => {
+                files.insert(image_url, Download { file, validators });
+            }
+            Err(e) => {
+                warn!("can't download {}: {:#}", image_url, e);
+                if let Some(previous) = previous {
+                    files.insert(image_url.clone(), previous.clone());
+                }
+            }
+        }
+    }
+
+    for entry in std::fs::read_dir(dir).context("while reading download directory")? {
+        let entry = entry.context("while reading download directory entry")?;
+        let name = entry.file_name();
+        let listed = name == META_FILE || files.values().any(|download| *download.file == name);
+        if !listed {
+            debug!(
+                "removing {}, as it is not listed anymore",
+                entry.path().display()
+            );
+            std::fs::remove_file(entry.path()).context("while removing old download")?;
+        }
+    }
+
+    let count = files.len();
+    meta.files = files;
+    let meta = serde_json::to_vec(&meta).context("while serializing downloads")?;
+    std::fs::write(&meta_path, meta).context("while writing downloads")?;
+
+    Ok(count)
+}
+
+#[cfg(not(feature = "remote"))]
+fn fetch(_url: &str, _dir: &Path, _extensions: &[String]) -> anyhow::Result<usize> {
+    anyhow::bail!("downloading images needs the `remote` feature")
+}
+
+/// Returns `None` if the file wasn't modified since it was downloaded with these validators
+#[cfg(feature = "remote")]
+fn get(
+    agent: &ureq::Agent,
+    url: &str,
+    validators: &Validators,
+) -> anyhow::Result<Option<ureq::Response>> {
+    use anyhow::Context;
+
+    let mut request = agent.get(url);
+    if let Some(etag) = &validators.etag {
+        request = request.set("If-None-Match", etag);
+    }
+    if let Some(last_modified) = &validators.last_modified {
+        request = request.set("If-Modified-Since", last_modified);
+    }
+    let response = request
+        .call()
+        .with_context(|| format!("while requesting {}", url))?;
+
+    Ok((response.status() != 304).then_some(response))
+}
+
+/// Downloads the image to `path`, unless it wasn't modified since the last download
+#[cfg(feature = "remote")]
+fn download(
+    agent: &ureq::Agent,
+    url: &str,
+    path: &Path,
+    validators: Validators,
+) -> anyhow::Result<Validators> {
+    use anyhow::Context;
+
+    let Some(response) = get(agent, url, &validators)? else {
+        return Ok(validators);
+    };
+    let validators = Validators::of(&response);
+    debug!("downloading {} to {}", url, path.display());
+    // the image may be shown right now, so replace it only when it is complete
+    let partial = path.with_extension("part");
+    let mut file = std::fs::File::create(&partial).context("while creating file")?;
+    std::io::copy(&mut response.into_reader(), &mut file).context("while downloading")?;
+    std::fs::rename(&partial, path).context("while moving download")?;
+
+    Ok(validators)
+}
+
+/// Returns the URLs of the images with one of the extensions
+///
+/// The body is either a JSON list of URLs or a HTML page with links like a directory index.
+/// Relative URLs are resolved against `base`.
+#[cfg(feature = "remote")]
+fn image_urls(base: &url::Url, body: &str, extensions: &[String]) -> Vec<String> {
+    let links = serde_json::from_str::<Vec<String>>(body).unwrap_or_else(|_| links(body));
+    let mut urls: Vec<_> = links
+        .iter()
+        .filter_map(|link| base.join(link).ok())
+        .filter(|url| matches!(url.scheme(), "http" | "https"))
+        .filter(|url| {
+            Path::new(url.path()).extension().is_some_and(|extension| {
+                extensions
+                    .iter()
+                    .any(|allowed| extension.eq_ignore_ascii_case(allowed.trim_start_matches('.')))
+            })
+        })
+        .map(String::from)
+        .collect();
+    urls.sort();
+    urls.dedup();
+    urls
+}
+
+/// Returns the targets of all `href` attributes
+#[cfg(feature = "remote")]
+fn links(html: &str) -> Vec<String> {
+    let mut links = Vec::new();
+    let mut rest = html;
+    while let Some(start) = rest.find("href=") {
+        rest = &rest[start + "href=".len()..];
+        let Some(quote) = rest.chars().next().filter(|c| *c == '"' || *c == '\'') else {
+            continue;
+        };
+        rest = &rest[1..];
+        let Some(end) = rest.find(quote) else {
+            break;
+        };
+        links.push(rest[..end].replace("&amp;", "&"));
+        rest = &rest[end + 1..];
+    }
+    links
+}
+
+/// Returns a file name which is unique for the URL and keeps its extension
+#[cfg(feature = "remote")]
+fn file_name(url: &str) -> String {
+    use std::hash::{DefaultHasher, Hash, Hasher};
+
+    let mut s = DefaultHasher::new();
+    url.hash(&mut s);
+    let last = url
+        .split(['?', '#'])
+        .next()
+        .and_then(|path| path.rsplit('/').next())
+        .unwrap_or_default();
+    let last: String = last
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_') {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    format!("{:016x}-{}", s.finish(), last)
+}
@@ -0,0 +1,79 @@
+//! Scales images to the resolution of the monitors before swww gets them
+//!
+//! Scaling needs the `prescale` feature. Without it, swww gets the original images.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use tracing::debug;
+
+/// Returns a copy of the image which is scaled and cropped to fill the size
+///
+/// The copies are stored in `dir` and reused until the image is modified.
+/// Reusing a copy marks it as recently used for [`evict`].
+#[cfg(feature = "prescale")]
+pub fn scaled(dir: &Path, image: &Path, (width, height): (u32, u32)) -> anyhow::Result<PathBuf> {
+    use std::hash::{DefaultHasher, Hash, Hasher};
+
+    let modified = std::fs::metadata(image)
+        .and_then(|m| m.modified())
+        .context("while reading modification time")?;
+    let mut s = DefaultHasher::new();
+    image.hash(&mut s);
+    modified.hash(&mut s);
+    let path = dir.join(format!("{:016x}-{}x{}.png", s.finish(), width, height));
+
+    if path.is_file() {
+        std::fs::File::options()
+            .append(true)
+            .open(&path)
+            .and_then(|file| file.set_modified(std::time::SystemTime::now()))
+            .context("while marking scaled image as used")?;
+        return Ok(path);
+    }
+
+    debug!("scaling {} to {}x{}", image.display(), width, height);
+    std::fs::create_dir_all(dir).context("while creating directory for scaled images")?;
+    image::open(image)
+        .context("while decoding image")?
+        .resize_to_fill(width, height, image::imageops::FilterType::Lanczos3)
+        .save(&path)
+        .with_context(|| format!("while saving scaled image {}", path.display()))?;
+
+    Ok(path)
+}
+
+#[cfg(not(feature = "prescale"))]
+pub fn scaled(_dir: &Path, _image: &Path, _size: (u32, u32)) -> anyhow::Result<PathBuf> {
+    anyhow::bail!("scaling images needs the `prescale` feature")
+}
+
+/// Removes the least recently used files in `dir` until they take at most `max_bytes`
+pub fn evict(dir: &Path, max_bytes: u64) -> anyhow::Result<()> {
+    if !dir.is_dir() {
+        return Ok(());
+    }
+    let mut files = Vec::new();
+    for entry in std::fs::read_dir(dir).context("while reading directory of scaled images")? {
+        let entry = entry.context("while reading directory entry")?;
+        let meta = entry.metadata().context("while reading metadata")?;
+        if meta.is_file() {
+            let used = meta.modified().context("while reading modification time")?;
+            files.push((used, meta.len(), entry.path()));
+        }
+    }
+    files.sort();
+
+    let mut total: u64 = files.iter().map(|(_used, len, _path)| len).sum();
+    for (_used, len, path) in files {
+        if total <= max_bytes {
+            break;
+        }
+        debug!("evicting scaled image {}", path.display());
+        std::fs::remove_file(&path)
+            .with_context(|| format!("while removing {}", path.display()))?;
+        total -= len;
+    }
+
+    Ok(())
+}
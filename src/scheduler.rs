@@ -0,0 +1,735 @@
+//! Decides when the daemon has to wake up and whether an update is due
+
+use std::{
+    collections::BTreeMap,
+    time::{Duration, Instant, SystemTime},
+};
+
+use chrono::{DateTime, Local, NaiveDate, TimeZone};
+use rand::Rng;
+
+use crate::{
+    config::{Cache, Config, ValidTime},
+    power::PowerProfile,
+};
+
+/// Why the daemon wakes up
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reason {
+    /// The update interval elapsed
+    Interval,
+    /// The time window of an image started or ended
+    Boundary,
+    /// The image of a monitor was shown for its `max_duration`
+    Expired,
+    /// Only check for changes, no update is due
+    Check,
+}
+
+impl Reason {
+    /// Returns whether the wallpaper should be updated when waking up for this reason
+    pub fn is_update(&self) -> bool {
+        !matches!(self, Self::Check)
+    }
+}
+
+impl std::fmt::Display for Reason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Interval => write!(f, "update interval elapsed"),
+            Self::Boundary => write!(f, "time window of an image started or ended"),
+            Self::Expired => write!(f, "image was shown for its maximum duration"),
+            Self::Check => write!(f, "check interval elapsed"),
+        }
+    }
+}
+
+/// Detects jumps of the wall clock, e.g. because the system was suspended
+///
+/// `Instant` doesn't advance while the system is suspended, but `SystemTime` does,
+/// so a sleep of the daemon can last much longer than intended.
+/// The times are passed in by the caller, so no clock is read here.
+#[derive(Debug, Clone, Copy)]
+pub struct ClockJumpDetector {
+    wall: SystemTime,
+    monotonic: Instant,
+    /// Differences up to this are not considered a jump
+    tolerance: Duration,
+}
+
+impl ClockJumpDetector {
+    /// Only jumps larger than the `check_interval` are reported, as the daemon checks
+    /// for due updates that often anyways
+    pub fn new(wall: SystemTime, monotonic: Instant, check_interval: Duration) -> Self {
+        Self {
+            wall,
+            monotonic,
+            tolerance: check_interval,
+        }
+    }
+
+    /// Returns how far the wall clock jumped since the last call
+    ///
+    /// Jumps backwards, e.g. because the time was set manually, are reported too.
+    pub fn check(&mut self, wall: SystemTime, monotonic: Instant) -> Option<Duration> {
+        let monotonic_elapsed = monotonic.saturating_duration_since(self.monotonic);
+        let expected = self.wall + monotonic_elapsed;
+        let jump = match wall.duration_since(expected) {
+            Ok(forward) => forward,
+            Err(e) => e.duration(),
+        };
+        self.wall = wall;
+        self.monotonic = monotonic;
+
+        Some(jump).filter(|jump| *jump > self.tolerance)
+    }
+}
+
+/// Returns all times in `(after, before)` at which one of the `times` starts or stops matching
+fn boundaries_between<'a>(
+    times: impl Iterator<Item = &'a ValidTime> + Clone,
+    after: SystemTime,
+    before: SystemTime,
+) -> Vec<SystemTime> {
+    let first_day = DateTime::<Local>::from(after).date_naive();
+    let last_day = DateTime::<Local>::from(before).date_naive();
+    let mut boundaries: Vec<SystemTime> = first_day
+        .iter_days()
+        .take_while(|day| *day <= last_day)
+        .flat_map(|day: NaiveDate| {
+            times.clone().flat_map(move |time| {
+                time.boundaries(day)
+                    .into_iter()
+                    .filter_map(move |boundary| {
+                        Local
+                            .from_local_datetime(&day.and_time(boundary))
+                            .earliest()
+                    })
+                    .filter(move |boundary| {
+                        // with weekdays a boundary doesn't apply to every day
+                        let before = *boundary - chrono::Duration::nanoseconds(1);
+                        time.matches(&before.naive_local()) != time.matches(&boundary.naive_local())
+                    })
+            })
+        })
+        .map(SystemTime::from)
+        .filter(|boundary| after < *boundary && *boundary < before)
+        .collect();
+    boundaries.sort();
+    boundaries.dedup();
+
+    boundaries
+}
+
+/// Returns the parts of the given day in which no image is valid
+///
+/// The parts are given as half open ranges of seconds since midnight.
+pub fn uncovered_seconds(config: &Config, date: NaiveDate) -> Vec<(u32, u32)> {
+    const DAY: u32 = 24 * 60 * 60;
+
+    let mut covered: Vec<_> = config
+        .images
+        .values()
+        .filter(|entry| {
+            entry
+                .dates
+                .as_ref()
+                .is_none_or(|dates| dates.matches(&date))
+        })
+        .flat_map(|entry| entry.times.iter())
+        .chain(config.image_sources.iter().flat_map(|source| &source.times))
+        .flat_map(|time| time.covered_seconds(date))
+        .collect();
+    covered.sort();
+
+    let mut gaps = Vec::new();
+    let mut covered_until = 0;
+    for (start, end) in covered {
+        if start > covered_until {
+            gaps.push((covered_until, start));
+        }
+        covered_until = covered_until.max(end);
+    }
+    if covered_until < DAY {
+        gaps.push((covered_until, DAY));
+    }
+
+    gaps
+}
+
+/// Computes when the next update should happen after an update at `last_update`.
+///
+/// The interval is looked up in the `interval_schedule` for every part of the day
+/// between `last_update` and the resulting time, so that a long interval can't delay
+/// the update past the start of a window with a shorter interval.
+/// The `interval_jitter` gets applied randomly in both directions,
+/// but the resulting interval is never shorter than the `check_interval`.
+pub fn next_update_time(
+    config: &Config,
+    profile: PowerProfile,
+    last_update: SystemTime,
+    rng: &mut impl Rng,
+) -> SystemTime {
+    let jitter = *config.interval_jitter;
+    let offset = if jitter.is_zero() {
+        jitter
+    } else {
+        rng.gen_range(std::time::Duration::ZERO..=jitter)
+    };
+    let lengthen = rng.gen::<bool>();
+    let interval_at = |time: SystemTime| {
+        let interval =
+            *config.update_interval_at(&DateTime::<Local>::from(time).naive_local(), profile);
+        let interval = if lengthen {
+            interval + offset
+        } else {
+            interval.saturating_sub(offset)
+        };
+        interval.max(*config.check_interval)
+    };
+
+    let next = last_update + interval_at(last_update);
+
+    // times at which the effective interval may change
+    let boundaries = boundaries_between(
+        config.interval_schedule.iter().map(|entry| &entry.times),
+        last_update,
+        next,
+    );
+
+    // the first part of the day in which the interval elapses determines the next update
+    for (i, boundary) in boundaries.iter().enumerate() {
+        let segment_end = boundaries.get(i + 1).copied().unwrap_or(next);
+        let candidate = (last_update + interval_at(*boundary)).max(*boundary);
+        if candidate < segment_end {
+            return candidate;
+        }
+    }
+
+    next
+}
+
+/// The interval updates of the monitors, each after its own last update
+///
+/// The daemon follows the last updates in the cache with [`Schedule::sync`], so a monitor which
+/// got a new image, e.g. with `wallpaper switch`, restarts only its own interval.
+#[derive(Debug, Clone, Default)]
+pub struct Schedule {
+    intervals: BTreeMap<String, Interval>,
+    /// No interval starts before this time, e.g. if the daemon started without an update
+    not_before: Option<SystemTime>,
+}
+
+#[derive(Debug, Clone)]
+struct Interval {
+    /// The last update of the monitor in the cache when the interval started
+    seen: SystemTime,
+    start: SystemTime,
+    end: SystemTime,
+}
+
+impl Schedule {
+    /// Starts the intervals of the monitors of the cache after their last updates
+    pub fn from_cache(
+        config: &Config,
+        profile: PowerProfile,
+        cache: &Cache,
+        rng: &mut impl Rng,
+    ) -> Self {
+        let mut schedule = Self::default();
+        schedule.sync(config, profile, cache, cache.last_images.keys(), rng);
+        schedule
+    }
+
+    /// Lets no interval start before the time
+    pub fn start_at(&mut self, time: SystemTime) {
+        self.not_before = Some(time);
+    }
+
+    /// Schedules only the monitors and restarts the intervals of those which were updated since
+    pub fn sync<'a>(
+        &mut self,
+        config: &Config,
+        profile: PowerProfile,
+        cache: &Cache,
+        monitors: impl IntoIterator<Item = &'a String>,
+        rng: &mut impl Rng,
+    ) {
+        let mut intervals = BTreeMap::new();
+        for monitor in monitors {
+            let seen = cache.last_update_of(monitor);
+            let interval = match self.intervals.remove(monitor) {
+                Some(interval) if interval.seen == seen => interval,
+                _ => {
+                    let start = self
+                        .not_before
+                        .map_or(seen, |not_before| seen.max(not_before));
+                    Interval {
+                        seen,
+                        start,
+                        end: next_update_time(config, profile, start, rng),
+                    }
+                }
+            };
+            intervals.insert(monitor.clone(), interval);
+        }
+        self.intervals = intervals;
+    }
+
+    /// Restarts the intervals of the monitors at the time, e.g. after their update was due
+    pub fn restart(
+        &mut self,
+        monitors: &[String],
+        config: &Config,
+        profile: PowerProfile,
+        time: SystemTime,
+        rng: &mut impl Rng,
+    ) {
+        for monitor in monitors {
+            if let Some(interval) = self.intervals.get_mut(monitor) {
+                interval.start = time;
+                interval.end = next_update_time(config, profile, time, rng);
+            }
+        }
+    }
+
+    /// Computes the ends of the intervals again, e.g. after the power profile changed
+    pub fn recompute(&mut self, config: &Config, profile: PowerProfile, rng: &mut impl Rng) {
+        for interval in self.intervals.values_mut() {
+            interval.end = next_update_time(config, profile, interval.start, rng);
+        }
+    }
+
+    /// The start of the oldest interval and the end of the interval which ends first,
+    /// `None` if no monitor is scheduled
+    pub fn next(&self) -> Option<(SystemTime, SystemTime)> {
+        let start = self
+            .intervals
+            .values()
+            .map(|interval| interval.start)
+            .min()?;
+        let end = self.intervals.values().map(|interval| interval.end).min()?;
+        Some((start, end))
+    }
+
+    /// The monitors whose interval ended at `now`
+    pub fn due(&self, now: SystemTime) -> Vec<String> {
+        self.intervals
+            .iter()
+            .filter(|(_monitor, interval)| interval.end <= now)
+            .map(|(monitor, _interval)| monitor.clone())
+            .collect()
+    }
+
+    /// All scheduled monitors
+    pub fn monitors(&self) -> Vec<String> {
+        self.intervals.keys().cloned().collect()
+    }
+
+    /// When the next interval update of the monitor is due, `None` if it isn't scheduled
+    pub fn next_update(&self, monitor: &str) -> Option<SystemTime> {
+        self.intervals.get(monitor).map(|interval| interval.end)
+    }
+}
+
+/// Estimates the start and end of the next interval from the cache, if no daemon keeps a schedule
+pub fn estimate_next_update(
+    config: &Config,
+    profile: PowerProfile,
+    cache: &Cache,
+    rng: &mut impl Rng,
+) -> (SystemTime, SystemTime) {
+    Schedule::from_cache(config, profile, cache, rng)
+        .next()
+        .unwrap_or_else(|| {
+            let last_update = *cache.last_update;
+            (
+                last_update,
+                next_update_time(config, profile, last_update, rng),
+            )
+        })
+}
+
+/// Returns when the current image of the monitor was shown for its `max_duration`
+///
+/// Monitors with a static image never expire.
+pub fn image_expiry(config: &Config, cache: &Cache, monitor: &str) -> Option<SystemTime> {
+    if config.static_image(monitor).is_some() {
+        return None;
+    }
+    let image = cache.last_images.get(monitor)?;
+    let since = cache.shown_since.get(monitor)?;
+    let max_duration = config.max_duration_of(image)?;
+    Some(**since + *max_duration)
+}
+
+/// Returns all monitors whose image was shown for its `max_duration` at `now`
+pub fn expired_monitors(config: &Config, cache: &Cache, now: SystemTime) -> Vec<String> {
+    cache
+        .last_images
+        .keys()
+        .filter(|monitor| image_expiry(config, cache, monitor).is_some_and(|expiry| expiry <= now))
+        .cloned()
+        .collect()
+}
+
+/// Computes when the wallpaper changes next and why
+///
+/// `next_update` is the result of [`next_update_time`] for `last_update`.
+/// If the time window of an image started or ended since `last_update`,
+/// the returned time is not after `now`, so the update is due immediately.
+/// Only the monitors from [`expired_monitors`] have to be updated if the reason is
+/// [`Reason::Expired`].
+pub fn next_change(
+    now: SystemTime,
+    last_update: SystemTime,
+    next_update: SystemTime,
+    config: &Config,
+    cache: &Cache,
+) -> (SystemTime, Reason) {
+    if next_update <= now {
+        // no need to look for boundaries, as the update is due anyways
+        return (next_update, Reason::Interval);
+    }
+
+    let mut change = (next_update, Reason::Interval);
+
+    let image_times = config
+        .images
+        .values()
+        .flat_map(|entry| entry.times.iter())
+        .chain(config.image_sources.iter().flat_map(|source| &source.times));
+    if let Some(boundary) = boundaries_between(image_times, last_update, change.0)
+        .first()
+        .copied()
+    {
+        change = (boundary, Reason::Boundary);
+    }
+
+    if let Some(expiry) = cache
+        .last_images
+        .keys()
+        .filter_map(|monitor| image_expiry(config, cache, monitor))
+        .min()
+    {
+        if expiry < change.0 {
+            change = (expiry, Reason::Expired);
+        }
+    }
+
+    change
+}
+
+/// Computes when the daemon has to wake up next and why
+///
+/// This is the [`next_change`], unless the config has to be checked for changes before.
+/// The end of the interval is passed in instead of being computed from `last_update`,
+/// because [`next_update_time`] draws a random jitter, which has to stay the same for the whole
+/// interval and is therefore kept in the [`Schedule`].
+pub fn next_wakeup(
+    now: SystemTime,
+    last_update: SystemTime,
+    next_update: SystemTime,
+    config: &Config,
+    cache: &Cache,
+) -> (SystemTime, Reason) {
+    let change = next_change(now, last_update, next_update, config, cache);
+    let next_check = now + *config.check_interval;
+    if next_check < change.0 {
+        (next_check, Reason::Check)
+    } else {
+        change
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::config::{ImageEntry, IntervalScheduleEntry};
+
+    /// The local time on the day of May 2024
+    fn at(day: u32, hour: u32, minute: u32) -> SystemTime {
+        let time = NaiveDate::from_ymd_opt(2024, 5, day)
+            .unwrap()
+            .and_hms_opt(hour, minute, 0)
+            .unwrap();
+        Local.from_local_datetime(&time).earliest().unwrap().into()
+    }
+
+    fn minutes(minutes: u64) -> humantime::Duration {
+        Duration::from_secs(minutes * 60).into()
+    }
+
+    /// A config with an image for each of the times, updated hourly and checked daily
+    fn config(times: &[&str]) -> Config {
+        let images = times
+            .iter()
+            .enumerate()
+            .map(|(i, time)| {
+                let entry = ImageEntry::new(vec![time.parse().unwrap()]);
+                (PathBuf::from(format!("{}.png", i)), entry)
+            })
+            .collect();
+        Config {
+            images,
+            update_interval: minutes(60),
+            check_interval: minutes(24 * 60),
+            ..Config::default()
+        }
+    }
+
+    fn wakeup(config: &Config, now: SystemTime, last_update: SystemTime) -> (SystemTime, Reason) {
+        let next_update = last_update + *config.update_interval;
+        next_wakeup(now, last_update, next_update, config, &Cache::default())
+    }
+
+    #[test]
+    fn wakes_up_for_the_interval() {
+        let config = config(&["*"]);
+        assert_eq!(
+            wakeup(&config, at(1, 10, 10), at(1, 10, 0)),
+            (at(1, 11, 0), Reason::Interval)
+        );
+        // an elapsed interval is due immediately
+        assert_eq!(
+            wakeup(&config, at(1, 12, 0), at(1, 10, 0)),
+            (at(1, 11, 0), Reason::Interval)
+        );
+    }
+
+    #[test]
+    fn wakes_up_at_the_start_of_a_window() {
+        let config = config(&["6-7", "7-6"]);
+        assert_eq!(
+            wakeup(&config, at(1, 5, 40), at(1, 5, 30)),
+            (at(1, 6, 0), Reason::Boundary)
+        );
+        // a boundary since the last update is due immediately
+        assert_eq!(
+            wakeup(&config, at(1, 6, 10), at(1, 5, 30)),
+            (at(1, 6, 0), Reason::Boundary)
+        );
+    }
+
+    #[test]
+    fn wakes_up_at_boundaries_around_midnight() {
+        let mut config = config(&["23-1", "1-23"]);
+        assert_eq!(
+            wakeup(&config, at(1, 22, 40), at(1, 22, 30)),
+            (at(1, 23, 0), Reason::Boundary)
+        );
+        assert_eq!(
+            wakeup(&config, at(2, 0, 40), at(2, 0, 30)),
+            (at(2, 1, 0), Reason::Boundary)
+        );
+        // no boundary at midnight, as the window continues on the next day
+        assert_eq!(
+            wakeup(&config, at(1, 23, 40), at(1, 23, 30)),
+            (at(2, 0, 30), Reason::Interval)
+        );
+
+        // the boundary after midnight is found for intervals spanning midnight
+        config.update_interval = minutes(8 * 60);
+        assert_eq!(
+            wakeup(&config, at(1, 23, 40), at(1, 23, 30)),
+            (at(2, 1, 0), Reason::Boundary)
+        );
+    }
+
+    #[test]
+    fn wakes_up_at_every_boundary_of_overlapping_windows() {
+        let mut config = config(&["6-9", "8-10", "9-10"]);
+        config.update_interval = minutes(8 * 60);
+        // updating at every boundary finds the next one
+        let expected = [
+            (at(1, 6, 0), Reason::Boundary),
+            // the second window starts while the first one is still valid
+            (at(1, 8, 0), Reason::Boundary),
+            // one window ends and another starts at the same time
+            (at(1, 9, 0), Reason::Boundary),
+            // both windows end at the same time
+            (at(1, 10, 0), Reason::Boundary),
+            (at(1, 18, 0), Reason::Interval),
+        ];
+        let mut last_update = at(1, 5, 0);
+        for expected in expected {
+            let wakeup = wakeup(&config, last_update, last_update);
+            assert_eq!(wakeup, expected);
+            last_update = wakeup.0;
+        }
+    }
+
+    #[test]
+    fn wakes_up_to_check_the_config() {
+        let mut config = config(&["6-7", "7-6"]);
+        config.check_interval = minutes(5);
+        assert_eq!(
+            wakeup(&config, at(1, 5, 30), at(1, 5, 30)),
+            (at(1, 5, 35), Reason::Check)
+        );
+        assert_eq!(
+            wakeup(&config, at(1, 5, 58), at(1, 5, 30)),
+            (at(1, 6, 0), Reason::Boundary)
+        );
+    }
+
+    fn rng(seed: u64) -> rand::rngs::StdRng {
+        rand::SeedableRng::seed_from_u64(seed)
+    }
+
+    /// A config with an image for all times, updated hourly and checked every 5 minutes
+    fn interval_config() -> Config {
+        Config {
+            check_interval: minutes(5),
+            ..config(&["*"])
+        }
+    }
+
+    #[test]
+    fn updates_after_the_interval_without_jitter() {
+        let config = interval_config();
+        for seed in 0..100 {
+            let next = next_update_time(&config, PowerProfile::Ac, at(1, 10, 0), &mut rng(seed));
+            assert_eq!(next, at(1, 11, 0));
+        }
+    }
+
+    #[test]
+    fn jitter_lengthens_and_shortens_the_interval() {
+        let mut config = interval_config();
+        config.interval_jitter = minutes(10);
+        let (mut shorter, mut longer) = (false, false);
+        for seed in 0..1000 {
+            let next = next_update_time(&config, PowerProfile::Ac, at(1, 10, 0), &mut rng(seed));
+            assert!(
+                at(1, 10, 50) <= next && next <= at(1, 11, 10),
+                "seed {}",
+                seed
+            );
+            shorter |= next < at(1, 11, 0);
+            longer |= next > at(1, 11, 0);
+        }
+        assert!(shorter && longer);
+    }
+
+    #[test]
+    fn jitter_does_not_shorten_the_interval_below_the_check_interval() {
+        let mut config = interval_config();
+        config.update_interval = minutes(10);
+        config.interval_jitter = minutes(30);
+        for seed in 0..1000 {
+            let next = next_update_time(&config, PowerProfile::Ac, at(1, 10, 0), &mut rng(seed));
+            assert!(
+                at(1, 10, 5) <= next && next <= at(1, 10, 40),
+                "seed {}",
+                seed
+            );
+        }
+    }
+
+    #[test]
+    fn shorter_scheduled_intervals_start_on_time() {
+        let mut config = interval_config();
+        config.update_interval = minutes(3 * 60);
+        config.interval_schedule = vec![IntervalScheduleEntry {
+            times: "12-14".parse().unwrap(),
+            update_interval: minutes(30),
+        }];
+        let next =
+            |last_update| next_update_time(&config, PowerProfile::Ac, last_update, &mut rng(0));
+        // the shorter interval elapsed already when its window starts
+        assert_eq!(next(at(1, 11, 0)), at(1, 12, 0));
+        assert_eq!(next(at(1, 12, 10)), at(1, 12, 40));
+        // the longer interval only applies after the window
+        assert_eq!(next(at(1, 13, 50)), at(1, 14, 20));
+        assert_eq!(next(at(1, 14, 0)), at(1, 17, 0));
+    }
+
+    #[test]
+    fn schedules_every_monitor_after_its_last_update() {
+        let config = interval_config();
+        let mut cache = Cache::default();
+        cache.last_update = at(1, 9, 0).into();
+        cache
+            .last_updates
+            .insert(String::from("DP-1"), at(1, 10, 0).into());
+        let monitors = [String::from("DP-1"), String::from("DP-2")];
+        let mut schedule = Schedule::default();
+        schedule.sync(&config, PowerProfile::Ac, &cache, &monitors, &mut rng(0));
+
+        assert_eq!(schedule.next(), Some((at(1, 9, 0), at(1, 10, 0))));
+        assert_eq!(schedule.next_update("DP-1"), Some(at(1, 11, 0)));
+        assert!(schedule.due(at(1, 9, 59)).is_empty());
+        assert_eq!(schedule.due(at(1, 10, 0)), ["DP-2"]);
+
+        // only the restarted monitor gets a new deadline
+        schedule.restart(
+            &[String::from("DP-2")],
+            &config,
+            PowerProfile::Ac,
+            at(1, 10, 0),
+            &mut rng(0),
+        );
+        assert_eq!(schedule.next(), Some((at(1, 10, 0), at(1, 11, 0))));
+        assert_eq!(schedule.due(at(1, 11, 0)), ["DP-1", "DP-2"]);
+
+        // a new image from a switch restarts the interval of the monitor
+        cache
+            .last_updates
+            .insert(String::from("DP-1"), at(1, 10, 30).into());
+        schedule.sync(&config, PowerProfile::Ac, &cache, &monitors, &mut rng(0));
+        assert_eq!(schedule.next_update("DP-1"), Some(at(1, 11, 30)));
+        assert_eq!(schedule.next_update("DP-2"), Some(at(1, 11, 0)));
+    }
+
+    #[test]
+    fn no_interval_starts_before_the_daemon() {
+        let config = interval_config();
+        let mut cache = Cache::default();
+        cache.last_update = at(1, 6, 0).into();
+        let mut schedule = Schedule::default();
+        schedule.start_at(at(1, 10, 0));
+        schedule.sync(
+            &config,
+            PowerProfile::Ac,
+            &cache,
+            &[String::from("DP-1")],
+            &mut rng(0),
+        );
+        assert_eq!(schedule.next(), Some((at(1, 10, 0), at(1, 11, 0))));
+    }
+
+    #[test]
+    fn detects_jumps_larger_than_the_check_interval() {
+        let (wall, monotonic) = (at(1, 22, 0), Instant::now());
+        let check_interval = Duration::from_secs(5 * 60);
+        let mut clock = ClockJumpDetector::new(wall, monotonic, check_interval);
+
+        let monotonic = monotonic + Duration::from_secs(60);
+        assert_eq!(clock.check(at(1, 22, 1), monotonic), None);
+        // a short suspend is covered by the check interval
+        assert_eq!(clock.check(at(1, 22, 6), monotonic), None);
+        // a suspend over night
+        assert_eq!(
+            clock.check(at(2, 7, 0), monotonic + Duration::from_secs(60)),
+            Some(Duration::from_secs((9 * 60 - 7) * 60))
+        );
+        // the jump is reported only once
+        assert_eq!(
+            clock.check(at(2, 7, 1), monotonic + Duration::from_secs(2 * 60)),
+            None
+        );
+    }
+
+    #[test]
+    fn detects_jumps_backwards() {
+        let (wall, monotonic) = (at(1, 22, 0), Instant::now());
+        let mut clock = ClockJumpDetector::new(wall, monotonic, Duration::from_secs(60));
+        assert_eq!(
+            clock.check(at(1, 21, 0), monotonic + Duration::from_secs(30)),
+            Some(Duration::from_secs(60 * 60 + 30))
+        );
+    }
+}
@@ -0,0 +1,466 @@
+//! `wallpaper select`, which puts the images of a directory into the config
+
+use std::{
+    collections::{BTreeMap, HashSet, VecDeque},
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+use tracing::{debug, info, warn};
+use wallpaper::{
+    ipc::{self, ImportMode, IpcEvent, IpcResponse, Selection},
+    query_monitors, update_wallpapers, Config, ImageEntry, Monitors, State, TimeRef, TimeRefs,
+    UpdateOptions, ValidTime,
+};
+
+use crate::{check_times, parse_times};
+
+/// Files which are never images, but often lie next to them
+const JUNK_FILES: &[&str] = &["Thumbs.db", "desktop.ini"];
+
+/// Returns why the file is skipped when walking a directory, if it is hidden or junk
+fn skip_reason(name: &std::ffi::OsStr) -> Option<&'static str> {
+    let name = name.to_string_lossy();
+    if name.starts_with('.') {
+        Some("hidden")
+    } else if name.ends_with('~')
+        || JUNK_FILES
+            .iter()
+            .any(|junk| junk.eq_ignore_ascii_case(&name))
+    {
+        Some("junk")
+    } else {
+        None
+    }
+}
+
+/// Copies or links the image into `image_dir` and returns its key
+///
+/// In a dry run only the key is returned.
+fn import_image(
+    image_dir: &Path,
+    image: &Path,
+    mode: ImportMode,
+    dry_run: bool,
+) -> anyhow::Result<PathBuf> {
+    if !image_dir.is_dir() {
+        anyhow::bail!("image_dir has to be a directory to import images");
+    }
+    let name = image
+        .file_name()
+        .with_context(|| format!("{} has no file name", image.display()))?;
+    let target = image_dir.join(name);
+    if target.symlink_metadata().is_ok() {
+        // a previous import can be reused
+        let imported = match mode {
+            ImportMode::Copy => false,
+            ImportMode::Symlink => std::fs::read_link(&target).is_ok_and(|link| link == image),
+        };
+        if !imported {
+            anyhow::bail!(
+                "can't import {}, because {} exists already",
+                image.display(),
+                target.display()
+            );
+        }
+    } else if !dry_run {
+        match mode {
+            ImportMode::Copy => std::fs::copy(image, &target).map(|_| ()),
+            ImportMode::Symlink => std::os::unix::fs::symlink(image, &target),
+        }
+        .with_context(|| format!("while importing {}", image.display()))?;
+        info!("imported {} as {}", image.display(), target.display());
+    }
+    Ok(PathBuf::from(name))
+}
+
+/// Walks the path and returns the images in it
+///
+/// The skipped files and unreadable directories are counted in `skipped` by reason,
+/// only the path itself has to be readable.
+pub fn find_images(
+    config: &Config,
+    path: &Path,
+    times: &TimeRefs,
+    all_files: bool,
+    skipped: &mut BTreeMap<String, usize>,
+) -> anyhow::Result<BTreeMap<PathBuf, ImageEntry>> {
+    let mut res = BTreeMap::new();
+    // symlinks may point to a parent, so every directory is only walked once
+    let mut visited = HashSet::new();
+    let mut queue = VecDeque::from([(path.to_owned(), 0)]);
+    while let Some((path, depth)) = queue.pop_front() {
+        // symlinks are resolved, so the same image is only selected once
+        let canonical = match path.canonicalize() {
+            Ok(canonical) => canonical,
+            Err(e) if depth == 0 => {
+                return Err(e).with_context(|| format!("can't read {}", path.display()))
+            }
+            Err(e) => {
+                warn!("not selecting {}: {}", path.display(), e);
+                *skipped.entry(String::from("broken symlink")).or_default() += 1;
+                continue;
+            }
+        };
+        if canonical.is_file() {
+            if let Some(reason) = config.rejection(&canonical).filter(|_| !all_files) {
+                debug!("not selecting {}, because {}", path.display(), reason);
+                let reason = if config.has_allowed_extension(&canonical) {
+                    "too large"
+                } else {
+                    "extension not allowed"
+                };
+                *skipped.entry(reason.to_owned()).or_default() += 1;
+                continue;
+            }
+            res.insert(
+                canonical,
+                ImageEntry {
+                    time_refs: times.clone(),
+                    ..ImageEntry::new(Vec::new())
+                },
+            );
+            continue;
+        }
+
+        if !canonical.is_dir() {
+            debug!("not selecting {}, because it is no file", path.display());
+            continue;
+        }
+        if !visited.insert(canonical.clone()) {
+            debug!("not walking {} again", path.display());
+            continue;
+        }
+        if depth >= config.select_max_depth {
+            warn!(
+                "not selecting in {}, because it is deeper than select_max_depth",
+                path.display()
+            );
+            *skipped.entry(String::from("too deep")).or_default() += 1;
+            continue;
+        }
+        let entries = match std::fs::read_dir(&canonical) {
+            Ok(entries) => entries,
+            Err(e) if depth == 0 => {
+                return Err(e).with_context(|| format!("can't read {}", path.display()))
+            }
+            Err(e) => {
+                warn!("not selecting in {}: {}", path.display(), e);
+                *skipped.entry(String::from("unreadable")).or_default() += 1;
+                continue;
+            }
+        };
+        for entry in entries {
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(e) if depth == 0 => {
+                    return Err(e).with_context(|| format!("can't read {}", path.display()))
+                }
+                Err(e) => {
+                    warn!("not selecting further in {}: {}", path.display(), e);
+                    *skipped.entry(String::from("unreadable")).or_default() += 1;
+                    break;
+                }
+            };
+            if let Some(reason) = skip_reason(&entry.file_name()).filter(|_| !all_files) {
+                debug!(
+                    "not selecting {}, because it is {}",
+                    entry.path().display(),
+                    reason
+                );
+                *skipped.entry(reason.to_owned()).or_default() += 1;
+                continue;
+            }
+            queue.push_back((entry.path(), depth + 1));
+        }
+    }
+    Ok(res)
+}
+
+/// How `wallpaper select` treats the found images
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SelectOptions {
+    /// Keep the configured images instead of replacing them
+    pub keep_old: bool,
+    /// Also select hidden files, junk and files without an allowed extension
+    pub all_files: bool,
+    pub import: Option<ImportMode>,
+    /// Derive the times of the images from their names with the `name_rules`
+    pub parse_names: bool,
+    /// Neither change the config nor the wallpapers
+    pub dry_run: bool,
+}
+
+/// Selects the images of the path
+pub fn run(
+    state: &mut State,
+    path: &str,
+    monitor: Option<&str>,
+    times: Option<&str>,
+    options: SelectOptions,
+) -> anyhow::Result<Selection> {
+    let SelectOptions {
+        keep_old,
+        all_files,
+        import,
+        parse_names,
+        dry_run,
+    } = options;
+
+    if let Some(monitor) = monitor {
+        let monitor_infos = query_monitors()?;
+        if !monitor_infos.iter().any(|info| info.name == monitor) {
+            anyhow::bail!("monitor {} is not connected", monitor);
+        }
+    }
+    // the times are checked before the config is changed
+    let times = match times {
+        Some(times) => parse_times(times)?,
+        None => TimeRefs::new(vec![TimeRef::Time(ValidTime::ALL)]),
+    };
+    let rules = if parse_names {
+        state
+            .config
+            .name_rules
+            .iter()
+            .map(|rule| rule.regex().map(|regex| (rule, regex)))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| anyhow::anyhow!(e))?
+    } else {
+        Vec::new()
+    };
+    let mut skipped = BTreeMap::new();
+    let new_images = find_images(
+        &state.config,
+        path.as_ref(),
+        &times,
+        all_files,
+        &mut skipped,
+    )?;
+    let selected = new_images.len();
+    let mut matched = BTreeMap::new();
+    let new_images = new_images
+        .into_iter()
+        .map(|(image, mut entry)| {
+            let rule = rules
+                .iter()
+                .find_map(|(rule, regex)| Some((rule, rule.times_of(regex, &image)?)));
+            if let Some((_rule, times)) = &rule {
+                let times = times.clone().map_err(|e| anyhow::anyhow!(e))?;
+                check_times(&times)?;
+                entry.time_refs = times;
+            }
+            let key = state.config.image_key(&image);
+            let key = match import {
+                Some(mode) if state.config.is_outside_image_dir(&key) => {
+                    import_image(&state.config.image_dir, &image, mode, dry_run)?
+                }
+                _ => key,
+            };
+            if let Some((rule, _times)) = rule {
+                matched.insert(key.clone(), rule.name.clone());
+            }
+            Ok((key, entry))
+        })
+        .collect::<anyhow::Result<BTreeMap<_, _>>>()?;
+
+    let empty = BTreeMap::new();
+    let old_images = match monitor {
+        Some(monitor) => state
+            .config
+            .monitor_settings
+            .get(monitor)
+            .map_or(&empty, |settings| &settings.images),
+        None => &state.config.images,
+    };
+    let selection = Selection {
+        images: selected,
+        added: new_images
+            .keys()
+            .filter(|image| !old_images.contains_key(*image))
+            .cloned()
+            .collect(),
+        dropped: if keep_old {
+            0
+        } else {
+            old_images
+                .keys()
+                .filter(|image| !new_images.contains_key(*image))
+                .count()
+        },
+        skipped,
+        matched,
+    };
+    if dry_run {
+        return Ok(selection);
+    }
+
+    info!(
+        "selected image path {} with {} images, skipped {} files",
+        path,
+        selected,
+        selection.skipped.values().sum::<usize>()
+    );
+
+    // the selection is saved, so it survives reloads
+    state
+        .edit_config(|config| {
+            let images = match monitor {
+                Some(monitor) => {
+                    &mut config
+                        .monitor_settings
+                        .entry(monitor.to_owned())
+                        .or_default()
+                        .images
+                }
+                None => &mut config.images,
+            };
+            if keep_old {
+                images.extend(new_images);
+            } else {
+                *images = new_images;
+            }
+        })
+        .context("while saving selection")?;
+
+    let options = UpdateOptions {
+        ignore_inhibition: true,
+        ..UpdateOptions::default()
+    };
+    let monitors = match monitor {
+        Some(monitor) => Monitors::Some(vec![monitor.to_owned()]),
+        None => Monitors::All,
+    };
+    update_wallpapers(state, monitors, options).context("while updating state")?;
+
+    Ok(selection)
+}
+
+/// Lets the daemon select the images and prints how many files were skipped
+pub fn request(event: IpcEvent) -> anyhow::Result<()> {
+    let dry_run = matches!(event, IpcEvent::Select { dry_run: true, .. });
+    let mut client = ipc::Client::connect()?;
+    let selection = match client.send(event)? {
+        IpcResponse::Selected(selection) => selection,
+        IpcResponse::Error(e) => anyhow::bail!("daemon returned error: {}", e),
+        response => anyhow::bail!("unexpected response from daemon: {:?}", response),
+    };
+
+    let (select, add, drop) = if dry_run {
+        ("would select", "would add", "would drop")
+    } else {
+        ("selected", "added", "dropped")
+    };
+    println!("{} {} images", select, selection.images);
+    for image in &selection.added {
+        println!("{} {}", add, image.display());
+    }
+    for (image, rule) in &selection.matched {
+        println!("times of {} from name rule {}", image.display(), rule);
+    }
+    if selection.dropped > 0 {
+        println!("{} {} configured images", drop, selection.dropped);
+    }
+    for (reason, count) in selection.skipped {
+        println!("skipped {} files: {}", count, reason);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs::Permissions, os::unix::fs::PermissionsExt};
+
+    use super::*;
+    use crate::test_util::TempDir;
+
+    #[test]
+    fn find_images_survives_symlink_cycles() {
+        let dir = TempDir::new();
+        let a = dir.touch("images/a.png");
+        let b = dir.touch("images/sub/b.png");
+        dir.touch("images/notes.txt");
+        let images = dir.path().join("images");
+        // links back to a parent and to an image which is found anyways
+        std::os::unix::fs::symlink(&images, images.join("sub/parent")).unwrap();
+        std::os::unix::fs::symlink(images.join("sub"), images.join("sub/self")).unwrap();
+        std::os::unix::fs::symlink(&a, images.join("sub/a-link.png")).unwrap();
+        std::os::unix::fs::symlink(images.join("gone.png"), images.join("broken.png")).unwrap();
+
+        let mut skipped = BTreeMap::new();
+        let found = find_images(
+            &Config::default(),
+            &images,
+            &TimeRefs::new(Vec::new()),
+            false,
+            &mut skipped,
+        )
+        .unwrap();
+        let found: Vec<_> = found.into_keys().collect();
+        assert_eq!(
+            found,
+            [a.canonicalize().unwrap(), b.canonicalize().unwrap()]
+        );
+        assert_eq!(
+            skipped,
+            BTreeMap::from([
+                (String::from("broken symlink"), 1),
+                (String::from("extension not allowed"), 1),
+            ])
+        );
+    }
+
+    /// A directory which can't be read, if there is one
+    ///
+    /// Root may read every directory of the temporary dir, but not the mapped files of init.
+    fn unreadable_dir(dir: &TempDir) -> Option<PathBuf> {
+        let locked = dir.path().join("locked");
+        std::fs::create_dir(&locked).unwrap();
+        std::fs::set_permissions(&locked, Permissions::from_mode(0o000)).unwrap();
+        // the error may only show up while reading the entries
+        let unreadable = |dir: &Path| {
+            std::fs::read_dir(dir)
+                .and_then(|mut entries| entries.next().transpose())
+                .is_err()
+        };
+        if unreadable(&locked) {
+            return Some(locked);
+        }
+        let map_files = Path::new("/proc/1/map_files");
+        (map_files.is_dir() && unreadable(map_files)).then(|| map_files.to_owned())
+    }
+
+    #[test]
+    fn find_images_skips_unreadable_directories() {
+        let dir = TempDir::new();
+        let a = dir.touch("images/a.png");
+        let images = dir.path().join("images");
+        let find = |path: &Path, skipped: &mut BTreeMap<String, usize>| {
+            find_images(
+                &Config::default(),
+                path,
+                &TimeRefs::new(Vec::new()),
+                false,
+                skipped,
+            )
+        };
+        let Some(unreadable) = unreadable_dir(&dir) else {
+            eprintln!("skipped, as every directory is readable");
+            return;
+        };
+        std::os::unix::fs::symlink(&unreadable, images.join("locked")).unwrap();
+
+        let mut skipped = BTreeMap::new();
+        let found = find(&images, &mut skipped);
+        // the selected directory itself still has to be readable
+        let unreadable_root = find(&unreadable, &mut BTreeMap::new());
+        // so the temporary dir can be removed
+        std::fs::set_permissions(dir.path().join("locked"), Permissions::from_mode(0o755)).unwrap();
+
+        let found: Vec<_> = found.unwrap().into_keys().collect();
+        assert_eq!(found, [a.canonicalize().unwrap()]);
+        assert_eq!(skipped, BTreeMap::from([(String::from("unreadable"), 1)]));
+        assert!(unreadable_root.is_err());
+    }
+}
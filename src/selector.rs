@@ -0,0 +1,195 @@
+//! Lets an external command choose the image
+//!
+//! The command is run with `sh -c` and gets the candidates on stdin, one absolute path per line.
+//! The name of the monitor is in the environment variable `WALLPAPER_MONITOR`.
+//! It has to print one of the candidates to stdout and exit successfully.
+//! In every other case the image is chosen randomly as if no command was configured.
+//! The command runs in its own process group, which is killed when the timeout elapses,
+//! including background processes which still hold its stdout.
+
+use std::{
+    io::{Read, Write},
+    os::unix::process::CommandExt,
+    path::{Path, PathBuf},
+    process::{Child, Command, Stdio},
+    sync::mpsc,
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Context};
+use tracing::debug;
+
+/// Runs the selector command and returns the image it chose
+///
+/// Fails if the command fails, takes longer than `timeout` or doesn't print one of the candidates.
+pub fn select(
+    command: &str,
+    timeout: Duration,
+    monitor: &str,
+    candidates: &[PathBuf],
+) -> anyhow::Result<PathBuf> {
+    debug!(
+        "running selector command {} with {} candidates",
+        command,
+        candidates.len()
+    );
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .env("WALLPAPER_MONITOR", monitor)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::inherit())
+        .process_group(0)
+        .spawn()
+        .context("while starting selector command")?;
+
+    // write and read in threads, so a command which doesn't read its input can't block us
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+    let input: String = candidates
+        .iter()
+        .map(|candidate| format!("{}\n", candidate.display()))
+        .collect();
+    std::thread::spawn(move || {
+        // the command may exit without reading everything
+        let _ = stdin.write_all(input.as_bytes());
+    });
+    let mut stdout = child.stdout.take().expect("stdout is piped");
+    let (sender, output) = mpsc::channel();
+    std::thread::spawn(move || {
+        let mut output = String::new();
+        let _ = sender.send(stdout.read_to_string(&mut output).map(|_| output));
+    });
+
+    let deadline = Instant::now() + timeout;
+    let status = loop {
+        if let Some(status) = child
+            .try_wait()
+            .context("while waiting for selector command")?
+        {
+            break status;
+        }
+        if Instant::now() >= deadline {
+            kill_group(&child);
+            let _ = child.wait();
+            bail!(
+                "selector command timed out after {}",
+                humantime::format_duration(timeout)
+            );
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    };
+    if !status.success() {
+        bail!("selector command failed with {}", status);
+    }
+
+    // a background process of the command may keep stdout open after the command exited
+    let output = match output.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+        Ok(output) => output,
+        Err(_) => {
+            kill_group(&child);
+            output
+                .recv_timeout(Duration::from_millis(100))
+                .context("selector command kept its output open")?
+        }
+    }
+    .context("while reading the output of the selector command")?;
+    let chosen = Path::new(output.trim());
+    match candidates.iter().find(|candidate| *candidate == chosen) {
+        Some(candidate) => Ok(candidate.clone()),
+        None => bail!(
+            "selector command printed {:?}, which is not a candidate",
+            output.trim()
+        ),
+    }
+}
+
+/// Kills the command and all processes it started which are still in its process group
+fn kill_group(child: &Child) {
+    // SAFETY: the command is the leader of its own process group, so the group has its id
+    unsafe {
+        libc::kill(-(child.id() as libc::pid_t), libc::SIGKILL);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    fn candidates() -> Vec<PathBuf> {
+        [
+            "/images/DP-1.png",
+            "/images/HDMI-A-1.png",
+            "/images/b c.png",
+        ]
+        .into_iter()
+        .map(PathBuf::from)
+        .collect()
+    }
+
+    #[test]
+    fn chooses_the_printed_candidate() {
+        let chosen = select("tail -n 1", TIMEOUT, "DP-1", &candidates()).unwrap();
+        assert_eq!(chosen, Path::new("/images/b c.png"));
+    }
+
+    #[test]
+    fn gets_the_monitor() {
+        let command = r#"grep "$WALLPAPER_MONITOR""#;
+        let chosen = select(command, TIMEOUT, "HDMI-A-1", &candidates()).unwrap();
+        assert_eq!(chosen, Path::new("/images/HDMI-A-1.png"));
+    }
+
+    #[test]
+    fn fails_without_a_candidate() {
+        for command in [
+            "echo /images/other.png",
+            "echo",
+            "cat",
+            "head -n 1; exit 1",
+            "exit 0",
+        ] {
+            assert!(
+                select(command, TIMEOUT, "DP-1", &candidates()).is_err(),
+                "{}",
+                command
+            );
+        }
+    }
+
+    #[test]
+    fn ignores_unread_input() {
+        let candidates: Vec<_> = (0..100_000)
+            .map(|i| PathBuf::from(format!("/images/{}.png", i)))
+            .collect();
+        let chosen = select("echo /images/7.png", TIMEOUT, "DP-1", &candidates).unwrap();
+        assert_eq!(chosen, Path::new("/images/7.png"));
+    }
+
+    #[test]
+    fn times_out() {
+        let start = Instant::now();
+        let timeout = Duration::from_millis(200);
+        let res = select("sleep 10; head -n 1", timeout, "DP-1", &candidates());
+        assert!(res.is_err());
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn kills_background_processes_holding_the_output() {
+        let start = Instant::now();
+        let timeout = Duration::from_millis(200);
+        let command = "head -n 1; sleep 10 &";
+        let chosen = select(command, timeout, "DP-1", &candidates()).unwrap();
+        assert_eq!(chosen, Path::new("/images/DP-1.png"));
+        assert!(start.elapsed() < Duration::from_secs(5));
+
+        // also when the timeout elapses before the command exits
+        let start = Instant::now();
+        let command = "sleep 10 & sleep 10";
+        assert!(select(command, timeout, "DP-1", &candidates()).is_err());
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+}
@@ -0,0 +1,116 @@
+//! Handles the signals of the daemon
+//!
+//! SIGTERM and SIGINT stop the daemon cleanly and SIGHUP reloads the config.
+//! The signals are blocked in every thread and received by a single thread with `sigwait`,
+//! so no code runs inside a signal handler. That thread wakes up the main loop with an
+//! internal [`IpcEvent`].
+
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    mpsc::Sender,
+};
+
+use anyhow::bail;
+use tracing::{debug, error, info};
+
+use crate::ipc::{IpcEvent, IpcRequest};
+
+static SHUTDOWN: AtomicBool = AtomicBool::new(false);
+/// Set while a reload for a SIGHUP is queued, so rapid signals cause only one reload
+static RELOAD_QUEUED: AtomicBool = AtomicBool::new(false);
+
+/// Whether the daemon has to stop
+pub fn shutdown_requested() -> bool {
+    SHUTDOWN.load(Ordering::SeqCst)
+}
+
+/// Lets the daemon stop after the current iteration of its main loop
+pub fn request_shutdown() {
+    SHUTDOWN.store(true, Ordering::SeqCst);
+}
+
+/// Has to be called when a reload starts, so a SIGHUP during the reload queues another one
+pub fn reload_started() {
+    RELOAD_QUEUED.store(false, Ordering::SeqCst);
+}
+
+/// SIGTERM, SIGINT and SIGHUP, blocked in the threads started after [`Signals::block`]
+#[derive(Clone, Copy)]
+pub struct Signals {
+    set: libc::sigset_t,
+}
+
+impl Signals {
+    /// Blocks the signals in the current thread and all threads it starts later
+    ///
+    /// Has to be called before any other thread is started, otherwise the signals may be
+    /// delivered to a thread which still has the default handler and kills the process.
+    /// Child processes get the default signal mask again, so they still can be interrupted.
+    pub fn block() -> anyhow::Result<Self> {
+        // SAFETY: the set is initialized by `sigemptyset` before it is used
+        let mut set: libc::sigset_t = unsafe { std::mem::zeroed() };
+        // SAFETY: `set` is a valid pointer and the signals are valid
+        let ret = unsafe {
+            libc::sigemptyset(&mut set);
+            libc::sigaddset(&mut set, libc::SIGTERM);
+            libc::sigaddset(&mut set, libc::SIGINT);
+            libc::sigaddset(&mut set, libc::SIGHUP);
+            libc::pthread_sigmask(libc::SIG_BLOCK, &set, std::ptr::null_mut())
+        };
+        if ret != 0 {
+            bail!(
+                "can't block signals: {}",
+                std::io::Error::from_raw_os_error(ret)
+            );
+        }
+        Ok(Self { set })
+    }
+
+    /// Waits for the signals in a background thread
+    ///
+    /// SIGTERM and SIGINT request the shutdown and wake up the daemon with
+    /// [`IpcEvent::Shutdown`], SIGHUP sends [`IpcEvent::Reload`].
+    pub fn spawn(self, sender: Sender<IpcRequest>) {
+        std::thread::spawn(move || loop {
+            let mut signal = 0;
+            // SAFETY: the set is initialized and `signal` is a valid pointer
+            let ret = unsafe { libc::sigwait(&self.set, &mut signal) };
+            if ret != 0 {
+                error!(
+                    "can't wait for signals: {}",
+                    std::io::Error::from_raw_os_error(ret)
+                );
+                return;
+            }
+            let event = match signal {
+                libc::SIGHUP => {
+                    if RELOAD_QUEUED.swap(true, Ordering::SeqCst) {
+                        debug!("received SIGHUP, reload is queued already");
+                        continue;
+                    }
+                    info!("received SIGHUP, reloading");
+                    IpcEvent::Reload
+                }
+                signal => {
+                    let name = if signal == libc::SIGTERM {
+                        "SIGTERM"
+                    } else {
+                        "SIGINT"
+                    };
+                    info!("received {}, shutting down", name);
+                    request_shutdown();
+                    IpcEvent::Shutdown
+                }
+            };
+            if sender.send(IpcRequest::internal(event)).is_err() {
+                return;
+            }
+        });
+    }
+}
+
+impl std::fmt::Debug for Signals {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Signals").finish_non_exhaustive()
+    }
+}
@@ -0,0 +1,82 @@
+//! Lists the files in the directories of `image_sources`
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use anyhow::Context;
+use tracing::debug;
+
+/// The contents of a directory when it was listed
+#[derive(Debug, Clone)]
+struct Listing {
+    modified: SystemTime,
+    files: Vec<PathBuf>,
+    dirs: Vec<PathBuf>,
+}
+
+/// Lists directories again only if they were modified since the last time
+#[derive(Debug, Clone, Default)]
+pub struct Listings {
+    dirs: HashMap<PathBuf, Listing>,
+}
+
+impl Listings {
+    /// Returns the files in the directory and, if `recursive`, in all its subdirectories
+    pub fn files(&mut self, dir: &Path, recursive: bool) -> anyhow::Result<Vec<PathBuf>> {
+        let mut files = Vec::new();
+        let mut pending = vec![dir.to_owned()];
+        while let Some(dir) = pending.pop() {
+            let listing = self.listing(&dir)?;
+            files.extend(listing.files.iter().cloned());
+            if recursive {
+                pending.extend(listing.dirs.iter().cloned());
+            }
+        }
+        files.sort();
+
+        Ok(files)
+    }
+
+    fn listing(&mut self, dir: &Path) -> anyhow::Result<&Listing> {
+        let modified = std::fs::metadata(dir)
+            .and_then(|m| m.modified())
+            .with_context(|| format!("while reading modification time of {}", dir.display()))?;
+        let fresh = self
+            .dirs
+            .get(dir)
+            .is_some_and(|listing| listing.modified == modified);
+        if !fresh {
+            debug!("listing files in {}", dir.display());
+            let listing = list(dir, modified)?;
+            self.dirs.insert(dir.to_owned(), listing);
+        }
+
+        Ok(&self.dirs[dir])
+    }
+}
+
+fn list(dir: &Path, modified: SystemTime) -> anyhow::Result<Listing> {
+    let mut listing = Listing {
+        modified,
+        files: Vec::new(),
+        dirs: Vec::new(),
+    };
+    for entry in
+        std::fs::read_dir(dir).with_context(|| format!("while reading {}", dir.display()))?
+    {
+        let entry = entry.context("while reading directory entry")?;
+        let path = entry.path();
+        let file_type = entry.file_type().context("while reading file type")?;
+        // symlinked directories are not followed, as they may form a loop
+        if file_type.is_dir() {
+            listing.dirs.push(path);
+        } else if path.is_file() {
+            listing.files.push(path);
+        }
+    }
+
+    Ok(listing)
+}
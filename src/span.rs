@@ -0,0 +1,143 @@
+//! Splits panoramic images into tiles for groups of monitors
+//!
+//! Cropping the images needs the `span` feature. Without it, span groups are ignored.
+
+use std::path::{Path, PathBuf};
+
+use crate::{config::SpanGroup, MonitorInfo};
+
+/// The part of a spanned image which a monitor shows
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Tile {
+    pub monitor: String,
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// The positions of the monitors of a span group
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpanLayout {
+    pub tiles: Vec<Tile>,
+    /// Width of the area covered by all monitors
+    pub width: u32,
+    /// Height of the area covered by all monitors
+    pub height: u32,
+    /// How much the aspect ratio of a panoramic image may differ from the area's
+    pub tolerance: f32,
+}
+
+impl SpanLayout {
+    /// Returns the layout of the group, if the sizes of all its monitors are known
+    ///
+    /// Monitors without a configured position are placed right of the previous ones.
+    pub fn new(group: &SpanGroup, monitors: &[MonitorInfo]) -> Option<Self> {
+        let mut tiles = Vec::new();
+        let mut right = 0;
+        for monitor in &group.monitors {
+            let (width, height) = monitors
+                .iter()
+                .find(|info| info.name == monitor.name)?
+                .size?;
+            let x = monitor.x.unwrap_or(right);
+            tiles.push(Tile {
+                monitor: monitor.name.clone(),
+                x,
+                y: monitor.y.unwrap_or(0),
+                width,
+                height,
+            });
+            right = right.max(x + width);
+        }
+        let width = tiles.iter().map(|tile| tile.x + tile.width).max()?;
+        let height = tiles.iter().map(|tile| tile.y + tile.height).max()?;
+
+        Some(Self {
+            tiles,
+            width,
+            height,
+            tolerance: group.tolerance(),
+        })
+    }
+
+    pub fn monitors(&self) -> impl Iterator<Item = &str> {
+        self.tiles.iter().map(|tile| tile.monitor.as_str())
+    }
+}
+
+/// Returns the tile of the image for every monitor of the layout
+///
+/// The tiles are stored in `dir` and only created again if the image was modified,
+/// then the outdated tiles get removed.
+#[cfg(feature = "span")]
+pub fn tiles(
+    dir: &Path,
+    image: &Path,
+    layout: &SpanLayout,
+) -> anyhow::Result<Vec<(String, PathBuf)>> {
+    use std::hash::{DefaultHasher, Hash, Hasher};
+
+    use anyhow::Context;
+    use tracing::debug;
+
+    // identifies the tiles of the image in this layout
+    let mut s = DefaultHasher::new();
+    image.hash(&mut s);
+    layout.tiles.hash(&mut s);
+    layout.width.hash(&mut s);
+    layout.height.hash(&mut s);
+
+    let modified = std::fs::metadata(image)
+        .and_then(|m| m.modified())
+        .context("while reading modification time")?
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default();
+    let prefix = format!("{:016x}-", s.finish());
+    let paths: Vec<_> = layout
+        .tiles
+        .iter()
+        .map(|tile| {
+            let name = format!("{}{}-{}.png", prefix, modified.as_nanos(), tile.monitor);
+            (tile.monitor.clone(), dir.join(name))
+        })
+        .collect();
+    if paths.iter().all(|(_monitor, path)| path.is_file()) {
+        return Ok(paths);
+    }
+
+    std::fs::create_dir_all(dir).context("while creating tile directory")?;
+    for entry in std::fs::read_dir(dir).context("while reading tile directory")? {
+        let entry = entry.context("while reading tile directory entry")?;
+        if entry.file_name().to_string_lossy().starts_with(&prefix) {
+            debug!("removing outdated tile {}", entry.path().display());
+            std::fs::remove_file(entry.path()).context("while removing outdated tile")?;
+        }
+    }
+
+    debug!("splitting {} into tiles", image.display());
+    let source = image::open(image)
+        .context("while decoding image")?
+        .resize_to_fill(
+            layout.width,
+            layout.height,
+            image::imageops::FilterType::Lanczos3,
+        );
+    for (tile, (_monitor, path)) in layout.tiles.iter().zip(&paths) {
+        source
+            .crop_imm(tile.x, tile.y, tile.width, tile.height)
+            .save(path)
+            .with_context(|| format!("while saving tile {}", path.display()))?;
+    }
+
+    Ok(paths)
+}
+
+#[cfg(not(feature = "span"))]
+pub fn tiles(
+    _dir: &Path,
+    _image: &Path,
+    _layout: &SpanLayout,
+) -> anyhow::Result<Vec<(String, PathBuf)>> {
+    anyhow::bail!("spanning images needs the `span` feature")
+}
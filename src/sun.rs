@@ -0,0 +1,56 @@
+//! Calculation of sunrise and sunset times
+//!
+//! This uses the sunrise equation as described on
+//! <https://en.wikipedia.org/wiki/Sunrise_equation>, which is precise to about a minute.
+
+use chrono::{DateTime, NaiveDate, Utc};
+
+/// Julian day of 2000-01-01 12:00 UTC
+const J2000: f64 = 2_451_545.0;
+/// Julian day of the unix epoch
+const UNIX_EPOCH_JULIAN: f64 = 2_440_587.5;
+
+/// Returns the sunrise and sunset on the given date
+///
+/// The latitude is positive in the north and the longitude is positive in the east.
+/// Returns `None` if the sun doesn't rise or set on this day (polar day or night).
+pub fn sun_times(
+    date: NaiveDate,
+    latitude: f64,
+    longitude: f64,
+) -> Option<(DateTime<Utc>, DateTime<Utc>)> {
+    let epoch = NaiveDate::from_ymd_opt(2000, 1, 1).expect("valid date");
+    let days = (date - epoch).num_days() as f64 + 0.0008;
+
+    // mean solar time
+    let mean_time = days - longitude / 360.0;
+    let anomaly = (357.5291 + 0.985_600_28 * mean_time).rem_euclid(360.0);
+    let anomaly_rad = anomaly.to_radians();
+    let center = 1.9148 * anomaly_rad.sin()
+        + 0.0200 * (2.0 * anomaly_rad).sin()
+        + 0.0003 * (3.0 * anomaly_rad).sin();
+    let ecliptic_longitude = (anomaly + center + 180.0 + 102.9372).rem_euclid(360.0);
+    let ecliptic_longitude_rad = ecliptic_longitude.to_radians();
+    let transit = J2000 + mean_time + 0.0053 * anomaly_rad.sin()
+        - 0.0069 * (2.0 * ecliptic_longitude_rad).sin();
+
+    let declination_sin = ecliptic_longitude_rad.sin() * 23.4397_f64.to_radians().sin();
+    let declination_cos = declination_sin.asin().cos();
+    let latitude_rad = latitude.to_radians();
+    let hour_angle_cos = ((-0.833_f64).to_radians().sin() - latitude_rad.sin() * declination_sin)
+        / (latitude_rad.cos() * declination_cos);
+    if !(-1.0..=1.0).contains(&hour_angle_cos) {
+        return None;
+    }
+    let hour_angle = hour_angle_cos.acos().to_degrees();
+
+    let to_utc = |julian_day: f64| {
+        let millis = ((julian_day - UNIX_EPOCH_JULIAN) * 86_400_000.0).round() as i64;
+        DateTime::from_timestamp_millis(millis)
+    };
+
+    Some((
+        to_utc(transit - hour_angle / 360.0)?,
+        to_utc(transit + hour_angle / 360.0)?,
+    ))
+}
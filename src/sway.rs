@@ -0,0 +1,263 @@
+//! Follows the focused workspaces, fullscreen windows and powered off outputs of sway
+//! over its ipc socket
+//!
+//! Every message has the header `i3-ipc`, followed by the length of the payload
+//! and the message type as native endian `u32`s and the json payload.
+
+use std::{
+    collections::BTreeSet,
+    io::{Read, Write},
+    os::unix::net::UnixStream,
+    sync::mpsc::Sender,
+};
+
+use anyhow::{bail, Context};
+use serde::Deserialize;
+use tracing::{debug, error, info};
+
+use crate::ipc::{IpcEvent, IpcRequest};
+
+const MAGIC: &[u8] = b"i3-ipc";
+const GET_WORKSPACES: u32 = 1;
+const SUBSCRIBE: u32 = 2;
+const GET_OUTPUTS: u32 = 3;
+const GET_TREE: u32 = 4;
+const WORKSPACE_EVENT: u32 = 0x8000_0000;
+const OUTPUT_EVENT: u32 = 0x8000_0001;
+const WINDOW_EVENT: u32 = 0x8000_0003;
+
+#[derive(Debug, Deserialize)]
+struct Workspace {
+    name: String,
+    output: String,
+    #[serde(default)]
+    visible: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct Output {
+    name: String,
+    /// Whether the output is powered on, since sway 1.8
+    power: Option<bool>,
+    /// Whether DPMS is on, before sway 1.8
+    dpms: Option<bool>,
+}
+
+#[derive(Debug, Deserialize)]
+struct WorkspaceEvent {
+    change: String,
+    current: Option<Workspace>,
+}
+
+fn send_message(stream: &mut UnixStream, kind: u32, payload: &[u8]) -> anyhow::Result<()> {
+    let len = u32::try_from(payload.len()).context("payload is too long")?;
+    let mut buf = Vec::with_capacity(MAGIC.len() + 8 + payload.len());
+    buf.extend_from_slice(MAGIC);
+    buf.extend_from_slice(&len.to_ne_bytes());
+    buf.extend_from_slice(&kind.to_ne_bytes());
+    buf.extend_from_slice(payload);
+    stream
+        .write_all(&buf)
+        .context("while sending message to sway")
+}
+
+fn read_message(stream: &mut UnixStream) -> anyhow::Result<(u32, Vec<u8>)> {
+    let mut header = [0; 14];
+    stream
+        .read_exact(&mut header)
+        .context("while reading message header from sway")?;
+    if &header[..6] != MAGIC {
+        bail!("invalid message header from sway");
+    }
+    let len = u32::from_ne_bytes(header[6..10].try_into().expect("4 bytes"));
+    let kind = u32::from_ne_bytes(header[10..14].try_into().expect("4 bytes"));
+    let mut payload = vec![0; len as usize];
+    stream
+        .read_exact(&mut payload)
+        .context("while reading message payload from sway")?;
+    Ok((kind, payload))
+}
+
+fn get_workspaces(stream: &mut UnixStream) -> anyhow::Result<Vec<Workspace>> {
+    send_message(stream, GET_WORKSPACES, b"")?;
+    let (_, payload) = read_message(stream)?;
+    serde_json::from_slice(&payload).context("invalid workspaces from sway")
+}
+
+/// Returns whether any window below the node is fullscreen
+fn has_fullscreen(node: &serde_json::Value) -> bool {
+    ["nodes", "floating_nodes"].iter().any(|key| {
+        node[key].as_array().is_some_and(|children| {
+            children.iter().any(|child| {
+                child["fullscreen_mode"]
+                    .as_u64()
+                    .is_some_and(|mode| mode != 0)
+                    || has_fullscreen(child)
+            })
+        })
+    })
+}
+
+/// Returns the outputs whose visible workspace contains a fullscreen window
+fn fullscreen_outputs(stream: &mut UnixStream) -> anyhow::Result<BTreeSet<String>> {
+    let visible: BTreeSet<_> = get_workspaces(stream)?
+        .into_iter()
+        .filter(|workspace| workspace.visible)
+        .map(|workspace| (workspace.output, workspace.name))
+        .collect();
+
+    send_message(stream, GET_TREE, b"")?;
+    let (_, payload) = read_message(stream)?;
+    let tree: serde_json::Value =
+        serde_json::from_slice(&payload).context("invalid tree from sway")?;
+
+    let mut outputs = BTreeSet::new();
+    for output in tree["nodes"].as_array().into_iter().flatten() {
+        let Some(output_name) = output["name"].as_str() else {
+            continue;
+        };
+        for workspace in output["nodes"].as_array().into_iter().flatten() {
+            let Some(workspace_name) = workspace["name"].as_str() else {
+                continue;
+            };
+            if visible.contains(&(output_name.to_owned(), workspace_name.to_owned()))
+                && has_fullscreen(workspace)
+            {
+                outputs.insert(output_name.to_owned());
+            }
+        }
+    }
+
+    Ok(outputs)
+}
+
+/// Returns the outputs which are powered off
+fn off_outputs(stream: &mut UnixStream) -> anyhow::Result<BTreeSet<String>> {
+    send_message(stream, GET_OUTPUTS, b"")?;
+    let (_, payload) = read_message(stream)?;
+    let outputs: Vec<Output> =
+        serde_json::from_slice(&payload).context("invalid outputs from sway")?;
+    Ok(outputs
+        .into_iter()
+        .filter(|output| output.power.or(output.dpms) == Some(false))
+        .map(|output| output.name)
+        .collect())
+}
+
+fn focus_request(workspace: Workspace) -> IpcRequest {
+    IpcRequest::internal(IpcEvent::WorkspaceFocus {
+        output: workspace.output,
+        workspace: workspace.name,
+    })
+}
+
+/// Connects to sway and sends the focused workspaces to the daemon in a background thread
+///
+/// The currently visible workspaces are sent first.
+pub fn spawn(sender: Sender<IpcRequest>) -> anyhow::Result<()> {
+    let socket_path = std::env::var_os("SWAYSOCK")
+        .ok_or_else(|| anyhow::anyhow!("SWAYSOCK is not set, is sway running?"))?;
+    let mut stream = UnixStream::connect(&socket_path)
+        .with_context(|| format!("while connecting to sway at {:?}", socket_path))?;
+
+    for workspace in get_workspaces(&mut stream)?
+        .into_iter()
+        .filter(|workspace| workspace.visible)
+    {
+        sender.send(focus_request(workspace))?;
+    }
+
+    // events and replies would be mixed up on one connection, so query on a separate one
+    let mut query = UnixStream::connect(&socket_path)
+        .with_context(|| format!("while connecting to sway at {:?}", socket_path))?;
+    let mut fullscreen = fullscreen_outputs(&mut query)?;
+    sender.send(IpcRequest::internal(IpcEvent::Fullscreen {
+        outputs: fullscreen.clone(),
+    }))?;
+    let mut off = off_outputs(&mut query)?;
+    sender.send(IpcRequest::internal(IpcEvent::OutputsOff {
+        outputs: off.clone(),
+    }))?;
+
+    send_message(
+        &mut stream,
+        SUBSCRIBE,
+        br#"["workspace", "window", "output"]"#,
+    )?;
+    let (_, payload) = read_message(&mut stream)?;
+    let reply: serde_json::Value =
+        serde_json::from_slice(&payload).context("invalid subscribe reply from sway")?;
+    if reply["success"] != true {
+        bail!("sway refused the subscription: {}", reply);
+    }
+    info!("following the workspaces of sway");
+
+    std::thread::spawn(move || loop {
+        let (kind, payload) = match read_message(&mut stream) {
+            Ok(message) => message,
+            Err(e) => {
+                error!("lost connection to sway: {:#}", e);
+                return;
+            }
+        };
+        if kind == OUTPUT_EVENT {
+            match off_outputs(&mut query) {
+                Ok(outputs) if outputs != off => {
+                    off = outputs;
+                    let request = IpcRequest::internal(IpcEvent::OutputsOff {
+                        outputs: off.clone(),
+                    });
+                    if sender.send(request).is_err() {
+                        return;
+                    }
+                }
+                Ok(_) => {}
+                Err(e) => error!("can't query outputs from sway: {:#}", e),
+            }
+            continue;
+        }
+        if kind != WORKSPACE_EVENT && kind != WINDOW_EVENT {
+            continue;
+        }
+
+        // windows can get fullscreen, closed or moved, so always check all outputs
+        match fullscreen_outputs(&mut query) {
+            Ok(outputs) if outputs != fullscreen => {
+                fullscreen = outputs;
+                let request = IpcRequest::internal(IpcEvent::Fullscreen {
+                    outputs: fullscreen.clone(),
+                });
+                if sender.send(request).is_err() {
+                    return;
+                }
+            }
+            Ok(_) => {}
+            Err(e) => error!("can't query fullscreen windows from sway: {:#}", e),
+        }
+
+        if kind != WORKSPACE_EVENT {
+            continue;
+        }
+        let event: WorkspaceEvent = match serde_json::from_slice(&payload) {
+            Ok(event) => event,
+            Err(e) => {
+                error!("invalid workspace event from sway: {}", e);
+                continue;
+            }
+        };
+        if event.change != "focus" {
+            continue;
+        }
+        if let Some(workspace) = event.current {
+            debug!(
+                "workspace {} focused on {}",
+                workspace.name, workspace.output
+            );
+            if sender.send(focus_request(workspace)).is_err() {
+                return;
+            }
+        }
+    });
+
+    Ok(())
+}
@@ -0,0 +1,122 @@
+//! Tells systemd when the daemon is ready, what it is waiting for and that it still runs
+//!
+//! Implements the `sd_notify` protocol: messages like `READY=1` are sent as datagrams to the
+//! socket in `NOTIFY_SOCKET`. Notifying needs the `systemd` feature. Without it, or if the
+//! daemon was not started by systemd, every method does nothing.
+
+use std::{
+    os::unix::net::{SocketAddr, UnixDatagram},
+    time::{Duration, Instant},
+};
+
+use tracing::{debug, warn};
+
+#[derive(Debug, Default)]
+pub struct Notifier {
+    socket: Option<(UnixDatagram, SocketAddr)>,
+    /// Half of the watchdog timeout, so a late ping doesn't restart the daemon
+    watchdog: Option<Duration>,
+    last_ping: Option<Instant>,
+    ready: bool,
+    status: String,
+}
+
+impl Notifier {
+    /// Connects to the socket of systemd, if the daemon was started by it
+    ///
+    /// Removes the variables from the environment, so swww, hooks and selectors don't notify
+    /// systemd in the name of the daemon. It has to be called before any thread is started.
+    #[cfg(feature = "systemd")]
+    pub fn from_env() -> Self {
+        use std::os::{linux::net::SocketAddrExt, unix::ffi::OsStrExt};
+
+        let path = std::env::var_os("NOTIFY_SOCKET");
+        let watchdog_pid = std::env::var("WATCHDOG_PID").ok();
+        let watchdog_usec = std::env::var("WATCHDOG_USEC").ok();
+        for var in ["NOTIFY_SOCKET", "WATCHDOG_PID", "WATCHDOG_USEC"] {
+            std::env::remove_var(var);
+        }
+
+        let Some(path) = path else {
+            debug!("not started by systemd, so it isn't notified");
+            return Self::default();
+        };
+        let addr = match path.as_bytes().strip_prefix(b"@") {
+            Some(name) => SocketAddr::from_abstract_name(name),
+            None => SocketAddr::from_pathname(&path),
+        };
+        let socket = addr.and_then(|addr| Ok((UnixDatagram::unbound()?, addr)));
+        let socket = match socket {
+            Ok(socket) => socket,
+            Err(e) => {
+                warn!("can't notify systemd at {:?}: {}", path, e);
+                return Self::default();
+            }
+        };
+        // the watchdog is meant for another process if the pid differs
+        let pid_matches = watchdog_pid.is_none_or(|pid| pid.parse() == Ok(std::process::id()));
+        let watchdog = watchdog_usec
+            .and_then(|usec| usec.parse().ok())
+            .filter(|_| pid_matches)
+            .map(|usec: u64| Duration::from_micros(usec) / 2);
+
+        Self {
+            socket: Some(socket),
+            watchdog,
+            ..Self::default()
+        }
+    }
+
+    #[cfg(not(feature = "systemd"))]
+    pub fn from_env() -> Self {
+        Self::default()
+    }
+
+    fn send(&self, message: &str) {
+        let Some((socket, addr)) = &self.socket else {
+            return;
+        };
+        if let Err(e) = socket.send_to_addr(message.as_bytes(), addr) {
+            warn!("can't notify systemd: {}", e);
+        }
+    }
+
+    /// Tells systemd that the daemon is ready, only the first call sends it
+    pub fn ready(&mut self) {
+        if self.socket.is_some() && !self.ready {
+            debug!("notifying systemd that the daemon is ready");
+            self.send("READY=1");
+        }
+        self.ready = true;
+    }
+
+    /// Shows the status in `systemctl status`, it is only sent if it changed
+    pub fn status(&mut self, status: String) {
+        if status != self.status {
+            self.send(&format!("STATUS={}", status));
+            self.status = status;
+        }
+    }
+
+    /// Tells systemd that the daemon stops on purpose
+    pub fn stopping(&self) {
+        self.send("STOPPING=1");
+    }
+
+    /// When the watchdog has to be pinged next, if it is enabled
+    pub fn next_ping(&self) -> Option<Instant> {
+        let watchdog = self.watchdog?;
+        Some(
+            self.last_ping
+                .map_or_else(Instant::now, |last| last + watchdog),
+        )
+    }
+
+    /// Tells the watchdog that the daemon still runs
+    pub fn ping(&mut self) {
+        if self.watchdog.is_some() {
+            self.send("WATCHDOG=1");
+            self.last_ping = Some(Instant::now());
+        }
+    }
+}
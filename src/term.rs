@@ -0,0 +1,72 @@
+//! Colors and aligned columns for output which is read on a terminal
+//!
+//! Colors are only used if stdout is a terminal and `NO_COLOR` is not set, see <https://no-color.org>.
+
+use std::{fmt::Display, io::IsTerminal};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Style {
+    Red,
+    Yellow,
+    Green,
+    Bold,
+    Dim,
+}
+
+impl Style {
+    fn code(self) -> &'static str {
+        match self {
+            Self::Red => "31",
+            Self::Yellow => "33",
+            Self::Green => "32",
+            Self::Bold => "1",
+            Self::Dim => "2",
+        }
+    }
+}
+
+/// Whether stdout is a terminal, so the output is read by a human instead of a script
+pub fn is_terminal() -> bool {
+    std::io::stdout().is_terminal()
+}
+
+/// Whether the output may be colored
+pub fn colors_enabled() -> bool {
+    is_terminal() && std::env::var_os("NO_COLOR").is_none_or(|no_color| no_color.is_empty())
+}
+
+/// Wraps the text in the escape codes of the style, if colors are enabled
+pub fn paint(text: impl Display, style: Style) -> String {
+    if colors_enabled() {
+        format!("\x1b[{}m{}\x1b[0m", style.code(), text)
+    } else {
+        text.to_string()
+    }
+}
+
+/// Pads the cells of every column but the last one to the width of the column
+pub fn table(rows: &[Vec<String>]) -> Vec<String> {
+    let mut widths = Vec::new();
+    for row in rows {
+        for (i, cell) in row.iter().enumerate() {
+            let width = cell.chars().count();
+            match widths.get_mut(i) {
+                Some(max) => *max = width.max(*max),
+                None => widths.push(width),
+            }
+        }
+    }
+    rows.iter()
+        .map(|row| {
+            let mut line = String::new();
+            for (i, cell) in row.iter().enumerate() {
+                if i + 1 == row.len() {
+                    line.push_str(cell);
+                } else {
+                    line.push_str(&format!("{:width$}  ", cell, width = widths[i]));
+                }
+            }
+            line
+        })
+        .collect()
+}
@@ -0,0 +1,92 @@
+//! `wallpaper export` and `wallpaper import`, which move the config and the cache to
+//! another machine
+
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use tracing::debug;
+use wallpaper::{
+    backup::Backup,
+    ipc::{self, IpcEvent, IpcResponse},
+    Config, State,
+};
+
+use crate::prompt;
+
+/// Prints the config file and the cache as a [`Backup`]
+pub fn export(state: &State) -> anyhow::Result<()> {
+    let backup = Backup::new(state.read_config_file()?, state.cache.clone());
+    let json = serde_json::to_string_pretty(&backup).context("while serializing backup")?;
+    println!("{}", json);
+    Ok(())
+}
+
+/// Restores the config and the cache from a [`Backup`]
+///
+/// The backup is checked completely before anything is written.
+/// A running daemon imports it itself, so it doesn't overwrite the import with its state.
+pub fn import(file: &Path, merge: bool, image_dir: Option<PathBuf>) -> anyhow::Result<()> {
+    let reader =
+        std::fs::File::open(file).with_context(|| format!("can't open {}", file.display()))?;
+    let backup = Backup::read(std::io::BufReader::new(reader))?;
+    let image_dir = match image_dir {
+        Some(image_dir) => image_dir,
+        None => import_image_dir(&backup)?,
+    };
+    let (config, cache) = backup.restore(&image_dir)?;
+
+    match ipc::Client::connect() {
+        Ok(mut client) => match client.send(IpcEvent::Import {
+            config: Box::new(config),
+            cache: Box::new(cache),
+            merge,
+        })? {
+            IpcResponse::Ok => {}
+            IpcResponse::Error(e) => anyhow::bail!("daemon returned error: {}", e),
+            response => anyhow::bail!("unexpected response from daemon: {:?}", response),
+        },
+        Err(e) => {
+            debug!("can't connect to daemon, importing locally: {:#}", e);
+            let mut state = State::load().context("while loading state")?;
+            state.import(config, cache, merge)?;
+        }
+    }
+    println!("imported {}", file.display());
+    Ok(())
+}
+
+/// Asks for the directory with the images if the one of the backup is not used here
+///
+/// The `image_dir` of the current config is the default, if there is one.
+fn import_image_dir(backup: &Backup) -> anyhow::Result<PathBuf> {
+    let config_file = State::config_file()?;
+    let current = if config_file.is_file() {
+        let file = std::fs::File::open(&config_file).context("while opening config file")?;
+        let config: Config = serde_json::from_reader(file).context("while parsing config file")?;
+        Some(config.image_dir)
+    } else {
+        None
+    };
+    let question = match &current {
+        Some(current) if *current != backup.image_dir => format!(
+            "the backup has its images in {} but this machine in {}\n\
+            directory with the images on this machine [{}]: ",
+            backup.image_dir.display(),
+            current.display(),
+            current.display()
+        ),
+        None if !backup.image_dir.is_dir() => format!(
+            "the backup has its images in {}, which doesn't exist here\n\
+            directory with the images on this machine [{}]: ",
+            backup.image_dir.display(),
+            backup.image_dir.display()
+        ),
+        _ => return Ok(backup.image_dir.clone()),
+    };
+    let answer = prompt(&question)?;
+    Ok(if answer.is_empty() {
+        current.unwrap_or_else(|| backup.image_dir.clone())
+    } else {
+        PathBuf::from(answer)
+    })
+}
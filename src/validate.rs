@@ -0,0 +1,63 @@
+//! Checks whether images can be decoded
+//!
+//! Decoding needs the `image-validate` feature. Without it, `check --deep` fails.
+
+use std::path::{Path, PathBuf};
+
+/// Returns the images which can't be decoded together with the decode error
+///
+/// Only the headers get decoded, unless `full` is set.
+/// The images are decoded in parallel.
+#[cfg(feature = "image-validate")]
+pub fn undecodable(
+    images: &[PathBuf],
+    full: bool,
+) -> anyhow::Result<Vec<(PathBuf, anyhow::Error)>> {
+    use rayon::prelude::*;
+
+    Ok(images
+        .par_iter()
+        .filter_map(|image| {
+            decode(image, full)
+                .err()
+                .map(|error| (image.clone(), error))
+        })
+        .collect())
+}
+
+#[cfg(not(feature = "image-validate"))]
+pub fn undecodable(
+    _images: &[PathBuf],
+    _full: bool,
+) -> anyhow::Result<Vec<(PathBuf, anyhow::Error)>> {
+    anyhow::bail!("decoding images needs the `image-validate` feature")
+}
+
+/// Fails if the header of the image can't be decoded
+///
+/// Without the `image-validate` feature, this never fails.
+pub fn check(image: &Path) -> anyhow::Result<()> {
+    #[cfg(feature = "image-validate")]
+    decode(image, false)?;
+    #[cfg(not(feature = "image-validate"))]
+    let _ = image;
+
+    Ok(())
+}
+
+#[cfg(feature = "image-validate")]
+fn decode(image: &std::path::Path, full: bool) -> anyhow::Result<()> {
+    use anyhow::Context;
+
+    let reader = image::ImageReader::open(image)
+        .context("while opening image")?
+        .with_guessed_format()
+        .context("while guessing format")?;
+    if full {
+        reader.decode().context("while decoding image")?;
+    } else {
+        reader.into_dimensions().context("while decoding header")?;
+    }
+
+    Ok(())
+}
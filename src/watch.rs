@@ -0,0 +1,322 @@
+//! Notices changes of files with inotify
+//!
+//! The config and cache files are watched through their directories instead of the files,
+//! because the files get replaced by renaming, e.g. by
+//! [`State::write_config`](crate::State::write_config) and most editors.
+//! Every change sends [`IpcEvent::FilesChanged`] to the daemon, which compares the hashes of
+//! the files, so a change which doesn't alter the content is ignored.
+//!
+//! The `image_dir` is watched with all its subdirectories. The added and removed files are
+//! collected and sent with [`IpcEvent::ImagesChanged`] once no more changes happened for
+//! [`DEBOUNCE`].
+
+use std::{
+    collections::{BTreeSet, HashMap},
+    ffi::{CString, OsStr},
+    fs::File,
+    io::Read,
+    os::{
+        fd::{AsRawFd, FromRawFd, OwnedFd},
+        unix::ffi::OsStrExt,
+    },
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::Sender,
+        Arc,
+    },
+    time::Duration,
+};
+
+use anyhow::Context;
+use tracing::{debug, error, info};
+
+use crate::ipc::{IpcEvent, IpcRequest};
+
+const FILES: [&str; 2] = ["config.json", "cache.json"];
+
+/// How long the `image_dir` has to stay unchanged before the daemon is told about the changes,
+/// so copying many images causes only one rescan
+pub const DEBOUNCE: Duration = Duration::from_millis(500);
+
+const IMAGE_DIR_MASK: u32 = libc::IN_CLOSE_WRITE
+    | libc::IN_CREATE
+    | libc::IN_DELETE
+    | libc::IN_MOVED_FROM
+    | libc::IN_MOVED_TO;
+
+/// An event read from inotify
+struct Event<'a> {
+    watch: i32,
+    mask: u32,
+    name: &'a OsStr,
+}
+
+struct Inotify {
+    file: File,
+}
+
+impl Inotify {
+    fn new() -> anyhow::Result<Self> {
+        // SAFETY: inotify_init1 has no memory safety requirements
+        let fd = unsafe { libc::inotify_init1(libc::IN_CLOEXEC) };
+        if fd == -1 {
+            return Err(std::io::Error::last_os_error()).context("can't initialize inotify");
+        }
+        // SAFETY: the file descriptor is valid and owned by nobody else
+        let file = File::from(unsafe { OwnedFd::from_raw_fd(fd) });
+        Ok(Self { file })
+    }
+
+    /// Watches the directory and returns the descriptor of the watch
+    fn add_watch(&self, dir: &Path, mask: u32) -> anyhow::Result<i32> {
+        let path = CString::new(dir.as_os_str().as_bytes())
+            .with_context(|| format!("invalid path {}", dir.display()))?;
+        // SAFETY: the file descriptor is valid and the path is a valid c string
+        let watch = unsafe { libc::inotify_add_watch(self.file.as_raw_fd(), path.as_ptr(), mask) };
+        if watch == -1 {
+            return Err(std::io::Error::last_os_error())
+                .with_context(|| format!("can't watch {}", dir.display()));
+        }
+        Ok(watch)
+    }
+
+    /// Waits for events and returns their bytes
+    fn read<'a>(&mut self, buf: &'a mut [u8]) -> std::io::Result<&'a [u8]> {
+        let len = self.file.read(buf)?;
+        Ok(&buf[..len])
+    }
+}
+
+/// Returns the events in the bytes read from inotify
+fn events(mut buf: &[u8]) -> impl Iterator<Item = Event<'_>> {
+    // struct inotify_event { int wd; uint32_t mask, cookie, len; char name[]; }
+    const HEADER: usize = 16;
+    std::iter::from_fn(move || {
+        let field = |at: usize| buf.get(at..at + 4)?.try_into().ok();
+        let watch = i32::from_ne_bytes(field(0)?);
+        let mask = u32::from_ne_bytes(field(4)?);
+        let len = u32::from_ne_bytes(field(12)?) as usize;
+        let name = buf.get(HEADER..HEADER + len)?;
+        buf = &buf[HEADER + len..];
+        // the name is padded with null bytes
+        let end = name.iter().position(|b| *b == 0).unwrap_or(name.len());
+        Some(Event {
+            watch,
+            mask,
+            name: OsStr::from_bytes(&name[..end]),
+        })
+    })
+}
+
+/// Watches the config and cache files in a background thread
+#[derive(Debug)]
+pub struct Watcher {
+    active: Arc<AtomicBool>,
+}
+
+impl Watcher {
+    /// Starts watching the directories, fails if inotify is not available
+    pub fn spawn(dirs: &[&Path], sender: Sender<IpcRequest>) -> anyhow::Result<Self> {
+        let mut inotify = Inotify::new()?;
+        for dir in dirs {
+            inotify.add_watch(
+                dir,
+                libc::IN_CLOSE_WRITE | libc::IN_MOVED_TO | libc::IN_DELETE,
+            )?;
+        }
+        info!("watching the config and cache files");
+
+        let active = Arc::new(AtomicBool::new(true));
+        let thread_active = Arc::clone(&active);
+        std::thread::spawn(move || {
+            let mut buf = [0; 4096];
+            loop {
+                let bytes = match inotify.read(&mut buf) {
+                    Ok(bytes) => bytes,
+                    Err(e) => {
+                        error!("can't watch the files anymore, polling them: {}", e);
+                        thread_active.store(false, Ordering::SeqCst);
+                        return;
+                    }
+                };
+                if !events(bytes).any(|event| FILES.iter().any(|file| event.name == *file)) {
+                    continue;
+                }
+                debug!("config or cache file changed");
+                if sender
+                    .send(IpcRequest::internal(IpcEvent::FilesChanged))
+                    .is_err()
+                {
+                    thread_active.store(false, Ordering::SeqCst);
+                    return;
+                }
+            }
+        });
+
+        Ok(Self { active })
+    }
+
+    /// Whether changes are still noticed, otherwise the files have to be polled
+    pub fn is_active(&self) -> bool {
+        self.active.load(Ordering::SeqCst)
+    }
+}
+
+/// Watches a directory and all its subdirectories in a background thread
+///
+/// The thread stops and removes its watches when this is dropped.
+#[derive(Debug)]
+pub struct ImageDirWatcher {
+    dir: PathBuf,
+    /// Closing the write end of the pipe wakes up the thread, which then stops
+    _stop: OwnedFd,
+}
+
+impl ImageDirWatcher {
+    /// Starts watching the directory, fails if it or one of its subdirectories can't be watched
+    pub fn spawn(dir: &Path, sender: Sender<IpcRequest>) -> anyhow::Result<Self> {
+        let mut inotify = Inotify::new()?;
+        let mut dirs = HashMap::new();
+        watch_recursively(&inotify, dir, &mut dirs)?;
+        info!(
+            "watching {} with {} directories for new images",
+            dir.display(),
+            dirs.len()
+        );
+
+        let mut pipe = [0; 2];
+        // SAFETY: `pipe` has space for both file descriptors
+        if unsafe { libc::pipe2(pipe.as_mut_ptr(), libc::O_CLOEXEC) } == -1 {
+            return Err(std::io::Error::last_os_error()).context("can't create pipe");
+        }
+        // SAFETY: both file descriptors are valid and owned by nobody else
+        let (stopped, stop) =
+            unsafe { (OwnedFd::from_raw_fd(pipe[0]), OwnedFd::from_raw_fd(pipe[1])) };
+
+        let thread_dir = dir.to_owned();
+        std::thread::spawn(move || {
+            let mut buf = [0; 4096];
+            let mut added = BTreeSet::new();
+            let mut removed = BTreeSet::new();
+            let mut changed = false;
+            loop {
+                let mut fds = [
+                    libc::pollfd {
+                        fd: inotify.file.as_raw_fd(),
+                        events: libc::POLLIN,
+                        revents: 0,
+                    },
+                    libc::pollfd {
+                        fd: stopped.as_raw_fd(),
+                        events: libc::POLLIN,
+                        revents: 0,
+                    },
+                ];
+                let timeout = if changed {
+                    DEBOUNCE.as_millis() as i32
+                } else {
+                    -1
+                };
+                // SAFETY: `fds` contains valid file descriptors
+                let ready = unsafe { libc::poll(fds.as_mut_ptr(), 2, timeout) };
+                if ready == -1 {
+                    let e = std::io::Error::last_os_error();
+                    if e.kind() == std::io::ErrorKind::Interrupted {
+                        continue;
+                    }
+                    error!("can't watch {} anymore: {}", thread_dir.display(), e);
+                    return;
+                }
+                if fds[1].revents != 0 {
+                    debug!("stopped watching {}", thread_dir.display());
+                    return;
+                }
+                if ready == 0 {
+                    changed = false;
+                    let event = IpcEvent::ImagesChanged {
+                        added: std::mem::take(&mut added).into_iter().collect(),
+                        removed: std::mem::take(&mut removed).into_iter().collect(),
+                    };
+                    if sender.send(IpcRequest::internal(event)).is_err() {
+                        return;
+                    }
+                    continue;
+                }
+
+                let bytes = match inotify.read(&mut buf) {
+                    Ok(bytes) => bytes,
+                    Err(e) => {
+                        error!("can't watch {} anymore: {}", thread_dir.display(), e);
+                        return;
+                    }
+                };
+                for event in events(bytes) {
+                    if event.mask & libc::IN_IGNORED != 0 {
+                        dirs.remove(&event.watch);
+                        continue;
+                    }
+                    let Some(path) = dirs.get(&event.watch).map(|dir| dir.join(event.name)) else {
+                        continue;
+                    };
+                    let is_dir = event.mask & libc::IN_ISDIR != 0;
+                    if event.mask & (libc::IN_DELETE | libc::IN_MOVED_FROM) != 0 {
+                        // a file which was only added in this round doesn't exist for the daemon
+                        if !added.remove(&path) {
+                            removed.insert(path);
+                        }
+                    } else if is_dir {
+                        if event.mask & (libc::IN_CREATE | libc::IN_MOVED_TO) != 0 {
+                            if let Err(e) = watch_recursively(&inotify, &path, &mut dirs) {
+                                error!("{:#}", e);
+                            }
+                        }
+                    } else if event.mask & (libc::IN_CLOSE_WRITE | libc::IN_MOVED_TO) != 0 {
+                        removed.remove(&path);
+                        added.insert(path);
+                    } else {
+                        // files are only complete once they were written
+                        continue;
+                    }
+                    changed = true;
+                }
+            }
+        });
+
+        Ok(Self {
+            dir: dir.to_owned(),
+            _stop: stop,
+        })
+    }
+
+    /// The watched directory
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+}
+
+/// Watches the directory and its subdirectories, symlinked directories are not followed
+fn watch_recursively(
+    inotify: &Inotify,
+    dir: &Path,
+    dirs: &mut HashMap<i32, PathBuf>,
+) -> anyhow::Result<()> {
+    let mut pending = vec![dir.to_owned()];
+    while let Some(dir) = pending.pop() {
+        let watch = inotify.add_watch(&dir, IMAGE_DIR_MASK | libc::IN_ONLYDIR)?;
+        for entry in
+            std::fs::read_dir(&dir).with_context(|| format!("while reading {}", dir.display()))?
+        {
+            let entry = entry.context("while reading directory entry")?;
+            if entry
+                .file_type()
+                .context("while reading file type")?
+                .is_dir()
+            {
+                pending.push(entry.path());
+            }
+        }
+        dirs.insert(watch, dir);
+    }
+    Ok(())
+}
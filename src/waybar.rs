@@ -0,0 +1,105 @@
+//! `wallpaper waybar`, a custom module of waybar which shows the current images
+
+use std::time::{Duration, SystemTime};
+
+use tracing::debug;
+use wallpaper::ipc::{self, IpcEvent, IpcResponse};
+
+/// How often `wallpaper waybar --watch` updates the time until the next change
+const REFRESH: Duration = Duration::from_secs(60);
+
+/// Prints the output of a custom module of waybar, and again after every change if `watch` is set
+pub fn run(watch: bool) -> anyhow::Result<()> {
+    let stopped = serde_json::json!({
+        "text": "",
+        "tooltip": "the daemon is not running",
+        "class": "stopped",
+    });
+    let mut client = match ipc::Client::connect() {
+        Ok(client) => client,
+        Err(e) => {
+            debug!("can't connect to daemon: {:#}", e);
+            println!("{}", stopped);
+            return Ok(());
+        }
+    };
+    // the subscribed connection only receives changes, so the queries need their own one
+    let mut subscription = if watch {
+        let mut subscription = ipc::Client::connect()?;
+        match subscription.send(IpcEvent::Subscribe)? {
+            IpcResponse::Ok => {}
+            IpcResponse::Error(e) => anyhow::bail!("daemon returned error: {}", e),
+            response => anyhow::bail!("unexpected response from daemon: {:?}", response),
+        }
+        Some(subscription)
+    } else {
+        None
+    };
+
+    loop {
+        println!("{}", module(&mut client)?);
+        let Some(subscription) = &mut subscription else {
+            return Ok(());
+        };
+        if !subscription.wait_for_change(REFRESH)? {
+            println!("{}", stopped);
+            return Ok(());
+        }
+    }
+}
+
+/// Returns the json of a custom module of waybar with the state of the daemon
+fn module(client: &mut ipc::Client) -> anyhow::Result<serde_json::Value> {
+    let summary = match client.send(IpcEvent::State)? {
+        IpcResponse::State(summary) => summary,
+        IpcResponse::Error(e) => anyhow::bail!("daemon returned error: {}", e),
+        response => anyhow::bail!("unexpected response from daemon: {:?}", response),
+    };
+    let (at, reason) = match client.send(IpcEvent::NextChange)? {
+        IpcResponse::NextChange { at, reason } => (at, reason),
+        IpcResponse::Error(e) => anyhow::bail!("daemon returned error: {}", e),
+        response => anyhow::bail!("unexpected response from daemon: {:?}", response),
+    };
+
+    let mut names: Vec<String> = Vec::new();
+    let mut tooltip = Vec::new();
+    for (monitor, info) in &summary.cache.monitors {
+        let Some(image) = &info.image else {
+            continue;
+        };
+        let name = image.file_name().map_or_else(
+            || image.display().to_string(),
+            |name| name.to_string_lossy().into_owned(),
+        );
+        if !names.contains(&name) {
+            names.push(name);
+        }
+        tooltip.push(format!("{}: {}", monitor, image.display()));
+    }
+    let remaining = at.duration_since(SystemTime::now()).unwrap_or_default();
+    // the module is only updated every minute, so seconds would be wrong most of the time
+    let remaining = Duration::from_secs(remaining.as_secs().div_ceil(60) * 60);
+    tooltip.push(format!(
+        "next change in {} at {} ({})",
+        humantime::format_duration(remaining),
+        chrono::DateTime::<chrono::Local>::from(at).format("%H:%M"),
+        reason
+    ));
+
+    let status = summary.daemon.map(|daemon| daemon.status);
+    let class = if status
+        .as_ref()
+        .is_some_and(|status| !status.inhibited.is_empty())
+    {
+        "inhibited"
+    } else if summary.cache.favorites_only {
+        "favorites"
+    } else {
+        "running"
+    };
+    Ok(serde_json::json!({
+        "text": names.join(" | "),
+        "tooltip": tooltip.join("\n"),
+        "class": class,
+    }))
+}
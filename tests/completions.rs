@@ -0,0 +1,61 @@
+//! Runs the helpers which the completion scripts call
+
+use std::{path::Path, process::Command};
+
+use test_util::TempDir;
+
+#[allow(dead_code)]
+#[path = "../src/test_util.rs"]
+mod test_util;
+
+/// Runs `wallpaper __complete` with the config in `dir` and returns the printed lines
+fn complete(dir: &Path, kind: &str) -> Vec<String> {
+    let output = Command::new(env!("CARGO_BIN_EXE_wallpaper"))
+        .args(["__complete", kind])
+        .env("HOME", dir)
+        .env("XDG_CONFIG_HOME", dir.join("config"))
+        .env("XDG_CACHE_HOME", dir.join("cache"))
+        .env("XDG_STATE_HOME", dir.join("state"))
+        .env_remove("WALLPAPER_LOG_DIR")
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    String::from_utf8(output.stdout)
+        .unwrap()
+        .lines()
+        .map(String::from)
+        .collect()
+}
+
+#[test]
+fn completes_the_configured_images_one_per_line() {
+    let dir = TempDir::new();
+    let config = dir.path().join("config/wallpaper");
+    std::fs::create_dir_all(&config).unwrap();
+    let config_json = serde_json::json!({
+        "check_interval": "5m",
+        "update_interval": "1h",
+        "transitions": [],
+        "image_dir": "/images",
+        "fps": 30,
+        "images": {
+            "sea/b c.png": "6-20",
+            "a.png": "*",
+        },
+        "monitor_settings": {
+            "DP-1": { "images": { "a.png": "*", "portrait/d.png": "*" } },
+        },
+    });
+    std::fs::write(config.join("config.json"), config_json.to_string()).unwrap();
+
+    assert_eq!(
+        complete(dir.path(), "images"),
+        ["a.png", "portrait/d.png", "sea/b c.png"]
+    );
+}
+
+#[test]
+fn completes_no_images_without_a_config() {
+    let dir = TempDir::new();
+    assert!(complete(dir.path(), "images").is_empty());
+}
@@ -0,0 +1,155 @@
+//! Runs the daemon against the fake swww of `tests/fixtures/bin`
+//!
+//! Only built with the `daemon-tests` feature, because it starts real processes
+//! and has to wait for them: `cargo test --features daemon-tests`.
+#![cfg(feature = "daemon-tests")]
+
+use std::{
+    path::{Path, PathBuf},
+    process::{Child, Command, ExitStatus, Stdio},
+    time::{Duration, Instant},
+};
+
+use test_util::TempDir;
+
+#[allow(dead_code)]
+#[path = "../src/test_util.rs"]
+mod test_util;
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+fn fixtures() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures")
+}
+
+/// Starts the daemon with its config, cache and runtime dir in `dir`
+fn spawn_daemon(dir: &Path) -> Child {
+    daemon_command(dir).spawn().unwrap()
+}
+
+/// The command of [`spawn_daemon`], to add more variables
+fn daemon_command(dir: &Path) -> Command {
+    let images = dir.join("images");
+    std::fs::create_dir_all(&images).unwrap();
+    std::fs::copy(fixtures().join("images/a.png"), images.join("a.png")).unwrap();
+    let config = dir.join("config/wallpaper");
+    std::fs::create_dir_all(&config).unwrap();
+    let config_json = serde_json::json!({
+        "check_interval": "5m",
+        "update_interval": "1h",
+        "transitions": [],
+        "image_dir": images,
+        "fps": 30,
+        "images": { "a.png": "0-24" },
+    });
+    std::fs::write(config.join("config.json"), config_json.to_string()).unwrap();
+    for sub in ["cache", "runtime", "state"] {
+        std::fs::create_dir_all(dir.join(sub)).unwrap();
+    }
+
+    let path = std::env::join_paths(std::iter::once(fixtures().join("bin")).chain(
+        std::env::split_paths(&std::env::var_os("PATH").unwrap_or_default()),
+    ))
+    .unwrap();
+    let mut command = Command::new(env!("CARGO_BIN_EXE_wallpaper"));
+    command
+        .arg("daemon")
+        .env("PATH", path)
+        .env("HOME", dir)
+        .env("XDG_CONFIG_HOME", dir.join("config"))
+        .env("XDG_CACHE_HOME", dir.join("cache"))
+        .env("XDG_RUNTIME_DIR", dir.join("runtime"))
+        .env("XDG_STATE_HOME", dir.join("state"))
+        .env("WAYLAND_DISPLAY", "wayland-1")
+        .env_remove("NOTIFY_SOCKET")
+        .env_remove("WALLPAPER_LOG_DIR")
+        .stdout(Stdio::null())
+        .stderr(Stdio::null());
+    command
+}
+
+/// Waits until `done` returns true, fails after [`TIMEOUT`]
+fn wait_for(what: &str, mut done: impl FnMut() -> bool) {
+    let start = Instant::now();
+    while !done() {
+        assert!(start.elapsed() < TIMEOUT, "timed out waiting for {}", what);
+        std::thread::sleep(Duration::from_millis(50));
+    }
+}
+
+fn wait_for_exit(daemon: &mut Child) -> ExitStatus {
+    let mut status = None;
+    let start = Instant::now();
+    while status.is_none() {
+        if start.elapsed() > TIMEOUT {
+            let _ = daemon.kill();
+            panic!("the daemon didn't stop");
+        }
+        std::thread::sleep(Duration::from_millis(50));
+        status = daemon.try_wait().unwrap();
+    }
+    status.unwrap()
+}
+
+fn signal(daemon: &Child, signal: libc::c_int) {
+    // SAFETY: the pid belongs to the child, which wasn't waited for yet
+    let ret = unsafe { libc::kill(daemon.id() as libc::pid_t, signal) };
+    assert_eq!(ret, 0, "can't send signal {}", signal);
+}
+
+#[test]
+fn sigterm_removes_the_socket() {
+    let dir = TempDir::new();
+    let socket = dir.path().join("runtime/wallpaper.socket");
+    let mut daemon = spawn_daemon(dir.path());
+    wait_for("the socket", || socket.exists());
+
+    signal(&daemon, libc::SIGTERM);
+    let status = wait_for_exit(&mut daemon);
+
+    assert!(status.success(), "the daemon exited with {}", status);
+    assert!(!socket.exists(), "the socket was left behind");
+}
+
+#[test]
+fn sigint_removes_the_socket() {
+    let dir = TempDir::new();
+    let socket = dir.path().join("runtime/wallpaper.socket");
+    let mut daemon = spawn_daemon(dir.path());
+    wait_for("the socket", || socket.exists());
+
+    signal(&daemon, libc::SIGINT);
+    let status = wait_for_exit(&mut daemon);
+
+    assert!(status.success(), "the daemon exited with {}", status);
+    assert!(!socket.exists(), "the socket was left behind");
+}
+
+#[cfg(feature = "systemd")]
+#[test]
+fn swww_does_not_inherit_the_notify_socket() {
+    let dir = TempDir::new();
+    let notify = dir.path().join("notify.socket");
+    let systemd = std::os::unix::net::UnixDatagram::bind(&notify).unwrap();
+    systemd.set_read_timeout(Some(TIMEOUT)).unwrap();
+    let log = dir.path().join("swww.log");
+    let mut daemon = daemon_command(dir.path())
+        .env("NOTIFY_SOCKET", &notify)
+        .env("SWWW_NOTIFY_LOG", &log)
+        .spawn()
+        .unwrap();
+
+    let mut buf = [0; 1024];
+    loop {
+        let len = systemd.recv(&mut buf).expect("the daemon didn't get ready");
+        if &buf[..len] == b"READY=1" {
+            break;
+        }
+    }
+    signal(&daemon, libc::SIGTERM);
+    assert!(wait_for_exit(&mut daemon).success());
+
+    let log = std::fs::read_to_string(log).unwrap();
+    assert!(!log.is_empty(), "swww wasn't run");
+    assert!(log.lines().all(|line| line == "unset"), "{}", log);
+}